@@ -0,0 +1,29 @@
+fn main() {
+    // self-update でダウンロードするリリースのファイル名に使う
+    println!(
+        "cargo:rustc-env=FILE_TRANSFER_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+    println!("cargo:rerun-if-env-changed=FILE_TRANSFER_UPDATE_KEY");
+
+    // cdylib フィーチャーでは C から使うためのヘッダーを生成する
+    #[cfg(feature = "cdylib")]
+    generate_header();
+}
+
+#[cfg(feature = "cdylib")]
+fn generate_header() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    // C の API は src/ffi.rs だけにあるため、クレート全体ではなくそのファイルだけを読む
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+        .expect("cbindgen.toml の読み込みに失敗");
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(format!("{}/src/ffi.rs", crate_dir))
+        .generate()
+        .expect("ヘッダーの生成に失敗")
+        .write_to_file(format!("{}/include/file_transfer.h", crate_dir));
+}
@@ -0,0 +1,36 @@
+#![no_main]
+
+use file_transfer::frame::{self, Decoded, HeaderDecoder, MAX_CHUNK_LEN};
+use libfuzzer_sys::fuzz_target;
+
+// 任意のバイト列をヘッダーとして読み、パニックしないこと・大きな領域を求めないこと・
+// ヘッダーより先を読まないことを確かめる
+fuzz_target!(|data: &[u8]| {
+    let _ = frame::decode_header(data);
+
+    // 接続から読むときと同じく、求められた長さだけを渡していく
+    let mut decoder = HeaderDecoder::default();
+    let mut pos = 0;
+    let mut len = 0;
+    loop {
+        match decoder.decode(&data[pos..pos + len]) {
+            Ok(Decoded::Incomplete(needed)) => {
+                assert!(needed > len && needed <= MAX_CHUNK_LEN);
+                if pos + needed > data.len() {
+                    break;
+                }
+                len = needed;
+            }
+            Ok(Decoded::Chunk(used)) => {
+                assert_eq!(used, len);
+                pos += used;
+                len = 0;
+            }
+            Ok(Decoded::Header(_, used)) => {
+                assert_eq!(used, len);
+                break;
+            }
+            Err(_) => break,
+        }
+    }
+});
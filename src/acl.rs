@@ -0,0 +1,197 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+// 設定ファイルの [[acl]] に書く、接続元ごとのアクセス制御ルール
+//
+// 上から順に照合し、最初に一致したルールを適用する。ルールが1つもなければ全て許可する
+#[derive(Debug, Clone, Deserialize)]
+pub struct AclRule {
+    // 接続元のIPアドレス（"local" でローカル接続、省略または "*" で全て）
+    pub peer: Option<String>,
+
+    // 提示されたトークンのID（省略するとトークンの有無を問わない）
+    pub token: Option<String>,
+
+    // ファイルの送信を許可するか
+    #[serde(default = "default_send")]
+    pub send: bool,
+
+    // サーバーの起動中に受信できる合計バイト数（接続元ごとではなく、このルールに一致した接続元すべての合計。
+    // 受信に成功した分だけを数える）
+    pub quota: Option<u64>,
+
+    // 保存を許可する、保存先フォルダからの相対パスのサブフォルダ（省略すると制限なし）
+    #[serde(default)]
+    pub subfolders: Vec<PathBuf>,
+}
+
+fn default_send() -> bool {
+    true
+}
+
+impl AclRule {
+    fn matches(&self, peer: &str, token_id: Option<&str>) -> bool {
+        let peer_matches = match self.peer.as_deref() {
+            None | Some("*") => true,
+            Some(rule_peer) => rule_peer == peer,
+        };
+        let token_matches = match self.token.as_deref() {
+            None => true,
+            Some(rule_token) => token_id == Some(rule_token),
+        };
+        peer_matches && token_matches
+    }
+}
+
+// アクセス制御ルールと、ルールごとの受信済みバイト数
+pub struct Acl {
+    rules: Vec<AclRule>,
+    used: Mutex<Vec<u64>>,
+}
+
+// 接続に適用するルール（ルールが設定されていない場合は None）
+#[derive(Clone, Copy)]
+pub struct Grant(Option<usize>);
+
+impl Acl {
+    pub fn new(rules: Vec<AclRule>) -> Self {
+        let used = Mutex::new(vec![0; rules.len()]);
+        Self { rules, used }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // 認証後の接続元に適用するルールを決め、送信が許可されているか確認する
+    pub fn check(&self, peer: &str, token_id: Option<&str>) -> Result<Grant> {
+        if self.rules.is_empty() {
+            return Ok(Grant(None));
+        }
+
+        let Some(index) = self
+            .rules
+            .iter()
+            .position(|rule| rule.matches(peer, token_id))
+        else {
+            anyhow::bail!("一致するアクセス制御ルールがありません");
+        };
+        if !self.rules[index].send {
+            anyhow::bail!("アクセス制御ルールで送信が許可されていません");
+        }
+
+        Ok(Grant(Some(index)))
+    }
+
+    // 受信するバイト数を容量制限に仮に計上する（超える場合は計上せずエラー）
+    //
+    // 返した計上は、受信に成功して commit するまでは、破棄されたときに戻す
+    pub fn reserve(&self, grant: Grant, bytes: u64) -> Result<Charge<'_>> {
+        let charge = Charge {
+            acl: self,
+            grant,
+            bytes: 0,
+        };
+        let Grant(Some(index)) = grant else {
+            return Ok(charge);
+        };
+        let Some(quota) = self.rules[index].quota else {
+            return Ok(charge);
+        };
+
+        let mut used = self.used.lock().unwrap();
+        match used[index].checked_add(bytes) {
+            Some(total) if total <= quota => {
+                used[index] = total;
+                Ok(Charge { bytes, ..charge })
+            }
+            _ => anyhow::bail!(
+                "受信容量の上限を超えます ({} + {} / {} バイト)",
+                used[index],
+                bytes,
+                quota
+            ),
+        }
+    }
+
+    // reserve で計上したバイト数を戻す
+    fn release(&self, grant: Grant, bytes: u64) {
+        let Grant(Some(index)) = grant else {
+            return;
+        };
+        let mut used = self.used.lock().unwrap();
+        used[index] = used[index].saturating_sub(bytes);
+    }
+
+    // 保存先フォルダからの相対パスが、許可されたサブフォルダの中か確認する
+    //
+    // 保存先フォルダの中のシンボリックリンクで許可されたサブフォルダの外を指せないよう、
+    // リンクをたどった先のパスで比べる
+    pub fn check_destination(&self, grant: Grant, save_dir: &Path, relative: &Path) -> Result<()> {
+        let Grant(Some(index)) = grant else {
+            return Ok(());
+        };
+        let subfolders = &self.rules[index].subfolders;
+        if subfolders.is_empty() {
+            return Ok(());
+        }
+
+        let resolved = resolve_existing(&save_dir.join(relative))?;
+        for subfolder in subfolders {
+            if resolved.starts_with(resolve_existing(&save_dir.join(subfolder))?) {
+                return Ok(());
+            }
+        }
+        anyhow::bail!("許可されていない保存先です: {:?}", relative)
+    }
+}
+
+// 容量制限に仮に計上したバイト数（commit せずに破棄すると計上を戻す）
+pub struct Charge<'a> {
+    acl: &'a Acl,
+    grant: Grant,
+    bytes: u64,
+}
+
+impl Charge<'_> {
+    // 受信に成功したため、計上したままにする
+    pub fn commit(mut self) {
+        self.bytes = 0;
+    }
+}
+
+impl Drop for Charge<'_> {
+    fn drop(&mut self) {
+        if self.bytes > 0 {
+            self.acl.release(self.grant, self.bytes);
+        }
+    }
+}
+
+// パスのうち存在する部分のシンボリックリンクをたどり、残りをつなげたパス
+fn resolve_existing(path: &Path) -> Result<PathBuf> {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        match fs::canonicalize(existing) {
+            Ok(resolved) => {
+                return Ok(rest
+                    .iter()
+                    .rev()
+                    .fold(resolved, |path, name| path.join(name)))
+            }
+            Err(_) => {
+                let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+                    anyhow::bail!("保存先のパスを解決できません: {:?}", path);
+                };
+                rest.push(name);
+                existing = parent;
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::buffer::{self, BUFFER_SIZE};
+use anyhow::{Context, Result};
+use bytes::BytesMut;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, Notify},
+};
+
+// チャンクの大きさの範囲
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+// 1チャンクの読み書きにかける時間の目安（速い回線ではチャンクを大きく、遅い回線では小さくする）
+const CHUNK_TIME: Duration = Duration::from_millis(10);
+
+// 先読みするチャンク数の範囲と、速度を計測する前の初期値
+const MIN_DEPTH: usize = 2;
+const MAX_DEPTH: usize = 64;
+const DEFAULT_DEPTH: usize = 8;
+
+// 速度の移動平均で、新しい計測値にかける重み
+const SMOOTHING: f64 = 0.25;
+
+// 計測した速度と往復時間から、チャンクの大きさと先読みするチャンク数を決める
+//
+// チャンクは CHUNK_TIME で読み書きできる大きさに近づけ（1回の調整で倍・半分まで）、
+// 先読みは往復時間の間に流れるデータ（帯域幅と遅延の積）を覆える数にする
+pub struct Tuner {
+    chunk_size: usize,
+    rtt: Option<Duration>,
+    // 計測した速度（バイト/秒）の移動平均
+    throughput: Option<f64>,
+}
+
+impl Tuner {
+    // rtt は接続先との往復時間（計測していなければ None）
+    pub fn new(rtt: Option<Duration>) -> Self {
+        Self {
+            chunk_size: BUFFER_SIZE,
+            rtt,
+            throughput: None,
+        }
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn depth(&self) -> usize {
+        let (Some(throughput), Some(rtt)) = (self.throughput, self.rtt) else {
+            return DEFAULT_DEPTH;
+        };
+        let in_flight = throughput * rtt.as_secs_f64();
+        ((in_flight / self.chunk_size as f64).ceil() as usize).clamp(MIN_DEPTH, MAX_DEPTH)
+    }
+
+    // bytes バイトを elapsed で読み書きできたことを記録し、チャンクの大きさを調整する
+    pub fn record(&mut self, bytes: usize, elapsed: Duration) {
+        if bytes == 0 {
+            return;
+        }
+        let sample = bytes as f64 / elapsed.as_secs_f64().max(1e-6);
+        let throughput = match self.throughput {
+            Some(average) => average + (sample - average) * SMOOTHING,
+            None => sample,
+        };
+        self.throughput = Some(throughput);
+        let target = (throughput * CHUNK_TIME.as_secs_f64()) as usize;
+        self.chunk_size = target
+            .clamp(self.chunk_size / 2, self.chunk_size * 2)
+            .clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+    }
+}
+
+// chunk_size バイトのチャンクを読む（reader が先に終われば短くなり、終わっていれば空になる）
+pub async fn read_chunk(
+    reader: &mut (impl AsyncRead + Unpin),
+    chunk_size: usize,
+) -> Result<BytesMut> {
+    let mut chunk = buffer::take(chunk_size);
+    while chunk.len() < chunk_size {
+        let n = (&mut *reader)
+            .take((chunk_size - chunk.len()) as u64)
+            .read_buf(&mut chunk)
+            .await
+            .context("ファイルデータの読み取りに失敗")?;
+        if n == 0 {
+            break;
+        }
+    }
+    Ok(chunk)
+}
+
+// reader から最大 length バイトを writer に送り、送ったバイト数を返す
+//
+// 読み取りと送信を別々に進め、送信の速さに合わせてチャンクの大きさと先読みするチャンク数を調整する
+pub async fn copy(
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    length: u64,
+    rtt: Option<Duration>,
+) -> Result<u64> {
+    let tuner = Mutex::new(Tuner::new(rtt));
+    let (tx, mut rx) = mpsc::channel::<BytesMut>(MAX_DEPTH);
+    // 送信の段がチャンクを受け取るたびに、読み取りの段を起こす
+    let taken = Notify::new();
+
+    let read = async {
+        let mut reader = reader.take(length);
+        loop {
+            let (chunk_size, depth) = {
+                let tuner = tuner.lock().unwrap();
+                (tuner.chunk_size(), tuner.depth())
+            };
+            // 先読みしたチャンクが depth 個に達していれば、送信が進むまで待つ
+            while MAX_DEPTH - tx.capacity() >= depth {
+                taken.notified().await;
+            }
+            let chunk = read_chunk(&mut reader, chunk_size).await?;
+            if chunk.is_empty() {
+                buffer::release(chunk);
+                break;
+            }
+            // 送信の段が失敗して受け取らなくなったら、そちらのエラーを返すため読み取りだけ止める
+            if tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        drop(tx);
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let write = async {
+        let mut sent = 0;
+        while let Some(chunk) = rx.recv().await {
+            taken.notify_one();
+            let started = Instant::now();
+            writer.write_all(&chunk).await?;
+            tuner.lock().unwrap().record(chunk.len(), started.elapsed());
+            sent += chunk.len() as u64;
+            buffer::release(chunk);
+        }
+        writer.flush().await?;
+        Ok::<_, anyhow::Error>(sent)
+    };
+
+    let (_, sent) = tokio::try_join!(read, write)?;
+    Ok(sent)
+}
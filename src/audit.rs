@@ -0,0 +1,118 @@
+use crate::dedup;
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+// 最初の記録の前に置く、直前の記録のハッシュ
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// 追記専用の監査ログ
+//
+// 1行が1件の記録で、タブ区切りで 通し番号・時刻・種別・接続元・内容・ハッシュ を並べる。
+// ハッシュは直前の記録のハッシュとこの記録の内容から求めるため、途中の行を書き換えたり
+// 削除したりすると以降の検証に失敗する
+pub struct AuditLog {
+    state: Mutex<AuditState>,
+}
+
+struct AuditState {
+    file: File,
+    seq: u64,
+    last_hash: String,
+}
+
+impl AuditLog {
+    // 監査ログを開き、既存の記録の続きから追記できるようにする
+    pub fn open(path: &Path) -> Result<Self> {
+        let (seq, last_hash) = if path.exists() {
+            verify(path)?
+        } else {
+            (0, GENESIS_HASH.to_string())
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("監査ログを開けません: {:?}", path))?;
+
+        Ok(Self {
+            state: Mutex::new(AuditState {
+                file,
+                seq,
+                last_hash,
+            }),
+        })
+    }
+
+    // 1件記録する
+    pub fn record(&self, event: &str, peer: &str, detail: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.seq + 1;
+        let body = format!(
+            "{}\t{}\t{}\t{}\t{}",
+            seq,
+            chrono::Utc::now().to_rfc3339(),
+            escape(event),
+            escape(peer),
+            escape(detail)
+        );
+        let hash = chain_hash(&state.last_hash, &body);
+
+        writeln!(state.file, "{}\t{}", body, hash).context("監査ログへの書き込みに失敗")?;
+        state
+            .file
+            .sync_data()
+            .context("監査ログへの書き込みに失敗")?;
+        state.seq = seq;
+        state.last_hash = hash;
+        Ok(())
+    }
+}
+
+// 監査ログのハッシュの連鎖を検証し、記録の件数と最後のハッシュを返す
+pub fn verify(path: &Path) -> Result<(u64, String)> {
+    let file = File::open(path).with_context(|| format!("監査ログを開けません: {:?}", path))?;
+
+    let mut seq = 0;
+    let mut last_hash = GENESIS_HASH.to_string();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.context("監査ログの読み込みに失敗")?;
+        let line_number = index + 1;
+        let Some((body, hash)) = line.rsplit_once('\t') else {
+            anyhow::bail!("監査ログの {} 行目の形式が不正です", line_number);
+        };
+        let expected_seq = (seq + 1).to_string();
+        if body.split('\t').next() != Some(expected_seq.as_str()) {
+            anyhow::bail!(
+                "監査ログの {} 行目の通し番号が連続していません",
+                line_number
+            );
+        }
+        if chain_hash(&last_hash, body) != hash {
+            anyhow::bail!("監査ログの {} 行目のハッシュが一致しません", line_number);
+        }
+        seq += 1;
+        last_hash = hash.to_string();
+    }
+
+    Ok((seq, last_hash))
+}
+
+fn chain_hash(last_hash: &str, body: &str) -> String {
+    dedup::sha256_hex(format!("{}\n{}", last_hash, body).as_bytes())
+}
+
+// 区切りのタブと改行が記録の内容に紛れ込まないようにする
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
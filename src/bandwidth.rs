@@ -0,0 +1,89 @@
+use anyhow::{Context, Result};
+use chrono::NaiveTime;
+use serde::Deserialize;
+
+// 設定ファイルの [[bandwidth]] に書く、時間帯ごとの速度の上限
+//
+// 上から順に照合し、最初に一致した時間帯の上限を使う。どれにも一致しなければ --limit-rate の値
+// （指定がなければ無制限）。from より to が前なら日をまたぐ時間帯（例: "22:00"〜"06:00"）
+#[derive(Debug, Clone, Deserialize)]
+pub struct BandwidthRule {
+    // 開始時刻（"09:00" の形式、ローカル時刻）
+    pub from: String,
+
+    // 終了時刻（この時刻を含まない）
+    pub to: String,
+
+    // 速度の上限（KB/s。省略すると無制限）
+    pub limit: Option<u64>,
+}
+
+struct Window {
+    from: NaiveTime,
+    to: NaiveTime,
+    bytes_per_sec: Option<u64>,
+}
+
+impl Window {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+}
+
+// 時刻から速度の上限を決める予定表
+pub struct Schedule {
+    windows: Vec<Window>,
+    default: Option<u64>,
+}
+
+impl Schedule {
+    // 常に同じ上限（None なら無制限）
+    pub fn fixed(bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            windows: Vec::new(),
+            default: bytes_per_sec,
+        }
+    }
+
+    // 設定ファイルのルールと、どの時間帯にも一致しない場合の上限（バイト/秒）から作る
+    pub fn new(rules: &[BandwidthRule], default: Option<u64>) -> Result<Self> {
+        let windows = rules
+            .iter()
+            .map(|rule| {
+                Ok(Window {
+                    from: parse_time(&rule.from)?,
+                    to: parse_time(&rule.to)?,
+                    bytes_per_sec: rule.limit.map(|limit| limit.saturating_mul(1024)),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { windows, default })
+    }
+
+    // どの時間帯でも無制限か（速度制限のレイヤーを入れる必要がない）
+    pub fn is_unlimited(&self) -> bool {
+        self.default.is_none() && self.windows.iter().all(|w| w.bytes_per_sec.is_none())
+    }
+
+    // 今の速度の上限（バイト/秒。None なら無制限）
+    pub fn current(&self) -> Option<u64> {
+        let now = chrono::Local::now().time();
+        self.windows
+            .iter()
+            .find(|window| window.contains(now))
+            .map_or(self.default, |window| window.bytes_per_sec)
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").with_context(|| {
+        format!(
+            "[[bandwidth]] の時刻の形式が不正です（\"09:00\" の形式）: {}",
+            time
+        )
+    })
+}
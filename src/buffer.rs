@@ -0,0 +1,46 @@
+use bytes::BytesMut;
+use std::sync::Mutex;
+
+// 転送データを読み書きするチャンク1つの大きさ（速度を計測する前の初期値）
+pub const BUFFER_SIZE: usize = 64 * 1024;
+
+// 使い終わったバッファーを残しておく合計の大きさの上限（超えた分は解放する）
+const MAX_POOLED_BYTES: usize = 64 * 1024 * 1024;
+
+// 同時に転送している接続の間で共有する、使い終わったバッファー
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    buffers: Vec::new(),
+    bytes: 0,
+});
+
+struct Pool {
+    buffers: Vec<BytesMut>,
+    // 残しているバッファーの容量の合計
+    bytes: usize,
+}
+
+// capacity バイト以上入る空のバッファーを取り出す（残っていなければ新しく確保する）
+pub fn take(capacity: usize) -> BytesMut {
+    let mut pool = POOL.lock().unwrap();
+    let Some(mut buf) = pool.buffers.pop() else {
+        return BytesMut::with_capacity(capacity);
+    };
+    pool.bytes -= buf.capacity();
+    drop(pool);
+    buf.reserve(capacity);
+    buf
+}
+
+// 使い終わったバッファーを戻す
+pub fn release(mut buf: BytesMut) {
+    buf.clear();
+    // 分割などで小さくなったものは使い回さない
+    if buf.capacity() < BUFFER_SIZE {
+        return;
+    }
+    let mut pool = POOL.lock().unwrap();
+    if pool.bytes + buf.capacity() <= MAX_POOLED_BYTES {
+        pool.bytes += buf.capacity();
+        pool.buffers.push(buf);
+    }
+}
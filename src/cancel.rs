@@ -0,0 +1,95 @@
+use crate::{layer::Layer, transport::BoxedConnection};
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+// 転送が CancellationToken で中断されたことを表すエラー
+//
+// 中断による失敗は e.downcast_ref::<Cancelled>() で他の失敗と区別できる
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "転送が中断されました")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+// token が取り消されると読み書きがエラーになるよう接続を包むレイヤー
+//
+// 読み書きの待機中に取り消された場合もすぐにエラーを返すため、受信処理のエラー時の
+// 後始末（一時ファイルの削除など）がそのまま実行される
+pub struct Cancel {
+    token: CancellationToken,
+}
+
+impl Cancel {
+    pub fn new(token: CancellationToken) -> Self {
+        Self { token }
+    }
+}
+
+impl Layer for Cancel {
+    fn wrap(&self, connection: BoxedConnection) -> BoxedConnection {
+        Box::new(Cancellable {
+            inner: connection,
+            cancelled: Box::pin(self.token.clone().cancelled_owned()),
+        })
+    }
+}
+
+struct Cancellable {
+    inner: BoxedConnection,
+    cancelled: Pin<Box<WaitForCancellationFutureOwned>>,
+}
+
+impl Cancellable {
+    fn poll_cancelled(&mut self, cx: &mut Context<'_>) -> Option<io::Error> {
+        match self.cancelled.as_mut().poll(cx) {
+            Poll::Ready(()) => Some(io::Error::new(io::ErrorKind::Interrupted, Cancelled)),
+            Poll::Pending => None,
+        }
+    }
+}
+
+impl AsyncRead for Cancellable {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(e) = self.poll_cancelled(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Cancellable {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(e) = self.poll_cancelled(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::{fs, io::Write, path::Path};
+
+// 保存先フォルダに置くハッシュの一覧（`sha256sum -c SHA256SUMS` で検証できる）
+pub const MANIFEST_FILE_NAME: &str = "SHA256SUMS";
+
+// 保存したファイルの SHA-256 の書き出し先
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumOutput {
+    /// 保存したファイルの隣に "<ファイル名>.sha256" を作る
+    Sidecar,
+    /// 保存先フォルダの SHA256SUMS に追記する
+    Manifest,
+}
+
+// 保存したファイル path のハッシュを outputs の指定どおりに書き出す（sha256sum と同じ形式）
+pub fn write(outputs: &[ChecksumOutput], save_dir: &Path, path: &Path, hash: &str) -> Result<()> {
+    for output in outputs {
+        match output {
+            ChecksumOutput::Sidecar => {
+                // ファイルと同じフォルダで検証できるよう、名前だけを書く
+                let name = path.file_name().unwrap_or_default();
+                let mut sidecar = path.as_os_str().to_owned();
+                sidecar.push(".sha256");
+                fs::write(&sidecar, format!("{}  {}\n", hash, name.to_string_lossy()))
+                    .with_context(|| {
+                        format!("チェックサムファイルの書き込みに失敗: {:?}", sidecar)
+                    })?;
+            }
+            ChecksumOutput::Manifest => {
+                let relative = path.strip_prefix(save_dir).unwrap_or(path);
+                let mut manifest = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(save_dir.join(MANIFEST_FILE_NAME))
+                    .context("チェックサムの一覧の書き込みに失敗")?;
+                // 区切り文字は sha256sum に合わせて '/' にする
+                let relative = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                writeln!(manifest, "{}  {}", hash, relative)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use anyhow::{Context, Result};
+use arboard::Clipboard;
+use clap::ValueEnum;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+// クリップボードの所有者（Linux では所有者が生きている間だけ貼り付けられるため、使い回す）
+static CLIPBOARD: Mutex<Option<Clipboard>> = Mutex::new(None);
+
+// 受信したファイルをクリップボードにコピーする形式
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardCopy {
+    /// 保存先のパスを文字列としてコピーする
+    Path,
+    /// ファイルそのものをコピーする（ファイルマネージャーやチャットに貼り付けられる）
+    File,
+}
+
+// 保存したファイル path をクリップボードにコピーする
+//
+// ファイルとしてコピーできない環境では、パスの文字列をコピーする
+pub fn copy(mode: ClipboardCopy, path: &Path) -> Result<()> {
+    // 貼り付けた先で開けるよう、保存先フォルダが相対パスでも絶対パスにする
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let mut guard = CLIPBOARD.lock().unwrap();
+    let clipboard = open(&mut guard)?;
+    if mode == ClipboardCopy::File && clipboard.set().file_list(&[&path]).is_ok() {
+        return Ok(());
+    }
+    clipboard
+        .set_text(path.to_string_lossy())
+        .context("クリップボードへのコピーに失敗")
+}
+
+// クリップボードにコピーされているファイル（Windows の CF_HDROP、macOS・Linux のファイルの URI）
+//
+// ファイルがコピーされていない場合は空
+pub fn files() -> Result<Vec<PathBuf>> {
+    let mut guard = CLIPBOARD.lock().unwrap();
+    let clipboard = open(&mut guard)?;
+    Ok(clipboard.get().file_list().unwrap_or_default())
+}
+
+// 使い回しているクリップボード（まだ開いていなければ開く）
+fn open<'a>(guard: &'a mut MutexGuard<Option<Clipboard>>) -> Result<&'a mut Clipboard> {
+    let clipboard = match guard.take() {
+        Some(clipboard) => clipboard,
+        None => Clipboard::new().context("クリップボードを開けません")?,
+    };
+    Ok(guard.insert(clipboard))
+}
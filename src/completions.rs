@@ -0,0 +1,67 @@
+use crate::{config::Config, ssh_config};
+use clap::Command;
+use clap_complete::Shell;
+use std::io::Write;
+
+// シェルの補完スクリプトを out に書き出す
+//
+// 引数の補完は clap_complete で生成し、bash・zsh・fish では client --server の値に
+// 設定ファイルの送信先（peers）と ~/.ssh/config の Host の別名を補完する処理を加える
+// （候補は補完のたびに `completions --peers` で読み出すため、設定ファイルを変えても作り直す必要はない）
+pub fn generate(shell: Shell, command: &mut Command, out: &mut impl Write) -> std::io::Result<()> {
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, command, &name, out);
+
+    let function = name.replace('-', "_");
+    match shell {
+        Shell::Bash => write!(
+            out,
+            r#"
+_{function}_peers() {{
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    if [[ "${{COMP_WORDS[1]}}" == "client" && ( "$prev" == "--server" || "$prev" == "-s" ) ]]; then
+        COMPREPLY=( $(compgen -W "$({name} completions --peers 2>/dev/null)" -- "${{COMP_WORDS[COMP_CWORD]}}") )
+        return 0
+    fi
+    _{name} "$@"
+}}
+complete -F _{function}_peers -o bashdefault -o default {name}
+"#
+        ),
+        Shell::Zsh => write!(
+            out,
+            r#"
+_{function}_peers() {{
+    if [[ "${{words[2]}}" == "client" && "${{words[CURRENT-1]}}" == (-s|--server) ]]; then
+        local -a peers
+        peers=(${{(f)"$({name} completions --peers 2>/dev/null)"}})
+        compadd -a peers
+        return
+    fi
+    _{name} "$@"
+}}
+compdef _{function}_peers {name}
+"#
+        ),
+        Shell::Fish => write!(
+            out,
+            r#"
+complete -c {name} -n "__fish_seen_subcommand_from client" -s s -l server -x -a "({name} completions --peers 2>/dev/null)"
+"#
+        ),
+        // PowerShell・Elvish は生成した補完だけ（送信先は補完しない）
+        _ => Ok(()),
+    }
+}
+
+// 補完の候補にする送信先（設定ファイルの peers と ~/.ssh/config の Host の別名）を1行ずつ出力する
+pub fn print_peers(config: &Config) {
+    for peer in &config.peers {
+        println!("{}", peer);
+    }
+    for alias in ssh_config::aliases() {
+        if !config.peers.contains(&alias) {
+            println!("{}", alias);
+        }
+    }
+}
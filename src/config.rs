@@ -0,0 +1,164 @@
+use crate::{
+    acl::AclRule, bandwidth::BandwidthRule, hotkey::Action, limits::ClientLimits,
+    log::LogFileConfig, trust::TrustedNetwork, webhook::WebhookConfig,
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+// 設定ファイル（config.toml）の内容
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // サーバーモードの既定の保存先フォルダ
+    pub save_dir: Option<PathBuf>,
+
+    // 保存先フォルダ内の保存パスのテンプレート（例: "{date}/{sender}/{filename}"）
+    pub save_template: Option<String>,
+
+    // GUIモードの送信先の一覧（IPアドレスまたは "unix:/path" などのアドレス）
+    pub peers: Vec<String>,
+
+    // サーバーモードの監査ログのパス
+    pub audit_log: Option<PathBuf>,
+
+    // サーバーモードの接続元ごとのアクセス制御ルール（[[acl]]）
+    pub acl: Vec<AclRule>,
+
+    // サーバーモードで受け入れの確認を省く、信頼するネットワークの認証済みの送信元（[[trusted_networks]]）
+    pub trusted_networks: Vec<TrustedNetwork>,
+
+    // 操作ごとのホットキー（[hotkeys] に `change-save-dir = "ctrl+shift+r"` のように書く。
+    // `file-transfer hotkey ...` で変更すると保存される）
+    pub hotkeys: HashMap<Action, String>,
+
+    // ホットキーを登録できなかった場合に順に試す代わりの組み合わせ（例: ["ctrl+alt+s"]）
+    pub hotkey_fallbacks: Vec<String>,
+
+    // サーバーモードのホットキー（古い書き方。[hotkeys] の change-save-dir があればそちらを使う）
+    pub server_hotkey: Option<String>,
+
+    // サーバーモードで受信の一時停止・再開を切り替えるホットキー（古い書き方。[hotkeys] の toggle-pause があればそちらを使う）
+    pub server_pause_hotkey: Option<String>,
+
+    // クライアントモードのホットキー（古い書き方。[hotkeys] の send-file があればそちらを使う）
+    pub client_hotkey: Option<String>,
+
+    // self-update でリリースの署名を検証する minisign の公開鍵（省略するとビルド時に埋め込んだもの）
+    pub update_public_key: Option<String>,
+
+    // 時間帯ごとの送受信の速度の上限（[[bandwidth]]。--limit-rate はどの時間帯にも一致しない場合の値）
+    pub bandwidth: Vec<BandwidthRule>,
+
+    // サーバーモードの接続元ごとの同時接続数・転送数・受信量の上限（[client_limits]）
+    pub client_limits: ClientLimits,
+
+    // サーバーモードで受信の完了・失敗・拒否を知らせる Webhook（[[webhooks]]）
+    pub webhooks: Vec<WebhookConfig>,
+
+    // 待ち受けと接続に使うポート（省略すると 8080。--bind でポートを指定した場合はそちらを使う）
+    pub port: Option<u16>,
+
+    // サーバーモードで port が使用中の場合に、続くポートを順に試す数（--port-fallbacks と同じ。省略すると 10、0 で試さない）
+    pub port_fallbacks: Option<u16>,
+
+    // この端末の名前（サーバーモードの起動時に接続先のアドレスと一緒に表示する）
+    pub device_name: Option<String>,
+
+    // サーバーモードでトークン認証を必須にする（--require-token と同じ）
+    pub require_token: bool,
+
+    // サーバーモードで内容の種類が拡張子・申告と合わないファイルを拒否する（--enforce-type と同じ）
+    pub enforce_type: bool,
+
+    // サーバーモードで認証していない送信元からのファイルを隔離フォルダに受信する（--quarantine と同じ）
+    pub quarantine: bool,
+
+    // サーバーモードで ssh-agent の鍵での認証に受け入れる公開鍵の一覧（--ssh-authorized-keys と同じ）
+    pub ssh_authorized_keys: Option<PathBuf>,
+
+    // 常駐させる場合のログファイルと切り替え・保持の設定（[log_file]）
+    pub log_file: LogFileConfig,
+
+    // クライアントモードの --wake で使う、送信先ごとの MAC アドレス（[wake_on_lan] に "送信先" = "aa:bb:cc:dd:ee:ff"）
+    pub wake_on_lan: HashMap<String, String>,
+}
+
+// 送信履歴・再開情報を置くフォルダ（dir を指定しなければユーザーの設定フォルダの file-transfer）
+pub fn data_dir(dir: Option<&Path>) -> Result<PathBuf> {
+    match dir {
+        Some(dir) => Ok(dir.to_path_buf()),
+        None => dirs::config_dir()
+            .map(|dir| dir.join("file-transfer"))
+            .context("設定フォルダが見つかりません"),
+    }
+}
+
+impl Config {
+    // 設定ファイルの既定のパス
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("file-transfer").join("config.toml"))
+    }
+
+    // 設定ファイルを読み込む（存在しない場合は既定値）
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("設定ファイルの読み込みに失敗: {:?}", path))?;
+        toml::from_str(&text).with_context(|| format!("設定ファイルの解析に失敗: {:?}", path))
+    }
+
+    // 設定ファイルの複数の項目をまとめて書き換える（他の項目はそのまま残すが、コメントは失われる）
+    pub fn set_values<'a>(values: impl IntoIterator<Item = (&'a str, toml::Value)>) -> Result<()> {
+        Self::update(|table| {
+            for (key, value) in values {
+                table.insert(key.to_string(), value);
+            }
+        })
+    }
+
+    // 設定ファイルの [hotkeys] の操作のホットキーを書き換える（set_values と同じく、コメントは失われる）
+    pub fn set_hotkey(action: Action, hotkey: &str) -> Result<()> {
+        Self::update(|table| {
+            let hotkeys = table
+                .entry("hotkeys")
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            if let Some(hotkeys) = hotkeys.as_table_mut() {
+                hotkeys.insert(
+                    action.key().to_string(),
+                    toml::Value::String(hotkey.to_string()),
+                );
+            }
+        })
+    }
+
+    // 設定ファイルを読み込んで update で書き換え、保存する
+    fn update(update: impl FnOnce(&mut toml::Table)) -> Result<()> {
+        let path = Self::path().context("設定フォルダが見つかりません")?;
+        let mut table = if path.exists() {
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("設定ファイルの読み込みに失敗: {:?}", path))?;
+            text.parse::<toml::Table>()
+                .with_context(|| format!("設定ファイルの解析に失敗: {:?}", path))?
+        } else {
+            toml::Table::new()
+        };
+        update(&mut table);
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(&table)?;
+        fs::write(&path, text).with_context(|| format!("設定ファイルの保存に失敗: {:?}", path))
+    }
+}
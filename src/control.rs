@@ -0,0 +1,324 @@
+use crate::{
+    config::Config,
+    hotkey::{Action, Hotkeys},
+    transport::{self, BoxedConnection, UNIX_PREFIX},
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    sync::{mpsc, oneshot},
+};
+use uuid::Uuid;
+
+// 制御ソケットで受け付けるコマンドの1行の最大長（改行を含む）
+const MAX_COMMAND_LEN: u64 = 4096;
+
+// 制御ソケットで操作する対象
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Target {
+    /// サーバーモード
+    Server,
+    /// クライアントモード
+    Client,
+}
+
+impl Target {
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Server => "server",
+            Target::Client => "client",
+        }
+    }
+
+    // 操作を指定せずにホットキーを変更した場合に変わる、モードの主なホットキーの操作
+    pub fn main_action(self) -> Action {
+        match self {
+            Target::Server => Action::ChangeSaveDir,
+            Target::Client => Action::SendFile,
+        }
+    }
+}
+
+// 制御ソケットで受け付けるコマンド
+pub enum Command {
+    // ホットキーを変更する（"hotkey ctrl+alt+r"、操作を指定する場合は "hotkey toggle-pause ctrl+alt+p"）
+    Hotkey(Option<Action>, String),
+    // サーバーモードの受信の受け付けを一時停止する（"pause"）
+    Pause,
+    // 一時停止した受け付けを再開する（"resume"）
+    Resume,
+    // 通知で確認を求めている受信を受け入れる（"accept <転送ID>"）
+    Accept(Uuid),
+    // 通知で確認を求めている受信を拒否する（"reject <転送ID> [理由]"）
+    Reject(Uuid, String),
+}
+
+// 制御ソケットで受け付けた要求（処理したら reply で結果を返す）
+pub struct Request {
+    pub command: Command,
+    reply: oneshot::Sender<Result<(), String>>,
+}
+
+impl Request {
+    pub fn reply(self, result: Result<()>) {
+        let _ = self.reply.send(result.map_err(|e| format!("{:#}", e)));
+    }
+}
+
+// 制御ソケットのアドレス（ユーザー・モードごとに1つ）
+//
+// 名前付きパイプは全ユーザーで同じ名前空間にあるため、名前にユーザーの SID を含める
+fn address(target: Target) -> Result<String> {
+    if cfg!(windows) {
+        Ok(format!(
+            r"{}file-transfer-{}-{}",
+            transport::PIPE_PREFIX,
+            user_sid()?,
+            target.name()
+        ))
+    } else {
+        let path = socket_dir().join(format!("{}.sock", target.name()));
+        Ok(format!("{}{}", UNIX_PREFIX, path.display()))
+    }
+}
+
+// このプロセスを実行しているユーザーの SID（"S-1-5-21-..." の形式）
+#[cfg(windows)]
+fn user_sid() -> Result<String> {
+    use windows_sys::{
+        core::PWSTR,
+        Win32::{
+            Foundation::{CloseHandle, LocalFree, HANDLE},
+            Security::{
+                Authorization::ConvertSidToStringSidW, GetTokenInformation, TokenUser, TOKEN_QUERY,
+                TOKEN_USER,
+            },
+            System::Threading::{GetCurrentProcess, OpenProcessToken},
+        },
+    };
+
+    let mut token: HANDLE = 0;
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return Err(std::io::Error::last_os_error()).context("ユーザーの SID の取得に失敗");
+    }
+    // TOKEN_USER の後ろに SID が続くため、必要な長さを問い合わせてから読む
+    // （ポインタを含む構造体なので、バッファは u64 で揃える）
+    let mut len = 0;
+    unsafe { GetTokenInformation(token, TokenUser, std::ptr::null_mut(), 0, &mut len) };
+    let mut buf = vec![0u64; (len as usize).div_ceil(8)];
+    let read =
+        unsafe { GetTokenInformation(token, TokenUser, buf.as_mut_ptr().cast(), len, &mut len) };
+    let error = std::io::Error::last_os_error();
+    unsafe { CloseHandle(token) };
+    if read == 0 {
+        return Err(error).context("ユーザーの SID の取得に失敗");
+    }
+
+    let user = unsafe { &*buf.as_ptr().cast::<TOKEN_USER>() };
+    let mut string: PWSTR = std::ptr::null_mut();
+    if unsafe { ConvertSidToStringSidW(user.User.Sid, &mut string) } == 0 {
+        return Err(std::io::Error::last_os_error()).context("ユーザーの SID の変換に失敗");
+    }
+    let len = (0..)
+        .take_while(|&i| unsafe { *string.add(i) } != 0)
+        .count();
+    let sid = String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(string, len) });
+    unsafe { LocalFree(string.cast()) };
+    Ok(sid)
+}
+
+#[cfg(not(windows))]
+fn user_sid() -> Result<String> {
+    anyhow::bail!("ユーザーの SID はWindowsでのみ取得できます")
+}
+
+// 制御ソケットを置くフォルダ（ユーザーごとに1つ）
+#[cfg(unix)]
+fn socket_dir() -> PathBuf {
+    let dir = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+    dir.join(format!("file-transfer-{}", unsafe { libc::getuid() }))
+}
+
+#[cfg(not(unix))]
+fn socket_dir() -> PathBuf {
+    dirs::runtime_dir().unwrap_or_else(std::env::temp_dir)
+}
+
+// 制御ソケットで待ち受ける（受け付けた要求はチャネルに流れる）
+//
+// 1行のコマンドを受け取り、"OK" または "ERROR: ..." を1行で返す
+pub async fn listen(target: Target) -> Result<mpsc::Receiver<Request>> {
+    let addr = address(target)?;
+    if addr.starts_with(UNIX_PREFIX) {
+        create_socket_dir()?;
+    }
+    let (tx, mut rx) = mpsc::channel(4);
+    transport::for_addr(&addr).listen(&addr, tx).await?;
+    if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+        restrict_to_owner(Path::new(path))?;
+    }
+
+    let (request_tx, request_rx) = mpsc::channel(4);
+    tokio::spawn(async move {
+        while let Some(accepted) = rx.recv().await {
+            tokio::spawn(serve(accepted.connection, request_tx.clone()));
+        }
+    });
+    Ok(request_rx)
+}
+
+// 他のユーザーがソケットに接続できないよう、所有者だけが使えるフォルダを作ってからソケットを置く
+//
+// ソケットファイルの権限は作った後にしか変えられないため、その間に接続されないようフォルダで防ぐ。
+// 他のユーザーが先に同じ名前のフォルダやリンクを作っていた場合は使わない
+#[cfg(unix)]
+fn create_socket_dir() -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    let dir = socket_dir();
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("制御ソケットのフォルダの作成に失敗: {:?}", dir))
+        }
+    }
+    let metadata = std::fs::symlink_metadata(&dir)
+        .with_context(|| format!("制御ソケットのフォルダの確認に失敗: {:?}", dir))?;
+    if !metadata.is_dir() || metadata.uid() != unsafe { libc::getuid() } {
+        anyhow::bail!(
+            "制御ソケットのフォルダが自分の所有するフォルダではありません: {:?}",
+            dir
+        );
+    }
+    if metadata.permissions().mode() & 0o777 != 0o700 {
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))
+            .with_context(|| format!("制御ソケットのフォルダの権限の設定に失敗: {:?}", dir))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_socket_dir() -> Result<()> {
+    Ok(())
+}
+
+// 他のユーザーから操作されないよう、ソケットファイルを所有者だけが使えるようにする
+#[cfg(unix)]
+fn restrict_to_owner(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("制御ソケットの権限の設定に失敗: {:?}", path))
+}
+
+#[cfg(not(unix))]
+fn restrict_to_owner(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+async fn serve(connection: BoxedConnection, requests: mpsc::Sender<Request>) {
+    // 改行のないまま送り続けられても読み込み続けないよう、1行の長さを制限する
+    let mut reader = BufReader::new(connection.take(MAX_COMMAND_LEN));
+    let mut line = String::new();
+    if reader.read_line(&mut line).await.is_err() {
+        return;
+    }
+
+    let command = if line.len() as u64 >= MAX_COMMAND_LEN && !line.ends_with('\n') {
+        Err(anyhow::anyhow!("コマンドが長すぎます"))
+    } else {
+        parse(line.trim())
+    };
+    let response = match command {
+        Ok(command) => {
+            let (reply, result) = oneshot::channel();
+            let _ = requests.send(Request { command, reply }).await;
+            match result.await {
+                Ok(Ok(())) => "OK".to_string(),
+                Ok(Err(e)) => format!("ERROR: {}", e),
+                Err(_) => "ERROR: 要求を処理できませんでした".to_string(),
+            }
+        }
+        Err(e) => format!("ERROR: {}", e),
+    };
+
+    let mut connection = reader.into_inner().into_inner();
+    let _ = connection
+        .write_all(format!("{}\n", response).as_bytes())
+        .await;
+}
+
+fn parse(line: &str) -> Result<Command> {
+    match line.split_once(' ') {
+        Some(("hotkey", rest)) if !rest.trim().is_empty() => parse_hotkey(rest.trim()),
+        Some(("accept", transfer_id)) => Ok(Command::Accept(parse_transfer_id(transfer_id)?)),
+        Some(("reject", rest)) => {
+            let rest = rest.trim();
+            let (transfer_id, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+            Ok(Command::Reject(
+                parse_transfer_id(transfer_id)?,
+                reason.trim().to_string(),
+            ))
+        }
+        None if line == "pause" => Ok(Command::Pause),
+        None if line == "resume" => Ok(Command::Resume),
+        _ => anyhow::bail!("不明なコマンド: {}", line),
+    }
+}
+
+// "hotkey" に続く "[操作] <組み合わせ>"
+fn parse_hotkey(rest: &str) -> Result<Command> {
+    let Some((action, hotkey)) = rest.split_once(' ') else {
+        return Ok(Command::Hotkey(None, rest.to_string()));
+    };
+    let action =
+        Action::from_str(action, true).map_err(|_| anyhow::anyhow!("不明な操作: {}", action))?;
+    Ok(Command::Hotkey(Some(action), hotkey.trim().to_string()))
+}
+
+fn parse_transfer_id(transfer_id: &str) -> Result<Uuid> {
+    Uuid::parse_str(transfer_id.trim())
+        .with_context(|| format!("転送IDが不正です: {}", transfer_id.trim()))
+}
+
+// 実行中のサーバー・クライアントにコマンドを送る
+pub async fn send(target: Target, command: &str) -> Result<()> {
+    let addr = address(target)?;
+    let mut connection = transport::connect(&addr).await.with_context(|| {
+        format!(
+            "制御ソケット {} に接続できません（{}モードが起動していない可能性があります）",
+            addr,
+            target.name()
+        )
+    })?;
+    connection
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+
+    let mut response = String::new();
+    BufReader::new(connection).read_line(&mut response).await?;
+    let response = response.trim();
+    match response.strip_prefix("ERROR: ") {
+        None if response == "OK" => Ok(()),
+        Some(e) => anyhow::bail!("{}", e),
+        None => anyhow::bail!("不明な応答: {}", response),
+    }
+}
+
+// action（省略するとモードの主なホットキー）のホットキーを変更し、次回の起動でも使われるよう設定ファイルに保存する
+pub fn rebind_hotkey(
+    hotkeys: &mut Hotkeys,
+    target: Target,
+    action: Option<Action>,
+    hotkey_str: &str,
+) -> Result<()> {
+    let action = action.unwrap_or(target.main_action());
+    hotkeys
+        .get_mut(action)
+        .with_context(|| format!("{} のホットキーを使用していません", action.key()))?
+        .rebind(hotkey_str)?;
+    Config::set_hotkey(action, hotkey_str)
+}
@@ -0,0 +1,284 @@
+use crate::{
+    client::{self, ClientArgs},
+    config::Config,
+    events::{TransferEvent, TransferEvents},
+    log::{error, info},
+    server::{self, FileReceiver, IncomingTransfer, ServerArgs},
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    pin::pin,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use zbus::{dbus_interface, fdo, ConnectionBuilder, SignalContext};
+
+// セッションバスに登録するサービス名とオブジェクトのパス
+pub const SERVICE_NAME: &str = "org.filetransfer.Daemon";
+const OBJECT_PATH: &str = "/org/filetransfer/Daemon";
+
+// 転送の向き（シグナルでは "send"・"receive" の文字列で送る）
+#[derive(Clone, Copy)]
+enum Direction {
+    Send,
+    Receive,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::Send => "send",
+            Direction::Receive => "receive",
+        }
+    }
+}
+
+// 転送の経過をシグナルを送るループに流す（進捗はシグナルにしないため流さない）
+struct DbusEvents {
+    direction: Direction,
+    tx: mpsc::UnboundedSender<(Direction, u64, TransferEvent)>,
+}
+
+impl TransferEvents for DbusEvents {
+    fn on_event(&self, id: u64, event: TransferEvent) {
+        if !matches!(event, TransferEvent::Progress { .. }) {
+            let _ = self.tx.send((self.direction, id, event));
+        }
+    }
+}
+
+// 受け入れの確認を待っている受信（転送IDごと）
+type Pending = Arc<Mutex<HashMap<String, IncomingTransfer>>>;
+
+// 送受信中の転送（経過の通知の番号ごとの転送ID）
+type Active = Arc<Mutex<HashMap<u64, String>>>;
+
+// D-Bus で公開するオブジェクト
+struct Daemon {
+    config: Config,
+    pending: Pending,
+    active: Active,
+    events: mpsc::UnboundedSender<(Direction, u64, TransferEvent)>,
+}
+
+#[dbus_interface(name = "org.filetransfer.Daemon")]
+impl Daemon {
+    // path（ファイルまたはフォルダ）を server に送信する（経過は TransferStarted・TransferFinished で知らせる）
+    fn send(&self, path: String, server: String) -> fdo::Result<()> {
+        let path = PathBuf::from(path);
+        if !path.exists() {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "ファイルが見つかりません: {:?}",
+                path
+            )));
+        }
+        let args = ClientArgs::try_parse_from(["client", "--server", server.as_str()])
+            .map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        let config = self.config.clone();
+        let events: Arc<dyn TransferEvents> = Arc::new(DbusEvents {
+            direction: Direction::Send,
+            tx: self.events.clone(),
+        });
+        tokio::spawn(async move {
+            let cancel = CancellationToken::new();
+            if let Err(e) = client::send_path(&args, &config, &path, Some(events), &cancel).await {
+                error!("D-Bus から求められた送信に失敗: {:?} ({:#})", path, e);
+            }
+        });
+        Ok(())
+    }
+
+    // 受信を一時停止しているか・確認待ちの受信の数・送受信中の転送の数
+    fn status(&self) -> (bool, u32, u32) {
+        (
+            server::is_paused(),
+            self.pending.lock().unwrap().len() as u32,
+            self.active.lock().unwrap().len() as u32,
+        )
+    }
+
+    // 確認待ちの受信を受け入れる
+    fn accept(&self, transfer_id: String) -> fdo::Result<()> {
+        self.take_pending(&transfer_id)?.accept();
+        Ok(())
+    }
+
+    // 確認待ちの受信を拒否する（reason は送信側に伝える理由。空でもよい）
+    fn reject(&self, transfer_id: String, reason: String) -> fdo::Result<()> {
+        self.take_pending(&transfer_id)?.reject(&reason);
+        Ok(())
+    }
+
+    // 受信の受け付けを一時停止する
+    fn pause(&self) {
+        server::set_paused(true);
+    }
+
+    // 一時停止した受信の受け付けを再開する
+    fn resume(&self) {
+        server::set_paused(false);
+    }
+
+    // 受け入れの確認を求める受信が届いた（Accept か Reject で答えるまで送信側は待つ）
+    #[dbus_interface(signal)]
+    async fn incoming_transfer(
+        ctxt: &SignalContext<'_>,
+        transfer_id: &str,
+        peer: &str,
+        filename: &str,
+        size: u64,
+        note: &str,
+    ) -> zbus::Result<()>;
+
+    // 送受信を開始した（direction は "send" か "receive"）
+    #[dbus_interface(signal)]
+    async fn transfer_started(
+        ctxt: &SignalContext<'_>,
+        transfer_id: &str,
+        direction: &str,
+        peer: &str,
+        filename: &str,
+        size: u64,
+    ) -> zbus::Result<()>;
+
+    // 送受信が終わった（result は "completed"・"failed"・"cancelled"・"rejected"）
+    #[dbus_interface(signal)]
+    async fn transfer_finished(
+        ctxt: &SignalContext<'_>,
+        transfer_id: &str,
+        direction: &str,
+        result: &str,
+        message: &str,
+    ) -> zbus::Result<()>;
+}
+
+impl Daemon {
+    fn take_pending(&self, transfer_id: &str) -> fdo::Result<IncomingTransfer> {
+        self.pending
+            .lock()
+            .unwrap()
+            .remove(transfer_id)
+            .ok_or_else(|| {
+                fdo::Error::InvalidArgs(format!("確認待ちの受信がありません: {}", transfer_id))
+            })
+    }
+}
+
+// D-Bus のサービスとして受信する（サーバーモードの --dbus）
+//
+// 受信のたびに IncomingTransfer シグナルで確認を求め、デスクトップのアプレットなどが
+// Accept・Reject で答える。終了するには Ctrl+C を押す
+pub async fn run(args: &ServerArgs, config: &Config) -> Result<()> {
+    let save_dir = args
+        .save_dir
+        .clone()
+        .or_else(|| config.save_dir.clone())
+        .context("--dbus では --save-dir か設定ファイルの save_dir で保存先を指定してください")?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let cancel = CancellationToken::new();
+    let events: Arc<dyn TransferEvents> = Arc::new(DbusEvents {
+        direction: Direction::Receive,
+        tx: tx.clone(),
+    });
+    let receiver =
+        FileReceiver::start(args, config, save_dir, Some(events), cancel.clone()).await?;
+
+    let pending = Pending::default();
+    let active = Active::default();
+    let daemon = Daemon {
+        config: config.clone(),
+        pending: pending.clone(),
+        active: active.clone(),
+        events: tx,
+    };
+    let connection = ConnectionBuilder::session()?
+        .name(SERVICE_NAME)?
+        .serve_at(OBJECT_PATH, daemon)?
+        .build()
+        .await
+        .context("D-Bus のセッションバスにサービスを登録できません")?;
+    let signals = SignalContext::new(&connection, OBJECT_PATH)?;
+    info!("D-Bus のサービス {} を開始しました", SERVICE_NAME);
+
+    let mut incoming = pin!(receiver.incoming());
+    loop {
+        tokio::select! {
+            transfer = incoming.next() => {
+                let Some(transfer) = transfer else {
+                    break;
+                };
+                // シグナルを受けてすぐに答えられるよう、先に確認待ちに加えておく
+                let transfer_id = transfer.transfer_id.to_string();
+                let (peer, filename, size, note) = (
+                    transfer.peer.clone(),
+                    transfer.filename.clone(),
+                    transfer.size,
+                    transfer.note.clone().unwrap_or_default(),
+                );
+                pending.lock().unwrap().insert(transfer_id.clone(), transfer);
+                let result =
+                    Daemon::incoming_transfer(&signals, &transfer_id, &peer, &filename, size, &note)
+                        .await;
+                if let Err(e) = result {
+                    error!("D-Bus のシグナルの送信に失敗: {}", e);
+                }
+            }
+            Some((direction, id, event)) = rx.recv() => {
+                if let Err(e) = emit(&signals, &active, direction, id, event).await {
+                    error!("D-Bus のシグナルの送信に失敗: {}", e);
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    // 確認待ちのものは破棄して拒否し、受信中のものは中断する
+    pending.lock().unwrap().clear();
+    cancel.cancel();
+    Ok(())
+}
+
+// 転送の経過をシグナルで知らせる
+async fn emit(
+    signals: &SignalContext<'_>,
+    active: &Active,
+    direction: Direction,
+    id: u64,
+    event: TransferEvent,
+) -> zbus::Result<()> {
+    let (result, message) = match event {
+        TransferEvent::Started {
+            peer,
+            filename,
+            total,
+            transfer_id,
+            ..
+        } => {
+            let transfer_id = transfer_id.to_string();
+            active.lock().unwrap().insert(id, transfer_id.clone());
+            return Daemon::transfer_started(
+                signals,
+                &transfer_id,
+                direction.name(),
+                &peer,
+                &filename,
+                total,
+            )
+            .await;
+        }
+        TransferEvent::Progress { .. } => return Ok(()),
+        TransferEvent::Completed => ("completed", String::new()),
+        TransferEvent::Failed(e) => ("failed", e),
+        TransferEvent::Cancelled => ("cancelled", String::new()),
+        TransferEvent::Rejected(reason) => ("rejected", reason),
+    };
+    // 開始する前に拒否されたものは転送IDが分からないため空にする
+    let transfer_id = active.lock().unwrap().remove(&id).unwrap_or_default();
+    Daemon::transfer_finished(signals, &transfer_id, direction.name(), result, &message).await
+}
@@ -0,0 +1,105 @@
+use crate::log::info;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+// 保存先フォルダに置く受信済みファイルのハッシュ一覧（sha256sum と同じ形式）
+pub const INDEX_FILE_NAME: &str = ".file-transfer-hashes";
+
+// 受信時の重複排除の動作
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DedupMode {
+    // 重複を確認しない
+    Off,
+    // 同じ内容のファイルがあれば書き込みを省略する
+    Skip,
+    // 同じ内容のファイルがあれば、新しい保存先にハードリンクを作る
+    Link,
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 同じハッシュのファイルが保存先フォルダに残っていればそのパスを返す
+pub fn find(save_dir: &Path, hash: &str) -> Result<Option<PathBuf>> {
+    let index_path = save_dir.join(INDEX_FILE_NAME);
+    if !index_path.exists() {
+        return Ok(None);
+    }
+
+    let index = fs::File::open(&index_path).context("ハッシュ一覧の読み込みに失敗")?;
+    for line in BufReader::new(index).lines() {
+        let line = line?;
+        let Some((line_hash, relative)) = line.split_once("  ") else {
+            continue;
+        };
+        if line_hash != hash {
+            continue;
+        }
+
+        // 一覧に残っていても、移動・削除・変更されたファイルは使わない
+        let path = save_dir.join(relative);
+        if path.is_file() && sha256_file(&path)? == hash {
+            return Ok(Some(path));
+        }
+    }
+
+    Ok(None)
+}
+
+// 保存したファイルのハッシュを一覧に追記する
+pub fn record(save_dir: &Path, hash: &str, path: &Path) -> Result<()> {
+    let relative = path.strip_prefix(save_dir).unwrap_or(path);
+    let mut index = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(save_dir.join(INDEX_FILE_NAME))
+        .context("ハッシュ一覧の書き込みに失敗")?;
+    writeln!(index, "{}  {}", hash, relative.to_string_lossy())?;
+    Ok(())
+}
+
+// 既存のファイルと同じ内容であれば、設定に応じてリンクを作成しそのパスを返す
+pub fn reuse_existing(
+    mode: DedupMode,
+    save_dir: &Path,
+    hash: &str,
+    save_path: &Path,
+) -> Result<Option<PathBuf>> {
+    let Some(existing) = find(save_dir, hash)? else {
+        return Ok(None);
+    };
+
+    if mode == DedupMode::Link && existing != save_path && !save_path.exists() {
+        fs::hard_link(&existing, save_path).context("ハードリンクの作成に失敗")?;
+        info!(
+            "既存のファイルへのハードリンクを作成しました: {:?}",
+            save_path
+        );
+    }
+
+    Ok(Some(existing))
+}
@@ -0,0 +1,119 @@
+use crate::log::warn;
+use anyhow::{Context, Result};
+use std::{fs, path::Path, process::Command};
+
+// 受信したファイルを保存した後に行う操作
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OnReceive {
+    // 既定のアプリで開く
+    Open,
+    // ファイルマネージャーで選択した状態で表示する
+    Reveal,
+}
+
+// 保存したファイル path に action を行う
+//
+// 外部のコマンドの終了は待たずに戻り、失敗した場合は警告を出す
+pub fn run(action: OnReceive, path: &Path) {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    std::thread::spawn(move || {
+        let result = match action {
+            OnReceive::Open => open(&path),
+            OnReceive::Reveal => reveal(&path),
+        };
+        if let Err(e) = result {
+            warn!("{:#}", e);
+        }
+    });
+}
+
+// 既定のアプリで開く
+fn open(path: &Path) -> Result<()> {
+    let result = if cfg!(windows) {
+        // explorer は成功しても 1 を返すことがあるため、起動できれば成功とみなす
+        Command::new("explorer")
+            .arg(path)
+            .status()
+            .map(drop)
+            .map_err(Into::into)
+    } else {
+        let program = if cfg!(target_os = "macos") {
+            "open"
+        } else {
+            "xdg-open"
+        };
+        let mut command = Command::new(program);
+        command.arg(path);
+        run_command(command)
+    };
+    result.with_context(|| format!("{:?} を開けません", path))
+}
+
+// ファイルマネージャーで選択した状態で表示する
+fn reveal(path: &Path) -> Result<()> {
+    reveal_in_file_manager(path)
+        .with_context(|| format!("{:?} をファイルマネージャーで表示できません", path))
+}
+
+#[cfg(windows)]
+fn reveal_in_file_manager(path: &Path) -> Result<()> {
+    use std::os::windows::process::CommandExt;
+
+    // explorer は "/select," とパスを1つの引数として受け取るため、引用符は自分で付ける
+    let mut command = Command::new("explorer");
+    command.raw_arg(format!("/select,\"{}\"", path.display()));
+    // open と同じく、起動できれば成功とみなす
+    command.status()?;
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn reveal_in_file_manager(path: &Path) -> Result<()> {
+    let mut command = Command::new("open");
+    command.arg("-R").arg(path);
+    run_command(command)
+}
+
+// freedesktop のファイルマネージャーの D-Bus インターフェースで選択させる
+// （対応していない環境では、ファイルのあるフォルダを開く）
+#[cfg(not(any(windows, target_os = "macos")))]
+fn reveal_in_file_manager(path: &Path) -> Result<()> {
+    let mut command = Command::new("dbus-send");
+    command.args([
+        "--session",
+        "--dest=org.freedesktop.FileManager1",
+        "--type=method_call",
+        "/org/freedesktop/FileManager1",
+        "org.freedesktop.FileManager1.ShowItems",
+    ]);
+    command.arg(format!("array:string:{}", file_uri(path)));
+    command.arg("string:");
+    if run_command(command).is_ok() {
+        return Ok(());
+    }
+    open(path.parent().unwrap_or(Path::new("/")))
+}
+
+// ファイルの file:// URI（英数字と一部の記号以外はパーセントエンコードする）
+#[cfg(not(any(windows, target_os = "macos")))]
+fn file_uri(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+fn run_command(mut command: Command) -> Result<()> {
+    let status = command.status()?;
+    if !status.success() {
+        anyhow::bail!("{:?} が失敗しました（{}）", command.get_program(), status);
+    }
+    Ok(())
+}
@@ -0,0 +1,372 @@
+use crate::{
+    bandwidth::Schedule,
+    config::Config,
+    hotkey::{self, Action},
+    picker, template,
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use local_ip_address::list_afinet_netifas;
+use std::{
+    fmt::Display,
+    fs,
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+// LANのIPアドレスへの接続を待つ時間
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+// mDNS のグループとポート
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+// mDNS の応答を待つ時間
+const MDNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+// mDNS で問い合わせる名前（サービスの一覧。mDNS の応答側は必ず答える）
+const MDNS_QUERY_NAME: &str = "_services._dns-sd._udp.local";
+
+// 問い合わせの識別子（応答がこの問い合わせへのものか確かめる）
+const MDNS_QUERY_ID: u16 = 0x4654;
+
+// 診断の結果を表示し、問題と警告の件数を数える
+#[derive(Default)]
+struct Report {
+    problems: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&self, item: &str, detail: impl Display) {
+        println!("[OK]   {}: {}", item, detail);
+    }
+
+    // 使えるが、機能が制限される
+    fn warn(&mut self, item: &str, detail: impl Display, fix: impl Display) {
+        self.warnings += 1;
+        println!("[警告] {}: {}", item, detail);
+        println!("       対処: {}", fix);
+    }
+
+    // このままでは送受信やその機能が使えない
+    fn fail(&mut self, item: &str, detail: impl Display, fix: impl Display) {
+        self.problems += 1;
+        println!("[NG]   {}: {}", item, detail);
+        println!("       対処: {}", fix);
+    }
+}
+
+// 動作環境を診断し、見つかった問題と対処を表示する（問題があればエラー）
+//
+// port はサーバーモードで待ち受けるポート。設定ファイルの誤りも診断するため、
+// 設定ファイルはここで読み込む
+pub async fn run(port: u16) -> Result<()> {
+    println!("ファイル転送の動作環境を診断します");
+    println!();
+
+    let mut report = Report::default();
+    let config = check_config(&mut report);
+    let listener = check_port(&mut report, port).await;
+    check_reachability(&mut report, port, listener).await;
+    check_mdns(&mut report).await;
+    check_display(&mut report);
+    check_hotkeys(&mut report, &config);
+
+    println!();
+    if report.problems > 0 {
+        anyhow::bail!(
+            "{} 件の問題が見つかりました（警告 {} 件）",
+            report.problems,
+            report.warnings
+        );
+    }
+    println!("問題は見つかりませんでした（警告 {} 件）", report.warnings);
+    Ok(())
+}
+
+// 設定ファイルを読み込み、読み込めても使うときに失敗する値を確かめる
+fn check_config(report: &mut Report) -> Config {
+    let Some(path) = Config::path() else {
+        report.warn(
+            "設定ファイル",
+            "設定フォルダが見つかりません",
+            "環境変数 HOME（Windows では APPDATA）が設定されているか確認してください",
+        );
+        return Config::default();
+    };
+    if !path.exists() {
+        report.ok(
+            "設定ファイル",
+            format!("{:?} はありません（既定値を使用）", path),
+        );
+        return Config::default();
+    }
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail(
+                "設定ファイル",
+                format!("{:#}", e),
+                format!(
+                    "{:?} の記述を修正するか、ファイルを削除して既定値に戻してください",
+                    path
+                ),
+            );
+            return Config::default();
+        }
+    };
+    report.ok("設定ファイル", format!("{:?} を読み込みました", path));
+
+    if let Some(save_template) = &config.save_template {
+        if let Err(e) = template::validate(save_template) {
+            report.fail(
+                "保存テンプレート",
+                e,
+                "設定ファイルの save_template に {filename} を含めてください",
+            );
+        }
+    }
+    if let Err(e) = Schedule::new(&config.bandwidth, None) {
+        report.fail(
+            "速度の上限",
+            format!("{:#}", e),
+            "設定ファイルの [[bandwidth]] の時刻を \"09:00\" の形式で指定してください",
+        );
+    }
+    if let Some(save_dir) = &config.save_dir {
+        match fs::metadata(save_dir) {
+            Ok(metadata) if !metadata.is_dir() => report.fail(
+                "保存先フォルダ",
+                format!("{:?} はフォルダではありません", save_dir),
+                "設定ファイルの save_dir にフォルダを指定してください",
+            ),
+            Ok(metadata) if metadata.permissions().readonly() => report.fail(
+                "保存先フォルダ",
+                format!("{:?} に書き込めません", save_dir),
+                "フォルダの書き込み権限を確認するか、save_dir に別のフォルダを指定してください",
+            ),
+            Ok(_) => report.ok("保存先フォルダ", format!("{:?}", save_dir)),
+            Err(_) => report.warn(
+                "保存先フォルダ",
+                format!("{:?} はありません", save_dir),
+                "受信を始めると作成されます。意図しないパスなら save_dir を修正してください",
+            ),
+        }
+    }
+
+    let hotkeys = [
+        &config.server_hotkey,
+        &config.server_pause_hotkey,
+        &config.client_hotkey,
+    ]
+    .into_iter()
+    .flatten()
+    .chain(config.hotkeys.values())
+    .chain(&config.hotkey_fallbacks);
+    for name in hotkeys {
+        if let Err(e) = hotkey::parse_hotkey(name) {
+            report.fail(
+                "ホットキーの設定",
+                format!("{}: {}", name, e),
+                "\"ctrl+shift+s\" のように修飾キーとキーを + でつないで指定してください",
+            );
+        }
+    }
+    config
+}
+
+// 待ち受けるポートが空いているか確かめる（空いていれば待ち受けたまま返す）
+async fn check_port(report: &mut Report, port: u16) -> Option<TcpListener> {
+    match TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await {
+        Ok(listener) => {
+            report.ok("ポート", format!("TCP {} で待ち受けできます", port));
+            Some(listener)
+        }
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            report.warn(
+                "ポート",
+                format!("TCP {} は使用中です", port),
+                "サーバーモードを起動済みなら問題ありません。他のアプリが使っている場合は終了するか、server --bind 0.0.0.0:<ポート> で別のポートを使ってください（doctor --port で確認できます）",
+            );
+            None
+        }
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            report.fail(
+                "ポート",
+                format!("TCP {} で待ち受ける権限がありません", port),
+                "1024 未満のポートには管理者権限が必要です。server --bind で 1024 以上のポートを指定してください",
+            );
+            None
+        }
+        Err(e) => {
+            report.fail(
+                "ポート",
+                format!("TCP {} で待ち受けできません: {}", port, e),
+                "server --bind で別のアドレスかポートを指定してください",
+            );
+            None
+        }
+    }
+}
+
+// LANのIPアドレスから自分自身に接続できるか確かめる（ファイアウォールで遮断されていないか）
+async fn check_reachability(report: &mut Report, port: u16, listener: Option<TcpListener>) {
+    // check_port で待ち受けたポートへの接続は受け付けてすぐに閉じる
+    let accepting = listener
+        .map(|listener| tokio::spawn(async move { while listener.accept().await.is_ok() {} }));
+
+    let interfaces = list_afinet_netifas().unwrap_or_default();
+    let addrs: Vec<_> = interfaces
+        .iter()
+        .filter(|(_, ip)| ip.is_ipv4() && !ip.is_loopback())
+        .collect();
+    if addrs.is_empty() {
+        report.warn(
+            "LANからの接続",
+            "LANのIPアドレスが見つかりません",
+            "ネットワークに接続しているか確認してください",
+        );
+    }
+    for (name, ip) in addrs {
+        let addr = SocketAddr::new(*ip, port);
+        let item = format!("LANからの接続（{}）", name);
+        match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => report.ok(&item, format!("{} に接続できました", addr)),
+            Ok(Err(e)) => report.fail(
+                &item,
+                format!("{} に接続できません: {}", addr, e),
+                firewall_fix(port),
+            ),
+            Err(_) => report.fail(
+                &item,
+                format!("{} への接続がタイムアウトしました", addr),
+                firewall_fix(port),
+            ),
+        }
+    }
+
+    if let Some(accepting) = accepting {
+        accepting.abort();
+    }
+}
+
+// ファイアウォールで受信を許可する方法
+fn firewall_fix(port: u16) -> String {
+    if cfg!(windows) {
+        format!(
+            "ファイアウォールで TCP {} の受信を許可してください（管理者の PowerShell で New-NetFirewallRule -DisplayName file-transfer -Direction Inbound -Protocol TCP -LocalPort {} -Action Allow）",
+            port, port
+        )
+    } else if cfg!(target_os = "macos") {
+        "システム設定の「ネットワーク > ファイアウォール」で file-transfer への受信接続を許可してください".to_string()
+    } else {
+        format!(
+            "ファイアウォールで TCP {} の受信を許可してください（例: sudo ufw allow {}/tcp、firewalld なら sudo firewall-cmd --add-port={}/tcp --permanent）",
+            port, port, port
+        )
+    }
+}
+
+// mDNS の問い合わせを送り、応答が届くか確かめる
+//
+// 5353 以外のポートから送った問い合わせには、応答側がそのポートに直接答える（RFC 6762 6.7）。
+// 多くのOSは自分自身の mDNS の応答側を動かしているため、LANに他の機器がなくても確かめられる
+async fn check_mdns(report: &mut Report) {
+    let fix = "ファイアウォールで UDP 5353 を許可し、Linux では avahi-daemon を起動してください（ルーターやWi-Fiがマルチキャストを遮断している場合はマルチキャスト配信も使えません）";
+    match probe_mdns().await {
+        Ok(true) => report.ok("mDNS", "問い合わせへの応答を受け取りました"),
+        Ok(false) => report.warn("mDNS", "問い合わせへの応答がありません", fix),
+        Err(e) => report.fail("mDNS", format!("問い合わせを送れません: {}", e), fix),
+    }
+}
+
+async fn probe_mdns() -> Result<bool> {
+    let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).await?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket
+        .send_to(&mdns_query(), SocketAddr::from((MDNS_GROUP, MDNS_PORT)))
+        .await?;
+
+    let mut buf = [0u8; 1500];
+    let received = tokio::time::timeout(MDNS_TIMEOUT, async {
+        loop {
+            let (n, _) = socket.recv_from(&mut buf).await?;
+            // 識別子が一致し、応答（QR ビットが 1）であるもの
+            if n >= 12 && buf[..2] == MDNS_QUERY_ID.to_be_bytes() && buf[2] & 0x80 != 0 {
+                return anyhow::Ok(());
+            }
+        }
+    })
+    .await;
+    match received {
+        Ok(result) => result.map(|()| true),
+        Err(_) => Ok(false),
+    }
+}
+
+// MDNS_QUERY_NAME の PTR レコードを問い合わせる DNS メッセージ
+fn mdns_query() -> Vec<u8> {
+    let mut query = Vec::new();
+    query.extend_from_slice(&MDNS_QUERY_ID.to_be_bytes());
+    // フラグ 0、質問 1 件、回答・権威・追加 0 件
+    query.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in MDNS_QUERY_NAME.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    // PTR レコード、IN クラス
+    query.extend_from_slice(&12u16.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    query
+}
+
+// 保存先やファイルを選ぶダイアログを表示できるか確かめる
+fn check_display(report: &mut Report) {
+    if picker::has_display() {
+        report.ok("画面", "保存先やファイルの選択にダイアログを使います");
+    } else {
+        report.warn(
+            "画面",
+            "ダイアログを表示できないため、選択はターミナルで行います",
+            "server --save-dir で保存先を、client に送信するファイルのパスを指定すると選択を省けます（SSH接続では X11 転送、Linux では DISPLAY か WAYLAND_DISPLAY が必要です）",
+        );
+    }
+}
+
+// サーバーモード・クライアントモードで使うホットキーを登録できるか確かめる
+fn check_hotkeys(report: &mut Report, config: &Config) {
+    if !picker::has_display() {
+        report.warn(
+            "ホットキー",
+            "画面がないため使用できません",
+            "server・client に --no-hotkey を指定すると、この環境でもホットキーなしで使えます",
+        );
+        return;
+    }
+    for action in Action::value_variants() {
+        let Some(name) = action.configured(config) else {
+            continue;
+        };
+        // 指定の誤りは設定ファイルの確認で表示済み
+        if hotkey::parse_hotkey(&name).is_err() {
+            continue;
+        }
+        let item = format!("ホットキー（{}）", action.key());
+        match hotkey::check(&name) {
+            Ok(()) => report.ok(&item, format!("{} を登録できます", name)),
+            Err(e) => report.fail(
+                &item,
+                format!("{:#}", e),
+                format!(
+                    "起動中の file-transfer が使っている場合は問題ありません。他のアプリと重なっている場合は file-transfer hotkey {} <組み合わせ> --action {} で変更するか、設定ファイルの hotkey_fallbacks に代わりの組み合わせを追加してください",
+                    action.target().name(),
+                    action.key()
+                ),
+            ),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::walk::{self, WalkOptions};
+use age::secrecy::Secret;
+use anyhow::{Context, Result};
+use rand::Rng;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// 暗号化したファイルの拡張子（age 形式。フォルダは tar にまとめてから暗号化する）
+const AGE_EXTENSION: &str = ".age";
+const TAR_EXTENSION: &str = ".tar";
+
+// 生成するパスワードの文字（見間違えやすい i・l・o・0・1 を除く）と、長さ・区切る間隔
+const PASSWORD_CHARS: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const PASSWORD_LEN: usize = 24;
+const PASSWORD_GROUP: usize = 4;
+
+// 転送ごとのパスワードを作る（"abcd-efgh-..." のように4文字ずつ区切る）
+pub fn generate_password() -> String {
+    let mut rng = rand::thread_rng();
+    let chars: Vec<char> = (0..PASSWORD_LEN)
+        .map(|_| PASSWORD_CHARS[rng.gen_range(0..PASSWORD_CHARS.len())] as char)
+        .collect();
+    chars
+        .chunks(PASSWORD_GROUP)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// path（ファイルまたはフォルダ）を password で暗号化して out_dir に書き出し、そのパスを返す
+//
+// ファイルは "名前.age"、フォルダは options で絞り込んだ中身を tar にまとめて "名前.tar.age" にする。
+// `age -d` またはこのツールの decrypt サブコマンドで復号できる
+pub fn encrypt(
+    path: &Path,
+    options: &WalkOptions,
+    password: &str,
+    out_dir: &Path,
+) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .context("ファイル名の取得に失敗")?
+        .to_string_lossy()
+        .into_owned();
+    let archive_name = if path.is_dir() {
+        format!("{}{}{}", name, TAR_EXTENSION, AGE_EXTENSION)
+    } else {
+        format!("{}{}", name, AGE_EXTENSION)
+    };
+    let archive_path = out_dir.join(archive_name);
+    let output = File::create(&archive_path)
+        .with_context(|| format!("暗号化したファイルの作成に失敗: {:?}", archive_path))?;
+
+    let encryptor = age::Encryptor::with_user_passphrase(Secret::new(password.to_string()));
+    let mut writer = encryptor
+        .wrap_output(BufWriter::new(output))
+        .context("暗号化の開始に失敗")?;
+    if path.is_dir() {
+        let mut builder = tar::Builder::new(&mut writer);
+        for entry in walk::collect_files(path, options)? {
+            let entry_name = format!("{}/{}", name, entry.relative);
+            match &entry.link_target {
+                Some(target) => {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    builder.append_link(&mut header, &entry_name, target)?;
+                }
+                None => {
+                    let mut file = File::open(&entry.path)
+                        .with_context(|| format!("ファイルの読み込みに失敗: {:?}", entry.path))?;
+                    builder.append_file(&entry_name, &mut file)?;
+                }
+            }
+        }
+        builder.finish().context("tar の書き込みに失敗")?;
+    } else {
+        let mut file =
+            File::open(path).with_context(|| format!("ファイルの読み込みに失敗: {:?}", path))?;
+        io::copy(&mut file, &mut writer).context("暗号化に失敗")?;
+    }
+    writer
+        .finish()
+        .and_then(|mut output| output.flush())
+        .context("暗号化したファイルの書き込みに失敗")?;
+    Ok(archive_path)
+}
+
+// decrypt サブコマンドの実装（パスワードは端末で入力する）
+pub fn run_decrypt(path: &Path, output: Option<&Path>) -> Result<PathBuf> {
+    let password = rpassword::prompt_password("パスワード: ")?;
+    decrypt(path, output, password.trim())
+}
+
+// 暗号化されたファイルを復号し、復号したファイル（".tar.age" なら展開したフォルダの親）のパスを返す
+//
+// output を省略すると暗号化されたファイルと同じフォルダに書き出す。既にあるファイルは上書きしない
+pub fn decrypt(path: &Path, output: Option<&Path>, password: &str) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .context("ファイル名の取得に失敗")?
+        .to_string_lossy()
+        .into_owned();
+    let Some(plain_name) = name.strip_suffix(AGE_EXTENSION) else {
+        anyhow::bail!(
+            "{} で終わるファイルを指定してください: {:?}",
+            AGE_EXTENSION,
+            path
+        );
+    };
+    let out_dir = match output {
+        Some(dir) => dir.to_path_buf(),
+        None => path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+    };
+    fs::create_dir_all(&out_dir).with_context(|| format!("フォルダの作成に失敗: {:?}", out_dir))?;
+
+    let input =
+        File::open(path).with_context(|| format!("ファイルの読み込みに失敗: {:?}", path))?;
+    let decryptor = match age::Decryptor::new(BufReader::new(input))
+        .context("暗号化されたファイルの形式が正しくありません")?
+    {
+        age::Decryptor::Passphrase(decryptor) => decryptor,
+        _ => anyhow::bail!("パスワードで暗号化されたファイルではありません: {:?}", path),
+    };
+    let mut reader = decryptor
+        .decrypt(&Secret::new(password.to_string()), None)
+        .context("復号に失敗しました（パスワードが違う可能性があります）")?;
+
+    if plain_name.ends_with(TAR_EXTENSION) {
+        // 展開先に同じ名前のファイルがあれば、上書きせずにエラーにする
+        let mut archive = tar::Archive::new(reader);
+        archive.set_overwrite(false);
+        archive.unpack(&out_dir).context("tar の展開に失敗")?;
+        return Ok(out_dir);
+    }
+    let plain_path = out_dir.join(plain_name);
+    if plain_path.exists() {
+        anyhow::bail!("既にファイルがあります: {:?}", plain_path);
+    }
+    let mut file = File::create(&plain_path)
+        .with_context(|| format!("ファイルの作成に失敗: {:?}", plain_path))?;
+    // 途中で改ざんが見つかった場合などは、書きかけのファイルを残さない
+    if let Err(e) = io::copy(&mut reader, &mut file) {
+        drop(file);
+        let _ = fs::remove_file(&plain_path);
+        return Err(e).context("復号に失敗");
+    }
+    Ok(plain_path)
+}
@@ -0,0 +1,186 @@
+use crate::{layer::Layer, transport::BoxedConnection};
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::mpsc,
+};
+use uuid::Uuid;
+
+// Progress を通知する最短の間隔
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// 転送の経過
+#[derive(Clone, Debug)]
+pub enum TransferEvent {
+    // 転送を開始した（total は転送するバイト数。note は送信側が添えたメモ）
+    //
+    // transfer_id は送信側と受信側で共通の転送ID（ログの各行にも付く）。以降の経過は id で対応付ける
+    Started {
+        peer: String,
+        filename: String,
+        total: u64,
+        note: Option<String>,
+        transfer_id: Uuid,
+    },
+    // 転送したバイト数
+    Progress {
+        bytes: u64,
+        total: u64,
+    },
+    Completed,
+    Failed(String),
+    // CancellationToken で中断した
+    Cancelled,
+    // 受信側が受け付けなかった（認証・容量制限・受け入れの確認での拒否など）
+    Rejected(String),
+}
+
+// 転送の経過を受け取る側
+//
+// GUIなどの組み込み先が標準出力を解析せずに進捗を表示できるよう、
+// 転送ごとの番号（new_id）とともに経過を通知する
+pub trait TransferEvents: Send + Sync {
+    fn on_event(&self, id: u64, event: TransferEvent);
+}
+
+// チャネルで受け取る場合
+impl TransferEvents for mpsc::UnboundedSender<(u64, TransferEvent)> {
+    fn on_event(&self, id: u64, event: TransferEvent) {
+        let _ = self.send((id, event));
+    }
+}
+
+// 転送ごとの番号を払い出す
+pub fn new_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// 読み書きしたバイト数を数えて Progress を通知するレイヤー
+//
+// 同じ Observe で包んだ接続（フォルダ送信の各ファイルなど）の合計を1つの転送として数える。
+// durable で作った場合は読み書きを数えず、acknowledge で受け取った受信側の書き込み済みバイト数を数える
+#[derive(Clone)]
+pub struct Observe {
+    state: Arc<ObserveState>,
+}
+
+struct ObserveState {
+    events: Arc<dyn TransferEvents>,
+    id: u64,
+    total: u64,
+    bytes: AtomicU64,
+    last_notified: Mutex<Option<Instant>>,
+    durable: bool,
+}
+
+impl Observe {
+    pub fn new(events: Arc<dyn TransferEvents>, id: u64, total: u64) -> Self {
+        Self::with_mode(events, id, total, false)
+    }
+
+    pub fn durable(events: Arc<dyn TransferEvents>, id: u64, total: u64) -> Self {
+        Self::with_mode(events, id, total, true)
+    }
+
+    fn with_mode(events: Arc<dyn TransferEvents>, id: u64, total: u64, durable: bool) -> Self {
+        Self {
+            state: Arc::new(ObserveState {
+                events,
+                id,
+                total,
+                bytes: AtomicU64::new(0),
+                last_notified: Mutex::new(None),
+                durable,
+            }),
+        }
+    }
+
+    // 受信側がディスクに書き込んだと通知してきたバイト数（前回の通知からの増分）を加える
+    pub fn acknowledge(&self, bytes: u64) {
+        self.state.add(bytes);
+    }
+}
+
+impl ObserveState {
+    fn add(&self, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        // ヘッダーや応答の分を含むため total を超えないようにする
+        let bytes = (self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes).min(self.total);
+
+        let mut last_notified = self.last_notified.lock().unwrap();
+        let due = last_notified.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL);
+        if due || bytes == self.total {
+            *last_notified = Some(Instant::now());
+            self.events.on_event(
+                self.id,
+                TransferEvent::Progress {
+                    bytes,
+                    total: self.total,
+                },
+            );
+        }
+    }
+}
+
+impl Layer for Observe {
+    fn wrap(&self, connection: BoxedConnection) -> BoxedConnection {
+        Box::new(Observed {
+            inner: connection,
+            state: self.state.clone(),
+        })
+    }
+}
+
+struct Observed {
+    inner: BoxedConnection,
+    state: Arc<ObserveState>,
+}
+
+impl AsyncRead for Observed {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if !self.state.durable {
+            self.state.add((buf.filled().len() - before) as u64);
+        }
+        result
+    }
+}
+
+impl AsyncWrite for Observed {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let (Poll::Ready(Ok(written)), false) = (&result, self.state.durable) {
+            self.state.add(*written as u64);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
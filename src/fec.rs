@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::collections::{BTreeMap, HashMap};
+
+// 1ブロックのデータパケット数とパリティパケット数の上限
+const MAX_DATA_SHARDS: u8 = 64;
+const MAX_PARITY_SHARDS: u8 = 32;
+
+// シャードの先頭（データの長さ 2バイトと、FIN か 1バイト）
+const SHARD_HEADER_LEN: usize = 3;
+
+// 誤り訂正（Reed-Solomon）の設定
+//
+// data 個のデータパケットごとに parity 個のパリティパケットを送り、
+// ブロックの中で parity 個までの損失は再送を待たずに受信側で復元する
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Fec {
+    pub data: u8,
+    pub parity: u8,
+}
+
+impl Fec {
+    // "10:2"（データパケット数:パリティパケット数）の形式を読む
+    pub fn parse(s: &str) -> Result<Self> {
+        let (data, parity) = s.split_once(':').with_context(|| {
+            format!("誤り訂正の設定は \"10:2\" の形式で指定してください: {}", s)
+        })?;
+        let fec = Self {
+            data: data
+                .trim()
+                .parse()
+                .with_context(|| format!("データパケット数が正しくありません: {}", data))?,
+            parity: parity
+                .trim()
+                .parse()
+                .with_context(|| format!("パリティパケット数が正しくありません: {}", parity))?,
+        };
+        if !fec.is_valid() {
+            anyhow::bail!(
+                "データパケット数は 1〜{}、パリティパケット数は 1〜{} で指定してください: {}",
+                MAX_DATA_SHARDS,
+                MAX_PARITY_SHARDS,
+                s
+            );
+        }
+        Ok(fec)
+    }
+
+    pub fn is_valid(self) -> bool {
+        (1..=MAX_DATA_SHARDS).contains(&self.data) && (1..=MAX_PARITY_SHARDS).contains(&self.parity)
+    }
+
+    fn codec(self) -> Option<ReedSolomon> {
+        ReedSolomon::new(self.data as usize, self.parity as usize).ok()
+    }
+}
+
+// データパケットの中身を、ブロックの中で長さをそろえられる形（長さ・FIN か・データ）にする
+fn shard_of(data: &[u8], fin: bool) -> Vec<u8> {
+    let mut shard = Vec::with_capacity(SHARD_HEADER_LEN + data.len());
+    shard.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    shard.push(fin as u8);
+    shard.extend_from_slice(data);
+    shard
+}
+
+// 復元したシャードからデータパケットの中身を取り出す
+fn parse_shard(shard: &[u8]) -> Option<(Bytes, bool)> {
+    if shard.len() < SHARD_HEADER_LEN {
+        return None;
+    }
+    let len = u16::from_be_bytes([shard[0], shard[1]]) as usize;
+    let data = shard.get(SHARD_HEADER_LEN..SHARD_HEADER_LEN + len)?;
+    Some((Bytes::copy_from_slice(data), shard[2] != 0))
+}
+
+// 送信側：送ったデータパケットを data 個ずつまとめ、パリティパケットを作る
+//
+// データパケットの番号は 0 から連続しているため、番号 / data がブロック番号になる
+pub struct Encoder {
+    fec: Fec,
+    codec: ReedSolomon,
+    shards: Vec<Vec<u8>>,
+}
+
+impl Encoder {
+    pub fn new(fec: Fec) -> Option<Self> {
+        Some(Self {
+            fec,
+            codec: fec.codec()?,
+            shards: Vec::new(),
+        })
+    }
+
+    // seq のデータパケットを加え、ブロックがそろったら（ブロック番号, パリティの一覧）を返す
+    pub fn push(&mut self, seq: u64, data: &[u8], fin: bool) -> Option<(u64, Vec<Vec<u8>>)> {
+        self.shards.push(shard_of(data, fin));
+        let data_shards = self.fec.data as usize;
+        if self.shards.len() < data_shards {
+            return None;
+        }
+        let mut shards = std::mem::take(&mut self.shards);
+        let len = shards.iter().map(Vec::len).max().unwrap_or(0);
+        for shard in &mut shards {
+            shard.resize(len, 0);
+        }
+        shards.extend((0..self.fec.parity).map(|_| vec![0; len]));
+        self.codec.encode(&mut shards).ok()?;
+        Some((seq / data_shards as u64, shards.split_off(data_shards)))
+    }
+}
+
+// 受信側で復元を待っているブロック（ブロックの中の位置ごとのシャード）
+#[derive(Default)]
+struct Block {
+    data: HashMap<u8, Vec<u8>>,
+    parity: HashMap<u8, Vec<u8>>,
+}
+
+// 受信側：届いたデータパケットとパリティパケットを溜め、欠けたデータパケットを復元する
+pub struct Decoder {
+    fec: Fec,
+    codec: ReedSolomon,
+    blocks: BTreeMap<u64, Block>,
+    // 次に受け取るデータパケットの番号と、受信側が溜めておけるパケット数（この範囲外のパリティは捨てる）
+    rcv_next: u64,
+    window: u64,
+}
+
+impl Decoder {
+    pub fn new(fec: Fec, window: u64) -> Option<Self> {
+        Some(Self {
+            fec,
+            codec: fec.codec()?,
+            blocks: BTreeMap::new(),
+            rcv_next: 0,
+            window,
+        })
+    }
+
+    // 届いたデータパケットを記録し、復元できたデータパケットの（番号, データ, FIN か）を返す
+    pub fn data(&mut self, seq: u64, data: &[u8], fin: bool) -> Vec<(u64, Bytes, bool)> {
+        let block = seq / self.fec.data as u64;
+        let index = (seq % self.fec.data as u64) as u8;
+        self.blocks
+            .entry(block)
+            .or_default()
+            .data
+            .insert(index, shard_of(data, fin));
+        self.recover(block)
+    }
+
+    // 届いたパリティパケットを記録し、復元できたデータパケットを返す
+    //
+    // 受け取り済みのブロックや受信ウィンドウより先のブロックのものは、溜め込まないよう捨てる
+    pub fn parity(&mut self, block: u64, index: u8, shard: Vec<u8>) -> Vec<(u64, Bytes, bool)> {
+        let data_shards = self.fec.data as u64;
+        let first = block.saturating_mul(data_shards);
+        if index >= self.fec.parity
+            || first.saturating_add(data_shards) <= self.rcv_next
+            || first >= self.rcv_next.saturating_add(self.window)
+        {
+            return Vec::new();
+        }
+        self.blocks
+            .entry(block)
+            .or_default()
+            .parity
+            .insert(index, shard);
+        self.recover(block)
+    }
+
+    // rcv_next より前のデータパケットだけのブロック（もう復元しなくてよい）を捨てる
+    pub fn forget_before(&mut self, rcv_next: u64) {
+        self.rcv_next = rcv_next;
+        let data_shards = self.fec.data as u64;
+        while let Some(entry) = self.blocks.first_entry() {
+            if (*entry.key() + 1) * data_shards > rcv_next {
+                break;
+            }
+            entry.remove();
+        }
+    }
+
+    // 欠けたデータパケットの数がパリティパケットの数以下になったら復元する
+    fn recover(&mut self, block: u64) -> Vec<(u64, Bytes, bool)> {
+        let data_shards = self.fec.data as usize;
+        let Some(entry) = self.blocks.get(&block) else {
+            return Vec::new();
+        };
+        if entry.data.len() == data_shards {
+            self.blocks.remove(&block);
+            return Vec::new();
+        }
+        if entry.parity.is_empty() || entry.data.len() + entry.parity.len() < data_shards {
+            return Vec::new();
+        }
+        let Some(entry) = self.blocks.remove(&block) else {
+            return Vec::new();
+        };
+
+        // パリティパケットの長さにそろえる（それより長いシャードがあれば壊れているため復元しない）
+        let len = entry.parity.values().next().map(Vec::len).unwrap_or(0);
+        let mut shards: Vec<Option<Vec<u8>>> = vec![None; data_shards + self.fec.parity as usize];
+        for (index, mut shard) in entry.data {
+            if shard.len() > len {
+                return Vec::new();
+            }
+            shard.resize(len, 0);
+            shards[index as usize] = Some(shard);
+        }
+        for (index, shard) in entry.parity {
+            if shard.len() != len {
+                return Vec::new();
+            }
+            shards[data_shards + index as usize] = Some(shard);
+        }
+        let missing: Vec<usize> = (0..data_shards).filter(|&i| shards[i].is_none()).collect();
+        if self.codec.reconstruct_data(&mut shards).is_err() {
+            return Vec::new();
+        }
+        let first = block * data_shards as u64;
+        missing
+            .into_iter()
+            .filter_map(|index| {
+                let (data, fin) = parse_shard(shards[index].as_deref()?)?;
+                Some((first + index as u64, data, fin))
+            })
+            .collect()
+    }
+}
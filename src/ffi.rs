@@ -0,0 +1,356 @@
+// C から使う API（cdylib フィーチャー）
+//
+// 送受信は ft_init で作ったコンテキストの中で非同期に動き、経過は ft_poll_event で取り出す。
+// ヘッダーはビルド時に include/file_transfer.h へ生成される（/// のコメントはヘッダーに残る）
+use crate::{
+    client::{self, ClientArgs},
+    config::Config,
+    events::{self, TransferEvent, TransferEvents},
+    server::{FileReceiver, ServerArgs},
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{c_char, CStr, CString},
+    path::PathBuf,
+    pin::pin,
+    ptr,
+    sync::{Arc, Mutex},
+};
+use tokio::{runtime::Runtime, sync::mpsc};
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+
+thread_local! {
+    // 直前に失敗した呼び出しのエラー（ft_last_error で取り出す）
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// 送受信の経過の種類
+#[repr(C)]
+pub enum FtEventKind {
+    /// 転送を開始した（peer・message・total が設定される）
+    Started,
+    /// 転送したバイト数（bytes・total が設定される）
+    Progress,
+    Completed,
+    /// 失敗した（message に理由が設定される）
+    Failed,
+    /// ft_cancel で中断した
+    Cancelled,
+    /// 受信側が受け付けなかった（message に応答が設定される）
+    Rejected,
+}
+
+/// ft_poll_event で取り出す経過（使い終わったら ft_event_free で解放する）
+#[repr(C)]
+pub struct FtEvent {
+    /// ft_send_file・ft_receiver_start が返した番号
+    pub handle: u64,
+    /// 転送ごとの番号（1つの受信の handle で複数の転送を受け取る）
+    pub id: u64,
+    pub kind: FtEventKind,
+    pub bytes: u64,
+    pub total: u64,
+    /// 相手のアドレス（Started 以外では NULL）
+    pub peer: *mut c_char,
+    /// ファイル名（Started）または理由（Failed・Rejected）。それ以外では NULL
+    pub message: *mut c_char,
+}
+
+/// 送受信を動かすコンテキスト（ft_init で作り、ft_free で解放する）
+pub struct FtContext {
+    runtime: Runtime,
+    config: Config,
+    events_tx: mpsc::UnboundedSender<(u64, u64, TransferEvent)>,
+    events_rx: Mutex<mpsc::UnboundedReceiver<(u64, u64, TransferEvent)>>,
+    // 実行中の送受信の handle と中断用の token
+    cancels: Arc<Mutex<HashMap<u64, CancellationToken>>>,
+}
+
+// 経過に ft_send_file・ft_receiver_start の handle を付けてコンテキストに流す
+struct Tagged {
+    handle: u64,
+    tx: mpsc::UnboundedSender<(u64, u64, TransferEvent)>,
+}
+
+impl TransferEvents for Tagged {
+    fn on_event(&self, id: u64, event: TransferEvent) {
+        let _ = self.tx.send((self.handle, id, event));
+    }
+}
+
+impl FtContext {
+    fn new() -> Result<Self> {
+        let runtime = Runtime::new().context("非同期ランタイムの起動に失敗")?;
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        Ok(Self {
+            runtime,
+            config: Config::load()?,
+            events_tx,
+            events_rx: Mutex::new(events_rx),
+            cancels: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    // 送受信を1つ登録し、経過の通知先と中断用の token を返す
+    fn register(&self) -> (u64, Arc<dyn TransferEvents>, CancellationToken) {
+        let handle = events::new_id();
+        let cancel = CancellationToken::new();
+        self.cancels.lock().unwrap().insert(handle, cancel.clone());
+        let events = Arc::new(Tagged {
+            handle,
+            tx: self.events_tx.clone(),
+        });
+        (handle, events, cancel)
+    }
+
+    fn start_receiver(&self, save_dir: PathBuf) -> Result<u64> {
+        let (handle, events, cancel) = self.register();
+        let args = ServerArgs::try_parse_from(["server"])?;
+        let receiver = self
+            .runtime
+            .block_on(FileReceiver::start(
+                &args,
+                &self.config,
+                save_dir,
+                Some(events),
+                cancel,
+            ))
+            .inspect_err(|_| {
+                self.cancels.lock().unwrap().remove(&handle);
+            })?;
+
+        // 受け入れの確認は行わず、すべて受け入れる（拒否は設定ファイルの acl などで行う）
+        self.runtime.spawn(async move {
+            let mut incoming = pin!(receiver.incoming());
+            while let Some(transfer) = incoming.next().await {
+                transfer.accept();
+            }
+        });
+        Ok(handle)
+    }
+
+    fn send_file(&self, addr: &str, path: PathBuf) -> Result<u64> {
+        // 不正な addr で clap がプロセスを終了させないよう、エラーとして返す
+        let args = ClientArgs::try_parse_from(["client", "--server", addr])
+            .with_context(|| format!("送信先のアドレスが不正です: {}", addr))?;
+        let (handle, events, cancel) = self.register();
+        let cancels = self.cancels.clone();
+        let config = self.config.clone();
+        self.runtime.spawn(async move {
+            // 結果は経過として通知される
+            let _ = client::send_path(&args, &config, &path, Some(events), &cancel).await;
+            cancels.lock().unwrap().remove(&handle);
+        });
+        Ok(handle)
+    }
+}
+
+impl FtEvent {
+    fn new(handle: u64, id: u64, event: TransferEvent) -> Self {
+        let mut ft_event = Self {
+            handle,
+            id,
+            kind: FtEventKind::Completed,
+            bytes: 0,
+            total: 0,
+            peer: ptr::null_mut(),
+            message: ptr::null_mut(),
+        };
+        match event {
+            TransferEvent::Started {
+                peer,
+                filename,
+                total,
+                ..
+            } => {
+                ft_event.kind = FtEventKind::Started;
+                ft_event.total = total;
+                ft_event.peer = into_c_string(peer);
+                ft_event.message = into_c_string(filename);
+            }
+            TransferEvent::Progress { bytes, total } => {
+                ft_event.kind = FtEventKind::Progress;
+                ft_event.bytes = bytes;
+                ft_event.total = total;
+            }
+            TransferEvent::Completed => {}
+            TransferEvent::Failed(e) => {
+                ft_event.kind = FtEventKind::Failed;
+                ft_event.message = into_c_string(e);
+            }
+            TransferEvent::Cancelled => ft_event.kind = FtEventKind::Cancelled,
+            TransferEvent::Rejected(response) => {
+                ft_event.kind = FtEventKind::Rejected;
+                ft_event.message = into_c_string(response);
+            }
+        }
+        ft_event
+    }
+}
+
+// C の文字列に変換する（途中の NUL は取り除く）
+fn into_c_string(s: String) -> *mut c_char {
+    CString::new(s.replace('\0', ""))
+        .unwrap_or_default()
+        .into_raw()
+}
+
+// 失敗したら ft_last_error で取り出せるよう記録し、failed を返す
+fn or_record<T>(result: Result<T>, failed: T) -> T {
+    result.unwrap_or_else(|e| {
+        let message = CString::new(format!("{:#}", e).replace('\0', "")).unwrap_or_default();
+        LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+        failed
+    })
+}
+
+// C の文字列の引数を読む
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str> {
+    anyhow::ensure!(!s.is_null(), "{} が NULL です", name);
+    CStr::from_ptr(s)
+        .to_str()
+        .with_context(|| format!("{} が UTF-8 ではありません", name))
+}
+
+// コンテキストの引数を読む
+unsafe fn context_arg<'a>(context: *const FtContext) -> Result<&'a FtContext> {
+    anyhow::ensure!(!context.is_null(), "context が NULL です");
+    Ok(&*context)
+}
+
+/// コンテキストを作る（失敗した場合は NULL を返す）
+#[no_mangle]
+pub extern "C" fn ft_init() -> *mut FtContext {
+    or_record(
+        FtContext::new().map(|context| Box::into_raw(Box::new(context))),
+        ptr::null_mut(),
+    )
+}
+
+/// コンテキストを解放する（実行中の送受信はすべて中断する）
+///
+/// # Safety
+/// context は ft_init が返したもので、解放後は使わないこと
+#[no_mangle]
+pub unsafe extern "C" fn ft_free(context: *mut FtContext) {
+    if context.is_null() {
+        return;
+    }
+    let context = Box::from_raw(context);
+    for cancel in context.cancels.lock().unwrap().values() {
+        cancel.cancel();
+    }
+}
+
+/// save_dir に保存する受信を開始し、handle を返す（失敗した場合は 0 を返す）
+///
+/// 受信の要求はすべて受け入れる。ft_cancel に handle を渡すと待ち受けを終了する
+///
+/// # Safety
+/// context は ft_init が返したもの、save_dir は NUL 終端の UTF-8 文字列であること
+#[no_mangle]
+pub unsafe extern "C" fn ft_receiver_start(
+    context: *const FtContext,
+    save_dir: *const c_char,
+) -> u64 {
+    or_record(
+        context_arg(context).and_then(|context| {
+            let save_dir = str_arg(save_dir, "save_dir")?;
+            context.start_receiver(PathBuf::from(save_dir))
+        }),
+        0,
+    )
+}
+
+/// path（ファイルまたはフォルダ）の addr への送信を開始し、handle を返す（失敗した場合は 0 を返す）
+///
+/// 送信の結果は ft_poll_event で Completed・Failed などとして受け取る
+///
+/// # Safety
+/// context は ft_init が返したもの、addr と path は NUL 終端の UTF-8 文字列であること
+#[no_mangle]
+pub unsafe extern "C" fn ft_send_file(
+    context: *const FtContext,
+    addr: *const c_char,
+    path: *const c_char,
+) -> u64 {
+    or_record(
+        context_arg(context).and_then(|context| {
+            let addr = str_arg(addr, "addr")?;
+            let path = str_arg(path, "path")?;
+            context.send_file(addr, PathBuf::from(path))
+        }),
+        0,
+    )
+}
+
+/// handle の送受信を中断する（終了済みなどで見つからない場合は false を返す）
+///
+/// # Safety
+/// context は ft_init が返したものであること
+#[no_mangle]
+pub unsafe extern "C" fn ft_cancel(context: *const FtContext, handle: u64) -> bool {
+    or_record(
+        context_arg(context).map(
+            |context| match context.cancels.lock().unwrap().remove(&handle) {
+                Some(cancel) => {
+                    cancel.cancel();
+                    true
+                }
+                None => false,
+            },
+        ),
+        false,
+    )
+}
+
+/// 経過を1つ取り出して event に書き込む（なければ false を返す）。待たずにすぐ戻る
+///
+/// # Safety
+/// context は ft_init が返したもの、event は書き込める FtEvent を指すこと
+#[no_mangle]
+pub unsafe extern "C" fn ft_poll_event(context: *const FtContext, event: *mut FtEvent) -> bool {
+    or_record(
+        context_arg(context).and_then(|context| {
+            anyhow::ensure!(!event.is_null(), "event が NULL です");
+            match context.events_rx.lock().unwrap().try_recv() {
+                Ok((handle, id, transfer_event)) => {
+                    event.write(FtEvent::new(handle, id, transfer_event));
+                    Ok(true)
+                }
+                Err(_) => Ok(false),
+            }
+        }),
+        false,
+    )
+}
+
+/// ft_poll_event が書き込んだ文字列を解放する
+///
+/// # Safety
+/// event は ft_poll_event が書き込んだもので、同じ event を2回解放しないこと
+#[no_mangle]
+pub unsafe extern "C" fn ft_event_free(event: *mut FtEvent) {
+    if event.is_null() {
+        return;
+    }
+    let event = &mut *event;
+    for s in [&mut event.peer, &mut event.message] {
+        if !s.is_null() {
+            drop(CString::from_raw(*s));
+            *s = ptr::null_mut();
+        }
+    }
+}
+
+/// このスレッドで直前に失敗した呼び出しのエラー（なければ NULL）
+///
+/// 文字列は次に失敗するまで有効で、解放しないこと
+#[no_mangle]
+pub extern "C" fn ft_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
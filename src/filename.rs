@@ -0,0 +1,208 @@
+use std::path::PathBuf;
+use unicode_normalization::UnicodeNormalization;
+
+// 送信するファイル名をNFCに正規化する（macOSのNFDの名前が他のOSで別名にならないように）
+pub fn to_wire(name: &str) -> String {
+    name.nfc().collect()
+}
+
+// 受信したファイル名（'/' 区切りの相対パス）を、このOSで使える形にする
+pub fn sanitize_received(name: &str) -> String {
+    name.nfc()
+        .collect::<String>()
+        .split('/')
+        .map(sanitize_component)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// パスの1要素として使えない文字を '_' に置き換える
+pub fn sanitize_component(component: &str) -> String {
+    let sanitized: String = component
+        .chars()
+        .map(|c| if is_invalid_char(c) { '_' } else { c })
+        .collect();
+
+    if cfg!(windows) {
+        sanitize_windows_name(sanitized)
+    } else {
+        sanitized
+    }
+}
+
+// Windowsの予約名（CON, NUL, COM1, CONIN$ など）と末尾のドット・空白を扱える名前にする
+fn sanitize_windows_name(name: String) -> String {
+    // "." と ".." はテンプレートの検証で弾くためそのまま残す
+    if name == "." || name == ".." {
+        return name;
+    }
+
+    // 末尾のドットと空白はWindowsが黙って取り除くため、別名にならないよう置き換える
+    let trimmed_len = name.trim_end_matches(['.', ' ']).len();
+    let mut name = format!(
+        "{}{}",
+        &name[..trimmed_len],
+        "_".repeat(name.len() - trimmed_len)
+    );
+
+    // 予約名は拡張子が付いていても使えない
+    let stem = name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    let is_reserved = matches!(
+        stem.as_str(),
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$"
+    ) || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+        && stem.len() == 4
+        && stem.as_bytes()[3].is_ascii_digit()
+        && stem.as_bytes()[3] != b'0');
+    if is_reserved {
+        name.insert(0, '_');
+    }
+
+    name
+}
+
+fn is_invalid_char(c: char) -> bool {
+    // 制御文字と、区切り文字として解釈されうる '\' はどのOSでも置き換える
+    if c.is_control() || c == '\\' {
+        return true;
+    }
+    cfg!(windows) && matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*')
+}
+
+// Windowsで MAX_PATH を超えるパスを \\?\ 形式（拡張パス）に変換する
+#[cfg(windows)]
+pub fn extend_long_path(path: PathBuf) -> PathBuf {
+    use std::ffi::OsString;
+
+    const MAX_PATH: usize = 260;
+    let text = path.as_os_str().to_string_lossy();
+    if text.len() < MAX_PATH || text.starts_with(r"\\?\") {
+        return path;
+    }
+
+    // 拡張パスでは '/' や相対パスが使えないため、絶対パスにしてから付け替える
+    let Ok(absolute) = std::path::absolute(&path) else {
+        return path;
+    };
+    let absolute = absolute.as_os_str().to_string_lossy().into_owned();
+    let extended = match absolute.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{}", unc),
+        None => format!(r"\\?\{}", absolute),
+    };
+    PathBuf::from(OsString::from(extended))
+}
+
+#[cfg(not(windows))]
+pub fn extend_long_path(path: PathBuf) -> PathBuf {
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parent_and_current_components_are_left_for_the_template_to_refuse() {
+        assert_eq!(sanitize_received("a/../b"), "a/../b");
+        assert_eq!(sanitize_received("../x"), "../x");
+        assert_eq!(sanitize_windows_name("..".to_string()), "..");
+        assert_eq!(sanitize_windows_name(".".to_string()), ".");
+    }
+
+    #[test]
+    fn backslashes_never_act_as_separators() {
+        assert_eq!(sanitize_received(r"..\..\x"), ".._.._x");
+        assert_eq!(sanitize_received(r"\\server\share\x"), "__server_share_x");
+        assert_eq!(
+            sanitize_received(r"C:\Windows\x"),
+            sanitize_received("C:_Windows_x")
+        );
+    }
+
+    #[test]
+    fn absolute_paths_keep_their_leading_separator() {
+        // 先頭の '/' は残し、保存先フォルダの外を指すパスとしてテンプレートの展開で弾く
+        assert_eq!(sanitize_received("/etc/passwd"), "/etc/passwd");
+    }
+
+    #[test]
+    fn drive_prefixes_are_plain_names_on_windows() {
+        let expected = if cfg!(windows) { "C_" } else { "C:" };
+        assert_eq!(sanitize_component("C:"), expected);
+        assert_eq!(
+            sanitize_received("C:/Windows"),
+            format!("{}/Windows", expected)
+        );
+    }
+
+    #[test]
+    fn control_characters_are_replaced() {
+        assert_eq!(sanitize_component("a\nb\u{7f}c\0"), "a_b_c_");
+    }
+
+    #[test]
+    fn names_are_normalized_to_nfc() {
+        assert_eq!(to_wire("e\u{301}"), "\u{e9}");
+        assert_eq!(sanitize_received("cafe\u{301}/x"), "caf\u{e9}/x");
+    }
+
+    #[test]
+    fn windows_reserved_names_get_a_prefix() {
+        for name in [
+            "CON",
+            "con",
+            "PRN",
+            "AUX",
+            "NUL",
+            "COM1",
+            "com9",
+            "LPT1",
+            "LPT9",
+            "CONIN$",
+            "conin$",
+            "CONOUT$",
+            "CONOUT$.log",
+        ] {
+            assert_eq!(
+                sanitize_windows_name(name.to_string()),
+                format!("_{}", name),
+                "{}",
+                name
+            );
+        }
+        // 拡張子が付いていても予約名として扱う
+        assert_eq!(sanitize_windows_name("nul.txt".to_string()), "_nul.txt");
+        assert_eq!(
+            sanitize_windows_name("Con.tar.gz".to_string()),
+            "_Con.tar.gz"
+        );
+    }
+
+    #[test]
+    fn similar_names_are_not_reserved() {
+        for name in [
+            "COM0",
+            "LPT0",
+            "COM10",
+            "CONSOLE",
+            "CONIN",
+            "NULL",
+            "AUXILIARY",
+            "xCON",
+        ] {
+            assert_eq!(sanitize_windows_name(name.to_string()), name, "{}", name);
+        }
+    }
+
+    #[test]
+    fn trailing_dots_and_spaces_are_replaced() {
+        assert_eq!(sanitize_windows_name("name. ".to_string()), "name__");
+        assert_eq!(sanitize_windows_name("name...".to_string()), "name___");
+        // 末尾を置き換えると予約名ではなくなる
+        assert_eq!(sanitize_windows_name("CON.".to_string()), "CON_");
+    }
+}
@@ -0,0 +1,620 @@
+use crate::protocol::{
+    self, AuthHeader, BundleEntry, BundleHeader, FileHeader, Header, Hello, KeepAliveHeader,
+    ManifestEntry, ManifestHeader, PartHeader, SparseHeader, SshAuthHeader, SymlinkHeader,
+    ACK_HEADER_MARKER, AUTH_HEADER_MARKER, BATCH_HEADER_MARKER, BUNDLE_HEADER_MARKER,
+    DRY_RUN_HEADER_MARKER, HELLO_HEADER_MARKER, KEEPALIVE_HEADER_MARKER, MANIFEST_HEADER_MARKER,
+    MAX_BUNDLE_ENTRIES, MAX_BUNDLE_FILE_SIZE, MAX_FILENAME_LEN, MAX_HELLO_FIELD_LEN,
+    MAX_MANIFEST_ENTRIES, MAX_MANIFEST_NAMES_LEN, MAX_METADATA_ENTRIES, MAX_METADATA_KEY_LEN,
+    MAX_METADATA_LEN, MAX_NOTE_LEN, MAX_PART_COUNT, MAX_SPARSE_EXTENTS, MAX_SSH_BLOB_LEN,
+    MAX_TOKEN_LEN, METADATA_HEADER_MARKER, NOTE_HEADER_MARKER, PART_HEADER_MARKER,
+    QUEUE_HEADER_MARKER, RESPONSE_HEADER_MARKER, SHA256_HEX_LEN, SPARSE_HEADER_MARKER,
+    SSH_AUTH_HEADER_MARKER, SYMLINK_HEADER_MARKER, TRANSFER_ID_HEADER_MARKER, VERIFY_HEADER_MARKER,
+};
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashSet};
+use uuid::Uuid;
+
+// 1度に解釈する部分（一覧の前までのヘッダー、または一覧の1要素）の最大長
+//
+// 申告された長さは読む前に上限を確かめるため、正しいヘッダーの部分がこれを超えることはない
+pub const MAX_CHUNK_LEN: usize = 32 * 1024;
+
+// 識別子に予約した範囲の下限（この範囲の知らない識別子は、ファイル名の長さとみなさずに断る）
+const MIN_RESERVED_MARKER: u32 = u32::MAX - 63;
+
+// 申告された件数を信用せずに確保しておく一覧の最大の大きさ（足りなければ読みながら広げる）
+const MAX_PREALLOCATED: usize = 1024;
+
+// HeaderDecoder::decode の結果
+pub enum Decoded {
+    // ヘッダーを読み終えた（使ったバイト数とともに返す）
+    Header(Header, usize),
+    // 一覧の前までのヘッダー、または一覧の1要素を読んだ（使ったバイト数。残りは続けて渡す）
+    Chunk(usize),
+    // 先頭から少なくともこのバイト数が揃うまで読めない
+    Incomplete(usize),
+}
+
+// 接続の先頭のヘッダーを、接続を使わずにバイト列から読む
+//
+// 読み手は、バイト列が足りなければ求められた長さまで読み足し、Chunk なら使った分を捨てて続ける。
+// 求める長さは MAX_CHUNK_LEN を超えないため、ヘッダーに続くデータまで読んでしまうことはなく、
+// 不正な申告で大きな領域を確保することもない
+#[derive(Default)]
+pub struct HeaderDecoder {
+    // 一覧の要素を読んでいる途中のヘッダー
+    pending: Option<Pending>,
+}
+
+impl HeaderDecoder {
+    pub fn decode(&mut self, buf: &[u8]) -> Result<Decoded> {
+        let mut input = Input {
+            buf,
+            pos: 0,
+            needed: None,
+        };
+        let decoded = match &mut self.pending {
+            None => decode_start(&mut input).map(Some),
+            Some(pending) => pending.decode_entry(&mut input).map(|()| None),
+        };
+        match decoded {
+            Ok(Some(Start::Header(header))) => return Ok(Decoded::Header(header, input.pos)),
+            Ok(Some(Start::List(pending))) => self.pending = Some(pending),
+            Ok(None) => {}
+            // 足りないだけなら、揃うまで待つ
+            Err(e) => {
+                return match input.needed {
+                    Some(needed) => Ok(Decoded::Incomplete(needed)),
+                    None => Err(e),
+                }
+            }
+        }
+        // 一覧を読み終えたら（要素がなければすぐに）ヘッダーを返す
+        match self.pending.take() {
+            Some(pending) if pending.remaining() == 0 => {
+                Ok(Decoded::Header(pending.into_header(), input.pos))
+            }
+            pending => {
+                self.pending = pending;
+                Ok(Decoded::Chunk(input.pos))
+            }
+        }
+    }
+}
+
+// バイト列の先頭からヘッダーを読み、ヘッダーと使ったバイト数を返す（足りなければ None）
+pub fn decode_header(buf: &[u8]) -> Result<Option<(Header, usize)>> {
+    let mut decoder = HeaderDecoder::default();
+    let mut pos = 0;
+    loop {
+        match decoder.decode(&buf[pos..])? {
+            Decoded::Header(header, used) => return Ok(Some((header, pos + used))),
+            Decoded::Chunk(used) => pos += used,
+            Decoded::Incomplete(_) => return Ok(None),
+        }
+    }
+}
+
+// 一覧の前までのヘッダーを読んだ結果
+enum Start {
+    Header(Header),
+    List(Pending),
+}
+
+// 一覧の要素を読んでいる途中のヘッダー
+enum Pending {
+    Sparse {
+        header: SparseHeader,
+        remaining: u32,
+        previous_end: u64,
+    },
+    Manifest {
+        header: ManifestHeader,
+        remaining: u32,
+        names: HashSet<String>,
+        names_len: usize,
+    },
+    Bundle {
+        header: BundleHeader,
+        remaining: u32,
+        names: HashSet<String>,
+    },
+}
+
+impl Pending {
+    fn remaining(&self) -> u32 {
+        match self {
+            Pending::Sparse { remaining, .. }
+            | Pending::Manifest { remaining, .. }
+            | Pending::Bundle { remaining, .. } => *remaining,
+        }
+    }
+
+    fn into_header(self) -> Header {
+        match self {
+            Pending::Sparse { header, .. } => Header::Sparse(header),
+            Pending::Manifest { header, .. } => Header::Manifest(header),
+            Pending::Bundle { header, .. } => Header::Bundle(header),
+        }
+    }
+
+    // 一覧の要素を1つ読む
+    //
+    // 足りなければ同じ要素を読み直すため、要素を読み終えるまで状態は変えない
+    fn decode_entry(&mut self, input: &mut Input) -> Result<()> {
+        match self {
+            Pending::Sparse {
+                header,
+                remaining,
+                previous_end,
+            } => {
+                let offset = input.u64()?;
+                let length = input.u64()?;
+                // 領域は重ならず昇順で、ファイルサイズに収まっている必要がある
+                let end = offset.checked_add(length);
+                if offset < *previous_end || !matches!(end, Some(end) if end <= header.file_size) {
+                    anyhow::bail!("スパースヘッダーの値が不正です");
+                }
+                *previous_end = offset + length;
+                header.extents.push((offset, length));
+                *remaining -= 1;
+            }
+            Pending::Manifest {
+                header,
+                remaining,
+                names,
+                names_len,
+            } => {
+                let filename = input.filename()?;
+                let size = input.u64()?;
+                // ハッシュがない場合は長さ 0
+                let sha256 = match input.u32()? {
+                    0 => None,
+                    SHA256_HEX_LEN => Some(input.sha256()?),
+                    hash_len => anyhow::bail!("ハッシュの長さが不正です: {}", hash_len),
+                };
+                if *names_len + filename.len() > MAX_MANIFEST_NAMES_LEN {
+                    anyhow::bail!("マニフェストが大きすぎます");
+                }
+                if !names.insert(filename.clone()) {
+                    anyhow::bail!("マニフェストのファイル名が重複しています: {}", filename);
+                }
+                *names_len += filename.len();
+                header.entries.push(ManifestEntry {
+                    filename,
+                    size,
+                    sha256,
+                });
+                *remaining -= 1;
+            }
+            Pending::Bundle {
+                header,
+                remaining,
+                names,
+            } => {
+                let filename = input.filename()?;
+                let size = input.u32()?;
+                if size > MAX_BUNDLE_FILE_SIZE {
+                    anyhow::bail!(
+                        "まとめて送るファイルが大きすぎます: {} ({} バイト)",
+                        filename,
+                        size
+                    );
+                }
+                if !names.insert(filename.clone()) {
+                    anyhow::bail!("まとめて送るファイルの名前が重複しています: {}", filename);
+                }
+                header.entries.push(BundleEntry { filename, size });
+                *remaining -= 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 先頭の識別子（旧形式のヘッダーではファイル名の長さ）から、一覧の前までのヘッダーを読む
+fn decode_start(input: &mut Input) -> Result<Start> {
+    let header = match input.u32()? {
+        PART_HEADER_MARKER => Header::Part(decode_part(input)?),
+        SYMLINK_HEADER_MARKER => {
+            let filename = input.filename()?;
+            let target_len = input.u32()?;
+            let target = input.string(target_len, MAX_FILENAME_LEN, "リンク先")?;
+            if target.is_empty() {
+                anyhow::bail!("リンク先が空です: {}", filename);
+            }
+            Header::Symlink(SymlinkHeader { filename, target })
+        }
+        SPARSE_HEADER_MARKER => {
+            let filename = input.filename()?;
+            let file_size = input.u64()?;
+            let extent_count = input.u32()?;
+            if extent_count > MAX_SPARSE_EXTENTS {
+                anyhow::bail!("データ領域が多すぎます: {}", extent_count);
+            }
+            return Ok(Start::List(Pending::Sparse {
+                header: SparseHeader {
+                    filename,
+                    file_size,
+                    extents: Vec::with_capacity((extent_count as usize).min(MAX_PREALLOCATED)),
+                },
+                remaining: extent_count,
+                previous_end: 0,
+            }));
+        }
+        AUTH_HEADER_MARKER => {
+            let token_len = input.u32()?;
+            let token = input.string(token_len, MAX_TOKEN_LEN as usize, "トークン")?;
+            Header::Auth(AuthHeader { token })
+        }
+        SSH_AUTH_HEADER_MARKER => {
+            let len = input.u32()?;
+            if len > MAX_SSH_BLOB_LEN {
+                anyhow::bail!("公開鍵が長すぎます: {} バイト", len);
+            }
+            let public_key = input.take(len as usize)?.to_vec();
+            Header::SshAuth(SshAuthHeader { public_key })
+        }
+        HELLO_HEADER_MARKER => {
+            let version = input.hello_field()?;
+            let features = input.hello_field()?;
+            if version.is_empty() {
+                anyhow::bail!("バージョンが空です");
+            }
+            Header::Hello(Hello {
+                version,
+                features: protocol::split_features(&features),
+            })
+        }
+        KEEPALIVE_HEADER_MARKER => Header::KeepAlive(KeepAliveHeader {
+            interval_secs: input.u32()?,
+        }),
+        RESPONSE_HEADER_MARKER => Header::Response,
+        ACK_HEADER_MARKER => Header::Ack,
+        MANIFEST_HEADER_MARKER => {
+            let batch_id = input.uuid()?;
+            let name_len = input.u32()?;
+            let name = input.string(name_len, MAX_FILENAME_LEN, "バッチ名")?;
+            let entry_count = input.u32()?;
+            if entry_count > MAX_MANIFEST_ENTRIES {
+                anyhow::bail!("マニフェストのファイルが多すぎます: {}", entry_count);
+            }
+            return Ok(Start::List(Pending::Manifest {
+                header: ManifestHeader {
+                    batch_id,
+                    name,
+                    entries: Vec::with_capacity((entry_count as usize).min(MAX_PREALLOCATED)),
+                },
+                remaining: entry_count,
+                names: HashSet::new(),
+                names_len: 0,
+            }));
+        }
+        BATCH_HEADER_MARKER => Header::Batch(input.uuid()?),
+        DRY_RUN_HEADER_MARKER => Header::DryRun,
+        VERIFY_HEADER_MARKER => Header::Verify,
+        NOTE_HEADER_MARKER => {
+            let len = input.u32()?;
+            let note = input.string(len, MAX_NOTE_LEN, "メモ")?;
+            // 表示を崩さないよう、改行などの制御文字は空白にする
+            Header::Note(
+                note.chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .collect(),
+            )
+        }
+        TRANSFER_ID_HEADER_MARKER => Header::TransferId(input.uuid()?),
+        QUEUE_HEADER_MARKER => Header::Queue,
+        METADATA_HEADER_MARKER => Header::Metadata(decode_metadata(input)?),
+        BUNDLE_HEADER_MARKER => {
+            let entry_count = input.u32()?;
+            if entry_count == 0 || entry_count > MAX_BUNDLE_ENTRIES {
+                anyhow::bail!("まとめて送るファイルの数が不正です: {}", entry_count);
+            }
+            return Ok(Start::List(Pending::Bundle {
+                header: BundleHeader {
+                    entries: Vec::with_capacity((entry_count as usize).min(MAX_PREALLOCATED)),
+                },
+                remaining: entry_count,
+                names: HashSet::new(),
+            }));
+        }
+        marker if marker >= MIN_RESERVED_MARKER => {
+            anyhow::bail!("不明なヘッダーです: {:#010x}", marker)
+        }
+        // 旧形式の単一ストリーム転送のヘッダー（ファイル名の長さ・データの長さ・ファイル名）
+        filename_len => {
+            let filedata_len = input.u32()?;
+            let filename = input.name(filename_len)?;
+            Header::File(FileHeader {
+                filename,
+                filedata_len,
+            })
+        }
+    };
+    Ok(Start::Header(header))
+}
+
+fn decode_part(input: &mut Input) -> Result<PartHeader> {
+    let transfer_id = input.uuid()?;
+    let part_count = input.u32()?;
+    let file_size = input.u64()?;
+    let offset = input.u64()?;
+    let length = input.u64()?;
+    let filename = input.filename()?;
+
+    let in_range = matches!(offset.checked_add(length), Some(end) if end <= file_size);
+    if part_count == 0 || part_count > MAX_PART_COUNT || !in_range {
+        anyhow::bail!("分割ヘッダーの値が不正です");
+    }
+
+    Ok(PartHeader {
+        transfer_id,
+        part_count,
+        file_size,
+        offset,
+        length,
+        filename,
+    })
+}
+
+// メタデータの組を読む（キーの重複・使えない文字と、合計の長さの超過は断る）
+fn decode_metadata(input: &mut Input) -> Result<BTreeMap<String, String>> {
+    let count = input.u32()?;
+    if count > MAX_METADATA_ENTRIES {
+        anyhow::bail!("メタデータの組が多すぎます: {}", count);
+    }
+    let mut metadata = BTreeMap::new();
+    let mut len = 0;
+    for _ in 0..count {
+        let key_len = input.u32()?;
+        let key = input.string(key_len, MAX_METADATA_KEY_LEN, "メタデータのキー")?;
+        let value_len = input.u32()?;
+        let value = input.string(
+            value_len,
+            MAX_METADATA_LEN.saturating_sub(len + key.len()),
+            "メタデータの値",
+        )?;
+        protocol::check_metadata_key(&key)?;
+        len += key.len() + value.len();
+        if metadata.insert(key.clone(), value).is_some() {
+            anyhow::bail!("メタデータのキーが重複しています: {}", key);
+        }
+    }
+    Ok(metadata)
+}
+
+// 解釈中のバイト列
+struct Input<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    // 足りずに読めなかった場合の、先頭から必要なバイト数
+    needed: Option<usize>,
+}
+
+impl<'a> Input<'a> {
+    // len バイトを取り出す（足りなければ、必要な長さを覚えて失敗する）
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos + len;
+        if end > MAX_CHUNK_LEN {
+            anyhow::bail!("ヘッダーが大きすぎます");
+        }
+        let Some(bytes) = self.buf.get(self.pos..end) else {
+            self.needed = Some(end);
+            anyhow::bail!("ヘッダーが途中で終わっています");
+        };
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn uuid(&mut self) -> Result<Uuid> {
+        Ok(Uuid::from_slice(self.take(16)?)?)
+    }
+
+    // 申告された長さの文字列を読む（長すぎる申告は読む前に断る）
+    fn string(&mut self, len: u32, max: usize, what: &str) -> Result<String> {
+        if len as usize > max {
+            anyhow::bail!("{}が長すぎます: {} バイト", what, len);
+        }
+        let bytes = self.take(len as usize)?;
+        String::from_utf8(bytes.to_vec()).with_context(|| format!("{}のUTF-8変換に失敗", what))
+    }
+
+    // 長さ付きのファイル名（フォルダを含む相対パス）を読む
+    fn filename(&mut self) -> Result<String> {
+        let len = self.u32()?;
+        self.name(len)
+    }
+
+    // 長さが len のファイル名を読む（空の名前は受け付けない）
+    fn name(&mut self, len: u32) -> Result<String> {
+        let filename = self.string(len, MAX_FILENAME_LEN, "ファイル名")?;
+        if filename.is_empty() {
+            anyhow::bail!("ファイル名が空です");
+        }
+        Ok(filename)
+    }
+
+    fn hello_field(&mut self) -> Result<String> {
+        let len = self.u32()?;
+        self.string(len, MAX_HELLO_FIELD_LEN as usize, "バージョン情報")
+    }
+
+    // SHA-256 の16進表記を読む
+    fn sha256(&mut self) -> Result<String> {
+        let sha256 = self.string(SHA256_HEX_LEN, SHA256_HEX_LEN as usize, "ハッシュ")?;
+        if !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            anyhow::bail!("ハッシュが16進表記ではありません");
+        }
+        Ok(sha256)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_file(filename: &str, filedata_len: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(filename.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&filedata_len.to_be_bytes());
+        bytes.extend_from_slice(filename.as_bytes());
+        bytes
+    }
+
+    fn manifest(filenames: &[&str]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MANIFEST_HEADER_MARKER.to_be_bytes());
+        bytes.extend_from_slice(Uuid::nil().as_bytes());
+        bytes.extend_from_slice(&5u32.to_be_bytes());
+        bytes.extend_from_slice(b"batch");
+        bytes.extend_from_slice(&(filenames.len() as u32).to_be_bytes());
+        for (index, filename) in filenames.iter().enumerate() {
+            bytes.extend_from_slice(&(filename.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(filename.as_bytes());
+            bytes.extend_from_slice(&(index as u64 * 100).to_be_bytes());
+            let sha256 = "ab".repeat(32);
+            bytes.extend_from_slice(&SHA256_HEX_LEN.to_be_bytes());
+            bytes.extend_from_slice(sha256.as_bytes());
+        }
+        bytes
+    }
+
+    // 接続から読むのと同じく、求められた長さまでを step バイトずつ読み足しながら読む
+    fn decode_in_pieces(bytes: &[u8], step: usize) -> Result<(Header, usize)> {
+        let mut decoder = HeaderDecoder::default();
+        let mut consumed = 0;
+        let mut read = 0;
+        loop {
+            match decoder.decode(&bytes[consumed..read])? {
+                Decoded::Header(header, used) => return Ok((header, consumed + used)),
+                Decoded::Chunk(used) => consumed += used,
+                Decoded::Incomplete(needed) => {
+                    assert!(needed <= MAX_CHUNK_LEN);
+                    assert!(
+                        consumed + needed <= bytes.len(),
+                        "ヘッダーの先まで求めました"
+                    );
+                    read = (read + step).min(consumed + needed).max(read + 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_a_legacy_file_header() {
+        let bytes = legacy_file("report.txt", 42);
+        let Some((Header::File(header), used)) = decode_header(&bytes).unwrap() else {
+            panic!("ファイルのヘッダーになりませんでした");
+        };
+        assert_eq!(header.filename, "report.txt");
+        assert_eq!(header.filedata_len, 42);
+        assert_eq!(used, bytes.len());
+    }
+
+    #[test]
+    fn truncated_input_asks_for_the_rest() {
+        let bytes = legacy_file("report.txt", 42);
+        for len in 0..bytes.len() {
+            assert!(decode_header(&bytes[..len]).unwrap().is_none());
+        }
+        let mut decoder = HeaderDecoder::default();
+        assert!(matches!(
+            decoder.decode(&bytes[..8]).unwrap(),
+            Decoded::Incomplete(needed) if needed == bytes.len()
+        ));
+    }
+
+    #[test]
+    fn oversized_lengths_are_refused_before_reading() {
+        // ファイル名の長さだけを申告し、名前は送らない
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(MAX_FILENAME_LEN as u32 + 1).to_be_bytes());
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        assert!(decode_header(&bytes).is_err());
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&AUTH_HEADER_MARKER.to_be_bytes());
+        bytes.extend_from_slice(&(MAX_TOKEN_LEN + 1).to_be_bytes());
+        assert!(decode_header(&bytes).is_err());
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&BUNDLE_HEADER_MARKER.to_be_bytes());
+        bytes.extend_from_slice(&(MAX_BUNDLE_ENTRIES + 1).to_be_bytes());
+        assert!(decode_header(&bytes).is_err());
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&SSH_AUTH_HEADER_MARKER.to_be_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decode_header(&bytes).is_err());
+    }
+
+    #[test]
+    fn unknown_and_reserved_markers_are_refused() {
+        for marker in [MIN_RESERVED_MARKER, METADATA_HEADER_MARKER - 1] {
+            let mut bytes = marker.to_be_bytes().to_vec();
+            bytes.extend_from_slice(&[0; 64]);
+            assert!(decode_header(&bytes).is_err(), "{:#010x}", marker);
+        }
+    }
+
+    #[test]
+    fn chained_headers_are_read_one_at_a_time() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&AUTH_HEADER_MARKER.to_be_bytes());
+        bytes.extend_from_slice(&5u32.to_be_bytes());
+        bytes.extend_from_slice(b"token");
+        let auth_len = bytes.len();
+        bytes.extend_from_slice(&legacy_file("report.txt", 42));
+        // 続くデータの一部
+        bytes.extend_from_slice(b"data");
+
+        let (header, used) = decode_in_pieces(&bytes, 3).unwrap();
+        assert!(matches!(header, Header::Auth(AuthHeader { token }) if token == "token"));
+        assert_eq!(used, auth_len);
+        let (header, used) = decode_in_pieces(&bytes[auth_len..], 3).unwrap();
+        assert!(
+            matches!(header, Header::File(FileHeader { filename, .. }) if filename == "report.txt")
+        );
+        assert_eq!(auth_len + used, bytes.len() - 4);
+    }
+
+    #[test]
+    fn a_manifest_split_across_reads_decodes_the_same() {
+        let bytes = manifest(&["a.txt", "photos/b.jpg", "photos/2024/c.jpg"]);
+        let Some((Header::Manifest(whole), used)) = decode_header(&bytes).unwrap() else {
+            panic!("マニフェストになりませんでした");
+        };
+        assert_eq!(used, bytes.len());
+
+        for step in [1, 2, 7, 64] {
+            let (header, used) = decode_in_pieces(&bytes, step).unwrap();
+            let Header::Manifest(split) = header else {
+                panic!("マニフェストになりませんでした");
+            };
+            assert_eq!(used, bytes.len());
+            assert_eq!(split.name, whole.name);
+            let names: Vec<&str> = split.entries.iter().map(|e| e.filename.as_str()).collect();
+            assert_eq!(names, ["a.txt", "photos/b.jpg", "photos/2024/c.jpg"]);
+            assert_eq!(split.entries[2].size, 200);
+        }
+    }
+
+    #[test]
+    fn duplicate_manifest_entries_are_refused() {
+        let bytes = manifest(&["a.txt", "a.txt"]);
+        assert!(decode_header(&bytes).is_err());
+    }
+}
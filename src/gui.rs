@@ -0,0 +1,598 @@
+use crate::{
+    client::{self, ClientArgs},
+    config::Config,
+    events::{TransferEvent, TransferEvents},
+    server::{self, FileReceiver, IncomingTransfer, ServerArgs},
+};
+use anyhow::Result;
+use clap::Parser;
+use eframe::egui;
+use rfd::FileDialog;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{runtime::Handle, sync::mpsc};
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+// 日本語を表示するために読み込むフォントの候補
+const JAPANESE_FONT_CANDIDATES: &[&str] = &[
+    "C:\\Windows\\Fonts\\YuGothM.ttc",
+    "C:\\Windows\\Fonts\\meiryo.ttc",
+    "C:\\Windows\\Fonts\\msgothic.ttc",
+    "/System/Library/Fonts/ヒラギノ角ゴシック W3.ttc",
+    "/System/Library/Fonts/Hiragino Sans GB.ttc",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+];
+
+// GUIモード（ウィンドウで送受信する）の実装
+pub fn run_gui(config: &Config) -> Result<()> {
+    let app = App::new(config.clone(), Handle::current());
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([520.0, 640.0])
+            .with_drag_and_drop(true),
+        ..Default::default()
+    };
+
+    eframe::run_native(
+        "ファイル転送",
+        options,
+        Box::new(|cc| {
+            install_japanese_font(&cc.egui_ctx);
+            Box::new(app)
+        }),
+    )
+    .map_err(|e| anyhow::anyhow!("GUIの起動に失敗: {}", e))
+}
+
+// 既定のフォントには日本語が含まれないため、OSのフォントを追加する
+fn install_japanese_font(ctx: &egui::Context) {
+    let Some(data) = JAPANESE_FONT_CANDIDATES
+        .iter()
+        .find_map(|path| std::fs::read(path).ok())
+    else {
+        eprintln!("日本語フォントが見つかりません。文字が正しく表示されない場合があります");
+        return;
+    };
+
+    let mut fonts = egui::FontDefinitions::default();
+    fonts
+        .font_data
+        .insert("japanese".to_string(), egui::FontData::from_owned(data));
+    for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+        fonts
+            .families
+            .entry(family)
+            .or_default()
+            .push("japanese".to_string());
+    }
+    ctx.set_fonts(fonts);
+}
+
+// 履歴の1件の状態
+enum Status {
+    Sending,
+    Sent,
+    Receiving,
+    Received,
+    Failed(String),
+    // 中断ボタンや受信の停止で中断した
+    Cancelled,
+    Rejected(String),
+    Declined,
+}
+
+// 送受信の履歴
+struct HistoryEntry {
+    time: String,
+    name: String,
+    peer: String,
+    status: Status,
+    // 転送済みのバイト数と全体のバイト数
+    progress: Option<(u64, u64)>,
+    // 送信側が添えたメモ
+    note: Option<String>,
+    // 送信側と受信側で共通の転送ID（ログの各行にも付く）
+    transfer_id: Option<Uuid>,
+    // 送信中の転送を中断する（送信のみ）
+    cancel: Option<CancellationToken>,
+}
+
+type History = Arc<Mutex<Vec<HistoryEntry>>>;
+
+// 受信の確認への答え
+enum Answer {
+    Accept,
+    // 選択したフォルダに保存する
+    AcceptInto(PathBuf),
+    Decline,
+}
+
+fn push_entry(history: &History, name: String, peer: String, status: Status) -> usize {
+    let mut history = history.lock().unwrap();
+    history.push(HistoryEntry {
+        time: chrono::Local::now().format("%H:%M:%S").to_string(),
+        name,
+        peer,
+        status,
+        progress: None,
+        note: None,
+        transfer_id: None,
+        cancel: None,
+    });
+    history.len() - 1
+}
+
+// 送信の経過を履歴の1件に反映する
+struct SendEvents {
+    history: History,
+    index: usize,
+}
+
+impl TransferEvents for SendEvents {
+    fn on_event(&self, _id: u64, event: TransferEvent) {
+        let mut history = self.history.lock().unwrap();
+        let entry = &mut history[self.index];
+        match event {
+            TransferEvent::Started {
+                total,
+                note,
+                transfer_id,
+                ..
+            } => {
+                entry.progress = Some((0, total));
+                entry.note = note;
+                entry.transfer_id = Some(transfer_id);
+            }
+            TransferEvent::Progress { bytes, total } => entry.progress = Some((bytes, total)),
+            TransferEvent::Completed => entry.status = Status::Sent,
+            TransferEvent::Failed(e) => entry.status = Status::Failed(e),
+            TransferEvent::Cancelled => entry.status = Status::Cancelled,
+            TransferEvent::Rejected(response) => entry.status = Status::Rejected(response),
+        }
+    }
+}
+
+// 受信の経過を履歴に反映する（受信を開始するたびに1件追加する）
+struct ReceiveEvents {
+    history: History,
+    // 転送の番号と履歴の位置の対応
+    entries: Mutex<HashMap<u64, usize>>,
+}
+
+impl TransferEvents for ReceiveEvents {
+    fn on_event(&self, id: u64, event: TransferEvent) {
+        let mut entries = self.entries.lock().unwrap();
+        if let TransferEvent::Started {
+            peer,
+            filename,
+            total,
+            note,
+            transfer_id,
+        } = event
+        {
+            let index = push_entry(&self.history, filename, peer, Status::Receiving);
+            let mut history = self.history.lock().unwrap();
+            history[index].progress = Some((0, total));
+            history[index].note = note;
+            history[index].transfer_id = Some(transfer_id);
+            entries.insert(id, index);
+            return;
+        }
+
+        // 受け入れる前に拒否したもの（受け入れの確認での拒否は answer_prompt で記録する）
+        let Some(&index) = entries.get(&id) else {
+            return;
+        };
+        let mut history = self.history.lock().unwrap();
+        let entry = &mut history[index];
+        match event {
+            TransferEvent::Progress { bytes, total } => entry.progress = Some((bytes, total)),
+            TransferEvent::Completed => entry.status = Status::Received,
+            TransferEvent::Failed(e) => entry.status = Status::Failed(e),
+            TransferEvent::Cancelled => entry.status = Status::Cancelled,
+            TransferEvent::Rejected(reason) => entry.status = Status::Rejected(reason),
+            TransferEvent::Started { .. } => {}
+        }
+    }
+}
+
+struct App {
+    runtime: Handle,
+    config: Config,
+
+    // 送信先の一覧（設定ファイルの peers と、画面で追加したもの）
+    peers: Vec<String>,
+    selected_peer: Option<usize>,
+    new_peer: String,
+
+    // 受信の状態
+    save_dir: Option<PathBuf>,
+    receiving: bool,
+    // 取り消すと待ち受けと受信中の転送を中断する
+    receive_cancel: CancellationToken,
+    receive_error: Arc<Mutex<Option<String>>>,
+    prompts: Option<mpsc::Receiver<IncomingTransfer>>,
+    pending: Vec<IncomingTransfer>,
+
+    history: History,
+}
+
+impl App {
+    fn new(config: Config, runtime: Handle) -> Self {
+        let peers = config.peers.clone();
+        Self {
+            runtime,
+            selected_peer: if peers.is_empty() { None } else { Some(0) },
+            peers,
+            new_peer: String::new(),
+            save_dir: config.save_dir.clone(),
+            receiving: false,
+            receive_cancel: CancellationToken::new(),
+            receive_error: Arc::new(Mutex::new(None)),
+            prompts: None,
+            pending: Vec::new(),
+            history: Arc::new(Mutex::new(Vec::new())),
+            config,
+        }
+    }
+
+    // 選択中の送信先にファイルまたはフォルダを送信する
+    fn send(&self, path: PathBuf) {
+        let Some(peer) = self.selected_peer.map(|index| self.peers[index].clone()) else {
+            return;
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string_lossy().into_owned());
+        let index = push_entry(&self.history, name, peer.clone(), Status::Sending);
+        let cancel = CancellationToken::new();
+        self.history.lock().unwrap()[index].cancel = Some(cancel.clone());
+
+        let history = self.history.clone();
+        let config = self.config.clone();
+        self.runtime.spawn(async move {
+            let args = ClientArgs::parse_from(["client", "--server", peer.as_str()]);
+            let events: Arc<dyn TransferEvents> = Arc::new(SendEvents {
+                history: history.clone(),
+                index,
+            });
+            // 送信を始める前の失敗は経過として通知されないため、ここで反映する
+            let result = client::send_path(&args, &config, &path, Some(events), &cancel).await;
+            let entry = &mut history.lock().unwrap()[index];
+            entry.cancel = None;
+            if let Err(e) = result {
+                if matches!(entry.status, Status::Sending) {
+                    entry.status = Status::Failed(format!("{:#}", e));
+                }
+            }
+        });
+    }
+
+    // 受信を開始する（受信のたびに確認のダイアログを出す）
+    fn start_receiving(&mut self, save_dir: PathBuf) {
+        let (tx, rx) = mpsc::channel(16);
+        self.prompts = Some(rx);
+        self.receiving = true;
+        self.receive_cancel = CancellationToken::new();
+
+        let config = self.config.clone();
+        let cancel = self.receive_cancel.clone();
+        let receive_error = self.receive_error.clone();
+        let events: Arc<dyn TransferEvents> = Arc::new(ReceiveEvents {
+            history: self.history.clone(),
+            entries: Mutex::new(HashMap::new()),
+        });
+        self.runtime.spawn(async move {
+            let args = ServerArgs::parse_from(["server"]);
+            let receiver =
+                match FileReceiver::start(&args, &config, save_dir, Some(events), cancel).await {
+                    Ok(receiver) => receiver,
+                    Err(e) => {
+                        *receive_error.lock().unwrap() = Some(format!("{:#}", e));
+                        return;
+                    }
+                };
+
+            // 受信の要求を画面での確認に回す
+            let mut incoming = pin!(receiver.incoming());
+            while let Some(transfer) = incoming.next().await {
+                if tx.send(transfer).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // 受信を停止する（確認待ちのものは拒否し、受信中のものは中断する）
+    fn stop_receiving(&mut self) {
+        self.receive_cancel.cancel();
+        self.receiving = false;
+        self.prompts = None;
+        for prompt in self.pending.drain(..) {
+            prompt.reject("");
+        }
+    }
+
+    // 受信の確認に答える（受け入れた場合は受信の開始時に履歴に記録される）
+    fn answer_prompt(&mut self, answer: Answer) {
+        let prompt = self.pending.remove(0);
+        match answer {
+            Answer::Accept => prompt.accept(),
+            Answer::AcceptInto(dir) => prompt.accept_into(dir),
+            Answer::Decline => {
+                let index = push_entry(
+                    &self.history,
+                    prompt.filename.clone(),
+                    prompt.peer.clone(),
+                    Status::Declined,
+                );
+                {
+                    let mut history = self.history.lock().unwrap();
+                    history[index].note = prompt.note.clone();
+                    history[index].transfer_id = Some(prompt.transfer_id);
+                }
+                prompt.reject("");
+            }
+        }
+    }
+
+    fn send_panel(&mut self, ui: &mut egui::Ui) {
+        ui.heading("送信");
+
+        ui.label("送信先");
+        for (index, peer) in self.peers.iter().enumerate() {
+            if ui
+                .selectable_label(self.selected_peer == Some(index), peer.as_str())
+                .clicked()
+            {
+                self.selected_peer = Some(index);
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_peer);
+            let new_peer = self.new_peer.trim().to_string();
+            if ui.button("追加").clicked() && !new_peer.is_empty() {
+                self.peers.push(new_peer);
+                self.selected_peer = Some(self.peers.len() - 1);
+                self.new_peer.clear();
+            }
+        });
+
+        // ドロップ領域
+        let hovering = ui.ctx().input(|i| !i.raw.hovered_files.is_empty());
+        let mut frame = egui::Frame::group(ui.style());
+        if hovering {
+            frame = frame.fill(ui.visuals().selection.bg_fill);
+        }
+        frame.show(ui, |ui| {
+            ui.set_min_size(egui::vec2(ui.available_width(), 100.0));
+            ui.vertical_centered(|ui| {
+                if self.selected_peer.is_none() {
+                    ui.label("送信先を追加してください");
+                    return;
+                }
+                ui.label("ここにファイルやフォルダをドロップして送信");
+                if ui.button("ファイルを選択...").clicked() {
+                    for path in FileDialog::new()
+                        .set_title("送信するファイルを選択")
+                        .pick_files()
+                        .unwrap_or_default()
+                    {
+                        self.send(path);
+                    }
+                }
+            });
+        });
+
+        let dropped: Vec<PathBuf> = ui.ctx().input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+        if self.selected_peer.is_some() {
+            for path in dropped {
+                self.send(path);
+            }
+        }
+    }
+
+    fn receive_panel(&mut self, ui: &mut egui::Ui) {
+        ui.heading("受信");
+
+        if let Some(error) = &*self.receive_error.lock().unwrap() {
+            ui.colored_label(egui::Color32::RED, format!("受信に失敗: {}", error));
+        }
+
+        if self.receiving {
+            ui.horizontal(|ui| {
+                if let Some(dir) = &self.save_dir {
+                    if server::is_paused() {
+                        ui.label(format!("一時停止中: {}", dir.display()));
+                    } else {
+                        ui.label(format!("受信中: {}", dir.display()));
+                    }
+                }
+                // 一時停止中も待ち受けは続け、新しい転送だけを断る
+                let pause = if server::is_paused() {
+                    "再開"
+                } else {
+                    "一時停止"
+                };
+                if ui.button(pause).clicked() {
+                    server::toggle_paused();
+                }
+                if ui.button("受信を停止").clicked() {
+                    self.stop_receiving();
+                }
+            });
+            return;
+        }
+
+        ui.horizontal(|ui| {
+            match &self.save_dir {
+                Some(dir) => ui.label(format!("保存先: {}", dir.display())),
+                None => ui.label("保存先: 未選択"),
+            };
+            if ui.button("変更...").clicked() {
+                if let Some(dir) = FileDialog::new()
+                    .set_title("ファイルの保存先フォルダを選択")
+                    .pick_folder()
+                {
+                    self.save_dir = Some(dir);
+                }
+            }
+        });
+        if let Some(dir) = self.save_dir.clone() {
+            if ui.button("受信を開始").clicked() {
+                self.start_receiving(dir);
+            }
+        }
+    }
+
+    fn history_panel(&self, ui: &mut egui::Ui) {
+        ui.heading("履歴");
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for entry in self.history.lock().unwrap().iter().rev() {
+                ui.horizontal(|ui| {
+                    ui.label(entry.time.as_str());
+                    match &entry.status {
+                        Status::Sending => {
+                            ui.spinner();
+                            ui.label(format!("{} → {} 送信中", entry.name, entry.peer));
+                            progress_bar(ui, entry.progress);
+                            if let Some(cancel) = &entry.cancel {
+                                if ui.button("中断").clicked() {
+                                    cancel.cancel();
+                                }
+                            }
+                        }
+                        Status::Sent => {
+                            ui.label(format!("{} → {} 送信完了", entry.name, entry.peer));
+                        }
+                        Status::Receiving => {
+                            ui.spinner();
+                            ui.label(format!("{} ← {} 受信中", entry.name, entry.peer));
+                            progress_bar(ui, entry.progress);
+                        }
+                        Status::Received => {
+                            ui.label(format!("{} ← {} 受信", entry.name, entry.peer));
+                        }
+                        Status::Failed(error) => {
+                            ui.colored_label(
+                                egui::Color32::RED,
+                                format!("{} / {} 失敗: {}", entry.name, entry.peer, error),
+                            );
+                        }
+                        Status::Cancelled => {
+                            ui.label(format!("{} / {} 中断", entry.name, entry.peer));
+                        }
+                        Status::Rejected(reason) => {
+                            ui.colored_label(
+                                egui::Color32::RED,
+                                format!(
+                                    "{} / {} 拒否されました: {}",
+                                    entry.name, entry.peer, reason
+                                ),
+                            );
+                        }
+                        Status::Declined => {
+                            ui.label(format!("{} ← {} 拒否", entry.name, entry.peer));
+                        }
+                    }
+                    if let Some(note) = &entry.note {
+                        ui.label(format!("メモ: {}", note));
+                    }
+                    if let Some(transfer_id) = entry.transfer_id {
+                        ui.weak(format!("転送ID: {}", transfer_id));
+                    }
+                });
+            }
+        });
+    }
+}
+
+// 転送済みの割合を表示する
+fn progress_bar(ui: &mut egui::Ui, progress: Option<(u64, u64)>) {
+    if let Some((bytes, total)) = progress {
+        let ratio = if total == 0 {
+            1.0
+        } else {
+            bytes as f32 / total as f32
+        };
+        ui.add(
+            egui::ProgressBar::new(ratio)
+                .desired_width(120.0)
+                .show_percentage(),
+        );
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // 受信の確認待ちを取り出す
+        if let Some(prompts) = &mut self.prompts {
+            while let Ok(prompt) = prompts.try_recv() {
+                self.pending.push(prompt);
+            }
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.send_panel(ui);
+            ui.separator();
+            self.receive_panel(ui);
+            ui.separator();
+            self.history_panel(ui);
+        });
+
+        if let Some(prompt) = self.pending.first() {
+            let message = format!(
+                "{} から {}（{} バイト）を受信しますか？",
+                prompt.peer, prompt.filename, prompt.size
+            );
+            let note = prompt.note.clone();
+            let mut answer = None;
+            egui::Window::new("受信の確認")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.label(message);
+                    if let Some(note) = &note {
+                        ui.label(format!("メモ: {}", note));
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("受け入れる").clicked() {
+                            answer = Some(Answer::Accept);
+                        }
+                        if ui.button("別の場所に保存...").clicked() {
+                            answer = FileDialog::new()
+                                .set_title("ファイルの保存先フォルダを選択")
+                                .pick_folder()
+                                .map(Answer::AcceptInto);
+                        }
+                        if ui.button("拒否").clicked() {
+                            answer = Some(Answer::Decline);
+                        }
+                    });
+                });
+            if let Some(answer) = answer {
+                self.answer_prompt(answer);
+            }
+        }
+
+        // バックグラウンドの送受信の状態を反映するため定期的に再描画する
+        ctx.request_repaint_after(Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::config;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// 送信履歴に残す期間（これより古い送信は重複の確認に使わず、履歴からも除く）
+const RECENT: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// 送信できたファイル
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sent {
+    // 送信先（接続先のアドレス）
+    pub peer: String,
+    pub filename: String,
+    pub size: u64,
+    pub sha256: String,
+    pub sent_at: i64,
+}
+
+// 送信履歴ファイル（history.toml）の内容
+#[derive(Default, Serialize, Deserialize)]
+struct History {
+    #[serde(default)]
+    sent: Vec<Sent>,
+}
+
+impl History {
+    // 送信履歴ファイルのパス
+    fn path(dir: Option<&Path>) -> Result<PathBuf> {
+        Ok(config::data_dir(dir)?.join("history.toml"))
+    }
+
+    // 読み込み、RECENT より古い送信を除く
+    fn load(dir: Option<&Path>) -> Result<Self> {
+        let path = Self::path(dir)?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("送信履歴の読み込みに失敗: {:?}", path))?;
+        let mut history: Self =
+            toml::from_str(&text).with_context(|| format!("送信履歴の解析に失敗: {:?}", path))?;
+        let since = chrono::Utc::now().timestamp() - RECENT.as_secs() as i64;
+        history.sent.retain(|sent| sent.sent_at >= since);
+        Ok(history)
+    }
+
+    fn save(&self, dir: Option<&Path>) -> Result<()> {
+        let path = Self::path(dir)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self)?;
+        fs::write(&path, text).with_context(|| format!("送信履歴の保存に失敗: {:?}", path))
+    }
+}
+
+// peer に最近送った、size バイトのファイルの送信（新しい順）
+//
+// 中身を比べる前に、ハッシュを計算するまでもないものを除くために使う
+pub fn recent(dir: Option<&Path>, peer: &str, size: u64) -> Result<Vec<Sent>> {
+    let mut sent: Vec<_> = History::load(dir)?
+        .sent
+        .into_iter()
+        .filter(|sent| sent.peer == peer && sent.size == size)
+        .collect();
+    sent.reverse();
+    Ok(sent)
+}
+
+// 送信できたファイルを履歴に加える
+pub fn record(
+    dir: Option<&Path>,
+    peer: &str,
+    filename: &str,
+    size: u64,
+    sha256: String,
+) -> Result<()> {
+    let mut history = History::load(dir)?;
+    history.sent.push(Sent {
+        peer: peer.to_string(),
+        filename: filename.to_string(),
+        size,
+        sha256,
+        sent_at: chrono::Utc::now().timestamp(),
+    });
+    history.save(dir)
+}
@@ -0,0 +1,344 @@
+use crate::{config::Config, control::Target, log::warn, picker};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use global_hotkey::{
+    hotkey::{Code, HotKey, Modifiers},
+    GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState,
+};
+use serde::Deserialize;
+
+// ホットキーで行う操作（設定ファイルの [hotkeys] に `send-file = "ctrl+shift+s"` のように書く）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// 送信するファイル（--folder ならフォルダ）を選ぶ（クライアントモード。既定は ctrl+shift+s）
+    SendFile,
+    /// クリップボードにコピーしたファイルを送信する（クライアントモード）
+    SendClipboard,
+    /// 受信の一時停止・再開を切り替える（サーバーモード）
+    TogglePause,
+    /// 保存先フォルダを選び直す（サーバーモード。既定は ctrl+shift+r）
+    ChangeSaveDir,
+    /// 最後に送信したファイルをもう一度送信する（クライアントモード）
+    ResendLast,
+}
+
+impl Action {
+    // 設定ファイルの [hotkeys] のキー
+    pub fn key(self) -> &'static str {
+        match self {
+            Action::SendFile => "send-file",
+            Action::SendClipboard => "send-clipboard",
+            Action::TogglePause => "toggle-pause",
+            Action::ChangeSaveDir => "change-save-dir",
+            Action::ResendLast => "resend-last",
+        }
+    }
+
+    // 操作を使うモード
+    pub fn target(self) -> Target {
+        match self {
+            Action::SendFile | Action::SendClipboard | Action::ResendLast => Target::Client,
+            Action::TogglePause | Action::ChangeSaveDir => Target::Server,
+        }
+    }
+
+    // 設定ファイルの組み合わせ（[hotkeys]、古い書き方の server_hotkey などの項目、既定値の順。なければ使わない）
+    pub fn configured(self, config: &Config) -> Option<String> {
+        let (legacy, default) = match self {
+            Action::SendFile => (config.client_hotkey.as_ref(), Some("ctrl+shift+s")),
+            Action::ChangeSaveDir => (config.server_hotkey.as_ref(), Some("ctrl+shift+r")),
+            Action::TogglePause => (config.server_pause_hotkey.as_ref(), None),
+            Action::SendClipboard | Action::ResendLast => (None, None),
+        };
+        config
+            .hotkeys
+            .get(&self)
+            .or(legacy)
+            .cloned()
+            .or_else(|| default.map(str::to_string))
+    }
+}
+
+// ホットキー文字列をパースする関数
+pub fn parse_hotkey(hotkey_str: &str) -> Result<HotKey> {
+    let parts: Vec<&str> = hotkey_str.split('+').collect();
+    let mut modifiers = Modifiers::empty();
+    let mut code = None;
+
+    for part in parts {
+        match part.trim().to_lowercase().as_str() {
+            "ctrl" | "control" => modifiers |= Modifiers::CONTROL,
+            "shift" => modifiers |= Modifiers::SHIFT,
+            "alt" => modifiers |= Modifiers::ALT,
+            "meta" | "cmd" | "command" | "win" | "windows" => modifiers |= Modifiers::META,
+            key => {
+                // キーコードの解析
+                code = Some(match key {
+                    "a" => Code::KeyA,
+                    "b" => Code::KeyB,
+                    "c" => Code::KeyC,
+                    "d" => Code::KeyD,
+                    "e" => Code::KeyE,
+                    "f" => Code::KeyF,
+                    "g" => Code::KeyG,
+                    "h" => Code::KeyH,
+                    "i" => Code::KeyI,
+                    "j" => Code::KeyJ,
+                    "k" => Code::KeyK,
+                    "l" => Code::KeyL,
+                    "m" => Code::KeyM,
+                    "n" => Code::KeyN,
+                    "o" => Code::KeyO,
+                    "p" => Code::KeyP,
+                    "q" => Code::KeyQ,
+                    "r" => Code::KeyR,
+                    "s" => Code::KeyS,
+                    "t" => Code::KeyT,
+                    "u" => Code::KeyU,
+                    "v" => Code::KeyV,
+                    "w" => Code::KeyW,
+                    "x" => Code::KeyX,
+                    "y" => Code::KeyY,
+                    "z" => Code::KeyZ,
+                    "0" => Code::Digit0,
+                    "1" => Code::Digit1,
+                    "2" => Code::Digit2,
+                    "3" => Code::Digit3,
+                    "4" => Code::Digit4,
+                    "5" => Code::Digit5,
+                    "6" => Code::Digit6,
+                    "7" => Code::Digit7,
+                    "8" => Code::Digit8,
+                    "9" => Code::Digit9,
+                    "f1" => Code::F1,
+                    "f2" => Code::F2,
+                    "f3" => Code::F3,
+                    "f4" => Code::F4,
+                    "f5" => Code::F5,
+                    "f6" => Code::F6,
+                    "f7" => Code::F7,
+                    "f8" => Code::F8,
+                    "f9" => Code::F9,
+                    "f10" => Code::F10,
+                    "f11" => Code::F11,
+                    "f12" => Code::F12,
+                    _ => anyhow::bail!("不明なキーコード: {}", key),
+                });
+            }
+        }
+    }
+
+    if let Some(code) = code {
+        Ok(HotKey::new(Some(modifiers), code))
+    } else {
+        anyhow::bail!("キーコードが指定されていません")
+    }
+}
+
+// 設定ファイルで代わりの組み合わせを指定しない場合に、元のキーに付け替えて試す修飾キー
+const DEFAULT_FALLBACK_MODIFIERS: &[&str] = &["ctrl+alt", "ctrl+shift+alt", "alt+shift"];
+
+// 登録済みのグローバルホットキー
+//
+// GlobalHotKeyManager は画面のない環境（SSH接続やコンテナなど）では初期化に失敗するため、
+// ホットキーを使う場合にだけ初期化する
+pub struct Hotkey {
+    // 破棄すると登録が解除されるため保持しておく
+    manager: GlobalHotKeyManager,
+    hotkey: HotKey,
+    // 実際に登録できた組み合わせ（代わりの組み合わせの場合もある）
+    name: String,
+}
+
+impl Hotkey {
+    // ホットキーを登録する（ホットキーを初期化できない環境では None）
+    //
+    // 他のアプリが使用中で登録できなければ fallbacks を順に試す。
+    // fallbacks が空なら元のキーに別の修飾キーを組み合わせて試す
+    fn register(hotkey_str: &str, fallbacks: &[String]) -> Result<Option<Self>> {
+        // 登録を試す前に、指定の誤りはその場で知らせる
+        parse_hotkey(hotkey_str)?;
+        let manager = match GlobalHotKeyManager::new() {
+            Ok(manager) => manager,
+            Err(e) => {
+                warn!("ホットキーを初期化できないため使用しません: {}", e);
+                return Ok(None);
+            }
+        };
+
+        let candidates = if fallbacks.is_empty() {
+            default_fallbacks(hotkey_str)
+        } else {
+            fallbacks.to_vec()
+        };
+        let mut failed = Vec::new();
+        for name in std::iter::once(hotkey_str.to_string()).chain(candidates) {
+            let hotkey = match parse_hotkey(&name) {
+                Ok(hotkey) => hotkey,
+                Err(e) => {
+                    warn!("代わりのホットキー {} を使用できません: {}", name, e);
+                    continue;
+                }
+            };
+            match manager.register(hotkey) {
+                Ok(()) => {
+                    if !failed.is_empty() {
+                        warn!("ホットキー {} の代わりに {} を使用します", hotkey_str, name);
+                    }
+                    return Ok(Some(Self {
+                        manager,
+                        hotkey,
+                        name,
+                    }));
+                }
+                Err(e) => {
+                    warn!("ホットキー {} を登録できません: {}", name, describe(&e));
+                    failed.push(name);
+                }
+            }
+        }
+
+        anyhow::bail!(
+            "ホットキーを登録できません（試した組み合わせ: {}）。--hotkey や設定ファイルの [hotkeys] で別の組み合わせを指定するか、hotkey_fallbacks に代わりの組み合わせを追加してください",
+            failed.join(", ")
+        )
+    }
+
+    // 登録を解除して別の組み合わせに変更する（失敗した場合は元の組み合わせのまま）
+    pub fn rebind(&mut self, hotkey_str: &str) -> Result<()> {
+        let hotkey = parse_hotkey(hotkey_str)?;
+        self.manager
+            .unregister(self.hotkey)
+            .with_context(|| format!("ホットキー {} の登録を解除できません", self.name))?;
+        if let Err(e) = self.manager.register(hotkey) {
+            let _ = self.manager.register(self.hotkey);
+            anyhow::bail!(
+                "ホットキー {} を登録できません: {}",
+                hotkey_str,
+                describe(&e)
+            );
+        }
+        self.hotkey = hotkey;
+        self.name = hotkey_str.to_string();
+        Ok(())
+    }
+
+    // 登録したホットキーの組み合わせ
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// モードで使う、操作ごとのホットキー
+pub struct Hotkeys(Vec<(Action, Hotkey)>);
+
+impl Hotkeys {
+    // actions の操作のホットキーを登録する（--no-hotkey の指定時や画面がない環境では空）
+    //
+    // 組み合わせはコマンドラインの指定があればそれ、なければ設定ファイルの値を使い、どちらもない操作は登録しない。
+    // 最初の操作はモードの主なホットキーとして、登録できなければ設定ファイルの hotkey_fallbacks を順に試す
+    pub fn register(
+        actions: Vec<(Action, Option<String>)>,
+        config: &Config,
+        disabled: bool,
+    ) -> Result<Self> {
+        let mut hotkeys = Vec::new();
+        if disabled {
+            return Ok(Self(hotkeys));
+        }
+        if !picker::has_display() {
+            warn!("画面がないためホットキーを使用しません");
+            return Ok(Self(hotkeys));
+        }
+        for (i, (action, arg)) in actions.into_iter().enumerate() {
+            let Some(hotkey_str) = arg.or_else(|| action.configured(config)) else {
+                continue;
+            };
+            let fallbacks: &[String] = if i == 0 {
+                config.hotkey_fallbacks.as_slice()
+            } else {
+                &[]
+            };
+            if let Some(hotkey) = Hotkey::register(&hotkey_str, fallbacks)? {
+                hotkeys.push((action, hotkey));
+            }
+        }
+        Ok(Self(hotkeys))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, action: Action) -> Option<&Hotkey> {
+        self.0
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map(|(_, hotkey)| hotkey)
+    }
+
+    pub fn get_mut(&mut self, action: Action) -> Option<&mut Hotkey> {
+        self.0
+            .iter_mut()
+            .find(|(a, _)| *a == action)
+            .map(|(_, hotkey)| hotkey)
+    }
+
+    // 前回の確認以降にホットキーが押された操作（押された順）
+    //
+    // 押されたイベントはすべてのホットキーで共有されるため、まとめて受け取って振り分ける。
+    // 切り替えの操作が2回に数えられないよう、キーを離したイベントは含めない
+    pub fn pressed(&self) -> Vec<Action> {
+        GlobalHotKeyEvent::receiver()
+            .try_iter()
+            .filter(|event| event.state == HotKeyState::Pressed)
+            .filter_map(|event| {
+                self.0
+                    .iter()
+                    .find(|(_, hotkey)| hotkey.hotkey.id() == event.id)
+                    .map(|(action, _)| *action)
+            })
+            .collect()
+    }
+}
+
+// 登録できるか試し、すぐに登録を解除する（doctor で使用）
+pub fn check(hotkey_str: &str) -> Result<()> {
+    let hotkey = parse_hotkey(hotkey_str)?;
+    let manager = GlobalHotKeyManager::new()
+        .map_err(|e| anyhow::anyhow!("ホットキーを初期化できません: {}", e))?;
+    if let Err(e) = manager.register(hotkey) {
+        anyhow::bail!(
+            "ホットキー {} を登録できません: {}",
+            hotkey_str,
+            describe(&e)
+        );
+    }
+    let _ = manager.unregister(hotkey);
+    Ok(())
+}
+
+// 元のキーに別の修飾キーを組み合わせた候補（元と同じ組み合わせは除く）
+fn default_fallbacks(hotkey_str: &str) -> Vec<String> {
+    let Some(key) = hotkey_str.rsplit('+').next().map(str::trim) else {
+        return Vec::new();
+    };
+    let original = parse_hotkey(hotkey_str).ok();
+    DEFAULT_FALLBACK_MODIFIERS
+        .iter()
+        .map(|modifiers| format!("{}+{}", modifiers, key))
+        .filter(|name| parse_hotkey(name).ok() != original)
+        .collect()
+}
+
+// 登録の失敗の理由
+fn describe(error: &global_hotkey::Error) -> String {
+    match error {
+        global_hotkey::Error::AlreadyRegistered(_) => "既に登録されています".to_string(),
+        global_hotkey::Error::FailedToRegister(_) => {
+            "他のアプリが使用している可能性があります".to_string()
+        }
+        e => e.to_string(),
+    }
+}
@@ -0,0 +1,649 @@
+use crate::{config::Config, pairing::SCHEME, picker, server::ServerArgs};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use std::path::Path;
+
+// ファイルマネージャーのメニューに表示する名前
+const MENU_NAME: &str = "file-transfer で送信";
+
+// ファイルマネージャーの右クリックメニューに「file-transfer で送信」を登録する
+//
+// 選択したファイル・フォルダを引数に `client --server <server>` を実行する
+// （Windows は「送る」と右クリックメニュー、Linux は Nautilus のスクリプト、macOS は Finder のクイックアクション）
+pub fn install(server: &str) -> Result<()> {
+    let exe = std::env::current_exe().context("実行ファイルのパスを取得できません")?;
+    let command = [
+        exe.to_string_lossy().into_owned(),
+        "client".to_string(),
+        "--server".to_string(),
+        server.to_string(),
+    ];
+    for path in platform::install(&command)? {
+        println!("登録しました: {}", path);
+    }
+    println!(
+        "ファイルマネージャーで選択して「{}」を選ぶと {} に送信します",
+        MENU_NAME, server
+    );
+    Ok(())
+}
+
+// ペアリング用のリンク（filetransfer://）を開くと `open <リンク>` を実行するよう登録する
+pub fn install_url_scheme() -> Result<()> {
+    let exe = std::env::current_exe().context("実行ファイルのパスを取得できません")?;
+    let command = [exe.to_string_lossy().into_owned(), "open".to_string()];
+    for path in platform::install_url_scheme(&command)? {
+        println!("登録しました: {}", path);
+    }
+    println!("{}:// のリンクを開くとこのプログラムが起動します", SCHEME);
+    Ok(())
+}
+
+// install・install_url_scheme で登録したものを削除する
+pub fn uninstall() -> Result<()> {
+    let mut removed = platform::uninstall()?;
+    removed.extend(platform::uninstall_url_scheme()?);
+    if removed.is_empty() {
+        println!("登録されていません");
+    }
+    for path in removed {
+        println!("削除しました: {}", path);
+    }
+    Ok(())
+}
+
+// autostart サブコマンドの操作
+#[derive(Subcommand)]
+pub enum AutostartCommand {
+    /// ログインしたときにサーバーモードを起動するよう登録する（登録済みなら引数を置き換える）
+    Enable {
+        /// ホットキーを使わずに起動する（画面がない環境では指定しなくても使わない）
+        #[arg(long)]
+        no_hotkey: bool,
+
+        /// サーバーモードに渡す引数（-- の後に書く。例: -- --save-dir /home/me/Downloads --notify）
+        #[arg(last = true)]
+        server_args: Vec<String>,
+    },
+    /// ログインしたときの起動の登録を削除する
+    Disable,
+}
+
+// ログインしたときにサーバーモードを起動するよう登録する・登録を削除する
+//
+// Windows はレジストリの Run キー、macOS は LaunchAgent、Linux は XDG autostart のデスクトップエントリを使う
+pub fn autostart(command: &AutostartCommand, config: &Config) -> Result<()> {
+    let (no_hotkey, server_args) = match command {
+        AutostartCommand::Enable {
+            no_hotkey,
+            server_args,
+        } => (*no_hotkey, server_args),
+        AutostartCommand::Disable => {
+            let removed = platform::uninstall_autostart()?;
+            if removed.is_empty() {
+                println!("登録されていません");
+            }
+            for path in removed {
+                println!("削除しました: {}", path);
+            }
+            return Ok(());
+        }
+    };
+
+    // ログインしたときに失敗しても気付けないため、引数の誤りはここで知らせる
+    let parsed = ServerArgs::try_parse_from(
+        std::iter::once("server").chain(server_args.iter().map(String::as_str)),
+    )
+    .context("サーバーモードの引数が正しくありません")?;
+    let mut args = vec!["server".to_string()];
+    // 画面がない環境ではログインしたときもホットキーを使えない
+    let no_hotkey = no_hotkey || parsed.no_hotkey || !picker::has_display();
+    if no_hotkey && !parsed.no_hotkey {
+        args.push("--no-hotkey".to_string());
+    }
+    // ホットキーで選べなければ、起動したときに保存先の選択画面を出して止まってしまう
+    if no_hotkey && parsed.save_dir.is_none() && config.save_dir.is_none() {
+        anyhow::bail!(
+            "ホットキーを使わずに起動する場合は、-- --save-dir か設定ファイルの save_dir で保存先を指定してください"
+        );
+    }
+    args.extend(server_args.iter().cloned());
+
+    let exe = std::env::current_exe().context("実行ファイルのパスを取得できません")?;
+    let command: Vec<_> = std::iter::once(exe.to_string_lossy().into_owned())
+        .chain(args)
+        .collect();
+    for path in platform::install_autostart(&command)? {
+        println!("登録しました: {}", path);
+    }
+    println!("ログインしたときに {} を起動します", command.join(" "));
+    if config.log_file.path.is_none() {
+        println!(
+            "起動したサーバーの出力は表示されないため、設定ファイルの [log_file] でログファイルを指定しておくと状況を確かめられます"
+        );
+    }
+    Ok(())
+}
+
+// path を削除する（存在しなかった場合は false）
+fn remove(path: &Path) -> Result<bool> {
+    let result = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("{:?} の削除に失敗", path)),
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::{remove, MENU_NAME, SCHEME};
+    use anyhow::{Context, Result};
+    use std::{path::PathBuf, process::Command};
+
+    // 右クリックメニューを登録するレジストリのキー（ファイルとフォルダ）
+    const REGISTRY_KEYS: [&str; 2] = [
+        r"HKCU\Software\Classes\*\shell\file-transfer",
+        r"HKCU\Software\Classes\Directory\shell\file-transfer",
+    ];
+
+    // 「送る」に置くバッチファイル（複数選択したファイルをまとめて受け取れる）
+    fn send_to_path() -> Result<PathBuf> {
+        let dir = dirs::config_dir().context("AppData フォルダが見つかりません")?;
+        Ok(dir
+            .join(r"Microsoft\Windows\SendTo")
+            .join(format!("{}.cmd", MENU_NAME)))
+    }
+
+    // 各引数を引用符で囲んだコマンド行（Windows のパスには " を含められない）
+    fn command_line(command: &[String]) -> String {
+        command
+            .iter()
+            .map(|arg| format!("\"{}\"", arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn install(command: &[String]) -> Result<Vec<String>> {
+        let command = command_line(command);
+
+        let path = send_to_path()?;
+        // 失敗した場合は結果を読めるよう、ウィンドウを閉じずに待つ
+        let script = format!(
+            "@echo off\r\nchcp 65001 > nul\r\n{} %*\r\nif errorlevel 1 pause\r\n",
+            command
+        );
+        std::fs::write(&path, script).with_context(|| format!("{:?} の作成に失敗", path))?;
+        let mut installed = vec![path.display().to_string()];
+
+        for key in REGISTRY_KEYS {
+            reg(&["add", key, "/ve", "/d", MENU_NAME, "/f"])?;
+            reg(&[
+                "add",
+                &format!(r"{}\command", key),
+                "/ve",
+                "/d",
+                &format!("{} \"%1\"", command),
+                "/f",
+            ])?;
+            installed.push(key.to_string());
+        }
+        Ok(installed)
+    }
+
+    pub fn uninstall() -> Result<Vec<String>> {
+        let path = send_to_path()?;
+        let mut removed = Vec::new();
+        if remove(&path)? {
+            removed.push(path.display().to_string());
+        }
+        for key in REGISTRY_KEYS {
+            // キーがなければ reg delete は失敗する
+            if reg(&["delete", key, "/f"]).is_ok() {
+                removed.push(key.to_string());
+            }
+        }
+        Ok(removed)
+    }
+
+    // URL スキームを登録するレジストリのキー
+    fn url_scheme_key() -> String {
+        format!(r"HKCU\Software\Classes\{}", SCHEME)
+    }
+
+    pub fn install_url_scheme(command: &[String]) -> Result<Vec<String>> {
+        let command = command_line(command);
+        let key = url_scheme_key();
+        reg(&["add", &key, "/ve", "/d", &format!("URL:{}", SCHEME), "/f"])?;
+        reg(&["add", &key, "/v", "URL Protocol", "/d", "", "/f"])?;
+        reg(&[
+            "add",
+            &format!(r"{}\shell\open\command", key),
+            "/ve",
+            "/d",
+            &format!("{} \"%1\"", command),
+            "/f",
+        ])?;
+        Ok(vec![key])
+    }
+
+    pub fn uninstall_url_scheme() -> Result<Vec<String>> {
+        let key = url_scheme_key();
+        Ok(if reg(&["delete", &key, "/f"]).is_ok() {
+            vec![key]
+        } else {
+            Vec::new()
+        })
+    }
+
+    // ログインしたときに起動するプログラムのレジストリのキーと値の名前
+    const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+    const RUN_VALUE: &str = "file-transfer";
+
+    pub fn install_autostart(command: &[String]) -> Result<Vec<String>> {
+        reg(&[
+            "add",
+            RUN_KEY,
+            "/v",
+            RUN_VALUE,
+            "/d",
+            &command_line(command),
+            "/f",
+        ])?;
+        Ok(vec![format!(r"{}\{}", RUN_KEY, RUN_VALUE)])
+    }
+
+    pub fn uninstall_autostart() -> Result<Vec<String>> {
+        // 値がなければ reg delete は失敗する
+        Ok(
+            if reg(&["delete", RUN_KEY, "/v", RUN_VALUE, "/f"]).is_ok() {
+                vec![format!(r"{}\{}", RUN_KEY, RUN_VALUE)]
+            } else {
+                Vec::new()
+            },
+        )
+    }
+
+    fn reg(args: &[&str]) -> Result<()> {
+        let output = Command::new("reg")
+            .args(args)
+            .output()
+            .context("reg を実行できません")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "レジストリの変更に失敗: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{remove, MENU_NAME};
+    use anyhow::{Context, Result};
+    use std::path::PathBuf;
+
+    // Finder のクイックアクション（Automator のサービス）
+    fn workflow_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().context("ホームフォルダが見つかりません")?;
+        Ok(home
+            .join("Library/Services")
+            .join(format!("{}.workflow", MENU_NAME)))
+    }
+
+    // Info.plist（Finder でファイル・フォルダを選択したときに表示する）
+    const INFO_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>NSServices</key>
+	<array>
+		<dict>
+			<key>NSMenuItem</key>
+			<dict>
+				<key>default</key>
+				<string>{name}</string>
+			</dict>
+			<key>NSMessage</key>
+			<string>runWorkflowAsService</string>
+			<key>NSRequiredContext</key>
+			<dict>
+				<key>NSApplicationIdentifier</key>
+				<string>com.apple.finder</string>
+			</dict>
+			<key>NSSendFileTypes</key>
+			<array>
+				<string>public.item</string>
+			</array>
+		</dict>
+	</array>
+</dict>
+</plist>
+"#;
+
+    // document.wflow（選択した項目を引数にシェルスクリプトを実行する）
+    const DOCUMENT_WFLOW: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AMApplicationBuild</key>
+	<string>523</string>
+	<key>AMApplicationVersion</key>
+	<string>2.10</string>
+	<key>AMDocumentVersion</key>
+	<string>2</string>
+	<key>actions</key>
+	<array>
+		<dict>
+			<key>action</key>
+			<dict>
+				<key>AMAccepts</key>
+				<dict>
+					<key>Container</key>
+					<string>List</string>
+					<key>Optional</key>
+					<true/>
+					<key>Types</key>
+					<array>
+						<string>com.apple.cocoa.string</string>
+					</array>
+				</dict>
+				<key>AMActionVersion</key>
+				<string>2.0.3</string>
+				<key>AMApplication</key>
+				<array>
+					<string>Automator</string>
+				</array>
+				<key>AMProvides</key>
+				<dict>
+					<key>Container</key>
+					<string>List</string>
+					<key>Types</key>
+					<array>
+						<string>com.apple.cocoa.string</string>
+					</array>
+				</dict>
+				<key>ActionBundlePath</key>
+				<string>/System/Library/Automator/Run Shell Script.action</string>
+				<key>ActionName</key>
+				<string>Run Shell Script</string>
+				<key>ActionParameters</key>
+				<dict>
+					<key>COMMAND_STRING</key>
+					<string>{script}</string>
+					<key>CheckedForUserDefaultShell</key>
+					<true/>
+					<key>inputMethod</key>
+					<integer>1</integer>
+					<key>shell</key>
+					<string>/bin/sh</string>
+					<key>source</key>
+					<string></string>
+				</dict>
+				<key>BundleIdentifier</key>
+				<string>com.apple.RunShellScript</string>
+				<key>CFBundleVersion</key>
+				<string>2.0.3</string>
+				<key>CanShowSelectedItemsWhenRun</key>
+				<false/>
+				<key>CanShowWhenRun</key>
+				<true/>
+				<key>Class Name</key>
+				<string>RunShellScriptAction</string>
+				<key>InputUUID</key>
+				<string>0F4D6A3E-5B8C-4B8E-9D5A-2E7C1A9B3F10</string>
+				<key>OutputUUID</key>
+				<string>7C2E9B41-1D3A-4F6B-8E2C-5A9D0B7F4E21</string>
+				<key>UUID</key>
+				<string>3A8F1C52-9E7D-4B0A-A6C3-1F5E8D2B9C34</string>
+				<key>isViewVisible</key>
+				<integer>1</integer>
+			</dict>
+		</dict>
+	</array>
+	<key>workflowMetaData</key>
+	<dict>
+		<key>serviceApplicationBundleID</key>
+		<string>com.apple.finder</string>
+		<key>serviceInputTypeIdentifier</key>
+		<string>com.apple.Automator.fileSystemObject</string>
+		<key>serviceOutputTypeIdentifier</key>
+		<string>com.apple.Automator.nothing</string>
+		<key>workflowTypeIdentifier</key>
+		<string>com.apple.Automator.servicesMenu</string>
+	</dict>
+</dict>
+</plist>
+"#;
+
+    // plist の文字列に埋め込めるようエスケープする
+    fn escape_xml(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    pub fn install(command: &[String]) -> Result<Vec<String>> {
+        let path = workflow_path()?;
+        let contents = path.join("Contents");
+        std::fs::create_dir_all(&contents)
+            .with_context(|| format!("{:?} の作成に失敗", contents))?;
+        let script = format!("{} \"$@\"", super::shell_command(command));
+        std::fs::write(
+            contents.join("Info.plist"),
+            INFO_PLIST.replace("{name}", &escape_xml(MENU_NAME)),
+        )?;
+        std::fs::write(
+            contents.join("document.wflow"),
+            DOCUMENT_WFLOW.replace("{script}", &escape_xml(&script)),
+        )?;
+        Ok(vec![path.display().to_string()])
+    }
+
+    pub fn uninstall() -> Result<Vec<String>> {
+        let path = workflow_path()?;
+        Ok(if remove(&path)? {
+            vec![path.display().to_string()]
+        } else {
+            Vec::new()
+        })
+    }
+
+    // macOS はリンクを引数ではなく Apple Event で渡すため、アプリのバンドルでなければ受け取れない
+    pub fn install_url_scheme(_command: &[String]) -> Result<Vec<String>> {
+        anyhow::bail!("macOS では URL スキームの登録に対応していません")
+    }
+
+    pub fn uninstall_url_scheme() -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    // ログインしたときに起動する LaunchAgent
+    const LAUNCH_AGENT_LABEL: &str = "org.filetransfer.autostart";
+
+    fn launch_agent_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().context("ホームフォルダが見つかりません")?;
+        Ok(home
+            .join("Library/LaunchAgents")
+            .join(format!("{}.plist", LAUNCH_AGENT_LABEL)))
+    }
+
+    pub fn install_autostart(command: &[String]) -> Result<Vec<String>> {
+        let path = launch_agent_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("{:?} の作成に失敗", dir))?;
+        }
+        let arguments: String = command
+            .iter()
+            .map(|arg| format!("\t\t<string>{}</string>\n", escape_xml(arg)))
+            .collect();
+        let plist = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Label</key>
+	<string>{}</string>
+	<key>ProgramArguments</key>
+	<array>
+{}	</array>
+	<key>RunAtLoad</key>
+	<true/>
+</dict>
+</plist>
+"#,
+            LAUNCH_AGENT_LABEL, arguments
+        );
+        std::fs::write(&path, plist).with_context(|| format!("{:?} の作成に失敗", path))?;
+        Ok(vec![path.display().to_string()])
+    }
+
+    pub fn uninstall_autostart() -> Result<Vec<String>> {
+        let path = launch_agent_path()?;
+        Ok(if remove(&path)? {
+            vec![path.display().to_string()]
+        } else {
+            Vec::new()
+        })
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    use super::{remove, MENU_NAME, SCHEME};
+    use anyhow::{Context, Result};
+    use std::{os::unix::fs::PermissionsExt, path::PathBuf, process::Command};
+
+    // Nautilus のスクリプト（選択したファイル・フォルダが引数で渡される）
+    fn script_path() -> Result<PathBuf> {
+        let dir = dirs::data_dir().context("データフォルダが見つかりません")?;
+        Ok(dir.join("nautilus/scripts").join(MENU_NAME))
+    }
+
+    pub fn install(command: &[String]) -> Result<Vec<String>> {
+        let path = script_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("{:?} の作成に失敗", dir))?;
+        }
+        let script = format!("#!/bin/sh\nexec {} \"$@\"\n", super::shell_command(command));
+        std::fs::write(&path, script).with_context(|| format!("{:?} の作成に失敗", path))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        Ok(vec![path.display().to_string()])
+    }
+
+    pub fn uninstall() -> Result<Vec<String>> {
+        let path = script_path()?;
+        Ok(if remove(&path)? {
+            vec![path.display().to_string()]
+        } else {
+            Vec::new()
+        })
+    }
+
+    // URL スキームを処理するデスクトップエントリ
+    const DESKTOP_FILE_NAME: &str = "file-transfer-url.desktop";
+
+    fn desktop_file_path() -> Result<PathBuf> {
+        let dir = dirs::data_dir().context("データフォルダが見つかりません")?;
+        Ok(dir.join("applications").join(DESKTOP_FILE_NAME))
+    }
+
+    // デスクトップエントリの Exec の引数として " で囲む
+    fn desktop_quote(arg: &str) -> String {
+        let mut quoted = String::from("\"");
+        for c in arg.chars() {
+            if matches!(c, '"' | '`' | '$' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    pub fn install_url_scheme(command: &[String]) -> Result<Vec<String>> {
+        let path = desktop_file_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("{:?} の作成に失敗", dir))?;
+        }
+        let exec = command
+            .iter()
+            .map(|arg| desktop_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        // 結果を読めるよう端末で開く
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName=file-transfer\nExec={} %u\nTerminal=true\nNoDisplay=true\nMimeType=x-scheme-handler/{};\n",
+            exec, SCHEME
+        );
+        std::fs::write(&path, entry).with_context(|| format!("{:?} の作成に失敗", path))?;
+
+        let status = Command::new("xdg-mime")
+            .args(["default", DESKTOP_FILE_NAME])
+            .arg(format!("x-scheme-handler/{}", SCHEME))
+            .status()
+            .context("xdg-mime を実行できません")?;
+        if !status.success() {
+            anyhow::bail!("xdg-mime が失敗しました（{}）", status);
+        }
+        Ok(vec![path.display().to_string()])
+    }
+
+    pub fn uninstall_url_scheme() -> Result<Vec<String>> {
+        let path = desktop_file_path()?;
+        Ok(if remove(&path)? {
+            vec![path.display().to_string()]
+        } else {
+            Vec::new()
+        })
+    }
+
+    // デスクトップにログインしたときに起動する XDG autostart のデスクトップエントリ
+    fn autostart_path() -> Result<PathBuf> {
+        let dir = dirs::config_dir().context("設定フォルダが見つかりません")?;
+        Ok(dir.join("autostart").join("file-transfer.desktop"))
+    }
+
+    pub fn install_autostart(command: &[String]) -> Result<Vec<String>> {
+        let path = autostart_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("{:?} の作成に失敗", dir))?;
+        }
+        let exec = command
+            .iter()
+            .map(|arg| desktop_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName=file-transfer\nExec={}\nTerminal=false\nX-GNOME-Autostart-enabled=true\n",
+            exec
+        );
+        std::fs::write(&path, entry).with_context(|| format!("{:?} の作成に失敗", path))?;
+        Ok(vec![path.display().to_string()])
+    }
+
+    pub fn uninstall_autostart() -> Result<Vec<String>> {
+        let path = autostart_path()?;
+        Ok(if remove(&path)? {
+            vec![path.display().to_string()]
+        } else {
+            Vec::new()
+        })
+    }
+}
+
+// sh のコマンド行（各引数を ' で囲む）
+#[cfg(not(windows))]
+fn shell_command(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| format!("'{}'", arg.replace('\'', r"'\''")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,163 @@
+use crate::{layer::Layer, transport::BoxedConnection};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    time::Sleep,
+};
+
+// 応答を待たせている間に送る PING の間隔
+pub const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+// 相手からの読み書きがこの時間進まなければ、接続が切れたとみなす
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+
+// 応答の前に置く PING（応答の文字列には現れない）
+pub const PING: u8 = 0;
+
+// 応答を書き込むまでの間、書き込みが interval 途切れるたびに PING を送るレイヤー（サーバー用）
+//
+// 受け入れの確認やファイルの保存で応答が遅れても、相手が IdleTimeout で接続を切らないようにする。
+// 接続は裏のタスクが持ち、読み書きは中継する
+pub struct KeepAlive {
+    interval: Duration,
+}
+
+impl KeepAlive {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval }
+    }
+}
+
+impl Layer for KeepAlive {
+    fn wrap(&self, connection: BoxedConnection) -> BoxedConnection {
+        let (near, far) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(relay(connection, far, self.interval));
+        Box::new(near)
+    }
+}
+
+async fn relay(connection: BoxedConnection, far: tokio::io::DuplexStream, interval: Duration) {
+    let (mut connection_reader, mut connection_writer) = tokio::io::split(connection);
+    let (mut far_reader, mut far_writer) = tokio::io::split(far);
+
+    // 相手からの読み取りはそのまま流す（相手が切断したら受信側にも終わりを伝える）
+    let inbound = async {
+        let _ = tokio::io::copy(&mut connection_reader, &mut far_writer).await;
+        let _ = far_writer.shutdown().await;
+    };
+
+    // 書き込みは中継し、途切れたら PING を送る（受信側が接続を閉じたら終わる）
+    let outbound = async {
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            let written = match tokio::time::timeout(interval, far_reader.read(&mut buf)).await {
+                Ok(Ok(0)) | Ok(Err(_)) => break,
+                Ok(Ok(n)) => connection_writer.write_all(&buf[..n]).await,
+                Err(_) => connection_writer.write_all(&[PING]).await,
+            };
+            if written.is_err() {
+                return;
+            }
+        }
+        let _ = connection_writer.shutdown().await;
+    };
+
+    tokio::select! {
+        _ = inbound => {}
+        _ = outbound => {}
+    }
+}
+
+// 読み書きが timeout の間進まなければエラーにするレイヤー
+//
+// 読み取りは待っている間だけ数えるため、応答を待たせている間（PING が届く）や
+// こちらが読んでいない間は切断されない
+pub struct IdleTimeout {
+    timeout: Duration,
+}
+
+impl IdleTimeout {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl Layer for IdleTimeout {
+    fn wrap(&self, connection: BoxedConnection) -> BoxedConnection {
+        Box::new(TimedConnection {
+            inner: connection,
+            timeout: self.timeout,
+            read_deadline: None,
+            write_deadline: None,
+        })
+    }
+}
+
+struct TimedConnection {
+    inner: BoxedConnection,
+    timeout: Duration,
+    read_deadline: Option<Pin<Box<Sleep>>>,
+    write_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+// 待ちが続いている間は期限を確認し、進んだら期限を外す
+fn check_deadline<T>(
+    result: Poll<io::Result<T>>,
+    deadline: &mut Option<Pin<Box<Sleep>>>,
+    timeout: Duration,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<T>> {
+    if result.is_ready() {
+        *deadline = None;
+        return result;
+    }
+    let sleep = deadline.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+    match sleep.as_mut().poll(cx) {
+        Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "相手からの応答が {} 秒ありません（接続が切れた可能性があります）",
+                timeout.as_secs()
+            ),
+        ))),
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+impl AsyncRead for TimedConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        check_deadline(result, &mut this.read_deadline, this.timeout, cx)
+    }
+}
+
+impl AsyncWrite for TimedConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        check_deadline(result, &mut this.write_deadline, this.timeout, cx)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{bandwidth::Schedule, transport::BoxedConnection};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+// 接続を包んで読み書きを変換・観測するレイヤー（速度制限など）
+pub trait Layer: Send + Sync {
+    fn wrap(&self, connection: BoxedConnection) -> BoxedConnection;
+}
+
+// 接続に順に適用するレイヤーの組（先に追加したものが内側になる）
+#[derive(Clone, Default)]
+pub struct Layers {
+    layers: Vec<Arc<dyn Layer>>,
+}
+
+impl Layers {
+    pub fn push(&mut self, layer: impl Layer + 'static) {
+        self.layers.push(Arc::new(layer));
+    }
+
+    pub fn wrap(&self, connection: BoxedConnection) -> BoxedConnection {
+        self.layers
+            .iter()
+            .fold(connection, |connection, layer| layer.wrap(connection))
+    }
+}
+
+// 送受信の速度を制限するレイヤー
+//
+// 同じ RateLimit で包んだ接続（分割転送の各ストリームなど）の読み書きの合計を
+// 予定表の今の上限以下に保つ
+pub struct RateLimit {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+// 予定表の上限が変わったかを確認する間隔
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// 読み書きしていない間に貯められる量（上限の何秒分か）。止まっていた後に上限を超えて送り続けないようにする
+const BURST_SECS: f64 = 1.0;
+
+// トークンバケット（上限の速さで貯まり、読み書きした分だけ減る。足りなければ貯まるまで待つ）
+struct Bucket {
+    schedule: Schedule,
+    // 今の上限（None なら無制限）
+    bytes_per_sec: Option<u64>,
+    checked: Instant,
+    // 読み書きできる残りのバイト数（読み書きした後は負になることがある）
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    // 時間帯が変わって上限が変わったら、その時点から数え直す
+    fn refresh(&mut self) {
+        if self.checked.elapsed() < SCHEDULE_CHECK_INTERVAL {
+            return;
+        }
+        self.checked = Instant::now();
+        let current = self.schedule.current();
+        if current != self.bytes_per_sec {
+            self.bytes_per_sec = current;
+            self.tokens = 0.0;
+            self.refilled = Instant::now();
+        }
+    }
+
+    // 前回からの経過時間の分を貯める（BURST_SECS 秒分まで）
+    fn refill(&mut self, bytes_per_sec: u64) {
+        let rate = bytes_per_sec.max(1) as f64;
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate * BURST_SECS);
+        self.refilled = now;
+    }
+
+    // 読み書きした分が貯まるまで待つ時間
+    fn delay(&mut self) -> Duration {
+        self.refresh();
+        let Some(bytes_per_sec) = self.bytes_per_sec else {
+            return Duration::ZERO;
+        };
+        self.refill(bytes_per_sec);
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / bytes_per_sec.max(1) as f64)
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        if self.bytes_per_sec.is_some() {
+            self.tokens -= bytes as f64;
+        }
+    }
+}
+
+impl RateLimit {
+    pub fn new(schedule: Schedule) -> Self {
+        let now = Instant::now();
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                bytes_per_sec: schedule.current(),
+                schedule,
+                checked: now,
+                tokens: 0.0,
+                refilled: now,
+            })),
+        }
+    }
+}
+
+impl Layer for RateLimit {
+    fn wrap(&self, connection: BoxedConnection) -> BoxedConnection {
+        Box::new(RateLimited {
+            inner: connection,
+            bucket: self.bucket.clone(),
+            sleep: None,
+        })
+    }
+}
+
+struct RateLimited {
+    inner: BoxedConnection,
+    bucket: Arc<Mutex<Bucket>>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl RateLimited {
+    // 制限を超えている間は待つ
+    fn poll_throttle(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            if let Some(sleep) = &mut self.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+            let delay = self.bucket.lock().unwrap().delay();
+            if delay.is_zero() {
+                return Poll::Ready(());
+            }
+            self.sleep = Some(Box::pin(tokio::time::sleep(delay)));
+        }
+    }
+
+    fn consume(&self, bytes: usize) {
+        self.bucket.lock().unwrap().consume(bytes);
+    }
+
+    // 1回に読み書きする量の上限（0.1秒分。大きな書き込みの後に長く止まらないようにする）
+    fn max_chunk(&self) -> usize {
+        match self.bucket.lock().unwrap().bytes_per_sec {
+            Some(bytes_per_sec) => (bytes_per_sec / 10).max(1) as usize,
+            None => usize::MAX,
+        }
+    }
+}
+
+impl AsyncRead for RateLimited {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_throttle(cx));
+
+        let len = buf.remaining().min(this.max_chunk());
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(len));
+        let result = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited));
+        let read = limited.filled().len();
+        buf.advance(read);
+        this.consume(read);
+        Poll::Ready(result)
+    }
+}
+
+impl AsyncWrite for RateLimited {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_throttle(cx));
+
+        let buf = &buf[..buf.len().min(this.max_chunk())];
+        let result = ready!(Pin::new(&mut this.inner).poll_write(cx, buf));
+        if let Ok(written) = result {
+            this.consume(written);
+        }
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,76 @@
+// ファイル転送の本体（main.rs のコマンドラインと、組み込む側のアプリから使う）
+
+pub mod acl;
+pub mod adaptive;
+pub mod audit;
+pub mod bandwidth;
+pub mod buffer;
+pub mod cancel;
+pub mod checksum;
+pub mod client;
+pub mod clipboard;
+pub mod completions;
+pub mod config;
+pub mod control;
+#[cfg(target_os = "linux")]
+pub mod dbus;
+pub mod dedup;
+pub mod desktop;
+pub mod doctor;
+pub mod encrypt;
+pub mod events;
+pub mod fec;
+#[cfg(feature = "cdylib")]
+mod ffi;
+pub mod filename;
+pub mod frame;
+pub mod gui;
+pub mod history;
+pub mod hotkey;
+pub mod integrate;
+pub mod keepalive;
+pub mod layer;
+pub mod limits;
+pub mod log;
+pub mod mime;
+pub mod multicast;
+pub mod notification;
+pub mod pairing;
+pub mod picker;
+pub mod pipeline;
+pub mod progress;
+pub mod protocol;
+pub mod proxy;
+#[cfg(feature = "python")]
+mod python;
+pub mod quarantine;
+pub mod queue;
+pub mod received;
+pub mod resume;
+pub mod review;
+pub mod scheduler;
+pub mod secrets;
+pub mod server;
+pub mod sparse;
+pub mod ssh_agent;
+pub mod ssh_config;
+pub mod template;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tls;
+pub mod token;
+pub mod tor;
+pub mod transport;
+pub mod trust;
+pub mod tui;
+pub mod udp;
+pub mod update;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+pub mod walk;
+pub mod webhook;
+pub mod wizard;
+pub mod wol;
+
+// ファイル転送用のポート
+pub const FILE_TRANSFER_PORT: u16 = 8080;
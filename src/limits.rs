@@ -0,0 +1,278 @@
+use crate::protocol::Reason;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+// 1分間の接続数の上限を超えた接続元を遮断する既定の秒数
+const DEFAULT_BAN_SECS: u64 = 5 * 60;
+
+// 設定ファイルの [client_limits] に書く、接続元ごとの上限（省略した項目は無制限）
+//
+// 同時接続数と1分間の接続数は接続元のIPアドレスごとに、転送数と受信バイト数はトークンを提示した接続ならトークンごと、
+// それ以外はIPアドレスごとに数える
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ClientLimits {
+    // 同時に開いておける接続の数
+    pub max_connections: Option<u32>,
+
+    // 直近1分間に受け付ける接続の数（超えた接続元は ban_secs の間、すべての接続を断る）
+    pub max_connections_per_minute: Option<u32>,
+
+    // max_connections_per_minute を超えた接続元を遮断する秒数（省略すると 300 秒）
+    pub ban_secs: Option<u64>,
+
+    // 直近1時間に受け付ける転送の数
+    pub max_transfers_per_hour: Option<u32>,
+
+    // 直近24時間に受信できるバイト数
+    pub max_bytes_per_day: Option<u64>,
+}
+
+// 接続元ごとの上限を超えた（応答と監査ログに含める理由）
+#[derive(Debug)]
+pub enum LimitExceeded {
+    Connections(u32),
+    // 1分間の接続数の上限を超えたため、遮断を始めた（遮断する秒数）
+    ConnectionRate {
+        limit: u32,
+        ban_secs: u64,
+    },
+    // 遮断している接続元からの接続（遮断が解けるまでの秒数）
+    Banned(u64),
+    Transfers(u32),
+    Bytes {
+        used: u64,
+        requested: u64,
+        limit: u64,
+    },
+}
+
+impl LimitExceeded {
+    // 送信側への応答の種類
+    pub fn reason(&self) -> Reason {
+        match self {
+            LimitExceeded::Connections(_)
+            | LimitExceeded::ConnectionRate { .. }
+            | LimitExceeded::Banned(_)
+            | LimitExceeded::Transfers(_) => Reason::TooManyRequests,
+            LimitExceeded::Bytes { .. } => Reason::QuotaExceeded,
+        }
+    }
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Connections(limit) => {
+                write!(f, "同時接続数の上限（{}）に達しています", limit)
+            }
+            LimitExceeded::ConnectionRate { limit, ban_secs } => write!(
+                f,
+                "1分間の接続数の上限（{}）を超えたため、{} 秒間接続を断ります",
+                limit, ban_secs
+            ),
+            LimitExceeded::Banned(remaining) => {
+                write!(
+                    f,
+                    "接続が多すぎるため遮断しています（残り {} 秒）",
+                    remaining
+                )
+            }
+            LimitExceeded::Transfers(limit) => {
+                write!(f, "1時間あたりの転送数の上限（{}）に達しています", limit)
+            }
+            LimitExceeded::Bytes {
+                used,
+                requested,
+                limit,
+            } => write!(
+                f,
+                "24時間あたりの受信量の上限を超えます ({} + {} / {} バイト)",
+                used, requested, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+// 受け付けた受信の記録
+struct Record {
+    time: Instant,
+    bytes: u64,
+    // 転送数に数えるか
+    transfer: bool,
+}
+
+#[derive(Default)]
+struct Usage {
+    connections: u32,
+    // 24時間より前の記録は捨てる
+    records: VecDeque<Record>,
+    // 直近1分間に接続してきた時刻（1分間の接続数の上限を設定した場合のみ）
+    attempts: VecDeque<Instant>,
+    // 遮断が解ける時刻
+    banned_until: Option<Instant>,
+}
+
+impl Usage {
+    fn prune(&mut self) {
+        while self
+            .records
+            .front()
+            .is_some_and(|record| record.time.elapsed() >= DAY)
+        {
+            self.records.pop_front();
+        }
+        while self
+            .attempts
+            .front()
+            .is_some_and(|time| time.elapsed() >= MINUTE)
+        {
+            self.attempts.pop_front();
+        }
+        if self
+            .banned_until
+            .is_some_and(|until| until <= Instant::now())
+        {
+            self.banned_until = None;
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.connections == 0
+            && self.records.is_empty()
+            && self.attempts.is_empty()
+            && self.banned_until.is_none()
+    }
+}
+
+// 接続元ごとの使用状況を数えて上限を適用する
+pub struct Limiter {
+    limits: ClientLimits,
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+// 数えている接続（破棄すると同時接続数から外れる）
+pub struct ConnectionPermit {
+    limiter: Arc<Limiter>,
+    peer: String,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut usage = self.limiter.usage.lock().unwrap();
+        if let Some(entry) = usage.get_mut(&self.peer) {
+            entry.connections = entry.connections.saturating_sub(1);
+            if entry.is_idle() {
+                usage.remove(&self.peer);
+            }
+        }
+    }
+}
+
+impl Limiter {
+    pub fn new(limits: ClientLimits) -> Arc<Self> {
+        Arc::new(Self {
+            limits,
+            usage: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // 接続元が遮断されている間か（接続数には数えない。TLS のハンドシェイクの前に断るのに使う）
+    pub fn is_banned(&self, peer: &str) -> bool {
+        let usage = self.usage.lock().unwrap();
+        usage
+            .get(peer)
+            .and_then(|entry| entry.banned_until)
+            .is_some_and(|until| until > Instant::now())
+    }
+
+    // 接続を受け付けたときに、接続元のIPアドレスの同時接続数と1分間の接続数に数える
+    //
+    // 1分間の接続数の上限を超えた接続元は、しばらくの間すべての接続を断る
+    pub fn connect(self: &Arc<Self>, peer: &str) -> Result<ConnectionPermit, LimitExceeded> {
+        let mut usage = self.usage.lock().unwrap();
+        let entry = usage.entry(peer.to_string()).or_default();
+        entry.prune();
+        if let Some(until) = entry.banned_until {
+            let remaining = until.saturating_duration_since(Instant::now());
+            return Err(LimitExceeded::Banned(remaining.as_secs().max(1)));
+        }
+        if let Some(limit) = self.limits.max_connections_per_minute {
+            entry.attempts.push_back(Instant::now());
+            if entry.attempts.len() > limit as usize {
+                let ban_secs = self.limits.ban_secs.unwrap_or(DEFAULT_BAN_SECS);
+                entry.banned_until = Some(Instant::now() + Duration::from_secs(ban_secs));
+                entry.attempts.clear();
+                return Err(LimitExceeded::ConnectionRate { limit, ban_secs });
+            }
+        }
+        if let Some(limit) = self.limits.max_connections {
+            if entry.connections >= limit {
+                return Err(LimitExceeded::Connections(limit));
+            }
+        }
+        entry.connections += 1;
+        Ok(ConnectionPermit {
+            limiter: self.clone(),
+            peer: peer.to_string(),
+        })
+    }
+
+    // 受信を始める前に、転送数と受信バイト数に数える（超える場合は数えずにエラー）
+    //
+    // new_transfer が false なら（分割転送の2本目以降のストリームなど）転送数には数えない
+    pub fn reserve(
+        &self,
+        client: &str,
+        bytes: u64,
+        new_transfer: bool,
+    ) -> Result<(), LimitExceeded> {
+        let mut usage = self.usage.lock().unwrap();
+        // 古い記録を捨て、使われていない接続元を忘れる
+        usage.retain(|_, usage| {
+            usage.prune();
+            !usage.is_idle()
+        });
+        let entry = usage.entry(client.to_string()).or_default();
+
+        if let (Some(limit), true) = (self.limits.max_transfers_per_hour, new_transfer) {
+            let recent = entry
+                .records
+                .iter()
+                .filter(|record| record.transfer && record.time.elapsed() < HOUR)
+                .count();
+            if recent >= limit as usize {
+                return Err(LimitExceeded::Transfers(limit));
+            }
+        }
+        if let Some(limit) = self.limits.max_bytes_per_day {
+            let used = entry.records.iter().map(|record| record.bytes).sum::<u64>();
+            if used.saturating_add(bytes) > limit {
+                return Err(LimitExceeded::Bytes {
+                    used,
+                    requested: bytes,
+                    limit,
+                });
+            }
+        }
+
+        if new_transfer || bytes > 0 {
+            entry.records.push_back(Record {
+                time: Instant::now(),
+                bytes,
+                transfer: new_transfer,
+            });
+        }
+        Ok(())
+    }
+}
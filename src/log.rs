@@ -0,0 +1,344 @@
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate};
+use serde::Deserialize;
+use std::{
+    cell::Cell,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        mpsc::Sender,
+        Mutex,
+    },
+};
+use uuid::Uuid;
+
+// 出力の転送先（TUIモードでは画面を崩さないよう、直接書かずにログ欄へ流す）
+static SINK: Mutex<Option<Sender<String>>> = Mutex::new(None);
+
+// ログファイル（設定すると標準出力・標準エラー出力の代わりにここへ書く）
+static FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+tokio::task_local! {
+    // このタスクで処理している転送のID（設定されていればログの各行の先頭に付ける）
+    static TRANSFER_ID: Cell<Option<Uuid>>;
+}
+
+// 設定ファイルの [log_file] に書く、常駐させる場合のログファイルの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogFileConfig {
+    // ログファイルのパス（--log-file で上書きできる。省略するとファイルには書かない）
+    pub path: Option<PathBuf>,
+
+    // この大きさ（バイト）を超えたら新しいファイルに切り替える
+    pub max_size: u64,
+
+    // 日付が変わったら新しいファイルに切り替える
+    pub daily: bool,
+
+    // 切り替えた古いファイルを残す数（"<path>.1" が最も新しい）
+    pub keep: u32,
+}
+
+impl Default for LogFileConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_size: 10 * 1024 * 1024,
+            daily: false,
+            keep: 5,
+        }
+    }
+}
+
+// 書き込み中のログファイル
+struct LogFile {
+    path: PathBuf,
+    config: LogFileConfig,
+    // 切り替えの途中で失敗した場合は None（次に書くときに開き直す）
+    file: Option<File>,
+    size: u64,
+    // 書き始めた日（daily の場合、この日と違う日に書くと切り替える）
+    date: NaiveDate,
+}
+
+impl LogFile {
+    fn open(path: &Path, config: &LogFileConfig) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("ログファイルのフォルダの作成に失敗: {:?}", dir))?;
+        }
+        let file = open_file(path)?;
+        let metadata = file.metadata()?;
+        // 既存のファイルに続けて書く場合は、最後に書いた日を書き始めた日とみなす
+        let date = match metadata.modified() {
+            Ok(modified) if metadata.len() > 0 => {
+                chrono::DateTime::<Local>::from(modified).date_naive()
+            }
+            _ => Local::now().date_naive(),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            config: config.clone(),
+            file: Some(file),
+            size: metadata.len(),
+            date,
+        })
+    }
+
+    fn write(&mut self, kind: Kind, line: &str) -> Result<()> {
+        let now = Local::now();
+        if self.size >= self.config.max_size || (self.config.daily && now.date_naive() != self.date)
+        {
+            self.rotate()?;
+        }
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => open_file(&self.path)?,
+        };
+        let line = format!(
+            "{} {} {}\n",
+            now.format("%Y-%m-%d %H:%M:%S"),
+            kind.label(),
+            line
+        );
+        let result = file.write_all(line.as_bytes());
+        self.file = Some(file);
+        result?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    // 古いファイルの番号を1つずつずらし、今のファイルを "<path>.1" にして新しいファイルを開く
+    //
+    // keep を超えた最も古いファイルは削除する
+    fn rotate(&mut self) -> Result<()> {
+        // 開いたままでは名前を変えられない環境があるため、先に閉じる
+        self.file = None;
+        let rotated = |index: u32| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+        if self.config.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated(self.config.keep));
+            for index in (1..self.config.keep).rev() {
+                let from = rotated(index);
+                if from.exists() {
+                    fs::rename(&from, rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))
+                .with_context(|| format!("ログファイルの切り替えに失敗: {:?}", self.path))?;
+        }
+        self.file = Some(open_file(&self.path)?);
+        self.size = 0;
+        self.date = Local::now().date_naive();
+        Ok(())
+    }
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("ログファイルを開けません: {:?}", path))
+}
+
+// 以降の出力をログファイル path に書く（大きさや日付で切り替え、古いファイルは keep 個まで残す）
+pub fn to_file(path: &Path, config: &LogFileConfig) -> Result<()> {
+    *FILE.lock().unwrap() = Some(LogFile::open(path, config)?);
+    Ok(())
+}
+
+// 出力の詳しさ（Verbosity を u8 にしたもの）
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+// 出力の詳しさ（--quiet・--verbose）
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    // エラーだけ
+    Quiet,
+    Normal,
+    // チャンク単位の送受信も
+    Verbose,
+}
+
+// 出力の種類（種類ごとに色と、表示する詳しさが決まる）
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Error,
+    Warn,
+    Success,
+    Info,
+    Debug,
+}
+
+impl Kind {
+    // この種類を表示する最低の詳しさ
+    fn verbosity(self) -> Verbosity {
+        match self {
+            Kind::Error => Verbosity::Quiet,
+            Kind::Warn | Kind::Success | Kind::Info => Verbosity::Normal,
+            Kind::Debug => Verbosity::Verbose,
+        }
+    }
+
+    // ANSI エスケープシーケンスの色（None は色を付けない）
+    fn color(self) -> Option<&'static str> {
+        match self {
+            Kind::Error => Some("31"),
+            Kind::Warn => Some("33"),
+            Kind::Success => Some("32"),
+            Kind::Info => None,
+            Kind::Debug => Some("2"),
+        }
+    }
+
+    // 標準エラー出力に書くか
+    fn is_error(self) -> bool {
+        matches!(self, Kind::Error | Kind::Warn)
+    }
+
+    // ログファイルの各行に付ける種類の表示
+    fn label(self) -> &'static str {
+        match self {
+            Kind::Error => "エラー",
+            Kind::Warn => "警告",
+            Kind::Success => "成功",
+            Kind::Info => "情報",
+            Kind::Debug => "詳細",
+        }
+    }
+}
+
+// 以降の出力の詳しさを変える
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+// kind の出力を表示するか（表示しない出力は文字列を組み立てずに済ませる）
+pub fn enabled(kind: Kind) -> bool {
+    kind.verbosity() as u8 <= VERBOSITY.load(Ordering::Relaxed)
+}
+
+// 以降の出力を sink に流す
+pub fn redirect(sink: Sender<String>) {
+    *SINK.lock().unwrap() = Some(sink);
+}
+
+// 出力先を標準出力・標準エラー出力に戻す
+pub fn restore() {
+    *SINK.lock().unwrap() = None;
+}
+
+// fut の中で、set_transfer_id で設定した転送IDをログの各行に付けられるようにする
+//
+// fut の中で新しく起動したタスクには引き継がれない
+pub async fn with_transfer_id<F: Future>(fut: F) -> F::Output {
+    TRANSFER_ID.scope(Cell::new(None), fut).await
+}
+
+// 以降にこのタスクで出力するログの各行に transfer_id を付ける（with_transfer_id の外では何もしない）
+pub fn set_transfer_id(transfer_id: Uuid) {
+    let _ = TRANSFER_ID.try_with(|current| current.set(Some(transfer_id)));
+}
+
+// このタスクで処理している転送のID
+fn transfer_id() -> Option<Uuid> {
+    TRANSFER_ID.try_with(Cell::get).ok().flatten()
+}
+
+pub fn write(kind: Kind, line: String) {
+    let line = match transfer_id() {
+        Some(transfer_id) => format!("[{}] {}", transfer_id, line),
+        None => line,
+    };
+    let line = match &*SINK.lock().unwrap() {
+        Some(sink) => match sink.send(line) {
+            Ok(()) => return,
+            Err(e) => e.0,
+        },
+        None => line,
+    };
+    if let Some(file) = &mut *FILE.lock().unwrap() {
+        match file.write(kind, &line) {
+            Ok(()) => return,
+            // 書けなければ標準エラー出力に知らせ、この行は標準出力・標準エラー出力に書く
+            Err(e) => eprintln!("ログファイルへの書き込みに失敗: {:#}", e),
+        }
+    }
+    let line = match kind.color() {
+        Some(color) if use_color(kind.is_error()) => format!("\x1b[{}m{}\x1b[0m", color, line),
+        _ => line,
+    };
+    if kind.is_error() {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+// 色を付けるか（書き込む先が端末で、NO_COLOR が設定されていない場合のみ）
+fn use_color(is_error: bool) -> bool {
+    if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return false;
+    }
+    if is_error {
+        std::io::stderr().is_terminal()
+    } else {
+        std::io::stdout().is_terminal()
+    }
+}
+
+// 出力の種類に応じて表示する（--quiet・--verbose で表示しない場合は何もしない）
+macro_rules! emit {
+    ($kind:expr, $($arg:tt)*) => {
+        if $crate::log::enabled($kind) {
+            $crate::log::write($kind, format!($($arg)*))
+        }
+    };
+}
+
+// println! の代わりに使う出力
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::emit!($crate::log::Kind::Info, $($arg)*)
+    };
+}
+
+// 成功した操作の出力（緑）
+macro_rules! success {
+    ($($arg:tt)*) => {
+        $crate::log::emit!($crate::log::Kind::Success, $($arg)*)
+    };
+}
+
+// 続けられるが注意が必要な出力（黄）
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log::emit!($crate::log::Kind::Warn, $($arg)*)
+    };
+}
+
+// eprintln! の代わりに使う出力（赤。--quiet でも表示する）
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::emit!($crate::log::Kind::Error, $($arg)*)
+    };
+}
+
+// --verbose の場合だけ表示する、チャンク単位の詳しい出力
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::emit!($crate::log::Kind::Debug, $($arg)*)
+    };
+}
+
+pub(crate) use {debug, emit, error, info, success, warn};
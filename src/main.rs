@@ -1,26 +1,24 @@
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use global_hotkey::{
-    hotkey::{Code, HotKey, Modifiers},
-    GlobalHotKeyEvent, GlobalHotKeyManager,
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use file_transfer::{
+    audit,
+    client::{run_client, run_verify, ClientArgs},
+    completions,
+    config::Config,
+    control, doctor, encrypt, gui,
+    hotkey::Action,
+    integrate::{self, AutostartCommand},
+    log::{self, Verbosity},
+    multicast, pairing, quarantine,
+    queue::{self, QueueCommand},
+    received,
+    secrets::{self, SecretCommand},
+    server::{run_server, ServerArgs},
+    token::{self, TokenCommand},
+    update, wizard,
 };
-use local_ip_address::local_ip;
-use rfd::FileDialog;
-use std::{
-    fs,
-    net::SocketAddr,
-    path::PathBuf,
-    sync::{Arc, Mutex},
-    time::Duration,
-};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
-    sync::mpsc,
-};
-
-// ファイル転送用のポート
-const FILE_TRANSFER_PORT: u16 = 8080;
+use std::{net::Ipv4Addr, path::PathBuf};
 
 // コマンドライン引数の定義
 #[derive(Parser)]
@@ -28,399 +26,352 @@ const FILE_TRANSFER_PORT: u16 = 8080;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// エラー以外を表示しない
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// チャンク単位の送受信も表示する
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// 出力を標準出力の代わりにこのファイルに書く（設定ファイルの [log_file] の path を上書き）
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// サーバーモード（ファイル受信）
-    Server {
-        /// ホットキー（例: "ctrl+shift+r"）
-        #[arg(short = 'k', long, default_value = "ctrl+shift+r")]
+    Server(ServerArgs),
+    /// クライアントモード（ファイル送信）
+    Client(ClientArgs),
+    /// 送信済みのファイル・フォルダが受信側と同じ内容かをハッシュで確かめる（送り直しはしない）
+    Verify(ClientArgs),
+    /// GUIモード（ウィンドウで送信先の選択・ドロップでの送信・受信の確認を行う）
+    Gui,
+    /// マルチキャスト送信（LAN内の多数の受信側へ同じファイルを配信）
+    MulticastSend {
+        /// 配信するファイル
+        file: PathBuf,
+
+        /// マルチキャストグループのアドレス
+        #[arg(long, default_value_t = multicast::MULTICAST_GROUP)]
+        group: Ipv4Addr,
+
+        /// マルチキャストのポート
+        #[arg(long, default_value_t = multicast::MULTICAST_PORT)]
+        port: u16,
+
+        /// 取りこぼしを補うために同じデータを繰り返す周回数
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+        rounds: u32,
+
+        /// 送信レート（KB/s）
+        #[arg(long, default_value_t = 4096, value_parser = clap::value_parser!(u64).range(1..))]
+        rate: u64,
+    },
+    /// マルチキャスト受信
+    MulticastReceive {
+        /// ファイルの保存先フォルダ
+        #[arg(long)]
+        save_dir: PathBuf,
+
+        /// マルチキャストグループのアドレス
+        #[arg(long, default_value_t = multicast::MULTICAST_GROUP)]
+        group: Ipv4Addr,
+
+        /// マルチキャストのポート
+        #[arg(long, default_value_t = multicast::MULTICAST_PORT)]
+        port: u16,
+    },
+    /// client --encrypt-with-password で送られたファイル（.age・.tar.age）を復号する（パスワードは端末で入力）
+    Decrypt {
+        /// 暗号化されたファイル
+        file: PathBuf,
+
+        /// 復号したファイル・フォルダを置くフォルダ（省略すると暗号化されたファイルと同じフォルダ）
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// 監査ログのハッシュの連鎖を検証する（改ざんや行の削除を検出）
+    AuditVerify {
+        /// 監査ログのパス
+        file: PathBuf,
+    },
+    /// 実行中のサーバー・クライアントのホットキーを再起動せずに変更する（設定ファイルにも保存）
+    Hotkey {
+        /// 変更する対象
+        #[arg(value_enum)]
+        target: control::Target,
+
+        /// 新しいホットキー（例: "ctrl+alt+r"）
         hotkey: String,
+
+        /// 変更する操作（省略するとサーバーは change-save-dir、クライアントは send-file）
+        #[arg(long, value_enum)]
+        action: Option<Action>,
     },
-    /// クライアントモード（ファイル送信）
-    Client {
-        /// サーバーのIPアドレス
+    /// 実行中のサーバーの受信の受け付けを一時停止する（新しい転送は理由を添えて断る）
+    Pause,
+    /// 一時停止したサーバーの受信の受け付けを再開する
+    Resume,
+    /// --notify で起動したサーバーが通知で確認を求めている受信を受け入れる
+    Accept {
+        /// 受け入れる転送の転送ID（通知のログに表示される）
+        transfer_id: String,
+    },
+    /// --notify で起動したサーバーが通知で確認を求めている受信を拒否する
+    Reject {
+        /// 拒否する転送の転送ID
+        transfer_id: String,
+
+        /// 送信側に伝える理由
+        #[arg(long, default_value = "")]
+        reason: String,
+    },
+    /// OSのキーチェーンに保存するシークレット（事前共有鍵やトークン）の管理
+    Secret {
+        #[command(subcommand)]
+        command: SecretCommand,
+    },
+    /// サーバーモードで受け付ける認証トークンの発行・失効
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand,
+    },
+    /// 送信先に接続できなかったため後で送るファイル（client --queue）の確認・送り直し
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommand,
+    },
+    /// 受信したファイルを索引から検索し、保存した場所（移動されていれば今の場所）を表示する
+    Search {
+        /// ファイル名・保存先のパス・送信元・メモ・ハッシュに含まれる文字列（大文字・小文字は区別しない）
+        query: String,
+    },
+    /// 隔離フォルダ（server --quarantine）に受信した転送を保存先に移す（隔離IDを省略すると一覧を表示する）
+    Release {
+        /// 移す転送の隔離ID（一覧に表示される。他と区別できれば先頭の一部でもよい）
+        id: Option<String>,
+
+        /// 保存先に移す代わりに、隔離したファイルを削除する
+        #[arg(long, requires = "id")]
+        discard: bool,
+    },
+    /// シェルの補完スクリプトを出力する（例: source <(file-transfer completions bash)）
+    Completions {
+        /// 対象のシェル
+        #[arg(value_enum, required_unless_present = "peers")]
+        shell: Option<Shell>,
+
+        /// 補完の候補にする送信先を出力する（補完スクリプトから呼び出す）
+        #[arg(long, hide = true, conflicts_with = "shell")]
+        peers: bool,
+    },
+    /// 動作環境を診断する（ポート・ファイアウォール・mDNS・ホットキー・画面・設定ファイル）
+    Doctor {
+        /// サーバーモードで待ち受けるポート
+        #[arg(long, default_value_t = file_transfer::FILE_TRANSFER_PORT)]
+        port: u16,
+    },
+    /// ファイルマネージャーの右クリックメニューに「file-transfer で送信」を登録する
+    Integrate {
+        /// 送信先（省略すると設定ファイルの peers の先頭）
         #[arg(short, long)]
         server: Option<String>,
 
-        /// ホットキー（例: "ctrl+shift+s"）
-        #[arg(short = 'k', long, default_value = "ctrl+shift+s")]
-        hotkey: String,
+        /// メニューの代わりに、ペアリング用のリンク（filetransfer://）を開く URL スキームを登録する
+        #[arg(long, conflicts_with = "server")]
+        url_scheme: bool,
+
+        /// 登録したメニューと URL スキームを削除する
+        #[arg(long, conflicts_with_all = ["server", "url_scheme"])]
+        uninstall: bool,
+    },
+    /// ログインしたときにサーバーモードを起動するよう登録する・登録を削除する
+    Autostart {
+        #[command(subcommand)]
+        command: AutostartCommand,
+    },
+    /// ペアリング用のリンク（filetransfer://）を開き、リンクの送信先への送信か受信を開始する
+    Open {
+        /// リンク（例: "filetransfer://192.168.1.10:8080?token=..."）
+        url: String,
+    },
+    /// 最新のリリースに更新する（署名を検証してから実行中のバイナリを置き換える）
+    SelfUpdate {
+        /// 新しいバージョンがあるか確認するだけで更新しない
+        #[arg(long)]
+        check: bool,
     },
 }
 
-// ホットキー文字列をパースする関数
-fn parse_hotkey(hotkey_str: &str) -> Result<HotKey> {
-    let parts: Vec<&str> = hotkey_str.split('+').collect();
-    let mut modifiers = Modifiers::empty();
-    let mut code = None;
-
-    for part in parts {
-        match part.trim().to_lowercase().as_str() {
-            "ctrl" | "control" => modifiers |= Modifiers::CONTROL,
-            "shift" => modifiers |= Modifiers::SHIFT,
-            "alt" => modifiers |= Modifiers::ALT,
-            "meta" | "cmd" | "command" | "win" | "windows" => modifiers |= Modifiers::META,
-            key => {
-                // キーコードの解析
-                code = Some(match key {
-                    "a" => Code::KeyA,
-                    "b" => Code::KeyB,
-                    "c" => Code::KeyC,
-                    "d" => Code::KeyD,
-                    "e" => Code::KeyE,
-                    "f" => Code::KeyF,
-                    "g" => Code::KeyG,
-                    "h" => Code::KeyH,
-                    "i" => Code::KeyI,
-                    "j" => Code::KeyJ,
-                    "k" => Code::KeyK,
-                    "l" => Code::KeyL,
-                    "m" => Code::KeyM,
-                    "n" => Code::KeyN,
-                    "o" => Code::KeyO,
-                    "p" => Code::KeyP,
-                    "q" => Code::KeyQ,
-                    "r" => Code::KeyR,
-                    "s" => Code::KeyS,
-                    "t" => Code::KeyT,
-                    "u" => Code::KeyU,
-                    "v" => Code::KeyV,
-                    "w" => Code::KeyW,
-                    "x" => Code::KeyX,
-                    "y" => Code::KeyY,
-                    "z" => Code::KeyZ,
-                    "0" => Code::Digit0,
-                    "1" => Code::Digit1,
-                    "2" => Code::Digit2,
-                    "3" => Code::Digit3,
-                    "4" => Code::Digit4,
-                    "5" => Code::Digit5,
-                    "6" => Code::Digit6,
-                    "7" => Code::Digit7,
-                    "8" => Code::Digit8,
-                    "9" => Code::Digit9,
-                    "f1" => Code::F1,
-                    "f2" => Code::F2,
-                    "f3" => Code::F3,
-                    "f4" => Code::F4,
-                    "f5" => Code::F5,
-                    "f6" => Code::F6,
-                    "f7" => Code::F7,
-                    "f8" => Code::F8,
-                    "f9" => Code::F9,
-                    "f10" => Code::F10,
-                    "f11" => Code::F11,
-                    "f12" => Code::F12,
-                    _ => anyhow::bail!("不明なキーコード: {}", key),
-                });
-            }
-        }
+#[tokio::main]
+async fn main() -> Result<()> {
+    // コマンドライン引数の確認
+    let args: Vec<String> = std::env::args().collect();
+    let cli = (args.len() > 1).then(Cli::parse);
+    if let Some(cli) = &cli {
+        log::set_verbosity(if cli.quiet {
+            Verbosity::Quiet
+        } else if cli.verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        });
     }
 
-    if let Some(code) = code {
-        Ok(HotKey::new(Some(modifiers), code))
-    } else {
-        anyhow::bail!("キーコードが指定されていません")
+    // 設定ファイルの誤りも診断できるよう、doctor は設定ファイルを読み込む前に実行する
+    if let Some(Cli {
+        command: Commands::Doctor { port },
+        ..
+    }) = &cli
+    {
+        return doctor::run(*port).await;
     }
-}
-
-// サーバーモード（ファイル受信）の実装
-async fn run_server(hotkey_str: &str) -> Result<()> {
-    println!("サーバーモード（ファイル受信）を開始します");
-    println!("ホットキー: {}", hotkey_str);
-
-    // ローカルIPアドレスの取得
-    let ip = local_ip()?;
-    println!("ローカルIPアドレス: {}", ip);
-
-    // TCPリスナーの作成
-    let addr = SocketAddr::from(([0, 0, 0, 0], FILE_TRANSFER_PORT));
-    let listener = TcpListener::bind(addr).await?;
-    println!("ポート {} でリッスン中", FILE_TRANSFER_PORT);
 
-    // ホットキーマネージャーの初期化
-    let hotkey_manager = GlobalHotKeyManager::new().unwrap();
-    let hotkey = parse_hotkey(hotkey_str)?;
-    hotkey_manager.register(hotkey).unwrap();
+    // 設定ファイルの読み込み
+    let config = Config::load()?;
 
-    // ファイル保存先の共有状態
-    let save_path = Arc::new(Mutex::new(None::<PathBuf>));
-    let save_path_clone = save_path.clone();
-
-    // ホットキーイベントの監視
-    let hotkey_channel = GlobalHotKeyEvent::receiver();
-
-    // 接続処理用のチャネル
-    let (tx, mut rx) = mpsc::channel::<TcpStream>(10);
-    let tx_clone = tx.clone();
+    // ログファイル（--log-file、なければ設定ファイルの [log_file] の path）
+    let log_file = cli.as_ref().and_then(|cli| cli.log_file.clone());
+    if let Some(path) = log_file.or_else(|| config.log_file.path.clone()) {
+        log::to_file(&path, &config.log_file)?;
+    }
 
-    // 接続受付ループ
-    tokio::spawn(async move {
-        loop {
-            match listener.accept().await {
-                Ok((socket, addr)) => {
-                    println!("新しい接続: {}", addr);
-                    if let Err(e) = tx_clone.send(socket).await {
-                        eprintln!("ソケットの送信に失敗: {}", e);
-                    }
-                }
-                Err(e) => {
-                    eprintln!("接続の受付に失敗: {}", e);
-                }
+    if let Some(cli) = cli {
+        // 引数がある場合は通常のCLIモード
+        match &cli.command {
+            Commands::Server(args) => {
+                run_server(args, &config).await?;
             }
-        }
-    });
-
-    println!("ファイル転送サーバーを起動しました");
-    println!("ホットキー {} を押すと保存先を選択できます", hotkey_str);
-
-    // メインループ
-    loop {
-        // ホットキーイベントの確認
-        if let Ok(event) = hotkey_channel.try_recv() {
-            if event.id == hotkey.id() {
-                println!("ホットキーが押されました");
-
-                // 保存先の選択
-                if let Some(path) = FileDialog::new()
-                    .set_title("ファイルの保存先フォルダを選択")
-                    .pick_folder()
-                {
-                    println!("保存先を選択: {:?}", path);
-                    *save_path.lock().unwrap() = Some(path);
-                }
+            Commands::Client(args) => {
+                run_client(args, &config).await?;
             }
-        }
-
-        // 新しい接続の確認
-        if let Ok(mut socket) = rx.try_recv() {
-            println!("ファイル転送の開始");
-
-            // 保存先の確認
-            let save_dir = save_path_clone.lock().unwrap().clone();
-
-            if let Some(save_dir) = save_dir {
-                // ファイル名とデータの受信
-                let mut filename_len_buf = [0u8; 4];
-                if let Err(e) = socket.read_exact(&mut filename_len_buf).await {
-                    eprintln!("ファイル名の長さの読み取りに失敗: {}", e);
-                    continue;
-                }
-                let filename_len = u32::from_be_bytes(filename_len_buf) as usize;
-
-                let mut filedata_len_buf = [0u8; 4];
-                if let Err(e) = socket.read_exact(&mut filedata_len_buf).await {
-                    eprintln!("ファイルデータの長さの読み取りに失敗: {}", e);
-                    continue;
-                }
-                let filedata_len = u32::from_be_bytes(filedata_len_buf) as usize;
-
-                let mut filename_buf = vec![0u8; filename_len];
-                if let Err(e) = socket.read_exact(&mut filename_buf).await {
-                    eprintln!("ファイル名の読み取りに失敗: {}", e);
-                    continue;
-                }
-                let filename = match String::from_utf8(filename_buf) {
-                    Ok(name) => name,
-                    Err(e) => {
-                        eprintln!("ファイル名のUTF-8変換に失敗: {}", e);
-                        continue;
-                    }
+            Commands::Verify(args) => {
+                run_verify(args, &config).await?;
+            }
+            Commands::Gui => {
+                // ウィンドウのイベントループはメインスレッドで動かす必要がある
+                gui::run_gui(&config)?;
+            }
+            Commands::MulticastSend {
+                file,
+                group,
+                port,
+                rounds,
+                rate,
+            } => {
+                multicast::send(file, *group, *port, *rounds, *rate).await?;
+            }
+            Commands::MulticastReceive {
+                save_dir,
+                group,
+                port,
+            } => {
+                multicast::receive(save_dir, *group, *port).await?;
+            }
+            Commands::Decrypt { file, output } => {
+                let path = encrypt::run_decrypt(file, output.as_deref())?;
+                println!("復号しました: {:?}", path);
+            }
+            Commands::AuditVerify { file } => {
+                let (count, _) = audit::verify(file)?;
+                println!(
+                    "監査ログを検証しました: {} 件の記録に問題はありません",
+                    count
+                );
+            }
+            Commands::Hotkey {
+                target,
+                hotkey,
+                action,
+            } => {
+                let command = match action {
+                    Some(action) => format!("hotkey {} {}", action.key(), hotkey),
+                    None => format!("hotkey {}", hotkey),
                 };
-
-                let mut filedata = vec![0u8; filedata_len];
-                if let Err(e) = socket.read_exact(&mut filedata).await {
-                    eprintln!("ファイルデータの読み取りに失敗: {}", e);
-                    continue;
-                }
-
-                // ファイルの保存
-                let save_path = save_dir.join(&filename);
-                if let Err(e) = fs::write(&save_path, &filedata) {
-                    eprintln!("ファイルの保存に失敗: {}", e);
-                } else {
-                    println!("ファイルを保存しました: {:?}", save_path);
-
-                    // 成功応答の送信
-                    let response = "OK".as_bytes();
-                    if let Err(e) = socket.write_all(response).await {
-                        eprintln!("応答の送信に失敗: {}", e);
-                    }
-                }
-            } else {
-                eprintln!("保存先が選択されていません");
-
-                // エラー応答の送信
-                let response = "ERROR: No save directory selected".as_bytes();
-                if let Err(e) = socket.write_all(response).await {
-                    eprintln!("エラー応答の送信に失敗: {}", e);
-                }
+                control::send(*target, &command).await?;
+                println!("ホットキーを {} に変更しました", hotkey);
             }
-        }
-
-        tokio::time::sleep(Duration::from_millis(100)).await;
-    }
-}
-
-// クライアントモード（ファイル送信）の実装
-async fn run_client(server_opt: Option<String>, hotkey_str: &str) -> Result<()> {
-    println!("クライアントモード（ファイル送信）を開始します");
-    println!("ホットキー: {}", hotkey_str);
-
-    // サーバーアドレスの設定
-    let server_addr = if let Some(server) = server_opt {
-        format!("{}:{}", server, FILE_TRANSFER_PORT)
-    } else {
-        format!("localhost:{}", FILE_TRANSFER_PORT)
-    };
-
-    println!("サーバーアドレス: {}", server_addr);
-
-    // ホットキーマネージャーの初期化
-    let hotkey_manager = GlobalHotKeyManager::new().unwrap();
-    let hotkey = parse_hotkey(hotkey_str)?;
-    hotkey_manager.register(hotkey).unwrap();
-
-    // ホットキーイベントの監視
-    let hotkey_channel = GlobalHotKeyEvent::receiver();
-
-    println!("ファイル転送クライアントを起動しました");
-    println!("ホットキー {} を押すとファイルを選択できます", hotkey_str);
-
-    // メインループ
-    loop {
-        // ホットキーイベントの確認
-        if let Ok(event) = hotkey_channel.try_recv() {
-            if event.id == hotkey.id() {
-                println!("ホットキーが押されました");
-
-                // ファイルの選択
-                if let Some(path) = FileDialog::new()
-                    .set_title("送信するファイルを選択")
-                    .pick_file()
-                {
-                    println!("ファイルを選択: {:?}", path);
-
-                    // ファイル転送の実行
-                    if let Err(e) = send_file(&server_addr, &path).await {
-                        eprintln!("ファイル転送に失敗: {}", e);
-                    }
+            Commands::Pause => {
+                control::send(control::Target::Server, "pause").await?;
+                println!("受信の受け付けを一時停止しました");
+            }
+            Commands::Resume => {
+                control::send(control::Target::Server, "resume").await?;
+                println!("受信の受け付けを再開しました");
+            }
+            Commands::Accept { transfer_id } => {
+                control::send(control::Target::Server, &format!("accept {}", transfer_id)).await?;
+                println!("受信を受け入れました");
+            }
+            Commands::Reject {
+                transfer_id,
+                reason,
+            } => {
+                control::send(
+                    control::Target::Server,
+                    &format!("reject {} {}", transfer_id, reason),
+                )
+                .await?;
+                println!("受信を拒否しました");
+            }
+            Commands::Secret { command } => {
+                secrets::run(command)?;
+            }
+            Commands::Token { command } => {
+                token::run(command, &config)?;
+            }
+            Commands::Queue { command } => {
+                queue::run(command, &config).await?;
+            }
+            Commands::Search { query } => {
+                received::run_search(query)?;
+            }
+            Commands::Release { id, discard } => {
+                quarantine::run(id.as_deref(), *discard)?;
+            }
+            Commands::Integrate {
+                server,
+                url_scheme,
+                uninstall,
+            } => {
+                if *uninstall {
+                    integrate::uninstall()?;
+                } else if *url_scheme {
+                    integrate::install_url_scheme()?;
+                } else {
+                    let server = server
+                        .as_ref()
+                        .or(config.peers.first())
+                        .context("送信先を --server で指定してください")?;
+                    integrate::install(server)?;
                 }
             }
-        }
-
-        tokio::time::sleep(Duration::from_millis(100)).await;
-    }
-}
-
-// ファイル送信関数
-async fn send_file(server_addr: &str, file_path: &PathBuf) -> Result<()> {
-    println!("ファイル転送を開始: {:?}", file_path);
-
-    // サーバーに接続
-    let mut socket = TcpStream::connect(server_addr).await?;
-    println!("サーバーに接続しました");
-
-    // ファイル名の取得
-    let filename = file_path
-        .file_name()
-        .context("ファイル名の取得に失敗")?
-        .to_string_lossy()
-        .into_owned();
-
-    // ファイルデータの読み込み
-    let filedata = fs::read(file_path)?;
-
-    // ファイル名の長さを送信
-    let filename_len = filename.len() as u32;
-    socket.write_all(&filename_len.to_be_bytes()).await?;
-
-    // ファイルデータの長さを送信
-    let filedata_len = filedata.len() as u32;
-    socket.write_all(&filedata_len.to_be_bytes()).await?;
-
-    // ファイル名を送信
-    socket.write_all(filename.as_bytes()).await?;
-    println!("ファイル名を送信: {}", filename);
-
-    // ファイルデータを送信
-    socket.write_all(&filedata).await?;
-    println!("ファイルデータを送信: {} バイト", filedata.len());
-
-    // 応答の受信
-    let mut response = [0u8; 1024];
-    let n = socket.read(&mut response).await?;
-    let response_str = String::from_utf8_lossy(&response[..n]);
-    println!("サーバーからの応答: {}", response_str);
-
-    println!("ファイル転送が完了しました");
-
-    Ok(())
-}
-
-// 対話的にモードを選択する関数
-async fn interactive_mode() -> Result<()> {
-    println!("ファイル転送プログラム");
-    println!("=====================");
-    println!("1. サーバーモード（ファイル受信）");
-    println!("2. クライアントモード（ファイル送信）");
-    println!("選択してください (1/2): ");
-
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input)?;
-
-    match input.trim() {
-        "1" => {
-            println!("サーバーモードを選択しました");
-            println!("ホットキー: ctrl+shift+r");
-            run_server("ctrl+shift+r").await?;
-        }
-        "2" => {
-            println!("クライアントモードを選択しました");
-            println!("サーバーのIPアドレスを入力してください: ");
-
-            let mut server_ip = String::new();
-            std::io::stdin().read_line(&mut server_ip)?;
-            let server_ip = server_ip.trim().to_string();
-
-            if server_ip.is_empty() {
-                println!("IPアドレスが入力されていません。localhostを使用します。");
-                run_client(None, "ctrl+shift+s").await?;
-            } else {
-                println!("サーバーIPアドレス: {}", server_ip);
-                run_client(Some(server_ip), "ctrl+shift+s").await?;
+            Commands::Autostart { command } => {
+                integrate::autostart(command, &config)?;
             }
-        }
-        _ => {
-            println!("無効な選択です。プログラムを終了します。");
-        }
-    }
-
-    Ok(())
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    // コマンドライン引数の確認
-    let args: Vec<String> = std::env::args().collect();
-
-    if args.len() <= 1 {
-        // 引数がない場合は対話モード
-        interactive_mode().await?;
-    } else {
-        // 引数がある場合は通常のCLIモード
-        let cli = Cli::parse();
-
-        match &cli.command {
-            Commands::Server { hotkey } => {
-                run_server(hotkey).await?;
+            Commands::Open { url } => {
+                pairing::open(url, &config).await?;
             }
-            Commands::Client { server, hotkey } => {
-                run_client(server.clone(), hotkey).await?;
+            Commands::SelfUpdate { check } => {
+                update::run(&config, *check).await?;
             }
+            Commands::Completions { shell, .. } => match shell {
+                Some(shell) => {
+                    completions::generate(*shell, &mut Cli::command(), &mut std::io::stdout())?
+                }
+                // shell を省略できるのは --peers を指定した場合のみ
+                None => completions::print_peers(&config),
+            },
+            // 設定ファイルを読み込む前に実行済み
+            Commands::Doctor { .. } => {}
         }
+    } else {
+        // 引数がない場合はセットアップウィザード
+        wizard::run(&config).await?;
     }
 
     Ok(())
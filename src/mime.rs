@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use std::{fs::File, io::Read, path::Path};
+
+// 種類の判定に読むファイルの先頭の長さ（tar の "ustar" は 257 バイト目から）
+const SNIFF_LEN: usize = 512;
+
+// 内容の先頭のバイト列（マジックナンバー）で判定できるファイルの種類
+pub struct Kind {
+    pub mime: &'static str,
+    // この種類の内容に付く拡張子（小文字。"" は拡張子なし）
+    pub extensions: &'static [&'static str],
+    // 実行できるプログラムか
+    pub executable: bool,
+    // いずれかの（オフセット, バイト列）の組がすべて一致すればこの種類
+    signatures: &'static [&'static [(usize, &'static [u8])]],
+}
+
+impl Kind {
+    fn matches(&self, head: &[u8]) -> bool {
+        self.signatures.iter().any(|signature| {
+            signature
+                .iter()
+                .all(|(offset, bytes)| head.get(*offset..offset + bytes.len()) == Some(*bytes))
+        })
+    }
+}
+
+const KINDS: &[Kind] = &[
+    Kind {
+        mime: "application/pdf",
+        extensions: &["pdf"],
+        executable: false,
+        signatures: &[&[(0, b"%PDF-")]],
+    },
+    Kind {
+        mime: "image/png",
+        extensions: &["png"],
+        executable: false,
+        signatures: &[&[(0, b"\x89PNG\r\n\x1a\n")]],
+    },
+    Kind {
+        mime: "image/jpeg",
+        extensions: &["jpg", "jpeg", "jpe", "jfif"],
+        executable: false,
+        signatures: &[&[(0, b"\xff\xd8\xff")]],
+    },
+    Kind {
+        mime: "image/gif",
+        extensions: &["gif"],
+        executable: false,
+        signatures: &[&[(0, b"GIF87a")], &[(0, b"GIF89a")]],
+    },
+    Kind {
+        mime: "image/webp",
+        extensions: &["webp"],
+        executable: false,
+        signatures: &[&[(0, b"RIFF"), (8, b"WEBP")]],
+    },
+    Kind {
+        mime: "video/mp4",
+        extensions: &["mp4", "m4v", "m4a", "heic", "heif", "avif", "3gp"],
+        executable: false,
+        signatures: &[&[(4, b"ftyp")]],
+    },
+    // Office の文書・EPUB・Java のアーカイブなども ZIP の形式
+    Kind {
+        mime: "application/zip",
+        extensions: &[
+            "zip", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub", "jar", "apk", "ipa",
+            "vsix", "whl", "nupkg", "xpi",
+        ],
+        executable: false,
+        signatures: &[&[(0, b"PK\x03\x04")], &[(0, b"PK\x05\x06")]],
+    },
+    Kind {
+        mime: "application/gzip",
+        extensions: &["gz", "tgz"],
+        executable: false,
+        signatures: &[&[(0, b"\x1f\x8b")]],
+    },
+    Kind {
+        mime: "application/x-7z-compressed",
+        extensions: &["7z"],
+        executable: false,
+        signatures: &[&[(0, b"7z\xbc\xaf\x27\x1c")]],
+    },
+    Kind {
+        mime: "application/vnd.rar",
+        extensions: &["rar"],
+        executable: false,
+        signatures: &[&[(0, b"Rar!\x1a\x07")]],
+    },
+    Kind {
+        mime: "application/x-tar",
+        extensions: &["tar"],
+        executable: false,
+        signatures: &[&[(257, b"ustar")]],
+    },
+    Kind {
+        mime: "application/vnd.microsoft.portable-executable",
+        extensions: &["exe", "dll", "sys", "scr", "cpl", "ocx", "efi"],
+        executable: true,
+        signatures: &[&[(0, b"MZ")]],
+    },
+    Kind {
+        mime: "application/x-executable",
+        extensions: &["", "so", "ko", "elf", "appimage"],
+        executable: true,
+        signatures: &[&[(0, b"\x7fELF")]],
+    },
+    Kind {
+        mime: "application/x-mach-binary",
+        extensions: &["", "dylib"],
+        executable: true,
+        signatures: &[
+            &[(0, b"\xfe\xed\xfa\xce")],
+            &[(0, b"\xfe\xed\xfa\xcf")],
+            &[(0, b"\xce\xfa\xed\xfe")],
+            &[(0, b"\xcf\xfa\xed\xfe")],
+        ],
+    },
+];
+
+// 先頭のバイト列から種類を判定する（判定できなければ None）
+pub fn sniff(head: &[u8]) -> Option<&'static Kind> {
+    KINDS.iter().find(|kind| kind.matches(head))
+}
+
+// 拡張子（小文字）から、内容で判定できる種類を返す
+fn kind_of_extension(extension: &str) -> Option<&'static Kind> {
+    KINDS
+        .iter()
+        .find(|kind| !extension.is_empty() && kind.extensions.contains(&extension))
+}
+
+// ファイル名の拡張子（小文字。なければ ""）と、その手前の拡張子（"invoice.pdf.exe" の "pdf"）
+fn extensions_of(filename: &str) -> (String, Option<String>) {
+    // 先頭の "." は隠しファイルの印で、拡張子の区切りではない
+    let mut parts = filename.trim_start_matches('.').rsplit('.');
+    let last = parts.next().unwrap_or_default().to_ascii_lowercase();
+    match (parts.next(), parts.next()) {
+        (None, _) => (String::new(), None),
+        (Some(_), None) => (last, None),
+        (Some(inner), Some(_)) => (last, Some(inner.to_ascii_lowercase())),
+    }
+}
+
+// 表示の向きを変えて拡張子を偽れる制御文字（"invoice\u{202e}fdp.exe" が "invoiceexe.pdf" に見える）
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' | '\u{200e}' | '\u{200f}')
+}
+
+// 受信したファイル（path）の内容が、ファイル名と送信側が申告した MIME タイプに合っているか
+//
+// 合っていなければ理由を返す。内容で判定できない種類（テキストなど）は、
+// 拡張子が判定できる種類を示す場合と実行ファイルの場合だけを疑う
+pub fn check(path: &Path, filename: &str, declared: Option<&str>) -> Result<Option<String>> {
+    if filename.chars().any(is_bidi_control) {
+        return Ok(Some(
+            "ファイル名に表示の向きを変える制御文字が含まれています".to_string(),
+        ));
+    }
+
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)
+        .and_then(|file| file.take(SNIFF_LEN as u64).read_to_end(&mut head))
+        .with_context(|| format!("受信したファイルを読めません: {:?}", path))?;
+    if head.is_empty() {
+        return Ok(None);
+    }
+    let sniffed = sniff(&head);
+    let (extension, inner) = extensions_of(filename);
+
+    if let Some(kind) = sniffed.filter(|kind| kind.executable) {
+        if !kind.extensions.contains(&extension.as_str()) {
+            return Ok(Some(format!(
+                "内容は実行ファイル（{}）ですが、拡張子が \".{}\" です",
+                kind.mime, extension
+            )));
+        }
+        if let Some(disguise) = inner.as_deref().and_then(kind_of_extension) {
+            return Ok(Some(format!(
+                "実行ファイルに {} に見せかける二重の拡張子が付いています",
+                disguise.mime
+            )));
+        }
+    }
+
+    if let Some(expected) = kind_of_extension(&extension) {
+        if sniffed.map(|kind| kind.mime) != Some(expected.mime) {
+            return Ok(Some(format!(
+                "拡張子 \".{}\" は {} ですが、内容は {} です",
+                extension,
+                expected.mime,
+                sniffed.map_or("不明な形式", |kind| kind.mime)
+            )));
+        }
+    }
+
+    // 申告された MIME タイプは、内容で判定できる種類の場合だけ照合する
+    let declared = declared.map(|mime| {
+        mime.split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    });
+    if let (Some(declared), Some(sniffed)) = (declared, sniffed) {
+        let known = KINDS.iter().any(|kind| kind.mime == declared);
+        if known && declared != sniffed.mime {
+            return Ok(Some(format!(
+                "申告された種類は {} ですが、内容は {} です",
+                declared, sniffed.mime
+            )));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,375 @@
+use crate::{
+    log::{error, info, success, warn},
+    server, template,
+};
+use anyhow::{Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Seek, SeekFrom, Write},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio::net::UdpSocket;
+use uuid::Uuid;
+
+// マルチキャスト配信の既定グループとポート
+pub const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 80, 80);
+pub const MULTICAST_PORT: u16 = 8081;
+
+// パケットの識別子
+const MAGIC: &[u8; 4] = b"FTMC";
+
+// 1パケットに載せるデータ量（一般的なMTUに収まる大きさ）
+const CHUNK_SIZE: usize = 1200;
+
+// ファイル情報パケットを挟む間隔（途中参加した受信側のため）
+const INFO_INTERVAL: u32 = 64;
+
+// 受け付けるファイルの大きさとチャンク数の上限（チャンクごとの受信済みの印をメモリに持つため）
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+const MAX_CHUNK_COUNT: u64 = 16 * 1024 * 1024;
+
+// 同時に受信するファイルの数の上限
+const MAX_INCOMING: usize = 16;
+
+// 受信を終えた（または無視すると決めた）転送IDを覚えておく数（超えたら忘れる）
+const MAX_COMPLETED: usize = 4096;
+
+// この間パケットが届かなかった受信中のファイルは破棄する
+const INCOMING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+const KIND_INFO: u8 = 0;
+const KIND_DATA: u8 = 1;
+
+// 受信したパケットの内容
+enum Packet<'a> {
+    Info {
+        transfer_id: Uuid,
+        file_size: u64,
+        chunk_size: u32,
+        filename: String,
+    },
+    Data {
+        transfer_id: Uuid,
+        index: u32,
+        payload: &'a [u8],
+    },
+}
+
+fn parse_packet(buf: &[u8]) -> Option<Packet<'_>> {
+    if buf.len() < 21 || &buf[..4] != MAGIC {
+        return None;
+    }
+    let transfer_id = Uuid::from_slice(&buf[4..20]).ok()?;
+    let body = &buf[21..];
+
+    match buf[20] {
+        KIND_INFO => {
+            let file_size = u64::from_be_bytes(body.get(..8)?.try_into().ok()?);
+            let chunk_size = u32::from_be_bytes(body.get(8..12)?.try_into().ok()?);
+            let name_len = u16::from_be_bytes(body.get(12..14)?.try_into().ok()?) as usize;
+            let filename = String::from_utf8(body.get(14..14 + name_len)?.to_vec()).ok()?;
+            Some(Packet::Info {
+                transfer_id,
+                file_size,
+                chunk_size,
+                filename,
+            })
+        }
+        KIND_DATA => {
+            let index = u32::from_be_bytes(body.get(..4)?.try_into().ok()?);
+            Some(Packet::Data {
+                transfer_id,
+                index,
+                payload: &body[4..],
+            })
+        }
+        _ => None,
+    }
+}
+
+fn packet_prefix(transfer_id: &Uuid, kind: u8) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(CHUNK_SIZE + 32);
+    packet.extend_from_slice(MAGIC);
+    packet.extend_from_slice(transfer_id.as_bytes());
+    packet.push(kind);
+    packet
+}
+
+// ファイルをマルチキャストで配信する
+//
+// 受信側からの再送要求は受け付けず、同じデータを rounds 回繰り返し送る
+// （途中参加や取りこぼしは次の周回で埋まる）
+pub async fn send(
+    file_path: &Path,
+    group: Ipv4Addr,
+    port: u16,
+    rounds: u32,
+    rate_kbps: u64,
+) -> Result<()> {
+    let filename = file_path
+        .file_name()
+        .context("ファイル名の取得に失敗")?
+        .to_string_lossy()
+        .into_owned();
+    let filedata = fs::read(file_path)?;
+    let chunk_count = filedata.len().div_ceil(CHUNK_SIZE).max(1) as u32;
+    let transfer_id = Uuid::new_v4();
+
+    let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).await?;
+    socket.set_multicast_ttl_v4(1)?;
+    let target = SocketAddr::V4(SocketAddrV4::new(group, port));
+
+    let mut info = packet_prefix(&transfer_id, KIND_INFO);
+    info.extend_from_slice(&(filedata.len() as u64).to_be_bytes());
+    info.extend_from_slice(&(CHUNK_SIZE as u32).to_be_bytes());
+    info.extend_from_slice(&(filename.len() as u16).to_be_bytes());
+    info.extend_from_slice(filename.as_bytes());
+
+    // 送信レートから1パケットあたりの待ち時間を求める
+    let packet_interval = Duration::from_secs_f64(CHUNK_SIZE as f64 / (rate_kbps as f64 * 1024.0));
+
+    info!(
+        "マルチキャスト配信を開始: {} ({} バイト) -> {}",
+        filename,
+        filedata.len(),
+        target
+    );
+
+    for round in 1..=rounds {
+        info!("配信 {}/{} 周目", round, rounds);
+        for index in 0..chunk_count {
+            if index % INFO_INTERVAL == 0 {
+                socket.send_to(&info, target).await?;
+            }
+
+            let start = index as usize * CHUNK_SIZE;
+            let end = (start + CHUNK_SIZE).min(filedata.len());
+            let mut packet = packet_prefix(&transfer_id, KIND_DATA);
+            packet.extend_from_slice(&index.to_be_bytes());
+            packet.extend_from_slice(&filedata[start..end]);
+            socket.send_to(&packet, target).await?;
+
+            tokio::time::sleep(packet_interval).await;
+        }
+    }
+
+    success!("マルチキャスト配信が完了しました");
+
+    Ok(())
+}
+
+// 受信中のファイル（転送IDごと）
+struct IncomingFile {
+    file: fs::File,
+    partial_path: PathBuf,
+    final_path: PathBuf,
+    file_size: u64,
+    chunk_size: u32,
+    received: Vec<bool>,
+    remaining: usize,
+    // 最後にパケットが届いた時刻
+    updated_at: Instant,
+}
+
+impl IncomingFile {
+    // ファイル情報パケットから受信を始める（保存先のパスは TCP の受信と同じ規則で求める）
+    fn create(save_dir: &Path, filename: &str, file_size: u64, chunk_size: u32) -> Result<Self> {
+        if chunk_size == 0 || chunk_size as usize > CHUNK_SIZE {
+            anyhow::bail!("チャンクの大きさが不正です: {}", chunk_size);
+        }
+        let chunk_count = file_size.div_ceil(chunk_size as u64).max(1);
+        if file_size > MAX_FILE_SIZE || chunk_count > MAX_CHUNK_COUNT {
+            anyhow::bail!("ファイルが大きすぎます: {} バイト", file_size);
+        }
+        let relative = template::expand(template::DEFAULT_SAVE_TEMPLATE, "", filename)?;
+        if relative.file_name().is_none() {
+            anyhow::bail!("ファイル名が空です");
+        }
+        // 認証のない配信で既にあるファイルを書き換えられないよう、同じ名前のファイルがあれば受信しない
+        server::refuse_links(save_dir, &relative)?;
+        let final_path = template::join(save_dir, &relative)?;
+        if final_path.symlink_metadata().is_ok() {
+            anyhow::bail!(
+                "保存先に同じ名前のファイルがあるため受信しません: {:?}",
+                final_path
+            );
+        }
+        let partial_path = server::partial_path_of(&final_path);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&partial_path)
+            .context("一時ファイルの作成に失敗")?;
+        file.set_len(file_size)
+            .context("一時ファイルの領域確保に失敗")?;
+
+        Ok(Self {
+            file,
+            partial_path,
+            final_path,
+            file_size,
+            chunk_size,
+            received: vec![false; chunk_count as usize],
+            remaining: chunk_count as usize,
+            updated_at: Instant::now(),
+        })
+    }
+
+    // データパケットを書き込む（すべてのチャンクが揃えば true）
+    fn write(&mut self, index: u32, payload: &[u8]) -> Result<bool> {
+        let index = index as usize;
+        if index >= self.received.len() || self.received[index] {
+            return Ok(false);
+        }
+        let offset = index as u64 * self.chunk_size as u64;
+        if offset + payload.len() as u64 > self.file_size {
+            return Ok(false);
+        }
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(payload)?;
+        self.received[index] = true;
+        self.remaining -= 1;
+        self.updated_at = Instant::now();
+        Ok(self.remaining == 0)
+    }
+
+    // 受信を終え、一時ファイルを保存先に移す
+    //
+    // 受信中に同じ名前のファイルが作られていれば上書きせずに破棄する
+    // （リンクを作れないファイルシステムでは、名前の変更で移す）
+    fn finish(self) -> Result<PathBuf> {
+        drop(self.file);
+        let moved = match fs::hard_link(&self.partial_path, &self.final_path) {
+            Ok(()) => fs::remove_file(&self.partial_path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+            Err(_) if self.final_path.symlink_metadata().is_ok() => {
+                Err(io::ErrorKind::AlreadyExists.into())
+            }
+            Err(_) => fs::rename(&self.partial_path, &self.final_path),
+        };
+        if let Err(e) = moved {
+            let _ = fs::remove_file(&self.partial_path);
+            return Err(e).with_context(|| format!("ファイルの保存に失敗: {:?}", self.final_path));
+        }
+        Ok(self.final_path)
+    }
+
+    // 受信をやめ、一時ファイルを削除する
+    fn discard(self) {
+        drop(self.file);
+        let _ = fs::remove_file(&self.partial_path);
+    }
+}
+
+// マルチキャストグループに参加し、配信されたファイルを受信し続ける
+pub async fn receive(save_dir: &Path, group: Ipv4Addr, port: u16) -> Result<()> {
+    // 同じマシン上の複数の受信側が同じポートを使えるようにする
+    let raw = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    raw.set_reuse_address(true)?;
+    raw.set_nonblocking(true)?;
+    raw.bind(&SocketAddr::from(([0, 0, 0, 0], port)).into())?;
+    let socket = UdpSocket::from_std(raw.into())?;
+    socket
+        .join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)
+        .context("マルチキャストグループへの参加に失敗")?;
+
+    info!("マルチキャストグループ {}:{} で受信待機中", group, port);
+
+    let mut incoming: HashMap<Uuid, IncomingFile> = HashMap::new();
+    let mut completed: HashSet<Uuid> = HashSet::new();
+    let mut buf = vec![0u8; CHUNK_SIZE + 64];
+
+    loop {
+        if completed.len() > MAX_COMPLETED {
+            completed.clear();
+        }
+        let (n, _) = socket.recv_from(&mut buf).await?;
+        let Some(packet) = parse_packet(&buf[..n]) else {
+            continue;
+        };
+
+        match packet {
+            Packet::Info {
+                transfer_id,
+                file_size,
+                chunk_size,
+                filename,
+            } => {
+                if completed.contains(&transfer_id) || incoming.contains_key(&transfer_id) {
+                    continue;
+                }
+                // 配信が途切れたファイルを片付けてから、同時に受信する数を確かめる
+                let expired: Vec<Uuid> = incoming
+                    .iter()
+                    .filter(|(_, entry)| entry.updated_at.elapsed() >= INCOMING_TIMEOUT)
+                    .map(|(transfer_id, _)| *transfer_id)
+                    .collect();
+                for transfer_id in expired {
+                    if let Some(entry) = incoming.remove(&transfer_id) {
+                        warn!(
+                            "配信が途切れたファイルを破棄しました: {:?}",
+                            entry.final_path
+                        );
+                        entry.discard();
+                    }
+                }
+                if incoming.len() >= MAX_INCOMING {
+                    warn!(
+                        "同時に受信できるファイルの数を超えたため無視しました: {}",
+                        filename
+                    );
+                    continue;
+                }
+
+                match IncomingFile::create(save_dir, &filename, file_size, chunk_size) {
+                    Ok(entry) => {
+                        info!("受信を開始: {} ({} バイト)", filename, file_size);
+                        incoming.insert(transfer_id, entry);
+                    }
+                    Err(e) => {
+                        warn!("ファイル情報を無視しました: {}: {:#}", filename, e);
+                        // 同じ配信のファイル情報は繰り返し届くため、二度と試さない
+                        completed.insert(transfer_id);
+                    }
+                }
+            }
+            Packet::Data {
+                transfer_id,
+                index,
+                payload,
+            } => {
+                let Some(entry) = incoming.get_mut(&transfer_id) else {
+                    continue;
+                };
+                let done = match entry.write(index, payload) {
+                    Ok(done) => done,
+                    Err(e) => {
+                        error!(
+                            "受信データの書き込みに失敗: {:?}: {:#}",
+                            entry.final_path, e
+                        );
+                        if let Some(entry) = incoming.remove(&transfer_id) {
+                            entry.discard();
+                        }
+                        completed.insert(transfer_id);
+                        continue;
+                    }
+                };
+                if !done {
+                    continue;
+                }
+                if let Some(entry) = incoming.remove(&transfer_id) {
+                    match entry.finish() {
+                        Ok(path) => success!("ファイルを保存しました: {:?}", path),
+                        Err(e) => error!("{:#}", e),
+                    }
+                }
+                completed.insert(transfer_id);
+            }
+        }
+    }
+}
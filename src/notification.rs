@@ -0,0 +1,211 @@
+use crate::{
+    desktop::{self, OnReceive},
+    events::{TransferEvent, TransferEvents},
+    log::{info, warn},
+    server::IncomingTransfer,
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// 通知のボタン（操作の ID と表示する名前）
+const ACCEPT: (&str, &str) = ("accept", "受け入れる");
+const REJECT: (&str, &str) = ("reject", "拒否");
+const OPEN_FOLDER: (&str, &str) = ("open-folder", "フォルダを開く");
+
+// 通知で受け入れの確認を待っている受信（転送IDごと）
+//
+// 通知のボタンのほか、制御ソケットの "accept <転送ID>"・"reject <転送ID>" でも答えられる
+#[derive(Clone, Default)]
+pub struct Prompts(Arc<Mutex<HashMap<Uuid, IncomingTransfer>>>);
+
+impl Prompts {
+    // 受け入れの確認を求められるたびに、受け入れ・拒否のボタンを付けた通知を出す
+    pub async fn serve(self, mut incoming: mpsc::Receiver<IncomingTransfer>) {
+        while let Some(transfer) = incoming.recv().await {
+            let transfer_id = transfer.transfer_id;
+            let title = format!("{} からの受信", transfer.peer);
+            let mut body = format!("{}（{} バイト）", transfer.filename, transfer.size);
+            if let Some(note) = &transfer.note {
+                body.push_str(&format!("\n{}", note));
+            }
+            info!(
+                "受け入れの確認を通知しました（file-transfer accept {} でも受け入れられます）",
+                transfer_id
+            );
+            // 通知の操作より先に制御ソケットで答えられるよう、先に確認待ちに加えておく
+            self.0.lock().unwrap().insert(transfer_id, transfer);
+
+            let prompts = self.clone();
+            tokio::task::spawn_blocking(move || {
+                let result = show(&title, &body, &[ACCEPT, REJECT])
+                    .and_then(|action| prompts.answer(transfer_id, action.as_deref()));
+                if let Err(e) = result {
+                    warn!("{:#}", e);
+                }
+            });
+        }
+    }
+
+    // 確認待ちの受信を受け入れる
+    pub fn accept(&self, transfer_id: Uuid) -> Result<()> {
+        self.take(transfer_id)?.accept();
+        Ok(())
+    }
+
+    // 確認待ちの受信を拒否する（reason は送信側に伝える理由）
+    pub fn reject(&self, transfer_id: Uuid, reason: &str) -> Result<()> {
+        self.take(transfer_id)?.reject(reason);
+        Ok(())
+    }
+
+    // 通知で押されたボタンに従って答える
+    fn answer(&self, transfer_id: Uuid, action: Option<&str>) -> Result<()> {
+        match action {
+            Some(id) if id == ACCEPT.0 => self.accept(transfer_id),
+            Some(id) if id == REJECT.0 => self.reject(transfer_id, "通知で拒否されました"),
+            // 閉じた場合は確認待ちのまま残す（制御ソケットで答えられる）
+            _ => Ok(()),
+        }
+    }
+
+    fn take(&self, transfer_id: Uuid) -> Result<IncomingTransfer> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&transfer_id)
+            .with_context(|| format!("確認待ちの受信がありません: {}", transfer_id))
+    }
+}
+
+// 受信し終えたファイルを通知する（「フォルダを開く」で保存先を開く）
+pub struct CompletionNotifier {
+    // 保存先（サーバーモードの保存先の共有状態）
+    save_path: Arc<Mutex<Option<PathBuf>>>,
+    // 受信中のファイル名（経過の通知の番号ごと）
+    names: Mutex<HashMap<u64, String>>,
+}
+
+impl CompletionNotifier {
+    pub fn new(save_path: Arc<Mutex<Option<PathBuf>>>) -> Self {
+        Self {
+            save_path,
+            names: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl TransferEvents for CompletionNotifier {
+    fn on_event(&self, id: u64, event: TransferEvent) {
+        let filename = match event {
+            TransferEvent::Started { filename, .. } => {
+                self.names.lock().unwrap().insert(id, filename);
+                return;
+            }
+            TransferEvent::Progress { .. } => return,
+            TransferEvent::Completed => self.names.lock().unwrap().remove(&id),
+            TransferEvent::Failed(_) | TransferEvent::Cancelled | TransferEvent::Rejected(_) => {
+                self.names.lock().unwrap().remove(&id);
+                return;
+            }
+        };
+        // 保存先がなければ受信していないため、通知しない
+        let (Some(filename), Some(folder)) = (filename, self.save_path.lock().unwrap().clone())
+        else {
+            return;
+        };
+
+        // 通知は操作されるまで戻らないことがあるため、別のスレッドで出す
+        std::thread::spawn(move || {
+            let body = format!("{} を受信しました", filename);
+            match show("受信完了", &body, &[OPEN_FOLDER]) {
+                Ok(Some(action)) if action == OPEN_FOLDER.0 => {
+                    desktop::run(OnReceive::Open, &folder)
+                }
+                Ok(_) => {}
+                Err(e) => warn!("{:#}", e),
+            }
+        });
+    }
+}
+
+// ボタンを付けた通知を出し、押されたボタンの操作の ID を返す（閉じた場合は None）
+//
+// 通知が操作されるか閉じられるまで戻らない
+#[cfg(windows)]
+fn show(title: &str, body: &str, actions: &[(&str, &str)]) -> Result<Option<String>> {
+    use tauri_winrt_notification::Toast;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut toast = Toast::new(Toast::POWERSHELL_APP_ID)
+        .title(title)
+        .text1(body)
+        .on_activated(move |action| {
+            let _ = tx.send(action);
+            Ok(())
+        });
+    for (id, label) in actions {
+        toast = toast.add_button(label, id);
+    }
+    toast
+        .show()
+        .map_err(|e| anyhow::anyhow!("通知を表示できません: {}", e))?;
+    // トーストの本文を押した場合はボタンの操作がない
+    Ok(rx.recv().ok().flatten())
+}
+
+// 通知センターの通知はボタンを2つまで付けられる（2つ目は「閉じる」の位置に出す）
+#[cfg(target_os = "macos")]
+fn show(title: &str, body: &str, actions: &[(&str, &str)]) -> Result<Option<String>> {
+    use mac_notification_sys::{MainButton, Notification, NotificationResponse};
+
+    let mut options = Notification::new();
+    if let Some((_, label)) = actions.first() {
+        options.main_button(MainButton::SingleAction(label));
+    }
+    if let Some((_, label)) = actions.get(1) {
+        options.close_button(label);
+    }
+    let response = mac_notification_sys::send_notification(title, None, body, Some(&options))
+        .map_err(|e| anyhow::anyhow!("通知を表示できません: {}", e))?;
+    let label = match response {
+        NotificationResponse::ActionButton(label) | NotificationResponse::CloseButton(label) => {
+            label
+        }
+        _ => return Ok(None),
+    };
+    Ok(actions
+        .iter()
+        .find(|(_, name)| *name == label)
+        .map(|(id, _)| id.to_string()))
+}
+
+// freedesktop の通知のアクションを使う
+#[cfg(not(any(windows, target_os = "macos")))]
+fn show(title: &str, body: &str, actions: &[(&str, &str)]) -> Result<Option<String>> {
+    let mut notification = notify_rust::Notification::new();
+    notification
+        .appname("file-transfer")
+        .summary(title)
+        .body(body)
+        .timeout(notify_rust::Timeout::Never);
+    for (id, label) in actions {
+        notification.action(id, label);
+    }
+    let handle = notification
+        .show()
+        .map_err(|e| anyhow::anyhow!("通知を表示できません: {}", e))?;
+    let mut chosen = None;
+    handle.wait_for_action(|action| {
+        // 閉じた場合は "__closed" が渡される
+        if action != "__closed" {
+            chosen = Some(action.to_string());
+        }
+    });
+    Ok(chosen)
+}
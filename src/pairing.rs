@@ -0,0 +1,225 @@
+use crate::{
+    client::{run_client, ClientArgs},
+    config::Config,
+    log::info,
+    picker,
+    server::{run_server, ServerArgs},
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use rfd::{MessageButtons, MessageDialog, MessageDialogResult, MessageLevel};
+use std::{
+    io::{self, IsTerminal, Write},
+    net::SocketAddr,
+};
+
+// ペアリング用のリンクの URL スキーム
+pub const SCHEME: &str = "filetransfer";
+
+// ペアリング用のリンクの内容
+//
+// "filetransfer://<アドレス>[:<ポート>]?token=<トークン>" はそのアドレスへの送信、
+// "filetransfer://receive[?port=<ポート>]" は受信の開始を表す
+pub enum Link {
+    Send {
+        server: String,
+        port: Option<u16>,
+        token: Option<String>,
+    },
+    Receive {
+        port: Option<u16>,
+    },
+}
+
+impl Link {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix(SCHEME)
+            .and_then(|rest| rest.strip_prefix("://"))
+            .with_context(|| format!("{}:// のリンクではありません: {}", SCHEME, url))?;
+        let (authority, query) = rest.split_once('?').unwrap_or((rest, ""));
+        // ブラウザによっては末尾に / を付けて渡す
+        let authority = authority.trim_end_matches('/');
+
+        let mut port = None;
+        let mut token = None;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value)?;
+            match key {
+                "port" => {
+                    port = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("ポートが正しくありません: {}", value))?,
+                    )
+                }
+                "token" => token = Some(value),
+                // 新しいバージョンで増えた項目は無視する
+                _ => {}
+            }
+        }
+
+        if authority == "receive" {
+            return Ok(Link::Receive { port });
+        }
+        let (server, port) = split_port(authority, port)?;
+        if server.is_empty() {
+            anyhow::bail!("送信先のアドレスがありません: {}", url);
+        }
+        Ok(Link::Send {
+            server,
+            port,
+            token,
+        })
+    }
+
+    pub fn to_url(&self) -> String {
+        match self {
+            Link::Send {
+                server,
+                port,
+                token,
+            } => {
+                let mut url = if server.contains(':') {
+                    format!("{}://[{}]", SCHEME, server)
+                } else {
+                    format!("{}://{}", SCHEME, server)
+                };
+                if let Some(port) = port {
+                    url.push_str(&format!(":{}", port));
+                }
+                if let Some(token) = token {
+                    url.push_str(&format!("?token={}", percent_encode(token)));
+                }
+                url
+            }
+            Link::Receive { port: Some(port) } => format!("{}://receive?port={}", SCHEME, port),
+            Link::Receive { port: None } => format!("{}://receive", SCHEME),
+        }
+    }
+}
+
+// "host:port" を分ける（IPv6 は "[::1]:8080" の形）
+fn split_port(authority: &str, port: Option<u16>) -> Result<(String, Option<u16>)> {
+    let (host, port_str) = match authority.rsplit_once(':') {
+        Some((host, port_str)) if !host.contains(':') || host.ends_with(']') => {
+            (host, Some(port_str))
+        }
+        _ => (authority, None),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = match port_str {
+        Some(port_str) => Some(
+            port_str
+                .parse()
+                .with_context(|| format!("ポートが正しくありません: {}", port_str))?,
+        ),
+        None => port,
+    };
+    Ok((percent_decode(host)?, port))
+}
+
+fn percent_decode(text: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' && tail.len() >= 2 {
+            let hex = std::str::from_utf8(&tail[..2])?;
+            bytes.push(
+                u8::from_str_radix(hex, 16)
+                    .with_context(|| format!("リンクの %{} が正しくありません", hex))?,
+            );
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).context("リンクが UTF-8 ではありません")
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for &byte in text.as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// リンクを開いたときの処理（リンクのアドレス・ポート・トークンでクライアントモードかサーバーモードを開始する）
+//
+// Web ページなどから知らないうちに開かれても送受信を始めないよう、リンクの内容を見せて確認してから始める。
+// リンクの内容は設定ファイルやキーチェーンには保存しない
+pub async fn open(url: &str, config: &Config) -> Result<()> {
+    let link = Link::parse(url)?;
+    let mut config = config.clone();
+    match link {
+        Link::Send {
+            server,
+            port,
+            token,
+        } => {
+            config.port = port.or(config.port);
+            let description = format!(
+                "送信先: {}:{}\nトークン: {}",
+                server,
+                config.port.unwrap_or(crate::FILE_TRANSFER_PORT),
+                if token.is_some() { "あり" } else { "なし" }
+            );
+            if !confirm("リンクのアドレスにファイルを送信しますか？", &description)?
+            {
+                info!("リンクを開くのをやめました");
+                return Ok(());
+            }
+            info!("リンクを開きました: {} に送信します", server);
+            let mut args = ClientArgs::try_parse_from(["client"])?;
+            args.server = Some(server);
+            args.token = token;
+            run_client(&args, &config).await
+        }
+        Link::Receive { port } => {
+            config.port = port.or(config.port);
+            // すべてのアドレスではなく、この端末の LAN のアドレスだけで待ち受ける
+            let ip =
+                local_ip_address::local_ip().context("この端末のIPアドレスを取得できません")?;
+            let bind = SocketAddr::new(ip, config.port.unwrap_or(crate::FILE_TRANSFER_PORT));
+            let description = format!("待ち受けるアドレス: {}", bind);
+            if !confirm("リンクを開いて受信を開始しますか？", &description)? {
+                info!("リンクを開くのをやめました");
+                return Ok(());
+            }
+            info!("リンクを開きました: 受信を開始します");
+            let mut args = ServerArgs::try_parse_from(["server"])?;
+            args.bind = vec![bind];
+            run_server(&args, &config).await
+        }
+    }
+}
+
+// リンクの内容を見せて、開いてよいかを確認する（画面があればダイアログ、なければ端末で確認する）
+fn confirm(title: &str, description: &str) -> Result<bool> {
+    if picker::has_display() {
+        let result = MessageDialog::new()
+            .set_level(MessageLevel::Warning)
+            .set_title(title)
+            .set_description(description)
+            .set_buttons(MessageButtons::YesNo)
+            .show();
+        return Ok(result == MessageDialogResult::Yes);
+    }
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("リンクを開いてよいか確認できる端末・画面がないため開きません");
+    }
+    println!("{}", title);
+    println!("{}", description);
+    print!("続けますか？ [y/N] ");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(matches!(line.trim(), "y" | "Y" | "yes"))
+}
@@ -0,0 +1,231 @@
+use anyhow::Result;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ignore::WalkBuilder;
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Terminal,
+};
+use rfd::FileDialog;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+// 端末の選択画面で列挙する最大件数
+const MAX_CANDIDATES: usize = 20_000;
+
+// 選択の対象
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    File,
+    Folder,
+}
+
+// ファイルを選択する（画面がなければ端末内の選択画面を使う）
+pub fn pick_file(title: &str) -> Option<PathBuf> {
+    pick(title, Kind::File)
+}
+
+// フォルダを選択する（画面がなければ端末内の選択画面を使う）
+pub fn pick_folder(title: &str) -> Option<PathBuf> {
+    pick(title, Kind::Folder)
+}
+
+fn pick(title: &str, kind: Kind) -> Option<PathBuf> {
+    if has_display() {
+        let dialog = FileDialog::new().set_title(title);
+        return match kind {
+            Kind::File => dialog.pick_file(),
+            Kind::Folder => dialog.pick_folder(),
+        };
+    }
+
+    match pick_in_terminal(title, kind) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("選択画面の表示に失敗: {}", e);
+            None
+        }
+    }
+}
+
+// ダイアログを表示できる画面があるか（SSH接続やWSLでは使えないことが多い）
+pub fn has_display() -> bool {
+    let has_var = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    if cfg!(any(windows, target_os = "macos")) {
+        !has_var("SSH_CONNECTION")
+    } else {
+        has_var("DISPLAY") || has_var("WAYLAND_DISPLAY")
+    }
+}
+
+// query の文字が順に含まれていればスコアを返す（連続して一致するほど高い）
+fn fuzzy_score(candidate: &str, query: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut previous_match = None;
+    let mut chars = candidate.chars().flat_map(char::to_lowercase).enumerate();
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let (index, _) = chars.find(|(_, c)| *c == q)?;
+        score += if previous_match.is_some_and(|previous| index == previous + 1) {
+            10
+        } else {
+            1
+        };
+        previous_match = Some(index);
+    }
+    // 短いパスを優先する
+    Some(score * 1000 - candidate.len() as i64)
+}
+
+// root 以下の候補を列挙する（root からの相対パス。フォルダは末尾に '/' を付ける）
+//
+// ファイルの選択でもフォルダに入れるよう、フォルダは常に候補に含める
+fn collect_candidates(root: &Path, kind: Kind) -> Vec<String> {
+    let mut candidates = Vec::new();
+    for entry in WalkBuilder::new(root).build().flatten() {
+        if entry.depth() == 0 {
+            continue;
+        }
+        let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+        if kind == Kind::Folder && !is_dir {
+            continue;
+        }
+        if let Ok(relative) = entry.path().strip_prefix(root) {
+            let mut relative = relative.to_string_lossy().into_owned();
+            if is_dir {
+                relative.push('/');
+            }
+            candidates.push(relative);
+        }
+        if candidates.len() >= MAX_CANDIDATES {
+            break;
+        }
+    }
+    candidates
+}
+
+// 端末内で文字を入力して絞り込みながら選択する
+fn pick_in_terminal(title: &str, kind: Kind) -> Result<Option<PathBuf>> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let result = select_loop(title, kind);
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+    Ok(result?.map(|path| path.components().collect()))
+}
+
+fn select_loop(title: &str, kind: Kind) -> Result<Option<PathBuf>> {
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let mut root = std::env::current_dir()?;
+    let mut candidates = collect_candidates(&root, kind);
+    let mut query = String::new();
+    let mut list = ListState::default();
+
+    loop {
+        // 絞り込み（フォルダの選択では root 自身も候補にする）
+        let mut matches: Vec<(i64, &str)> = candidates
+            .iter()
+            .filter_map(|c| fuzzy_score(c, &query).map(|score| (score, c.as_str())))
+            .collect();
+        matches.sort_by(|a, b| b.0.cmp(&a.0));
+        if kind == Kind::Folder && query.is_empty() {
+            matches.insert(0, (0, "./"));
+        }
+        match list.selected() {
+            _ if matches.is_empty() => list.select(None),
+            Some(selected) if selected < matches.len() => {}
+            _ => list.select(Some(0)),
+        }
+
+        terminal.draw(|frame| {
+            let areas = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Min(3),
+                    Constraint::Length(1),
+                ])
+                .split(frame.size());
+
+            let input = Paragraph::new(format!("> {}", query)).block(
+                Block::default().borders(Borders::ALL).title(format!(
+                    "{} - {}",
+                    title,
+                    root.display()
+                )),
+            );
+            frame.render_widget(input, areas[0]);
+
+            let items: Vec<ListItem> = matches
+                .iter()
+                .take(areas[1].height as usize + list.selected().unwrap_or(0))
+                .map(|(_, path)| ListItem::new(*path))
+                .collect();
+            let items = List::new(items)
+                .block(Block::default().borders(Borders::ALL))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            frame.render_stateful_widget(items, areas[1], &mut list);
+
+            let help = Paragraph::new(
+                "↑↓: 選択  Enter: 決定  ←: 親フォルダへ  →: フォルダに入る  Esc: キャンセル",
+            );
+            frame.render_widget(help, areas[2]);
+        })?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let selected = list.selected().map(|index| matches[index].1.to_string());
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+            KeyCode::Enter => match selected {
+                // ファイルの選択ではフォルダに入る
+                Some(dir) if kind == Kind::File && dir.ends_with('/') => {
+                    root = root.join(dir.trim_end_matches('/'));
+                    candidates = collect_candidates(&root, kind);
+                    query.clear();
+                    list.select(Some(0));
+                }
+                Some(selected) => return Ok(Some(root.join(selected.trim_end_matches('/')))),
+                None => {}
+            },
+            KeyCode::Up => list.select(list.selected().map(|index| index.saturating_sub(1))),
+            KeyCode::Down => {
+                let last = matches.len().saturating_sub(1);
+                list.select(list.selected().map(|index| (index + 1).min(last)));
+            }
+            KeyCode::Left => {
+                if let Some(parent) = root.parent() {
+                    root = parent.to_path_buf();
+                    candidates = collect_candidates(&root, kind);
+                    query.clear();
+                    list.select(Some(0));
+                }
+            }
+            KeyCode::Right => {
+                if let Some(dir) = selected.filter(|dir| dir.ends_with('/') && dir != "./") {
+                    root = root.join(dir.trim_end_matches('/'));
+                    candidates = collect_candidates(&root, kind);
+                    query.clear();
+                    list.select(Some(0));
+                }
+            }
+            KeyCode::Backspace => {
+                query.pop();
+            }
+            KeyCode::Char(c) => query.push(c),
+            _ => {}
+        }
+    }
+}
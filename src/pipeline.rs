@@ -0,0 +1,199 @@
+use crate::{
+    adaptive::{self, Tuner},
+    buffer, dedup,
+    log::debug,
+    protocol,
+    transport::Connection,
+};
+use anyhow::{Context, Result};
+use bytes::BytesMut;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, watch},
+};
+
+// Linux で io-uring フィーチャーを有効にした場合は、ディスクへの書き込みを io_uring で行う
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring::write_stage;
+
+// 段の間に溜めておけるチャンクの数
+const PIPELINE_DEPTH: usize = 8;
+
+// 受信済みバイト数を通知する間隔
+pub const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+// 受信したデータを、読み取り → ハッシュ計算 → ディスクへの書き込み の段に分けて流す
+//
+// 段の間には PIPELINE_DEPTH 個のチャンクまでしか溜めないため、ディスクが遅ければ読み取りも止まり、
+// メモリの使用量は一定に収まる。ハッシュ計算と書き込みは別のタスクで読み取りと並行して進む。
+// 送信側が求めていれば、ディスクに書き込み済みのバイト数を ACK_INTERVAL ごとに通知する
+pub struct Pipeline {
+    acks: bool,
+    // これまでに書き込んだバイト数（スパースファイルのように複数回に分けて流す場合の合計）
+    written: u64,
+    hasher: Option<Sha256>,
+}
+
+impl Pipeline {
+    // hash が true なら、流したデータの SHA-256 を計算する（データが先頭から順に届く場合のみ使う）
+    pub fn new(acks: bool, hash: bool) -> Self {
+        Self {
+            acks,
+            written: 0,
+            hasher: hash.then(Sha256::new),
+        }
+    }
+
+    // socket から最大 length バイトを file の今の位置から書き込み、file と書き込んだバイト数を返す
+    pub async fn copy(
+        &mut self,
+        socket: &mut impl Connection,
+        file: File,
+        length: u64,
+    ) -> Result<(File, u64)> {
+        let (mut reader, mut writer) = tokio::io::split(socket);
+        let (read_tx, read_rx) = mpsc::channel(PIPELINE_DEPTH);
+
+        // ハッシュを計算しない場合は、読み取りから書き込みへ直接渡す
+        let (write_rx, hashing) = match self.hasher.take() {
+            Some(hasher) => {
+                let (hash_tx, hash_rx) = mpsc::channel(PIPELINE_DEPTH);
+                let hashing = tokio::spawn(hash_stage(hasher, read_rx, hash_tx));
+                (hash_rx, Some(hashing))
+            }
+            None => (read_rx, None),
+        };
+
+        let (acked_tx, acked_rx) = watch::channel(self.written);
+        let writing = tokio::spawn(write_stage(
+            file,
+            write_rx,
+            self.written,
+            self.acks.then_some(acked_tx),
+        ));
+
+        let (read, ack) = tokio::join!(
+            read_stage(&mut reader, read_tx, length),
+            ack_stage(&mut writer, acked_rx)
+        );
+
+        if let Some(hashing) = hashing {
+            self.hasher = Some(
+                hashing
+                    .await
+                    .context("ハッシュ計算の段が異常終了しました")?,
+            );
+        }
+        // 書き込みが失敗した場合は読み取りも止まるため、書き込みのエラーを優先する
+        let (file, written) = writing.await.context("書き込みの段が異常終了しました")??;
+        read?;
+        ack?;
+
+        self.written += written;
+        Ok((file, written))
+    }
+
+    // すべてのデータを流し終えたら、ディスクへの書き込みを確定させて最後の通知を送る
+    pub async fn finish(&self, socket: &mut impl Connection, file: &mut File) -> Result<()> {
+        if !self.acks {
+            return Ok(());
+        }
+        sync(file).await?;
+        protocol::write_ack(socket, self.written).await
+    }
+
+    // 流したデータの SHA-256（new で hash を指定した場合のみ）
+    pub fn sha256(self) -> Option<String> {
+        self.hasher.map(|hasher| dedup::to_hex(&hasher.finalize()))
+    }
+}
+
+// ネットワークからチャンクを読み取って次の段に渡す（次の段が詰まっていれば待つ）
+//
+// チャンクの大きさは、読み取りの速さに合わせて調整する
+async fn read_stage(
+    reader: &mut (impl AsyncRead + Unpin),
+    tx: mpsc::Sender<BytesMut>,
+    length: u64,
+) -> Result<()> {
+    let mut reader = reader.take(length);
+    let mut tuner = Tuner::new(None);
+    loop {
+        let started = Instant::now();
+        let chunk = adaptive::read_chunk(&mut reader, tuner.chunk_size()).await?;
+        if chunk.is_empty() {
+            buffer::release(chunk);
+            break;
+        }
+        tuner.record(chunk.len(), started.elapsed());
+        debug!("チャンクを受信: {} バイト", chunk.len());
+        // 後の段が失敗して受け取らなくなったら、そちらのエラーを返すため読み取りだけ止める
+        if tx.send(chunk).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn hash_stage(
+    mut hasher: Sha256,
+    mut rx: mpsc::Receiver<BytesMut>,
+    tx: mpsc::Sender<BytesMut>,
+) -> Sha256 {
+    while let Some(chunk) = rx.recv().await {
+        hasher.update(&chunk);
+        if tx.send(chunk).await.is_err() {
+            break;
+        }
+    }
+    hasher
+}
+
+// チャンクをディスクに書き込み、ACK_INTERVAL ごとに書き込みを確定させて通知の段に伝える
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+async fn write_stage(
+    mut file: File,
+    mut rx: mpsc::Receiver<BytesMut>,
+    base: u64,
+    acked: Option<watch::Sender<u64>>,
+) -> Result<(File, u64)> {
+    let mut written = 0;
+    let mut last = Instant::now();
+    while let Some(chunk) = rx.recv().await {
+        file.write_all(&chunk)
+            .await
+            .context("ファイルの書き込みに失敗")?;
+        written += chunk.len() as u64;
+        buffer::release(chunk);
+        if let (Some(acked), true) = (&acked, last.elapsed() >= ACK_INTERVAL) {
+            sync(&mut file).await?;
+            let _ = acked.send(base + written);
+            last = Instant::now();
+        }
+    }
+    file.flush().await?;
+    Ok((file, written))
+}
+
+// 書き込みが確定したバイト数を送信側に通知する（書き込みの段が終わると終わる）
+async fn ack_stage(
+    writer: &mut (impl AsyncWrite + Unpin),
+    mut acked: watch::Receiver<u64>,
+) -> Result<()> {
+    while acked.changed().await.is_ok() {
+        let bytes = *acked.borrow_and_update();
+        debug!("書き込み済みのバイト数を通知: {}", bytes);
+        protocol::write_ack(writer, bytes).await?;
+    }
+    Ok(())
+}
+
+async fn sync(file: &mut File) -> Result<()> {
+    file.flush().await?;
+    file.sync_data()
+        .await
+        .context("ファイルの書き込みの確定に失敗")
+}
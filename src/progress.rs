@@ -0,0 +1,186 @@
+use crate::transport::BoxedConnection;
+use std::{
+    collections::VecDeque,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::sync::CancellationToken;
+
+// 転送ごとに残す1秒ごとの受信量の数（直近2分）
+pub const MAX_SAMPLES: usize = 120;
+
+// 一覧に残す終わった転送の数（超えたら古いものから消す）
+const MAX_FINISHED: usize = 100;
+
+// 一覧の転送に振る番号（一覧から消しても他の転送の番号は変わらない）
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// 受信中の転送の状態
+#[derive(Clone)]
+pub enum TransferState {
+    Receiving,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+// 受信中・受信済みの転送（TUIモードで一覧表示する）
+pub struct Transfer {
+    id: u64,
+    pub peer: String,
+    pub filename: String,
+    pub total: u64,
+    pub received: Arc<AtomicU64>,
+    // 取り消すと受信中の接続の読み書きがエラーになる
+    pub cancel: CancellationToken,
+    pub state: TransferState,
+    // 1秒ごとの受信バイト数（古い順。TUIモードのグラフに表示する）
+    pub samples: VecDeque<u64>,
+    // 前回 sample で記録した時点の受信バイト数
+    sampled: u64,
+}
+
+impl Transfer {
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    // 受信を中断する
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    // 前回からの受信バイト数を1秒分として記録し、それを返す
+    fn sample(&mut self) -> u64 {
+        let received = self.received();
+        let bytes = received.saturating_sub(self.sampled);
+        self.sampled = received;
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(bytes);
+        bytes
+    }
+}
+
+pub type Transfers = Arc<Mutex<Vec<Transfer>>>;
+
+// 受信中の転送の1秒ごとの受信量を記録し、すべての転送の合計を返す（1秒ごとに呼ぶ）
+//
+// 速度が0に落ちてはまた戻るならネットワークの途切れ、低いまま一定ならディスクの書き込みの遅さを疑える
+pub fn sample(transfers: &Transfers) -> u64 {
+    transfers
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .filter(|transfer| matches!(transfer.state, TransferState::Receiving))
+        .map(Transfer::sample)
+        .sum()
+}
+
+// 転送を一覧に登録し、転送の番号と、受信したバイト数を数える接続を返す
+//
+// cancel は接続に適用済みのもの（一覧から中断できるよう保持する）。
+// 長く動かすサーバーで一覧が増え続けないよう、終わった転送は MAX_FINISHED 件だけ残す
+pub fn track(
+    transfers: &Transfers,
+    connection: BoxedConnection,
+    peer: &str,
+    filename: &str,
+    total: u64,
+    cancel: CancellationToken,
+) -> (u64, BoxedConnection) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let received = Arc::new(AtomicU64::new(0));
+    let mut transfers = transfers.lock().unwrap();
+    prune(&mut transfers);
+    transfers.push(Transfer {
+        id,
+        peer: peer.to_string(),
+        filename: filename.to_string(),
+        total,
+        received: received.clone(),
+        cancel,
+        state: TransferState::Receiving,
+        samples: VecDeque::new(),
+        sampled: 0,
+    });
+
+    let tracked = Tracked {
+        inner: connection,
+        received,
+    };
+    (id, Box::new(tracked))
+}
+
+// 終わった転送が MAX_FINISHED 件を超えていれば、古いものから消す
+fn prune(transfers: &mut Vec<Transfer>) {
+    let finished = transfers
+        .iter()
+        .filter(|transfer| !matches!(transfer.state, TransferState::Receiving))
+        .count();
+    let mut excess = finished.saturating_sub(MAX_FINISHED);
+    transfers.retain(|transfer| {
+        if excess > 0 && !matches!(transfer.state, TransferState::Receiving) {
+            excess -= 1;
+            return false;
+        }
+        true
+    });
+}
+
+// 受信の結果を一覧に反映する（id は track が返した番号）
+pub fn finish(transfers: &Transfers, id: u64, result: Result<(), String>) {
+    let mut transfers = transfers.lock().unwrap();
+    let Some(transfer) = transfers.iter_mut().find(|transfer| transfer.id == id) else {
+        return;
+    };
+    transfer.state = match result {
+        Ok(()) => TransferState::Done,
+        Err(_) if transfer.cancel.is_cancelled() => TransferState::Cancelled,
+        Err(e) => TransferState::Failed(e),
+    };
+}
+
+struct Tracked {
+    inner: BoxedConnection,
+    received: Arc<AtomicU64>,
+}
+
+impl AsyncRead for Tracked {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        self.received.fetch_add(read, Ordering::Relaxed);
+        result
+    }
+}
+
+impl AsyncWrite for Tracked {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,848 @@
+use crate::frame::{Decoded, HeaderDecoder};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+// 旧形式のヘッダーでファイル名の長さが入る位置に置く、分割転送ヘッダーの識別子
+pub const PART_HEADER_MARKER: u32 = u32::MAX;
+
+// 同じ位置に置く、シンボリックリンクのヘッダーの識別子
+pub const SYMLINK_HEADER_MARKER: u32 = u32::MAX - 1;
+
+// 同じ位置に置く、スパースファイルのヘッダーの識別子
+pub const SPARSE_HEADER_MARKER: u32 = u32::MAX - 2;
+
+// 同じ位置に置く、認証トークンのヘッダーの識別子（続けて通常のヘッダーを送る）
+pub const AUTH_HEADER_MARKER: u32 = u32::MAX - 3;
+
+// 同じ位置に置く、バージョン情報のヘッダーの識別子（サーバーは "HELLO ..." の1行を返す）
+pub const HELLO_HEADER_MARKER: u32 = u32::MAX - 4;
+
+// 同じ位置に置く、接続の維持を求めるヘッダーの識別子（認証の後、通常のヘッダーの前に送る）
+pub const KEEPALIVE_HEADER_MARKER: u32 = u32::MAX - 5;
+
+// 同じ位置に置く、応答を構造化した形式（Response の1行）で求めるヘッダーの識別子
+// （認証の後、通常のヘッダーの前に送る）
+pub const RESPONSE_HEADER_MARKER: u32 = u32::MAX - 6;
+
+// 同じ位置に置く、受信済みバイト数の通知（ACK）を求めるヘッダーの識別子
+// （認証の後、通常のヘッダーの前に送る）
+pub const ACK_HEADER_MARKER: u32 = u32::MAX - 7;
+
+// 同じ位置に置く、複数のファイルを送る前にまとめて確認を求める一覧（マニフェスト）のヘッダーの識別子
+// （サーバーは受け入れた場合、既に持っているため送らなくてよいファイルを応答で返す）
+pub const MANIFEST_HEADER_MARKER: u32 = u32::MAX - 8;
+
+// 同じ位置に置く、受け入れ済みのバッチに含まれる転送であることを示すヘッダーの識別子
+// （認証の後、通常のヘッダーの前に送る）
+pub const BATCH_HEADER_MARKER: u32 = u32::MAX - 9;
+
+// 同じ位置に置く、ssh-agent の鍵で認証するヘッダーの識別子
+// （サーバーは SSH_CHALLENGE_LEN バイトのチャレンジを返し、クライアントはその署名を送ってから
+// 通常のヘッダーを送る）
+pub const SSH_AUTH_HEADER_MARKER: u32 = u32::MAX - 10;
+
+// 同じ位置に置く、小さなファイルをまとめて送るヘッダーの識別子
+// （ファイルの一覧に続けて、各ファイルのデータを一覧の順に続けて送る。サーバーは全体で1つの応答を返す）
+pub const BUNDLE_HEADER_MARKER: u32 = u32::MAX - 11;
+
+// 同じ位置に置く、続くマニフェストを受け入れずに、送った場合の結果だけを求めるヘッダーの識別子
+// （認証の後、マニフェストの前に送る）
+pub const DRY_RUN_HEADER_MARKER: u32 = u32::MAX - 12;
+
+// 同じ位置に置く、続くマニフェストのファイルが保存先にあるものと同じ内容かを確かめるヘッダーの識別子
+// （認証の後、マニフェストの前に送る。サーバーは一致しないファイルを応答で返す）
+pub const VERIFY_HEADER_MARKER: u32 = u32::MAX - 13;
+
+// 同じ位置に置く、転送に添えるメモのヘッダーの識別子
+// （認証の後、通常のヘッダーの前に送る。続けて長さ付きの UTF-8 の文字列を送る）
+pub const NOTE_HEADER_MARKER: u32 = u32::MAX - 15;
+
+// 同じ位置に置く、転送IDのヘッダーの識別子
+// （認証の後、通常のヘッダーの前に送る。続けて UUID の16バイトを送る。サーバーはログと応答に同じIDを使う）
+pub const TRANSFER_ID_HEADER_MARKER: u32 = u32::MAX - 16;
+
+// 同じ位置に置く、同時に受信できる転送の数を超えて待たされている間の順番の通知（QUEUED）を求めるヘッダーの識別子
+// （認証の後、通常のヘッダーの前に送る）
+pub const QUEUE_HEADER_MARKER: u32 = u32::MAX - 17;
+
+// 同じ位置に置く、転送に添えるメタデータ（キーと値の組）のヘッダーの識別子
+// （認証の後、通常のヘッダーの前に送る。続けて組の数と、長さ付きのキー・値を順に送る）
+pub const METADATA_HEADER_MARKER: u32 = u32::MAX - 18;
+
+// ssh-agent の鍵での認証でサーバーが送るチャレンジの長さ
+pub const SSH_CHALLENGE_LEN: usize = 32;
+
+// 応答の前に置く、ディスクに書き込み済みのバイト数の通知（続けて u64 を送る。PING と同様に応答には現れない）
+pub const ACK: u8 = 1;
+
+// 応答の前に置く、受信の順番待ちの通知（続けて何番目かを u32 で送る。PING と同様に応答には現れない）
+pub const QUEUED: u8 = 2;
+
+// マニフェストに載せられるファイルの最大数
+pub const MAX_MANIFEST_ENTRIES: u32 = 1 << 17;
+
+// まとめて送れるファイルの最大数と、1つのファイルの最大サイズ
+pub const MAX_BUNDLE_ENTRIES: u32 = 4096;
+pub const MAX_BUNDLE_FILE_SIZE: u32 = 1024 * 1024;
+
+// マニフェストのファイル名の合計の最大長
+pub const MAX_MANIFEST_NAMES_LEN: usize = 16 * 1024 * 1024;
+
+// ファイル名（フォルダを含む相対パス）・リンク先の最大長
+pub const MAX_FILENAME_LEN: usize = 4096;
+
+// SHA-256 の16進表記の長さ
+pub const SHA256_HEX_LEN: u32 = 64;
+
+// 分割転送のストリーム数の最大
+pub const MAX_PART_COUNT: u32 = 64;
+
+// 認証トークンの最大長
+pub const MAX_TOKEN_LEN: u32 = 1024;
+
+// 転送に添えるメモの最大長（バイト）
+pub const MAX_NOTE_LEN: usize = 1024;
+
+// メタデータの組の最大数・キーの最大長・キーと値の合計の最大長（バイト）
+pub const MAX_METADATA_ENTRIES: u32 = 64;
+pub const MAX_METADATA_KEY_LEN: usize = 128;
+pub const MAX_METADATA_LEN: usize = 16 * 1024;
+
+// 組み込み先で共通に使うメタデータのキー（これ以外のキーも自由に使える）
+pub const META_MIME: &str = "mime";
+pub const META_SOURCE_APP: &str = "source-app";
+pub const META_ORIGINAL_PATH: &str = "original-path";
+
+// SSH の公開鍵・署名の最大長
+pub const MAX_SSH_BLOB_LEN: u32 = 16 * 1024;
+
+// バージョン情報の各項目の最大長
+pub const MAX_HELLO_FIELD_LEN: u32 = 1024;
+
+// バージョン情報で交換する機能の名前
+pub const FEATURE_PARALLEL: &str = "parallel";
+pub const FEATURE_SPARSE: &str = "sparse";
+pub const FEATURE_SYMLINK: &str = "symlink";
+pub const FEATURE_KEEPALIVE: &str = "keepalive";
+pub const FEATURE_RESPONSE: &str = "response";
+pub const FEATURE_ACK: &str = "ack";
+pub const FEATURE_BATCH: &str = "batch";
+pub const FEATURE_BUNDLE: &str = "bundle";
+pub const FEATURE_DRY_RUN: &str = "dry-run";
+pub const FEATURE_VERIFY: &str = "verify";
+pub const FEATURE_NOTE: &str = "note";
+pub const FEATURE_TRANSFER_ID: &str = "transfer-id";
+pub const FEATURE_QUEUE: &str = "queue";
+pub const FEATURE_METADATA: &str = "metadata";
+
+// このバージョンが対応している機能
+pub const FEATURES: &[&str] = &[
+    FEATURE_PARALLEL,
+    FEATURE_SPARSE,
+    FEATURE_SYMLINK,
+    FEATURE_KEEPALIVE,
+    FEATURE_RESPONSE,
+    FEATURE_ACK,
+    FEATURE_BATCH,
+    FEATURE_BUNDLE,
+    FEATURE_DRY_RUN,
+    FEATURE_VERIFY,
+    FEATURE_NOTE,
+    FEATURE_TRANSFER_ID,
+    FEATURE_QUEUE,
+    FEATURE_METADATA,
+];
+
+// 認証が必要なサーバーがバージョン情報の応答の機能の一覧に加える印（送信前の確認で使う）
+pub const AUTH_REQUIRED: &str = "auth-required";
+
+// バージョン情報に対応する前のバージョンが対応していた機能
+//
+// これ以降に追加する機能はここに含めず、バージョン情報で対応を確認してから使う
+pub const LEGACY_FEATURES: &[&str] = &[FEATURE_PARALLEL, FEATURE_SPARSE, FEATURE_SYMLINK];
+
+// スパースファイルのヘッダーに載せられるデータ領域の最大数
+pub const MAX_SPARSE_EXTENTS: u32 = 1 << 20;
+
+// 単一ストリーム転送のヘッダー
+pub struct FileHeader {
+    pub filename: String,
+    pub filedata_len: u32,
+}
+
+// 分割転送で各ストリームの先頭に送るヘッダー
+pub struct PartHeader {
+    pub transfer_id: Uuid,
+    pub part_count: u32,
+    pub file_size: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub filename: String,
+}
+
+// シンボリックリンクをリンクのまま送るヘッダー（データは続かない）
+pub struct SymlinkHeader {
+    pub filename: String,
+    pub target: String,
+}
+
+// スパースファイルのヘッダー（続けて各データ領域の中身を順に送る）
+pub struct SparseHeader {
+    pub filename: String,
+    pub file_size: u64,
+    // データ領域（オフセット, 長さ）の一覧。穴の部分は送らない
+    pub extents: Vec<(u64, u64)>,
+}
+
+// 認証トークンのヘッダー
+pub struct AuthHeader {
+    pub token: String,
+}
+
+// ssh-agent の鍵で認証するヘッダー
+pub struct SshAuthHeader {
+    // SSH の形式の公開鍵
+    pub public_key: Vec<u8>,
+}
+
+// 接続の維持を求めるヘッダー（サーバーは応答までの間 interval_secs ごとに PING を送る）
+pub struct KeepAliveHeader {
+    pub interval_secs: u32,
+}
+
+// 複数のファイルを送る前に送る一覧（データは続かない）
+//
+// サーバーはまとめて受け入れるかを1度だけ確認し、受け入れたバッチの各ファイルは
+// batch_id を付けた接続で確認なしに受け取る
+pub struct ManifestHeader {
+    pub batch_id: Uuid,
+    // 確認で表示する名前（フォルダ名など）
+    pub name: String,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl ManifestHeader {
+    // 一覧のファイルの合計サイズ
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+}
+
+// マニフェストの1ファイル分
+pub struct ManifestEntry {
+    // 各ファイルのヘッダーで送るのと同じ名前
+    pub filename: String,
+    pub size: u64,
+    // 内容の SHA-256（シンボリックリンクなど、中身を送らないものは None）
+    pub sha256: Option<String>,
+}
+
+// 小さなファイルをまとめて送るヘッダー
+//
+// ファイルごとに接続と応答を待つ往復を省くため、一覧の各ファイルのデータを1つの接続で続けて送る
+pub struct BundleHeader {
+    pub entries: Vec<BundleEntry>,
+}
+
+impl BundleHeader {
+    // 一覧のファイルの合計サイズ
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size as u64).sum()
+    }
+}
+
+// まとめて送る1ファイル分
+pub struct BundleEntry {
+    pub filename: String,
+    pub size: u32,
+}
+
+// バージョン情報（クライアントはヘッダーで、サーバーは応答の1行で送る）
+pub struct Hello {
+    pub version: String,
+    pub features: Vec<String>,
+}
+
+impl Hello {
+    // このバージョンの情報
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+        }
+    }
+
+    // バージョン情報に対応する前のバージョンとみなす場合
+    pub fn legacy() -> Self {
+        Self {
+            version: "不明".to_string(),
+            features: LEGACY_FEATURES
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect(),
+        }
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    // サーバーがトークンか鍵での認証を求めているか
+    pub fn requires_auth(&self) -> bool {
+        self.supports(AUTH_REQUIRED)
+    }
+
+    // サーバーの応答の1行（"HELLO 0.1.0 parallel,sparse,symlink"）
+    pub fn to_response(&self) -> String {
+        format!("HELLO {} {}", self.version, self.features.join(","))
+    }
+
+    pub fn parse_response(response: &str) -> Option<Self> {
+        let mut fields = response.trim().strip_prefix("HELLO ")?.split(' ');
+        let version = fields.next()?.to_string();
+        let features = split_features(fields.next().unwrap_or_default());
+        Some(Self { version, features })
+    }
+}
+
+// 受信の結果の種類（構造化した応答の reason。表示の文言が変わっても判定に使える）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    // ファイルを保存した
+    Saved,
+    // 分割転送の途中のストリームを書き込んだ
+    Part,
+    // 同じ内容のファイルが既にあったため保存を省略した
+    Duplicate,
+    NoSaveDirectory,
+    Unauthorized,
+    QuotaExceeded,
+    Declined,
+    // バッチを受け入れた（送らなくてよいファイルは skip に入る）
+    BatchAccepted,
+    // 検証を終えた（一致しないファイルは conflicts と missing に入る）
+    Verified,
+    // 保存先の空き容量が足りない
+    InsufficientStorage,
+    // 接続元ごとの接続数・転送数の上限を超えた
+    TooManyRequests,
+    // 受け入れた後に受信に失敗した（保存先が許可されていない・書き込めないなど）
+    Failed,
+    // 受信側が受け付けを一時停止している
+    Paused,
+    // 旧形式の応答で種類が分からないもの
+    #[serde(other)]
+    Unknown,
+}
+
+impl Reason {
+    // HTTP に倣った状態コード
+    pub fn status(self) -> u16 {
+        match self {
+            Reason::Saved | Reason::Part | Reason::BatchAccepted | Reason::Verified => 200,
+            Reason::Duplicate => 208,
+            Reason::Unauthorized => 401,
+            Reason::Declined => 403,
+            Reason::QuotaExceeded => 413,
+            Reason::TooManyRequests => 429,
+            Reason::NoSaveDirectory | Reason::Paused => 503,
+            Reason::InsufficientStorage => 507,
+            Reason::Failed | Reason::Unknown => 500,
+        }
+    }
+}
+
+// サーバーの応答
+//
+// FEATURE_RESPONSE に対応したクライアントが求めた場合は JSON の1行で、
+// それ以外は旧形式の "OK"・"ERROR: ..." の文字列で送る
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub status: u16,
+    pub reason: Reason,
+    // 拒否の理由など、人が読むための補足
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    // 保存先フォルダからの相対パスで表した、最終的な保存名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    // 保存した内容の SHA-256（受信側で計算した場合のみ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    // バッチのうち、受信側が既に持っているため送らなくてよいファイル
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip: Vec<String>,
+    // 試し送信で上書きされるファイル・検証で内容が一致しないファイル
+    // （保存先に内容の異なる同じ名前のファイルがある）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<String>,
+    // 検証で、保存先にないファイル
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<String>,
+    // 応答した転送のID（送信側が送ったもの。送られなければ受信側で割り当てたもの）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer_id: Option<Uuid>,
+}
+
+impl Response {
+    pub fn new(reason: Reason) -> Self {
+        Self {
+            status: reason.status(),
+            reason,
+            message: None,
+            filename: None,
+            sha256: None,
+            skip: Vec::new(),
+            conflicts: Vec::new(),
+            missing: Vec::new(),
+            transfer_id: None,
+        }
+    }
+
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    // 構造化した形式（改行で終わる JSON の1行）
+    pub fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap_or_default();
+        line.push('\n');
+        line
+    }
+
+    // 旧形式の文字列
+    pub fn to_legacy(&self) -> String {
+        let text = match self.reason {
+            Reason::Saved | Reason::Part | Reason::BatchAccepted | Reason::Verified => "OK",
+            Reason::Duplicate => "OK: already have it",
+            Reason::NoSaveDirectory => "ERROR: No save directory selected",
+            Reason::Unauthorized => "ERROR: Unauthorized",
+            Reason::QuotaExceeded => "ERROR: Quota exceeded",
+            Reason::Declined => "ERROR: Declined",
+            Reason::InsufficientStorage => "ERROR: Insufficient storage",
+            Reason::TooManyRequests => "ERROR: Too many requests",
+            Reason::Failed => "ERROR: Failed",
+            Reason::Paused => "ERROR: Paused",
+            // 旧形式の応答は受け取ったまま返す
+            Reason::Unknown => return self.message.clone().unwrap_or_else(|| "ERROR".to_string()),
+        };
+        match &self.message {
+            Some(message) => format!("{}: {}", text, message),
+            None => text.to_string(),
+        }
+    }
+
+    // 受け取った応答を読む（JSON でなければ旧形式として解釈する）
+    pub fn parse(response: &str) -> Self {
+        let response = response.trim();
+        if let Ok(parsed) = serde_json::from_str(response) {
+            return parsed;
+        }
+        let reason = match response {
+            "OK: already have it" => Reason::Duplicate,
+            _ if response.starts_with("OK") => Reason::Saved,
+            _ => [
+                Reason::NoSaveDirectory,
+                Reason::Unauthorized,
+                Reason::QuotaExceeded,
+                Reason::InsufficientStorage,
+                Reason::TooManyRequests,
+                Reason::Declined,
+                Reason::Failed,
+                Reason::Paused,
+            ]
+            .into_iter()
+            .find(|reason| response.starts_with(&Self::new(*reason).to_legacy()))
+            .unwrap_or(Reason::Unknown),
+        };
+        let mut parsed = Self::new(reason);
+        // 失敗の応答は ": " の後に補足が続くことがある
+        if !parsed.is_success() {
+            let message = match reason {
+                Reason::Unknown => response,
+                _ => response[parsed.to_legacy().len()..].trim_start_matches(": "),
+            };
+            if !message.is_empty() {
+                parsed.message = Some(message.to_string());
+            }
+        }
+        parsed
+    }
+}
+
+impl std::fmt::Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_legacy())?;
+        if let Some(filename) = &self.filename {
+            write!(f, "（保存名: {}）", filename)?;
+        }
+        Ok(())
+    }
+}
+
+pub enum Header {
+    Auth(AuthHeader),
+    SshAuth(SshAuthHeader),
+    Hello(Hello),
+    KeepAlive(KeepAliveHeader),
+    // 応答を構造化した形式で求める（データは続かない）
+    Response,
+    // 受信済みバイト数の通知を求める（データは続かない）
+    Ack,
+    // 受け入れ済みのバッチに含まれる転送であることを示す（データは続かない）
+    Batch(Uuid),
+    // 続くマニフェストを試し送信として確認する（データは続かない）
+    DryRun,
+    // 続くマニフェストのファイルを検証する（データは続かない）
+    Verify,
+    // 転送に添えるメモ（データは続かない）
+    Note(String),
+    // 送信側が割り当てた転送ID（データは続かない）
+    TransferId(Uuid),
+    // 順番待ちの通知を求める（データは続かない）
+    Queue,
+    // 転送に添えるメタデータ（データは続かない）
+    Metadata(BTreeMap<String, String>),
+    Manifest(ManifestHeader),
+    Bundle(BundleHeader),
+    File(FileHeader),
+    Part(PartHeader),
+    Symlink(SymlinkHeader),
+    Sparse(SparseHeader),
+}
+
+// 接続の先頭からヘッダーを読み取る
+//
+// 解釈は frame::HeaderDecoder に任せ、ここでは足りないと言われた分だけを読む
+// （ヘッダーに続くデータは読まず、一覧のあるヘッダーは読んだ要素から捨てていく）
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Header> {
+    let mut decoder = HeaderDecoder::default();
+    let mut buf = Vec::new();
+    loop {
+        match decoder.decode(&buf)? {
+            Decoded::Header(header, _) => return Ok(header),
+            Decoded::Chunk(used) => {
+                buf.drain(..used);
+            }
+            Decoded::Incomplete(needed) => {
+                let start = buf.len();
+                buf.resize(needed, 0);
+                reader
+                    .read_exact(&mut buf[start..])
+                    .await
+                    .context("ヘッダーの読み取りに失敗")?;
+            }
+        }
+    }
+}
+
+// SSH の公開鍵・署名（長さ付きのバイト列）を読む
+async fn read_ssh_blob<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>> {
+    let len = reader.read_u32().await?;
+    if len > MAX_SSH_BLOB_LEN {
+        anyhow::bail!("長すぎます: {} バイト", len);
+    }
+    let mut blob = vec![0u8; len as usize];
+    reader.read_exact(&mut blob).await?;
+    Ok(blob)
+}
+
+// ssh-agent の鍵での認証で、チャレンジに対する署名を読む
+pub async fn read_ssh_signature<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>> {
+    read_ssh_blob(reader).await.context("署名の読み取りに失敗")
+}
+
+// メタデータのキーとして使えるか（英数字と "-", "_", ".", ":" だけ）
+pub fn check_metadata_key(key: &str) -> Result<()> {
+    if key.is_empty() || key.len() > MAX_METADATA_KEY_LEN {
+        anyhow::bail!(
+            "メタデータのキーは1〜{} バイトで指定してください: {}",
+            MAX_METADATA_KEY_LEN,
+            key
+        );
+    }
+    if !key
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+    {
+        anyhow::bail!(
+            "メタデータのキーに使えない文字が含まれています（英数字と -_.: が使えます）: {}",
+            key
+        );
+    }
+    Ok(())
+}
+
+// メタデータの組の数・合計の長さが上限に収まるか
+pub fn check_metadata(metadata: &BTreeMap<String, String>) -> Result<()> {
+    if metadata.len() > MAX_METADATA_ENTRIES as usize {
+        anyhow::bail!(
+            "メタデータは {} 組以内で指定してください",
+            MAX_METADATA_ENTRIES
+        );
+    }
+    let len: usize = metadata
+        .iter()
+        .map(|(key, value)| key.len() + value.len())
+        .sum();
+    if len > MAX_METADATA_LEN {
+        anyhow::bail!(
+            "メタデータのキーと値は合計 {} バイト以内で指定してください",
+            MAX_METADATA_LEN
+        );
+    }
+    metadata.keys().try_for_each(|key| check_metadata_key(key))
+}
+
+// "parallel,sparse" を機能の名前の一覧にする
+pub fn split_features(features: &str) -> Vec<String> {
+    features
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// 単一ストリーム転送のヘッダーを書き込む
+pub async fn write_file_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &FileHeader,
+) -> Result<()> {
+    writer.write_u32(header.filename.len() as u32).await?;
+    writer.write_u32(header.filedata_len).await?;
+    writer.write_all(header.filename.as_bytes()).await?;
+    Ok(())
+}
+
+// 分割転送のヘッダーを書き込む
+pub async fn write_part_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &PartHeader,
+) -> Result<()> {
+    writer.write_u32(PART_HEADER_MARKER).await?;
+    writer.write_all(header.transfer_id.as_bytes()).await?;
+    writer.write_u32(header.part_count).await?;
+    writer.write_u64(header.file_size).await?;
+    writer.write_u64(header.offset).await?;
+    writer.write_u64(header.length).await?;
+    writer.write_u32(header.filename.len() as u32).await?;
+    writer.write_all(header.filename.as_bytes()).await?;
+    Ok(())
+}
+
+// シンボリックリンクのヘッダーを書き込む
+pub async fn write_symlink_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &SymlinkHeader,
+) -> Result<()> {
+    writer.write_u32(SYMLINK_HEADER_MARKER).await?;
+    writer.write_u32(header.filename.len() as u32).await?;
+    writer.write_all(header.filename.as_bytes()).await?;
+    writer.write_u32(header.target.len() as u32).await?;
+    writer.write_all(header.target.as_bytes()).await?;
+    Ok(())
+}
+
+// スパースファイルのヘッダーを書き込む
+pub async fn write_sparse_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &SparseHeader,
+) -> Result<()> {
+    writer.write_u32(SPARSE_HEADER_MARKER).await?;
+    writer.write_u32(header.filename.len() as u32).await?;
+    writer.write_all(header.filename.as_bytes()).await?;
+    writer.write_u64(header.file_size).await?;
+    writer.write_u32(header.extents.len() as u32).await?;
+    for (offset, length) in &header.extents {
+        writer.write_u64(*offset).await?;
+        writer.write_u64(*length).await?;
+    }
+    Ok(())
+}
+
+// 認証トークンのヘッダーを書き込む
+pub async fn write_auth_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &AuthHeader,
+) -> Result<()> {
+    writer.write_u32(AUTH_HEADER_MARKER).await?;
+    writer.write_u32(header.token.len() as u32).await?;
+    writer.write_all(header.token.as_bytes()).await?;
+    Ok(())
+}
+
+// ssh-agent の鍵で認証するヘッダーを書き込む
+pub async fn write_ssh_auth_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &SshAuthHeader,
+) -> Result<()> {
+    writer.write_u32(SSH_AUTH_HEADER_MARKER).await?;
+    writer.write_u32(header.public_key.len() as u32).await?;
+    writer.write_all(&header.public_key).await?;
+    Ok(())
+}
+
+// ssh-agent の鍵での認証で、チャレンジに対する署名を書き込む
+pub async fn write_ssh_signature<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    signature: &[u8],
+) -> Result<()> {
+    writer.write_u32(signature.len() as u32).await?;
+    writer.write_all(signature).await?;
+    Ok(())
+}
+
+// バージョン情報のヘッダーを書き込む
+pub async fn write_hello_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    hello: &Hello,
+) -> Result<()> {
+    let features = hello.features.join(",");
+    writer.write_u32(HELLO_HEADER_MARKER).await?;
+    writer.write_u32(hello.version.len() as u32).await?;
+    writer.write_all(hello.version.as_bytes()).await?;
+    writer.write_u32(features.len() as u32).await?;
+    writer.write_all(features.as_bytes()).await?;
+    Ok(())
+}
+
+// 接続の維持を求めるヘッダーを書き込む
+pub async fn write_keepalive_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &KeepAliveHeader,
+) -> Result<()> {
+    writer.write_u32(KEEPALIVE_HEADER_MARKER).await?;
+    writer.write_u32(header.interval_secs).await?;
+    Ok(())
+}
+
+// マニフェストのヘッダーを書き込む
+pub async fn write_manifest_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &ManifestHeader,
+) -> Result<()> {
+    writer.write_u32(MANIFEST_HEADER_MARKER).await?;
+    writer.write_all(header.batch_id.as_bytes()).await?;
+    writer.write_u32(header.name.len() as u32).await?;
+    writer.write_all(header.name.as_bytes()).await?;
+    writer.write_u32(header.entries.len() as u32).await?;
+    for entry in &header.entries {
+        writer.write_u32(entry.filename.len() as u32).await?;
+        writer.write_all(entry.filename.as_bytes()).await?;
+        writer.write_u64(entry.size).await?;
+        let sha256 = entry.sha256.as_deref().unwrap_or_default();
+        writer.write_u32(sha256.len() as u32).await?;
+        writer.write_all(sha256.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+// 小さなファイルをまとめて送るヘッダーを書き込む（続けて各ファイルのデータを一覧の順に送る）
+pub async fn write_bundle_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &BundleHeader,
+) -> Result<()> {
+    writer.write_u32(BUNDLE_HEADER_MARKER).await?;
+    writer.write_u32(header.entries.len() as u32).await?;
+    for entry in &header.entries {
+        writer.write_u32(entry.filename.len() as u32).await?;
+        writer.write_all(entry.filename.as_bytes()).await?;
+        writer.write_u32(entry.size).await?;
+    }
+    Ok(())
+}
+
+// 受け入れ済みのバッチに含まれる転送であることを示すヘッダーを書き込む
+pub async fn write_batch_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    batch_id: Uuid,
+) -> Result<()> {
+    writer.write_u32(BATCH_HEADER_MARKER).await?;
+    writer.write_all(batch_id.as_bytes()).await?;
+    Ok(())
+}
+
+pub async fn write_response_header<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<()> {
+    writer.write_u32(RESPONSE_HEADER_MARKER).await?;
+    Ok(())
+}
+
+pub async fn write_dry_run_header<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<()> {
+    writer.write_u32(DRY_RUN_HEADER_MARKER).await?;
+    Ok(())
+}
+
+pub async fn write_verify_header<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<()> {
+    writer.write_u32(VERIFY_HEADER_MARKER).await?;
+    Ok(())
+}
+
+pub async fn write_note_header<W: AsyncWrite + Unpin>(writer: &mut W, note: &str) -> Result<()> {
+    writer.write_u32(NOTE_HEADER_MARKER).await?;
+    writer.write_u32(note.len() as u32).await?;
+    writer.write_all(note.as_bytes()).await?;
+    Ok(())
+}
+
+pub async fn write_metadata_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    metadata: &BTreeMap<String, String>,
+) -> Result<()> {
+    writer.write_u32(METADATA_HEADER_MARKER).await?;
+    writer.write_u32(metadata.len() as u32).await?;
+    for (key, value) in metadata {
+        writer.write_u32(key.len() as u32).await?;
+        writer.write_all(key.as_bytes()).await?;
+        writer.write_u32(value.len() as u32).await?;
+        writer.write_all(value.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+pub async fn write_transfer_id_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    transfer_id: Uuid,
+) -> Result<()> {
+    writer.write_u32(TRANSFER_ID_HEADER_MARKER).await?;
+    writer.write_all(transfer_id.as_bytes()).await?;
+    Ok(())
+}
+
+pub async fn write_queue_header<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<()> {
+    writer.write_u32(QUEUE_HEADER_MARKER).await?;
+    Ok(())
+}
+
+// 受信の順番待ちで何番目かを通知する
+pub async fn write_queued<W: AsyncWrite + Unpin>(writer: &mut W, position: u32) -> Result<()> {
+    let mut frame = [0u8; 5];
+    frame[0] = QUEUED;
+    frame[1..].copy_from_slice(&position.to_be_bytes());
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub async fn write_ack_header<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<()> {
+    writer.write_u32(ACK_HEADER_MARKER).await?;
+    Ok(())
+}
+
+// 接続の開始からディスクに書き込んだバイト数を通知する
+pub async fn write_ack<W: AsyncWrite + Unpin>(writer: &mut W, bytes: u64) -> Result<()> {
+    let mut frame = [0u8; 9];
+    frame[0] = ACK;
+    frame[1..].copy_from_slice(&bytes.to_be_bytes());
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
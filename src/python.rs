@@ -0,0 +1,227 @@
+// Python から使うモジュール file_transfer（python フィーチャー。pyproject.toml で maturin からビルドする）
+//
+// 送受信は共有のランタイムで動き、経過や受信の確認はコールバックで受け取る
+use crate::{
+    cancel::Cancelled,
+    client::{self, ClientArgs},
+    config::Config,
+    events::{TransferEvent, TransferEvents},
+    server::{FileReceiver, IncomingTransfer, ServerArgs},
+};
+use clap::Parser;
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+use std::{
+    path::PathBuf,
+    pin::pin,
+    sync::{Arc, OnceLock},
+};
+use tokio::runtime::Runtime;
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+
+create_exception!(
+    file_transfer,
+    CancelledError,
+    PyException,
+    "転送が中断された"
+);
+
+// すべての送受信で共有するランタイム
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("非同期ランタイムの起動に失敗"))
+}
+
+fn to_py_err(e: anyhow::Error) -> PyErr {
+    if e.is::<Cancelled>() {
+        CancelledError::new_err(e.to_string())
+    } else {
+        PyException::new_err(format!("{:#}", e))
+    }
+}
+
+// 経過を辞書にして Python のコールバックに渡す
+struct PyEvents {
+    callback: PyObject,
+}
+
+impl TransferEvents for PyEvents {
+    fn on_event(&self, id: u64, event: TransferEvent) {
+        Python::with_gil(|py| {
+            let result = event_dict(py, id, event)
+                .and_then(|event| self.callback.call1(py, (event,)).map(drop));
+            if let Err(e) = result {
+                e.print(py);
+            }
+        });
+    }
+}
+
+// {"id": 1, "kind": "progress", "bytes": 100, "total": 200} のような辞書にする
+fn event_dict(py: Python<'_>, id: u64, event: TransferEvent) -> PyResult<&PyDict> {
+    let dict = PyDict::new(py);
+    dict.set_item("id", id)?;
+    let kind = match event {
+        TransferEvent::Started {
+            peer,
+            filename,
+            total,
+            note,
+            transfer_id,
+        } => {
+            dict.set_item("peer", peer)?;
+            dict.set_item("filename", filename)?;
+            dict.set_item("total", total)?;
+            dict.set_item("note", note)?;
+            dict.set_item("transfer_id", transfer_id.to_string())?;
+            "started"
+        }
+        TransferEvent::Progress { bytes, total } => {
+            dict.set_item("bytes", bytes)?;
+            dict.set_item("total", total)?;
+            "progress"
+        }
+        TransferEvent::Completed => "completed",
+        TransferEvent::Failed(e) => {
+            dict.set_item("message", e)?;
+            "failed"
+        }
+        TransferEvent::Cancelled => "cancelled",
+        TransferEvent::Rejected(response) => {
+            dict.set_item("message", response)?;
+            "rejected"
+        }
+    };
+    dict.set_item("kind", kind)?;
+    Ok(dict)
+}
+
+fn events_of(on_event: Option<PyObject>) -> Option<Arc<dyn TransferEvents>> {
+    on_event.map(|callback| Arc::new(PyEvents { callback }) as Arc<dyn TransferEvents>)
+}
+
+// path（ファイルまたはフォルダ）を addr に送信し、終わるまで待つ
+//
+// on_event を渡すと経過の辞書を引数に呼び出す。受信側が受け付けなかった場合なども例外になる
+#[pyfunction]
+#[pyo3(signature = (addr, path, on_event=None))]
+fn send_file(
+    py: Python<'_>,
+    addr: &str,
+    path: PathBuf,
+    on_event: Option<PyObject>,
+) -> PyResult<()> {
+    let config = Config::load().map_err(to_py_err)?;
+    // 不正な addr で clap がプロセスを終了させないよう、例外にする
+    let args = ClientArgs::try_parse_from(["client", "--server", addr]).map_err(|e| {
+        PyValueError::new_err(format!("送信先のアドレスが不正です: {}: {}", addr, e))
+    })?;
+    let events = events_of(on_event);
+    let cancel = CancellationToken::new();
+    py.allow_threads(|| {
+        runtime().block_on(client::send_path(&args, &config, &path, events, &cancel))
+    })
+    .map_err(to_py_err)
+}
+
+// save_dir への受信（作った時点で待ち受けを始め、stop か破棄で終了する）
+//
+// on_incoming を渡すと (peer, filename, size) を引数に呼び出し、真を返したものだけを受け入れる。
+// 渡さなければすべて受け入れる
+#[pyclass]
+struct Receiver {
+    cancel: CancellationToken,
+}
+
+#[pymethods]
+impl Receiver {
+    #[new]
+    #[pyo3(signature = (save_dir, on_event=None, on_incoming=None))]
+    fn new(
+        py: Python<'_>,
+        save_dir: PathBuf,
+        on_event: Option<PyObject>,
+        on_incoming: Option<PyObject>,
+    ) -> PyResult<Self> {
+        let config = Config::load().map_err(to_py_err)?;
+        let args = ServerArgs::try_parse_from(["server"])
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let events = events_of(on_event);
+        let cancel = CancellationToken::new();
+        let receiver = py
+            .allow_threads(|| {
+                runtime().block_on(FileReceiver::start(
+                    &args,
+                    &config,
+                    save_dir,
+                    events,
+                    cancel.clone(),
+                ))
+            })
+            .map_err(to_py_err)?;
+
+        let on_incoming = on_incoming.map(Arc::new);
+        runtime().spawn(async move {
+            let mut incoming = pin!(receiver.incoming());
+            while let Some(transfer) = incoming.next().await {
+                // コールバックの間も他の受信が止まらないよう別のスレッドで確認する
+                let on_incoming = on_incoming.clone();
+                tokio::task::spawn_blocking(move || answer(on_incoming.as_deref(), transfer));
+            }
+        });
+        Ok(Self { cancel })
+    }
+
+    // 待ち受けを終了し、受信中の転送も中断する
+    fn stop(&self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+// on_incoming の戻り値で受け入れるか決める（例外の場合は拒否する）
+fn answer(on_incoming: Option<&PyObject>, transfer: IncomingTransfer) {
+    let Some(on_incoming) = on_incoming else {
+        transfer.accept();
+        return;
+    };
+    let accepted = Python::with_gil(|py| {
+        on_incoming
+            .call1(
+                py,
+                (
+                    transfer.peer.as_str(),
+                    transfer.filename.as_str(),
+                    transfer.size,
+                ),
+            )
+            .and_then(|result| result.is_true(py))
+            .unwrap_or_else(|e| {
+                e.print(py);
+                false
+            })
+    });
+    if accepted {
+        transfer.accept();
+    } else {
+        transfer.reject("");
+    }
+}
+
+#[pymodule]
+fn file_transfer(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(send_file, m)?)?;
+    m.add_class::<Receiver>()?;
+    m.add("CancelledError", py.get_type::<CancelledError>())?;
+    Ok(())
+}
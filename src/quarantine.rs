@@ -0,0 +1,282 @@
+use crate::{
+    checksum, dedup, received, template,
+    walk::{self, LinkPolicy, WalkEntry, WalkOptions},
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+// 転送ごとの隔離フォルダに置く、隔離した転送の情報
+const RECORD_FILE_NAME: &str = "quarantine.toml";
+// 転送ごとの隔離フォルダの中で、受信したファイルを置くフォルダ
+const FILES_DIR: &str = "files";
+
+// 同じ転送の接続が同時に届いても、隔離フォルダを1つだけ作るよう admit を直列化する
+static ADMIT_LOCK: Mutex<()> = Mutex::new(());
+
+// 隔離した転送（`file-transfer release` で本来の保存先に移すまで隔離フォルダに置く）
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    // 隔離ID（受信側で作る、転送ごとの隔離フォルダの名前）
+    #[serde(skip)]
+    pub id: String,
+    // 送信側が付けた転送ID（送信元が同じ場合だけ、同じ隔離フォルダに受信する）
+    pub transfer_id: String,
+    // 解放したときに移す、本来の保存先フォルダ
+    pub save_dir: PathBuf,
+    pub sender: String,
+    // 送信側が転送に添えたメモ
+    pub note: Option<String>,
+    // 送信側が転送に添えたメタデータ
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    // 解放したファイルを索引に記録するか（サーバーが --no-index でなければ記録する）
+    pub index: bool,
+    pub quarantined_at: i64,
+}
+
+// 隔離フォルダ（転送ごとのフォルダをこの中に作る）
+pub fn root() -> Result<PathBuf> {
+    dirs::config_dir()
+        .map(|dir| dir.join("file-transfer").join("quarantine"))
+        .context("設定フォルダが見つかりません")
+}
+
+// path が隔離フォルダの中か（隔離して受け入れたバッチの続きのファイルなど）
+pub fn contains(path: &Path) -> bool {
+    root().is_ok_and(|root| path.starts_with(root))
+}
+
+// 転送を隔離し、隔離IDと受信したファイルを置くフォルダを返す
+//
+// 同じ送信元からの同じ転送の2本目以降の接続（分割転送のストリームなど）は、最初の接続の記録のまま
+// 同じフォルダに受信する。転送IDは送信側が付けるため、別の送信元が同じ転送IDを使っても別のフォルダにする
+pub fn admit(
+    transfer_id: Uuid,
+    save_dir: &Path,
+    sender: &str,
+    note: Option<&str>,
+    metadata: &BTreeMap<String, String>,
+    index: bool,
+) -> Result<(String, PathBuf)> {
+    let _guard = ADMIT_LOCK.lock().unwrap();
+    let root = root()?;
+    create_private_dir(&root)?;
+    let transfer_id = transfer_id.to_string();
+    let existing = list()?
+        .into_iter()
+        .find(|record| record.transfer_id == transfer_id && record.sender == sender);
+    let id = existing.map_or_else(|| Uuid::new_v4().simple().to_string(), |record| record.id);
+    let dir = root.join(&id);
+    create_private_dir(&dir)?;
+
+    let record_path = dir.join(RECORD_FILE_NAME);
+    if !record_path.exists() {
+        let record = Record {
+            id: id.clone(),
+            transfer_id,
+            save_dir: fs::canonicalize(save_dir).unwrap_or_else(|_| save_dir.to_path_buf()),
+            sender: sender.to_string(),
+            note: note.map(str::to_string),
+            metadata: metadata.clone(),
+            index,
+            quarantined_at: chrono::Utc::now().timestamp(),
+        };
+        fs::write(&record_path, toml::to_string(&record)?)
+            .with_context(|| format!("隔離した転送の情報の保存に失敗: {:?}", record_path))?;
+    }
+
+    let files = dir.join(FILES_DIR);
+    create_private_dir(&files)?;
+    Ok((id, files))
+}
+
+// 他のユーザーから読めないよう、所有者だけが使えるフォルダを作る
+fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("隔離フォルダの作成に失敗: {:?}", dir))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+            .with_context(|| format!("隔離フォルダの権限の設定に失敗: {:?}", dir))?;
+    }
+    Ok(())
+}
+
+// 隔離した転送の一覧（古い順。情報を読めないフォルダは飛ばす）
+pub fn list() -> Result<Vec<Record>> {
+    let root = root()?;
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut records = Vec::new();
+    for entry in
+        fs::read_dir(&root).with_context(|| format!("隔離フォルダの読み込みに失敗: {:?}", root))?
+    {
+        let entry = entry?;
+        let Ok(text) = fs::read_to_string(entry.path().join(RECORD_FILE_NAME)) else {
+            continue;
+        };
+        if let Ok(mut record) = toml::from_str::<Record>(&text) {
+            record.id = entry.file_name().to_string_lossy().into_owned();
+            records.push(record);
+        }
+    }
+    records.sort_by_key(|record| record.quarantined_at);
+    Ok(records)
+}
+
+// 隔離ID（先頭の一部でもよい）に一致する隔離した転送
+fn find(id: &str) -> Result<Record> {
+    let mut records: Vec<Record> = list()?
+        .into_iter()
+        .filter(|record| record.id.starts_with(id))
+        .collect();
+    if records.len() > 1 {
+        anyhow::bail!(
+            "{} に一致する隔離した転送が {} 件あります。隔離IDをもっと長く指定してください",
+            id,
+            records.len()
+        );
+    }
+    records
+        .pop()
+        .with_context(|| format!("隔離した転送 {} はありません", id))
+}
+
+// 転送ごとの隔離フォルダ
+fn dir_of(record: &Record) -> Result<PathBuf> {
+    Ok(root()?.join(&record.id))
+}
+
+// 隔離フォルダに受信したファイル
+//
+// 受信の途中の一時ファイルと、重複の検出・ハッシュの一覧・ミラーのために保存先に作るものは含めない
+fn received_files(record: &Record) -> Result<Vec<WalkEntry>> {
+    let dir = dir_of(record)?.join(FILES_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let options = WalkOptions {
+        no_ftignore: true,
+        links: LinkPolicy::Preserve,
+        ..WalkOptions::default()
+    };
+    let files = walk::collect_files(&dir, &options)?
+        .into_iter()
+        .filter(|entry| {
+            let relative = entry.relative.as_str();
+            !relative.ends_with(".part")
+                && !relative.ends_with(".part.resume")
+                && relative != dedup::INDEX_FILE_NAME
+                && relative != checksum::MANIFEST_FILE_NAME
+        })
+        .collect();
+    Ok(files)
+}
+
+// 隔離した転送のファイルを本来の保存先に移し、移したパスを返す
+//
+// 保存先に同じ名前のファイルが1つでもあれば、上書きせずに何も移さない
+pub fn release(id: &str) -> Result<(Record, Vec<PathBuf>)> {
+    let record = find(id)?;
+    let files = received_files(&record)?;
+    for entry in &files {
+        let destination = template::resolve(&record.save_dir, Path::new(&entry.relative));
+        if destination.symlink_metadata().is_ok() {
+            anyhow::bail!(
+                "保存先に同じ名前のファイルがあるため解放できません: {:?}",
+                destination
+            );
+        }
+    }
+
+    let mut released = Vec::new();
+    for entry in &files {
+        let destination = template::join(&record.save_dir, Path::new(&entry.relative))?;
+        // 保存先が別のドライブの場合は名前の変更ができないため複製する
+        fs::rename(&entry.path, &destination)
+            .or_else(|_| {
+                fs::copy(&entry.path, &destination)?;
+                fs::remove_file(&entry.path)
+            })
+            .with_context(|| format!("隔離したファイルの移動に失敗: {:?}", entry.path))?;
+        if record.index {
+            received::record(
+                &record.save_dir,
+                &destination,
+                None,
+                &record.sender,
+                record.note.as_deref(),
+                &record.metadata,
+            )?;
+        }
+        released.push(destination);
+    }
+
+    let dir = dir_of(&record)?;
+    fs::remove_dir_all(&dir).with_context(|| format!("隔離フォルダの削除に失敗: {:?}", dir))?;
+    Ok((record, released))
+}
+
+// 隔離した転送を、ファイルごと削除する
+pub fn discard(id: &str) -> Result<Record> {
+    let record = find(id)?;
+    let dir = dir_of(&record)?;
+    fs::remove_dir_all(&dir).with_context(|| format!("隔離フォルダの削除に失敗: {:?}", dir))?;
+    Ok(record)
+}
+
+// release サブコマンドの実装（id を省略すると隔離した転送の一覧を表示する）
+pub fn run(id: Option<&str>, discard_files: bool) -> Result<()> {
+    let Some(id) = id else {
+        let records = list()?;
+        if records.is_empty() {
+            println!("隔離した転送はありません");
+        }
+        for record in &records {
+            let quarantined_at =
+                chrono::DateTime::<chrono::Utc>::from_timestamp(record.quarantined_at, 0)
+                    .map(|time| {
+                        time.with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+            println!("{}  {}  {}", quarantined_at, record.id, record.sender);
+            println!("    転送ID: {}", record.transfer_id);
+            println!("    保存先: {:?}", record.save_dir);
+            if let Some(note) = &record.note {
+                println!("    メモ: {}", note);
+            }
+            for entry in received_files(record)? {
+                println!("    {}", entry.relative);
+            }
+        }
+        return Ok(());
+    };
+
+    if discard_files {
+        let record = discard(id)?;
+        println!("隔離した転送 {} を削除しました", record.id);
+        return Ok(());
+    }
+    let (record, released) = release(id)?;
+    for path in &released {
+        println!("{:?}", path);
+    }
+    println!(
+        "隔離した転送 {} の {} 個のファイルを {:?} に移しました",
+        record.id,
+        released.len(),
+        record.save_dir
+    );
+    Ok(())
+}
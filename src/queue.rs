@@ -0,0 +1,148 @@
+use crate::{client, config::Config};
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use uuid::Uuid;
+
+// queue サブコマンドの操作
+#[derive(Subcommand)]
+pub enum QueueCommand {
+    /// 送信待ちのファイルを一覧表示する
+    List,
+    /// 送信待ちのファイルを一覧から除く
+    Remove {
+        /// 送信待ちのID
+        id: String,
+    },
+    /// 送信待ちの一覧を空にする
+    Clear,
+    /// 送信先に接続できるようになるまで、送信待ちのファイルを送り直し続ける（一覧が空になったら終了）
+    Run {
+        /// 送り直す間隔（秒）
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+}
+
+// 送信先に接続できなかったため、後で送るファイル
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    // 送信時の --server（省略した場合は None）
+    pub server: Option<String>,
+    pub path: PathBuf,
+    pub queued_at: i64,
+}
+
+// 送信待ちの一覧ファイル（queue.toml）の内容
+#[derive(Default, Serialize, Deserialize)]
+struct Queue {
+    #[serde(default)]
+    jobs: Vec<Job>,
+}
+
+impl Queue {
+    // 送信待ちの一覧ファイルのパス
+    fn path() -> Result<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join("file-transfer").join("queue.toml"))
+            .context("設定フォルダが見つかりません")
+    }
+
+    fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("送信待ちの一覧の読み込みに失敗: {:?}", path))?;
+        toml::from_str(&text).with_context(|| format!("送信待ちの一覧の解析に失敗: {:?}", path))
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self)?;
+        fs::write(&path, text).with_context(|| format!("送信待ちの一覧の保存に失敗: {:?}", path))
+    }
+}
+
+// queue サブコマンドの実装
+pub async fn run(command: &QueueCommand, config: &Config) -> Result<()> {
+    match command {
+        QueueCommand::List => {
+            let queue = Queue::load()?;
+            if queue.jobs.is_empty() {
+                println!("送信待ちのファイルはありません");
+            }
+            for job in &queue.jobs {
+                println!(
+                    "{}  {}  {:?}  {}",
+                    job.id,
+                    job.server.as_deref().unwrap_or("localhost"),
+                    job.path,
+                    chrono::DateTime::<chrono::Utc>::from_timestamp(job.queued_at, 0)
+                        .map(|time| time
+                            .with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string())
+                        .unwrap_or_default()
+                );
+            }
+        }
+        QueueCommand::Remove { id } => {
+            if !remove(id)? {
+                anyhow::bail!("送信待ち {} は存在しません", id);
+            }
+            println!("送信待ち {} を一覧から除きました", id);
+        }
+        QueueCommand::Clear => {
+            Queue::default().save()?;
+            println!("送信待ちの一覧を空にしました");
+        }
+        QueueCommand::Run { interval } => {
+            client::run_queue(config, Duration::from_secs(*interval)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// 送信待ちの一覧に入れ、IDを返す
+pub fn push(server: Option<&str>, path: &Path) -> Result<String> {
+    let mut queue = Queue::load()?;
+    let id = Uuid::new_v4().simple().to_string()[..8].to_string();
+    queue.jobs.push(Job {
+        id: id.clone(),
+        server: server.map(str::to_string),
+        // 別のフォルダから送り直しても同じファイルを指すよう、絶対パスにする
+        path: fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+        queued_at: chrono::Utc::now().timestamp(),
+    });
+    queue.save()?;
+    Ok(id)
+}
+
+// 送信待ちの一覧（入れた順）
+pub fn jobs() -> Result<Vec<Job>> {
+    Ok(Queue::load()?.jobs)
+}
+
+// 送信待ちの一覧から除く（存在しなかった場合は false）
+pub fn remove(id: &str) -> Result<bool> {
+    let mut queue = Queue::load()?;
+    let len = queue.jobs.len();
+    queue.jobs.retain(|job| job.id != id);
+    if queue.jobs.len() == len {
+        return Ok(false);
+    }
+    queue.save()?;
+    Ok(true)
+}
@@ -0,0 +1,184 @@
+use crate::{
+    dedup,
+    walk::{self, WalkOptions},
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+// 受信したファイルの索引の1件（索引ファイル received.jsonl の1行）
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    // 保存したときのファイル名
+    pub filename: String,
+    // 保存したパス
+    pub path: PathBuf,
+    // 受信したときの保存先フォルダ（ファイルが移動された場合に探す範囲）
+    pub save_dir: PathBuf,
+    pub size: u64,
+    // 計算しなかった場合は None
+    pub sha256: Option<String>,
+    pub sender: String,
+    // 送信側が転送に添えたメモ
+    pub note: Option<String>,
+    // 送信側が転送に添えたメタデータ（メタデータに対応する前の索引の行にはない）
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    pub received_at: i64,
+}
+
+impl Entry {
+    // query（大文字・小文字は区別しない）がファイル名・パス・送信元・メモ・ハッシュ・メタデータの値のいずれかに含まれるか
+    fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        let path = self.path.to_string_lossy();
+        [
+            Some(self.filename.as_str()),
+            Some(path.as_ref()),
+            Some(self.sender.as_str()),
+            self.note.as_deref(),
+            self.sha256.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.metadata.values().map(String::as_str))
+        .any(|field| field.to_lowercase().contains(&query))
+    }
+
+    // ファイルの今の場所
+    //
+    // 保存したパスになければ、保存先フォルダの中から同じ名前・サイズ（ハッシュがあれば同じ内容）の
+    // ファイルを探す（日付ごとのフォルダなどに移した場合）
+    pub fn locate(&self) -> Result<Option<PathBuf>> {
+        if self.is_same_file(&self.path)? {
+            return Ok(Some(self.path.clone()));
+        }
+        if !self.save_dir.is_dir() {
+            return Ok(None);
+        }
+        for entry in walk::collect_files(&self.save_dir, &WalkOptions::default())? {
+            let same_name = entry
+                .path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy() == self.filename);
+            if same_name && self.is_same_file(&entry.path)? {
+                return Ok(Some(entry.path));
+            }
+        }
+        Ok(None)
+    }
+
+    fn is_same_file(&self, path: &Path) -> Result<bool> {
+        let Ok(metadata) = fs::metadata(path) else {
+            return Ok(false);
+        };
+        if !metadata.is_file() || metadata.len() != self.size {
+            return Ok(false);
+        }
+        match &self.sha256 {
+            Some(hash) => Ok(dedup::sha256_file(path)? == *hash),
+            None => Ok(true),
+        }
+    }
+}
+
+// 索引ファイルのパス
+fn index_path() -> Result<PathBuf> {
+    dirs::config_dir()
+        .map(|dir| dir.join("file-transfer").join("received.jsonl"))
+        .context("設定フォルダが見つかりません")
+}
+
+// 保存したファイルを索引に追記する
+pub fn record(
+    save_dir: &Path,
+    path: &Path,
+    sha256: Option<&str>,
+    sender: &str,
+    note: Option<&str>,
+    metadata: &BTreeMap<String, String>,
+) -> Result<()> {
+    let entry = Entry {
+        filename: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        path: fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+        save_dir: fs::canonicalize(save_dir).unwrap_or_else(|_| save_dir.to_path_buf()),
+        size: fs::metadata(path)?.len(),
+        sha256: sha256.map(str::to_string),
+        sender: sender.to_string(),
+        note: note.map(str::to_string),
+        metadata: metadata.clone(),
+        received_at: chrono::Utc::now().timestamp(),
+    };
+
+    let index_path = index_path()?;
+    if let Some(dir) = index_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut index = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&index_path)
+        .with_context(|| format!("受信したファイルの索引を開けません: {:?}", index_path))?;
+    writeln!(index, "{}", serde_json::to_string(&entry)?)
+        .with_context(|| format!("受信したファイルの索引への書き込みに失敗: {:?}", index_path))
+}
+
+// query に一致する受信したファイル（新しい順）
+pub fn search(query: &str) -> Result<Vec<Entry>> {
+    let index_path = index_path()?;
+    if !index_path.exists() {
+        return Ok(Vec::new());
+    }
+    let index = fs::File::open(&index_path)
+        .with_context(|| format!("受信したファイルの索引の読み込みに失敗: {:?}", index_path))?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(index).lines() {
+        // 書き込みの途中で止まった行などは読み飛ばす
+        let Ok(entry) = serde_json::from_str::<Entry>(&line?) else {
+            continue;
+        };
+        if entry.matches(query) {
+            entries.push(entry);
+        }
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+// search サブコマンドの実装
+pub fn run_search(query: &str) -> Result<()> {
+    let entries = search(query)?;
+    if entries.is_empty() {
+        println!("一致する受信したファイルはありません");
+    }
+    for entry in &entries {
+        let received_at = chrono::DateTime::<chrono::Utc>::from_timestamp(entry.received_at, 0)
+            .map(|time| {
+                time.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        println!("{}  {}  {}", received_at, entry.sender, entry.filename);
+        match entry.locate()? {
+            Some(path) if path == entry.path => println!("    {:?}", path),
+            Some(path) => println!("    {:?}（{:?} から移動）", path, entry.path),
+            None => println!("    見つかりません（保存したパス: {:?}）", entry.path),
+        }
+        if let Some(note) = &entry.note {
+            println!("    メモ: {}", note);
+        }
+        for (key, value) in &entry.metadata {
+            println!("    {}: {}", key, value);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use crate::{config, protocol::PartHeader};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+// 送信の再開情報の読み書きをプロセス内で直列化する（並行して送るファイルの更新を失わないように）
+static UPLOADS_LOCK: Mutex<()> = Mutex::new(());
+
+// 送信側：分割送信中のファイル（送信側を再起動しても同じ転送IDで続きから送る）
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Upload {
+    pub transfer_id: String,
+    // 送信先（接続先のアドレス）
+    pub server: String,
+    pub path: PathBuf,
+    // ファイルが変更されていないかの確認用（サイズと更新日時）
+    pub file_size: u64,
+    pub modified: u64,
+    pub part_count: u32,
+    // ストリームごとの、受信側がディスクに書き込んだと通知してきたバイト数
+    pub acked: Vec<u64>,
+}
+
+// 送信の再開情報ファイル（resume.toml）の内容
+#[derive(Default, Serialize, Deserialize)]
+struct Uploads {
+    #[serde(default)]
+    uploads: Vec<Upload>,
+}
+
+impl Uploads {
+    // 送信の再開情報ファイルのパス
+    fn path(dir: Option<&Path>) -> Result<PathBuf> {
+        Ok(config::data_dir(dir)?.join("resume.toml"))
+    }
+
+    fn load(dir: Option<&Path>) -> Result<Self> {
+        let path = Self::path(dir)?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("再開情報の読み込みに失敗: {:?}", path))?;
+        toml::from_str(&text).with_context(|| format!("再開情報の解析に失敗: {:?}", path))
+    }
+
+    fn save(&self, dir: Option<&Path>) -> Result<()> {
+        let path = Self::path(dir)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self)?;
+        fs::write(&path, text).with_context(|| format!("再開情報の保存に失敗: {:?}", path))
+    }
+}
+
+impl Upload {
+    // 新しく分割送信を始めるファイル
+    pub fn new(
+        transfer_id: String,
+        server: &str,
+        path: &Path,
+        metadata: &fs::Metadata,
+        part_count: u32,
+    ) -> Self {
+        Self {
+            transfer_id,
+            server: server.to_string(),
+            path: absolute(path),
+            file_size: metadata.len(),
+            modified: modified_of(metadata),
+            part_count,
+            acked: vec![0; part_count as usize],
+        }
+    }
+
+    // 同じ送信先に、変更されていない同じファイルを同じ分割数で送りかけていれば、その再開情報
+    pub fn find(
+        dir: Option<&Path>,
+        server: &str,
+        path: &Path,
+        metadata: &fs::Metadata,
+        part_count: u32,
+    ) -> Result<Option<Self>> {
+        let _lock = UPLOADS_LOCK.lock().unwrap();
+        let path = absolute(path);
+        let modified = modified_of(metadata);
+        Ok(Uploads::load(dir)?.uploads.into_iter().find(|upload| {
+            upload.server == server
+                && upload.path == path
+                && upload.file_size == metadata.len()
+                && upload.modified == modified
+                && upload.part_count == part_count
+                && upload.acked.len() == part_count as usize
+        }))
+    }
+
+    // 再開情報を保存する（同じ転送IDの情報は置き換える）
+    pub fn save(&self, dir: Option<&Path>) -> Result<()> {
+        let _lock = UPLOADS_LOCK.lock().unwrap();
+        let mut uploads = Uploads::load(dir)?;
+        uploads
+            .uploads
+            .retain(|upload| upload.transfer_id != self.transfer_id);
+        uploads.uploads.push(self.clone());
+        uploads.save(dir)
+    }
+
+    // 送信が終わった（または再開できなくなった）ファイルの再開情報を消す
+    pub fn remove(&self, dir: Option<&Path>) -> Result<()> {
+        let _lock = UPLOADS_LOCK.lock().unwrap();
+        let mut uploads = Uploads::load(dir)?;
+        let len = uploads.uploads.len();
+        uploads
+            .uploads
+            .retain(|upload| upload.transfer_id != self.transfer_id);
+        if uploads.uploads.len() == len {
+            return Ok(());
+        }
+        uploads.save(dir)
+    }
+}
+
+// 別のフォルダから送り直しても同じファイルを指すよう、絶対パスにする
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// 更新日時（UNIX 時刻のミリ秒。取得できなければ 0）
+fn modified_of(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// 受信側：分割受信中の一時ファイル（.part）の横に置く再開情報（.part.resume）
+//
+// 受信側を再起動しても、同じ転送IDのストリームが届けば一時ファイルの続きに書き込む
+#[derive(Serialize, Deserialize)]
+pub struct PartialState {
+    pub transfer_id: String,
+    pub file_size: u64,
+    pub part_count: u32,
+    // 受信済みのストリームの終端のオフセット
+    pub received: Vec<u64>,
+}
+
+impl PartialState {
+    pub fn new(header: &PartHeader, received: &HashSet<u64>) -> Self {
+        let mut received: Vec<u64> = received.iter().copied().collect();
+        received.sort_unstable();
+        Self {
+            transfer_id: header.transfer_id.to_string(),
+            file_size: header.file_size,
+            part_count: header.part_count,
+            received,
+        }
+    }
+
+    // 一時ファイルと再開情報が残っていて、同じ分割転送のものであれば読み込む
+    pub fn load(partial_path: &Path, header: &PartHeader) -> Option<Self> {
+        if !partial_path.exists() {
+            return None;
+        }
+        let text = fs::read_to_string(path_of(partial_path)).ok()?;
+        let state: Self = toml::from_str(&text).ok()?;
+        let matches = state.transfer_id == header.transfer_id.to_string()
+            && state.file_size == header.file_size
+            && state.part_count == header.part_count;
+        matches.then_some(state)
+    }
+
+    pub fn save(&self, partial_path: &Path) -> Result<()> {
+        let path = path_of(partial_path);
+        let text = toml::to_string(self)?;
+        fs::write(&path, text).with_context(|| format!("再開情報の保存に失敗: {:?}", path))
+    }
+
+    pub fn remove(partial_path: &Path) {
+        let _ = fs::remove_file(path_of(partial_path));
+    }
+}
+
+// 一時ファイルの再開情報のパス
+fn path_of(partial_path: &Path) -> PathBuf {
+    let mut path = partial_path.as_os_str().to_owned();
+    path.push(".resume");
+    PathBuf::from(path)
+}
@@ -0,0 +1,320 @@
+use crate::{
+    picker,
+    walk::{self, WalkEntry, WalkOptions},
+};
+use anyhow::Result;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph, Row, Table, TableState},
+    Terminal,
+};
+use rfd::{MessageButtons, MessageDialog, MessageDialogResult, MessageLevel};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, IsTerminal},
+    path::Path,
+};
+
+// ダイアログの要約に載せる大きなファイルの数
+const LARGEST_COUNT: usize = 5;
+
+// ダイアログの要約に載せる除外したファイルの数
+const EXCLUDED_COUNT: usize = 5;
+
+// 送る前に確認するファイル
+struct Item {
+    entry: WalkEntry,
+    size: u64,
+    selected: bool,
+}
+
+// フォルダ送信の前に、ファイル数・合計サイズ・大きなファイル・除外したファイルを表示して確認する
+//
+// 端末では一覧から送らないファイルを外せる。端末がなく画面があれば、要約をダイアログで確認する
+// （すべて送るかやめるかだけを選ぶ）。送るファイルを元の順で返し、やめた場合は None を返す
+pub fn review(
+    dir: &Path,
+    files: Vec<WalkEntry>,
+    options: &WalkOptions,
+) -> Result<Option<Vec<WalkEntry>>> {
+    let excluded = excluded(dir, &files, options)?;
+    let mut items: Vec<Item> = files
+        .into_iter()
+        .map(|entry| Item {
+            size: fs::metadata(&entry.path).map_or(0, |metadata| metadata.len()),
+            entry,
+            selected: true,
+        })
+        .collect();
+    // 大きい順に表示する（同じサイズはフォルダ内の順）
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(items[index].size));
+
+    let confirmed = if io::stdin().is_terminal() {
+        review_in_terminal(dir, &mut items, &order, &excluded)?
+    } else if picker::has_display() {
+        confirm_in_dialog(dir, &items, &order, &excluded)
+    } else {
+        anyhow::bail!("送るファイルを確認できる端末・画面がないため送信しません");
+    };
+    if !confirmed || !items.iter().any(|item| item.selected) {
+        return Ok(None);
+    }
+    Ok(Some(
+        items
+            .into_iter()
+            .filter(|item| item.selected)
+            .map(|item| item.entry)
+            .collect(),
+    ))
+}
+
+// --include・--exclude・.gitignore・.ftignore で送らないことになったファイル（フォルダからの相対パス）
+fn excluded(dir: &Path, files: &[WalkEntry], options: &WalkOptions) -> Result<Vec<String>> {
+    let everything = WalkOptions {
+        no_ftignore: true,
+        links: options.links,
+        ..WalkOptions::default()
+    };
+    let included: HashSet<&str> = files.iter().map(|entry| entry.relative.as_str()).collect();
+    Ok(walk::collect_files(dir, &everything)?
+        .into_iter()
+        .map(|entry| entry.relative)
+        .filter(|relative| !included.contains(relative.as_str()))
+        .collect())
+}
+
+// バイト数を "1.5 GB" のような表示にする
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} バイト", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// "12 / 15 個のファイル、合計 1.5 GB（除外 3 個）" 形式の要約
+fn summary(items: &[Item], excluded: usize) -> String {
+    let selected: Vec<&Item> = items.iter().filter(|item| item.selected).collect();
+    format!(
+        "{} / {} 個のファイル、合計 {}（除外 {} 個）",
+        selected.len(),
+        items.len(),
+        format_size(selected.iter().map(|item| item.size).sum()),
+        excluded
+    )
+}
+
+// relative（フォルダからの相対パス）が folder（"" はフォルダの直下）の中にあるか
+fn in_folder(relative: &str, folder: &str) -> bool {
+    match relative.strip_prefix(folder) {
+        Some(rest) if !folder.is_empty() => rest.starts_with('/'),
+        _ => folder.is_empty() && !relative.contains('/'),
+    }
+}
+
+// 要約をダイアログで表示し、送るかを確認する
+fn confirm_in_dialog(dir: &Path, items: &[Item], order: &[usize], excluded: &[String]) -> bool {
+    let mut description = summary(items, excluded.len());
+    description.push_str("\n\n大きなファイル:");
+    for &index in order.iter().take(LARGEST_COUNT) {
+        let item = &items[index];
+        description.push_str(&format!(
+            "\n  {}  {}",
+            format_size(item.size),
+            item.entry.relative
+        ));
+    }
+    if !excluded.is_empty() {
+        description.push_str("\n\n除外したファイル:");
+        for relative in excluded.iter().take(EXCLUDED_COUNT) {
+            description.push_str(&format!("\n  {}", relative));
+        }
+        if excluded.len() > EXCLUDED_COUNT {
+            description.push_str(&format!("\n  ほか {} 個", excluded.len() - EXCLUDED_COUNT));
+        }
+    }
+    let result = MessageDialog::new()
+        .set_level(MessageLevel::Info)
+        .set_title(format!("{} を送信しますか？", dir.display()))
+        .set_description(description)
+        .set_buttons(MessageButtons::YesNo)
+        .show();
+    result == MessageDialogResult::Yes
+}
+
+// 端末内の一覧で送らないファイルを外してもらい、送るかを返す
+fn review_in_terminal(
+    dir: &Path,
+    items: &mut [Item],
+    order: &[usize],
+    excluded: &[String],
+) -> Result<bool> {
+    let _screen = Screen::enter()?;
+    review_loop(dir, items, order, excluded)
+}
+
+// 端末を一覧の表示用（raw モードと代替画面）に切り替え、エラーやパニックで抜けても必ず元に戻す
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Screen> {
+        enable_raw_mode()?;
+        let screen = Screen;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+fn review_loop(
+    dir: &Path,
+    items: &mut [Item],
+    order: &[usize],
+    excluded: &[String],
+) -> Result<bool> {
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let mut table = TableState::default();
+    if !order.is_empty() {
+        table.select(Some(0));
+    }
+
+    loop {
+        terminal.draw(|frame| {
+            let areas = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Min(5),
+                    Constraint::Length(8),
+                    Constraint::Length(1),
+                ])
+                .split(frame.size());
+
+            let header = Paragraph::new(summary(items, excluded.len())).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("送信するフォルダ - {}", dir.display())),
+            );
+            frame.render_widget(header, areas[0]);
+
+            // ファイルの多いフォルダでも軽いよう、表示する範囲までの行だけを作る
+            let visible = areas[1].height as usize + table.selected().unwrap_or(0);
+            let rows: Vec<Row> = order
+                .iter()
+                .take(visible)
+                .map(|&index| {
+                    let item = &items[index];
+                    Row::new(vec![
+                        if item.selected { "[x]" } else { "[ ]" }.to_string(),
+                        format_size(item.size),
+                        item.entry.relative.clone(),
+                    ])
+                })
+                .collect();
+            let files = Table::new(
+                rows,
+                [
+                    Constraint::Length(3),
+                    Constraint::Length(12),
+                    Constraint::Min(20),
+                ],
+            )
+            .header(
+                Row::new(vec!["", "サイズ", "ファイル（大きい順）"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().borders(Borders::ALL).title("ファイル"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            frame.render_stateful_widget(files, areas[1], &mut table);
+
+            let lines: Vec<Line> = excluded
+                .iter()
+                .take(areas[2].height.saturating_sub(2) as usize)
+                .map(|relative| Line::from(relative.as_str()))
+                .collect();
+            let excluded = Paragraph::new(lines).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("除外したファイル（{} 個）", excluded.len())),
+            );
+            frame.render_widget(excluded, areas[2]);
+
+            let help = Paragraph::new(
+                "↑↓: 選択  Space: 送る・送らない  d: 同じフォルダのファイルをまとめて  a: すべて  Enter: 送信  Esc: やめる",
+            );
+            frame.render_widget(help, areas[3]);
+        })?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let selected = table.selected().map(|row| order[row]);
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(false),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(false)
+            }
+            KeyCode::Enter => return Ok(true),
+            KeyCode::Up | KeyCode::Char('k') => {
+                table.select(table.selected().map(|row| row.saturating_sub(1)));
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                let last = order.len().saturating_sub(1);
+                table.select(table.selected().map(|row| (row + 1).min(last)));
+            }
+            KeyCode::Char(' ') => {
+                if let Some(index) = selected {
+                    items[index].selected = !items[index].selected;
+                }
+            }
+            // 選択中のファイルのフォルダ（サブフォルダを含む）のファイルを、選択中のファイルと逆の状態にそろえる
+            KeyCode::Char('d') => {
+                if let Some(index) = selected {
+                    let relative = &items[index].entry.relative;
+                    let folder = relative
+                        .rsplit_once('/')
+                        .map_or("", |(folder, _)| folder)
+                        .to_string();
+                    let send = !items[index].selected;
+                    for item in items.iter_mut() {
+                        if in_folder(&item.entry.relative, &folder) {
+                            item.selected = send;
+                        }
+                    }
+                }
+            }
+            KeyCode::Char('a') => {
+                let send = !items.iter().all(|item| item.selected);
+                for item in items.iter_mut() {
+                    item.selected = send;
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+// 同時に受信する転送の数を抑え、あふれた転送を順番に待たせる（サーバーの --max-concurrent-transfers）
+//
+// 空きができたら、受信中の転送が少ない接続元の転送から始める（同じ数なら先に来た順）。
+// 多くのストリームで送ってくる接続元がいても、他の接続元の転送が後回しになり続けないようにする
+pub struct Scheduler {
+    max: usize,
+    state: Mutex<State>,
+    // 空きができたり、待っている転送が減ったりしたときに待っているものを起こす
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    next_ticket: u64,
+    // 受信中の転送の総数と、接続元ごとの数
+    running: usize,
+    running_by_peer: HashMap<String, usize>,
+    // 待っている転送（来た順）
+    waiting: Vec<(u64, String)>,
+}
+
+impl State {
+    // 待っている転送のうち ticket の順番（0 が次に始める転送）
+    fn position(&self, ticket: u64) -> Option<usize> {
+        let key = |ticket: u64, peer: &str| {
+            (self.running_by_peer.get(peer).copied().unwrap_or(0), ticket)
+        };
+        let (_, peer) = self.waiting.iter().find(|(t, _)| *t == ticket)?;
+        let own = key(ticket, peer);
+        Some(
+            self.waiting
+                .iter()
+                .filter(|(t, peer)| key(*t, peer) < own)
+                .count(),
+        )
+    }
+
+    fn start(&mut self, peer: &str) {
+        self.running += 1;
+        *self.running_by_peer.entry(peer.to_string()).or_default() += 1;
+    }
+}
+
+// 受信中の転送の枠（破棄すると空き、待っている転送が始まる）
+pub struct Slot {
+    scheduler: Arc<Scheduler>,
+    peer: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        state.running -= 1;
+        if let Some(count) = state.running_by_peer.get_mut(&self.peer) {
+            *count -= 1;
+            if *count == 0 {
+                state.running_by_peer.remove(&self.peer);
+            }
+        }
+        drop(state);
+        self.scheduler.changed.notify_waiters();
+    }
+}
+
+// 順番を待っている転送（枠を得る前に破棄すると、待つのをやめる）
+pub struct Waiting {
+    scheduler: Arc<Scheduler>,
+    ticket: u64,
+    peer: String,
+    // 最後に知らせた順番
+    reported: Option<usize>,
+}
+
+// 待っている転送の状況
+pub enum Turn {
+    // 待っている順番が変わった（何番目か。1 なら次に始める）
+    Queued(usize),
+    // 受信を始めてよい
+    Ready(Slot),
+}
+
+impl Scheduler {
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            state: Mutex::new(State::default()),
+            changed: Notify::new(),
+        })
+    }
+
+    // peer からの転送を順番待ちに加える
+    pub fn enqueue(self: &Arc<Self>, peer: &str) -> Waiting {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push((ticket, peer.to_string()));
+        Waiting {
+            scheduler: self.clone(),
+            ticket,
+            peer: peer.to_string(),
+            reported: None,
+        }
+    }
+}
+
+impl Waiting {
+    // 順番が来れば枠を返し、来なければ順番が変わるまで待ってそれを返す
+    pub async fn next(&mut self) -> Turn {
+        loop {
+            // 状態を確かめてから待つまでの間の変化を取りこぼさないよう、先に登録しておく
+            let changed = self.scheduler.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            {
+                let mut state = self.scheduler.state.lock().unwrap();
+                let position = state.position(self.ticket).unwrap_or(0);
+                if position == 0 && state.running < self.scheduler.max {
+                    state.waiting.retain(|(ticket, _)| *ticket != self.ticket);
+                    state.start(&self.peer);
+                    // 枠が残っていれば、次に待っているものも始められる
+                    drop(state);
+                    self.scheduler.changed.notify_waiters();
+                    return Turn::Ready(Slot {
+                        scheduler: self.scheduler.clone(),
+                        peer: std::mem::take(&mut self.peer),
+                    });
+                }
+                if self.reported != Some(position) {
+                    self.reported = Some(position);
+                    return Turn::Queued(position + 1);
+                }
+            }
+            changed.await;
+        }
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        let len = state.waiting.len();
+        state.waiting.retain(|(ticket, _)| *ticket != self.ticket);
+        let removed = state.waiting.len() != len;
+        drop(state);
+        if removed {
+            self.scheduler.changed.notify_waiters();
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use clap::Subcommand;
+
+// キーチェーンに保存するときのサービス名
+const SERVICE: &str = "file-transfer";
+
+// secret サブコマンドの操作
+#[derive(Subcommand)]
+pub enum SecretCommand {
+    /// シークレットを保存する（値は端末から入力）
+    Set {
+        /// シークレットの名前（例: "psk", "token"）
+        name: String,
+    },
+    /// シークレットを表示する
+    Get {
+        /// シークレットの名前
+        name: String,
+    },
+    /// シークレットを削除する
+    Delete {
+        /// シークレットの名前
+        name: String,
+    },
+}
+
+// secret サブコマンドの実装
+pub fn run(command: &SecretCommand) -> Result<()> {
+    match command {
+        SecretCommand::Set { name } => {
+            let value = rpassword::prompt_password(format!("{} の値: ", name))?;
+            if value.is_empty() {
+                anyhow::bail!("値が入力されていません");
+            }
+            set(name, &value)?;
+            println!("シークレット {} をキーチェーンに保存しました", name);
+        }
+        SecretCommand::Get { name } => match get(name)? {
+            Some(value) => println!("{}", value),
+            None => anyhow::bail!("シークレット {} は保存されていません", name),
+        },
+        SecretCommand::Delete { name } => {
+            if delete(name)? {
+                println!("シークレット {} を削除しました", name);
+            } else {
+                println!("シークレット {} は保存されていません", name);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn entry(name: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, name).context("キーチェーンの項目の作成に失敗")
+}
+
+// OSのキーチェーンからシークレットを読み出す
+pub fn get(name: &str) -> Result<Option<String>> {
+    match entry(name)?.get_password() {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).context("キーチェーンからの読み出しに失敗"),
+    }
+}
+
+// OSのキーチェーンにシークレットを保存する
+pub fn set(name: &str, value: &str) -> Result<()> {
+    entry(name)?
+        .set_password(value)
+        .context("キーチェーンへの保存に失敗")
+}
+
+// OSのキーチェーンからシークレットを削除する（存在しなかった場合は false）
+pub fn delete(name: &str) -> Result<bool> {
+    match entry(name)?.delete_password() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(e).context("キーチェーンからの削除に失敗"),
+    }
+}
@@ -0,0 +1,2907 @@
+use crate::{
+    acl::{Acl, Grant},
+    audit::AuditLog,
+    bandwidth::Schedule,
+    cancel::Cancel,
+    checksum::{self, ChecksumOutput},
+    clipboard::{self, ClipboardCopy},
+    config::Config,
+    control::{self, Command, Target},
+    dedup::{self, DedupMode},
+    desktop::{self, OnReceive},
+    events::{self, Observe, TransferEvent, TransferEvents},
+    filename,
+    hotkey::{Action, Hotkeys},
+    keepalive::{IdleTimeout, KeepAlive, IDLE_TIMEOUT},
+    layer::{Layer, Layers, RateLimit},
+    limits::{LimitExceeded, Limiter},
+    log::{self, debug, error, info, success, warn},
+    mime,
+    notification::{CompletionNotifier, Prompts},
+    picker,
+    pipeline::Pipeline,
+    progress::{self, Transfers},
+    protocol::{
+        self, BundleHeader, FileHeader, Header, Hello, ManifestEntry, ManifestHeader, PartHeader,
+        Reason, Response, SparseHeader, SymlinkHeader, AUTH_REQUIRED, META_MIME,
+    },
+    quarantine, received,
+    resume::PartialState,
+    scheduler::{Scheduler, Slot, Turn},
+    ssh_agent::{self, AuthorizedSshKeys},
+    template,
+    tls::{self, Tls},
+    token, tor,
+    transport::{Accepted, Connection, Pipe, Tcp, Transport, TransportKind, Unix},
+    trust::TrustedNetworks,
+    tui,
+    udp::Udp,
+    webhook::{Payload, WebhookEvent, Webhooks},
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use local_ip_address::list_afinet_netifas;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    fs,
+    future::Future,
+    io::{self, SeekFrom},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::{mpsc, oneshot, OnceCell, Semaphore},
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+// 接続が切れた分割転送の一時ファイルを残して再送を待つ時間
+const RESUME_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+// 接続してからヘッダーを読み終えるまでの期限
+const HEADER_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 拒否の応答の後、送信側が送り続けているデータを読み捨てて待つ時間の上限
+const REJECT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// ヘッダーを読み終えていない接続の最大数（超えた分は読まずに切断する）
+const MAX_PENDING_HEADERS: usize = 64;
+
+// 受け入れたバッチを、最後にファイルが届いてから覚えておく時間
+const BATCH_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+// ポートが使用中の場合に続くポートを試す既定の数
+const DEFAULT_PORT_FALLBACKS: u16 = 10;
+
+// --notify で起動していないサーバーに受け入れ・拒否を求められたときのエラー
+const NOT_NOTIFYING: &str =
+    "受け入れの確認を通知していません（サーバーを --notify で起動してください）";
+
+// 受信の受け付けを一時停止しているか（プロセス内のすべての待ち受けで共有する）
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+// サーバーモードの引数
+#[derive(Parser, Clone)]
+pub struct ServerArgs {
+    /// 保存先を選ぶホットキー（例: "ctrl+shift+r"。省略すると設定ファイルの [hotkeys] の change-save-dir、なければ "ctrl+shift+r"）
+    #[arg(short = 'k', long)]
+    pub hotkey: Option<String>,
+
+    /// ホットキーを使わない（画面のない環境では指定しなくても使わない）
+    #[arg(long)]
+    pub no_hotkey: bool,
+
+    /// 受信の一時停止・再開を切り替えるホットキー（省略すると設定ファイルの [hotkeys] の toggle-pause、なければ使わない）
+    #[arg(long)]
+    pub pause_hotkey: Option<String>,
+
+    /// ファイルの保存先フォルダ（指定するとホットキーで選択しなくても受信できる）
+    #[arg(long)]
+    pub save_dir: Option<PathBuf>,
+
+    /// 保存先フォルダ内の保存パスのテンプレート（例: "{date}/{sender}/{filename}"）
+    ///
+    /// 使用できる置換子: {date}, {time}, {sender}, {filename}
+    #[arg(long)]
+    pub save_template: Option<String>,
+
+    /// 同じ内容のファイルが保存先フォルダに既にあれば書き込みを省略する
+    #[arg(long)]
+    pub dedup: bool,
+
+    /// --dedup で省略した場合も、新しい保存先に既存ファイルへのハードリンクを作る
+    #[arg(long)]
+    pub dedup_link: bool,
+
+    /// 保存したファイルの SHA-256 を書き出す（複数指定できる）
+    ///
+    /// sidecar: ファイルの隣に "<ファイル名>.sha256"、manifest: 保存先フォルダの SHA256SUMS に追記
+    #[arg(long, value_enum)]
+    pub checksum: Vec<ChecksumOutput>,
+
+    /// 受信したファイルをクリップボードにコピーする（値を省略すると path）
+    ///
+    /// path: 保存先のパスを文字列で、file: ファイルそのもの（貼り付けてチャットやメールに添付できる）
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "path")]
+    pub clipboard: Option<ClipboardCopy>,
+
+    /// 受信したファイルを保存した後に既定のアプリで開く
+    #[arg(long, conflicts_with = "on_receive_reveal")]
+    pub on_receive_open: bool,
+
+    /// 受信したファイルを保存した後にファイルマネージャーで選択した状態で表示する
+    #[arg(long)]
+    pub on_receive_reveal: bool,
+
+    /// 受信したファイルを索引（`file-transfer search` で検索する）に記録しない
+    #[arg(long)]
+    pub no_index: bool,
+
+    /// 受信したファイルの内容（先頭のマジックナンバー）が拡張子・送信側の申告した MIME タイプと合わなければ、
+    /// 警告する代わりに保存せずに拒否する（"invoice.pdf.exe" のような偽装への備え）
+    #[arg(long)]
+    pub enforce_type: bool,
+
+    /// 認証していない送信元（トークン・SSH の鍵を提示しない接続）からのファイルを、保存先の代わりに
+    /// 所有者だけが読める隔離フォルダに受信する（`file-transfer release <転送ID>` で保存先に移す）
+    #[arg(long)]
+    pub quarantine: bool,
+
+    /// `file-transfer token create` で発行したトークンを提示しない接続を拒否する
+    /// （--ssh-authorized-keys の鍵で認証した接続は受け入れる）
+    #[arg(long)]
+    pub require_token: bool,
+
+    /// ssh-agent の鍵での認証で受け入れる公開鍵の一覧（authorized_keys の形式。省略すると設定ファイルの値）
+    #[arg(long)]
+    pub ssh_authorized_keys: Option<PathBuf>,
+
+    /// TLSで待ち受けるためのサーバー証明書（PEM）
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// サーバー証明書の秘密鍵（PEM）
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// 指定したCAが発行したクライアント証明書を提示する接続だけを受け付ける（相互TLS）
+    #[arg(long, requires = "tls_cert")]
+    pub client_ca: Option<PathBuf>,
+
+    /// 受信速度の上限（KB/s。すべての接続の合計。設定ファイルの [[bandwidth]] の時間帯以外に適用）
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub limit_rate: Option<u64>,
+
+    /// 同時に受信する転送の数の上限（超えた転送は、受信中の転送が少ない接続元から順に空くのを待たせる）
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_concurrent_transfers: Option<u32>,
+
+    /// 認証・受付の判断・受信したファイルのハッシュを記録する監査ログのパス
+    #[arg(long)]
+    pub audit_log: Option<PathBuf>,
+
+    /// 転送の一覧・進捗・ログを表示し、キー操作で受け入れ・拒否・中断を行う画面で起動する
+    #[arg(long)]
+    pub tui: bool,
+
+    /// D-Bus のサービス（org.filetransfer.Daemon）として起動し、受け入れの確認や送信を D-Bus で行う（Linux のみ）
+    #[arg(long, conflicts_with = "tui")]
+    pub dbus: bool,
+
+    /// 受信のたびに受け入れ・拒否のボタンを付けたデスクトップの通知で確認し、受信が終わったら「フォルダを開く」を付けて通知する
+    #[arg(long, conflicts_with_all = ["tui", "dbus"])]
+    pub notify: bool,
+
+    /// 接続の方式（udp: TCPに加えて、同じアドレスとポートの UDP でも待ち受ける。遅延の大きい回線向け。送信側が求めれば誤り訂正を使う）
+    #[arg(long, value_enum, default_value_t = TransportKind::Tcp)]
+    pub transport: TransportKind,
+
+    /// TCPに加えて待ち受けるUnixドメインソケットのパス
+    #[arg(long)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// TCPに加えて待ち受ける名前付きパイプ（例: "\\.\pipe\file-transfer"、Windowsのみ）
+    #[arg(long)]
+    pub named_pipe: Option<String>,
+
+    /// Tor のオニオンサービスとして公開し、直接届かない相手からも .onion のアドレスで受信する
+    ///
+    /// Tor の ControlPort が有効である必要がある
+    #[arg(long)]
+    pub tor: bool,
+
+    /// Tor のコントロールポートのアドレス
+    #[arg(long, default_value = tor::DEFAULT_CONTROL_ADDR, requires = "tor")]
+    pub tor_control: String,
+
+    /// TCPで待ち受けるアドレス（例: "10.8.0.2"、"[::]:9000"。複数指定できる。省略するとすべてのIPv4アドレス）
+    #[arg(long, value_parser = parse_bind)]
+    pub bind: Vec<SocketAddr>,
+
+    /// ポートが使用中の場合に続くポートを順に試す数（0 で試さない。--bind の指定時は試さない。省略すると設定ファイルの port_fallbacks、なければ 10）
+    #[arg(long)]
+    pub port_fallbacks: Option<u16>,
+}
+
+// --bind の値を読む（ポートを省略すると既定のポート）
+fn parse_bind(s: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = s.parse() {
+        return Ok(addr);
+    }
+    let ip: IpAddr = s
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .with_context(|| format!("待ち受けるアドレスが不正です: {}", s))?;
+    Ok(SocketAddr::new(ip, crate::FILE_TRANSFER_PORT))
+}
+
+// addr で待ち受け、ポートが使用中なら続くポートを fallbacks 個まで順に試す（待ち受けたアドレスを返す）
+async fn listen_with_fallback(
+    tcp: &dyn Transport,
+    mut addr: SocketAddr,
+    fallbacks: u16,
+    tx: &mpsc::Sender<Accepted>,
+) -> Result<SocketAddr> {
+    let requested = addr.port();
+    let last = requested.saturating_add(fallbacks);
+    loop {
+        match tcp.listen(&addr.to_string(), tx.clone()).await {
+            Ok(()) => break,
+            Err(e) if addr.port() < last && is_addr_in_use(&e) => {
+                debug!("ポート {} は使用中です", addr.port());
+                addr.set_port(addr.port() + 1);
+            }
+            Err(e) => return Err(e).with_context(|| format!("{} で待ち受けられません", addr)),
+        }
+    }
+    if addr.port() != requested {
+        warn!(
+            "ポート {} は使用中のため {} で待ち受けます（送信側では設定ファイルの port を {} にしてください）",
+            requested,
+            addr.port(),
+            addr.port()
+        );
+    }
+    Ok(addr)
+}
+
+// 待ち受けに失敗した原因が、ポートを他のプログラムが使用していることか
+fn is_addr_in_use(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::AddrInUse)
+    })
+}
+
+// 待ち受けるアドレスから接続できるアドレスの一覧を作る
+//
+// 0.0.0.0 や :: のように全体で待ち受ける場合は、同じ種類のインターフェースのアドレスをすべて挙げる
+fn reachable_addrs(binds: &[SocketAddr]) -> Vec<(String, SocketAddr)> {
+    let interfaces = match list_afinet_netifas() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            error!("ネットワークインターフェースの取得に失敗: {}", e);
+            Vec::new()
+        }
+    };
+    let mut addrs = Vec::new();
+    for bind in binds {
+        if !bind.ip().is_unspecified() {
+            let name = interfaces
+                .iter()
+                .find(|(_, ip)| *ip == bind.ip())
+                .map_or_else(String::new, |(name, _)| name.clone());
+            addrs.push((name, *bind));
+            continue;
+        }
+        for (name, ip) in &interfaces {
+            if ip.is_loopback() || ip.is_ipv4() != bind.is_ipv4() {
+                continue;
+            }
+            addrs.push((name.clone(), SocketAddr::new(*ip, bind.port())));
+        }
+    }
+    addrs
+}
+
+// 受信の受け付けを一時停止・再開する
+//
+// 一時停止中も待ち受けは続け、新しい転送は理由を添えて断る（受信中の転送と、
+// 受け入れ済みのバッチ・分割転送の続きのストリームはそのまま受け取る）
+pub fn set_paused(paused: bool) {
+    if PAUSED.swap(paused, Ordering::Relaxed) != paused {
+        if paused {
+            info!("受信の受け付けを一時停止しました");
+        } else {
+            info!("受信の受け付けを再開しました");
+        }
+    }
+}
+
+// 受信の受け付けを一時停止しているか
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+// 一時停止と再開を切り替え、切り替えた後に一時停止しているかを返す
+pub fn toggle_paused() -> bool {
+    let paused = !is_paused();
+    set_paused(paused);
+    paused
+}
+
+// サーバーモード（ファイル受信）の実装
+pub async fn run_server(args: &ServerArgs, config: &Config) -> Result<()> {
+    if args.tui {
+        return tui::run(args, config).await;
+    }
+    if args.dbus {
+        return run_dbus(args, config).await;
+    }
+
+    info!("サーバーモード（ファイル受信）を開始します");
+
+    // ファイル保存先の共有状態（受信の完了の通知でも使うため、待ち受けの前に作っておく）
+    let save_path = Arc::new(Mutex::new(None));
+
+    // --notify では受け入れの確認と完了をデスクトップの通知で知らせる
+    let prompts = args.notify.then(Prompts::default);
+    let (prompt, events) = match &prompts {
+        Some(prompts) => {
+            let (tx, incoming) = mpsc::channel(16);
+            tokio::spawn(prompts.clone().serve(incoming));
+            let events: Arc<dyn TransferEvents> =
+                Arc::new(CompletionNotifier::new(save_path.clone()));
+            (Some(tx), Some(events))
+        }
+        None => (None, None),
+    };
+    let (context, mut rx) =
+        start(args, config, prompt, None, events, CancellationToken::new()).await?;
+
+    // ホットキーの登録（--no-hotkey や画面がない環境では使わない。受信の一時停止・再開は指定した場合のみ）
+    let mut hotkeys = Hotkeys::register(
+        vec![
+            (Action::ChangeSaveDir, args.hotkey.clone()),
+            (Action::TogglePause, args.pause_hotkey.clone()),
+        ],
+        config,
+        args.no_hotkey,
+    )?;
+
+    // ホットキーの変更や受信の一時停止を再起動せずに行えるよう制御ソケットで待ち受ける
+    let mut control = control::listen(Target::Server)
+        .await
+        .map_err(|e| warn!("制御ソケットで待ち受けできません: {:#}", e))
+        .ok();
+
+    // ファイル保存先の共有状態（--save-dir、なければ設定ファイルの値を初期値とする）
+    let mut default_save_dir = args.save_dir.clone().or_else(|| config.save_dir.clone());
+    if hotkeys.get(Action::ChangeSaveDir).is_none() && default_save_dir.is_none() {
+        // ホットキーで後から選べないため、ここで選択する
+        default_save_dir = picker::pick_folder("ファイルの保存先フォルダを選択");
+        if default_save_dir.is_none() {
+            anyhow::bail!("ホットキーを使わない場合は --save-dir で保存先を指定してください");
+        }
+    }
+    if let Some(dir) = &default_save_dir {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("保存先フォルダの作成に失敗: {:?}", dir))?;
+        info!("保存先: {:?}", dir);
+    }
+    *save_path.lock().unwrap() = default_save_dir;
+    let save_path_clone = save_path.clone();
+
+    info!("ファイル転送サーバーを起動しました");
+    match hotkeys.get(Action::ChangeSaveDir) {
+        None if hotkeys.is_empty() => info!("ホットキーは使用しません"),
+        None => {}
+        Some(hotkey) if save_path.lock().unwrap().is_some() => {
+            info!("ホットキー {} を押すと保存先を変更できます", hotkey.name())
+        }
+        Some(hotkey) => info!("ホットキー {} を押すと保存先を選択できます", hotkey.name()),
+    }
+    if let Some(hotkey) = hotkeys.get(Action::TogglePause) {
+        info!(
+            "ホットキー {} を押すと受信の一時停止・再開を切り替えられます",
+            hotkey.name()
+        );
+    }
+
+    // メインループ
+    loop {
+        // ホットキーイベントの確認
+        for action in hotkeys.pressed() {
+            match action {
+                Action::TogglePause => toggle_paused(),
+                Action::ChangeSaveDir => {
+                    info!("ホットキーが押されました");
+
+                    // 保存先の選択
+                    if let Some(path) = picker::pick_folder("ファイルの保存先フォルダを選択")
+                    {
+                        info!("保存先を選択: {:?}", path);
+                        *save_path.lock().unwrap() = Some(path);
+                    }
+                }
+                // クライアントモードの操作は登録しない
+                Action::SendFile | Action::SendClipboard | Action::ResendLast => {}
+            }
+        }
+
+        // 制御ソケットからの要求の確認
+        if let Some(request) = control.as_mut().and_then(|control| control.try_recv().ok()) {
+            let result = match &request.command {
+                Command::Hotkey(action, name) => {
+                    control::rebind_hotkey(&mut hotkeys, Target::Server, *action, name)
+                        .map(|()| info!("ホットキーを {} に変更しました", name))
+                }
+                Command::Pause => {
+                    set_paused(true);
+                    Ok(())
+                }
+                Command::Resume => {
+                    set_paused(false);
+                    Ok(())
+                }
+                Command::Accept(transfer_id) => prompts
+                    .as_ref()
+                    .context(NOT_NOTIFYING)
+                    .and_then(|prompts| prompts.accept(*transfer_id)),
+                Command::Reject(transfer_id, reason) => prompts
+                    .as_ref()
+                    .context(NOT_NOTIFYING)
+                    .and_then(|prompts| prompts.reject(*transfer_id, reason)),
+            };
+            request.reply(result);
+        }
+
+        // 新しい接続を待つ（届いたらすぐに受け付け、ホットキーと制御ソケットを確認するため 100ms で戻る）
+        tokio::select! {
+            Some(accepted) = rx.recv() => {
+                info!("ファイル転送の開始");
+
+                // 保存先の確認
+                let save_dir = save_path_clone.lock().unwrap().clone();
+
+                // 分割転送の各ストリームを並行して受信できるよう接続ごとにタスクを起動
+                tokio::spawn(log::with_transfer_id(handle_connection(
+                    accepted,
+                    save_dir,
+                    context.clone(),
+                )));
+            }
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+        }
+    }
+}
+
+// D-Bus のサービスとして受信する（--dbus）
+#[cfg(target_os = "linux")]
+async fn run_dbus(args: &ServerArgs, config: &Config) -> Result<()> {
+    crate::dbus::run(args, config).await
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn run_dbus(_args: &ServerArgs, _config: &Config) -> Result<()> {
+    anyhow::bail!("--dbus は Linux でのみ使用できます")
+}
+
+// ホットキーを使わずに受信する（TUIモード用。受信のたびに prompt で受け入れるか確認する）
+//
+// transfers を渡すと、受信中の転送の進捗を記録し、中断できるようにする。
+// events を渡すと、受信の開始・進捗・結果を通知する。
+// cancel を取り消すと待ち受けを終了し、受信中の転送も中断して戻る
+pub async fn run_prompted(
+    args: &ServerArgs,
+    config: &Config,
+    save_dir: PathBuf,
+    prompt: mpsc::Sender<IncomingTransfer>,
+    transfers: Option<Transfers>,
+    events: Option<Arc<dyn TransferEvents>>,
+    cancel: CancellationToken,
+) -> Result<()> {
+    fs::create_dir_all(&save_dir)
+        .with_context(|| format!("保存先フォルダの作成に失敗: {:?}", save_dir))?;
+    let (context, rx) = start(args, config, Some(prompt), transfers, events, cancel).await?;
+    serve(context, rx, save_dir).await;
+    Ok(())
+}
+
+// 受信を他の非同期アプリに組み込むためのAPI（GUIモードで使用）
+//
+// 受信の要求は incoming() で順に受け取り、呼び出し側が受け入れるか決める
+pub struct FileReceiver {
+    transfers: mpsc::Receiver<IncomingTransfer>,
+}
+
+impl FileReceiver {
+    // 待ち受けを開始する（save_dir は accept で受け入れた場合の保存先）
+    //
+    // cancel を取り消すと待ち受けを終了し、受信中の転送も中断する
+    pub async fn start(
+        args: &ServerArgs,
+        config: &Config,
+        save_dir: PathBuf,
+        events: Option<Arc<dyn TransferEvents>>,
+        cancel: CancellationToken,
+    ) -> Result<Self> {
+        fs::create_dir_all(&save_dir)
+            .with_context(|| format!("保存先フォルダの作成に失敗: {:?}", save_dir))?;
+        let (tx, transfers) = mpsc::channel(16);
+        let (context, rx) = start(args, config, Some(tx), None, events, cancel).await?;
+        tokio::spawn(serve(context, rx, save_dir));
+        Ok(Self { transfers })
+    }
+
+    // 受信の要求（accept か reject を呼ぶまで送信側は待つ）
+    pub fn incoming(self) -> impl Stream<Item = IncomingTransfer> {
+        ReceiverStream::new(self.transfers)
+    }
+}
+
+// --bind などの待ち受けの設定を使わず、transport の addr だけで待ち受けを開始する
+//
+// 同じプロセスの中で送信側と受信側を動かす場合（testing フィーチャーのループバックなど）に使う。
+// 待ち受けを始めたら戻り、受信は cancel を取り消すまで裏で続ける
+pub async fn serve_on(
+    args: &ServerArgs,
+    config: &Config,
+    save_dir: PathBuf,
+    transport: &dyn Transport,
+    addr: &str,
+    cancel: CancellationToken,
+) -> Result<()> {
+    fs::create_dir_all(&save_dir)
+        .with_context(|| format!("保存先フォルダの作成に失敗: {:?}", save_dir))?;
+    let context = receive_context(args, config, None, None, None, cancel)?;
+    let (tx, rx) = mpsc::channel::<Accepted>(10);
+    transport
+        .listen(addr, tx)
+        .await
+        .with_context(|| format!("{} で待ち受けられません", addr))?;
+    tokio::spawn(serve(context, rx, save_dir));
+    Ok(())
+}
+
+// 受け付けた接続ごとに受信処理を起動する（取り消されると rx を閉じて待ち受けを終了する）
+async fn serve(context: ReceiveContext, mut rx: mpsc::Receiver<Accepted>, save_dir: PathBuf) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = rx.recv() => accepted,
+            _ = context.cancel.cancelled() => None,
+        };
+        let Some(accepted) = accepted else {
+            break;
+        };
+        tokio::spawn(log::with_transfer_id(handle_connection(
+            accepted,
+            Some(save_dir.clone()),
+            context.clone(),
+        )));
+    }
+}
+
+// 受信処理の共有状態を用意し、待ち受けを開始する（受け付けた接続はチャネルに流れる）
+async fn start(
+    args: &ServerArgs,
+    config: &Config,
+    prompt: Option<mpsc::Sender<IncomingTransfer>>,
+    transfers: Option<Transfers>,
+    events: Option<Arc<dyn TransferEvents>>,
+    cancel: CancellationToken,
+) -> Result<(ReceiveContext, mpsc::Receiver<Accepted>)> {
+    // 待ち受けるアドレス（--bind、なければすべてのIPv4アドレスで、設定ファイルの port か既定のポート）
+    let mut binds = if args.bind.is_empty() {
+        let port = config.port.unwrap_or(crate::FILE_TRANSFER_PORT);
+        vec![SocketAddr::from(([0, 0, 0, 0], port))]
+    } else {
+        args.bind.clone()
+    };
+
+    // TLSの設定（TCPの接続にのみ適用する）
+    let tls_acceptor = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            let (acceptor, fingerprint) = tls::acceptor(cert, key, args.client_ca.as_deref())?;
+            info!("TLS: 有効（サーバー証明書のSHA-256: {}）", fingerprint);
+            if args.client_ca.is_some() {
+                info!("クライアント証明書: 必須");
+            }
+            Some(acceptor)
+        }
+        _ => None,
+    };
+
+    let context = receive_context(args, config, prompt, transfers, events, cancel)?;
+
+    // 接続処理用のチャネル
+    let (tx, rx) = mpsc::channel::<Accepted>(10);
+
+    // TCP（TLSの設定があればTLSで包む）
+    let tcp: Arc<dyn Transport> = match &tls_acceptor {
+        Some(acceptor) => Arc::new(Tls::server(
+            Arc::new(Tcp),
+            acceptor.clone(),
+            context.limiter.clone(),
+            context.pending_headers.clone(),
+        )),
+        None => Arc::new(Tcp),
+    };
+    if args.bind.is_empty() {
+        // 既定のアドレスのポートが使用中なら続くポートで待ち受ける（--bind で指定したアドレスはそのまま使う）
+        let fallbacks = args
+            .port_fallbacks
+            .or(config.port_fallbacks)
+            .unwrap_or(DEFAULT_PORT_FALLBACKS);
+        binds[0] = listen_with_fallback(tcp.as_ref(), binds[0], fallbacks, &tx).await?;
+    } else {
+        for addr in &binds {
+            tcp.listen(&addr.to_string(), tx.clone())
+                .await
+                .with_context(|| format!("{} で待ち受けられません", addr))?;
+        }
+    }
+
+    // 実際に待ち受けたポートで接続先を案内する
+    if let Some(name) = &config.device_name {
+        info!("端末名: {}", name);
+    }
+    for (name, addr) in reachable_addrs(&binds) {
+        if name.is_empty() {
+            info!("接続先のアドレス: {}", addr);
+        } else {
+            info!("接続先のアドレス: {}（{}）", addr, name);
+        }
+    }
+
+    // UDP（--transport udp の場合。TLSの設定があればTLSで包む）
+    if args.transport == TransportKind::Udp {
+        let udp: Arc<dyn Transport> = match tls_acceptor {
+            Some(acceptor) => Arc::new(Tls::server(
+                Arc::new(Udp::new(None)),
+                acceptor,
+                context.limiter.clone(),
+                context.pending_headers.clone(),
+            )),
+            None => Arc::new(Udp::new(None)),
+        };
+        for addr in &binds {
+            udp.listen(&addr.to_string(), tx.clone())
+                .await
+                .with_context(|| format!("{} で待ち受けられません（UDP）", addr))?;
+        }
+    }
+
+    // オニオンサービスの転送先は最初に待ち受けたアドレス（全体で待ち受けている場合はループバック）
+    if args.tor {
+        let mut target = binds[0];
+        if target.ip().is_unspecified() {
+            target.set_ip(match target {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let onion = tor::publish(
+            &args.tor_control,
+            crate::FILE_TRANSFER_PORT,
+            target,
+            context.cancel.clone(),
+        )
+        .await?;
+        info!("接続先のアドレス: {}（Tor）", onion);
+    }
+
+    if let Some(path) = &args.unix_socket {
+        Unix.listen(&path.to_string_lossy(), tx.clone()).await?;
+    }
+    if let Some(name) = &args.named_pipe {
+        Pipe.listen(name, tx).await?;
+    }
+
+    Ok((context, rx))
+}
+
+// 受信処理で共有する状態を用意する
+fn receive_context(
+    args: &ServerArgs,
+    config: &Config,
+    prompt: Option<mpsc::Sender<IncomingTransfer>>,
+    transfers: Option<Transfers>,
+    events: Option<Arc<dyn TransferEvents>>,
+    cancel: CancellationToken,
+) -> Result<ReceiveContext> {
+    // 保存パスのテンプレート（--save-template、なければ設定ファイルの値）
+    let save_template = args
+        .save_template
+        .clone()
+        .or_else(|| config.save_template.clone())
+        .unwrap_or_else(|| template::DEFAULT_SAVE_TEMPLATE.to_string());
+    template::validate(&save_template)?;
+    info!("保存テンプレート: {}", save_template);
+
+    // 監査ログ（--audit-log、なければ設定ファイルの値）
+    let audit = match args.audit_log.as_ref().or(config.audit_log.as_ref()) {
+        Some(path) => {
+            let audit = AuditLog::open(path)?;
+            info!("監査ログ: {:?}", path);
+            Some(Arc::new(audit))
+        }
+        None => None,
+    };
+
+    // ssh-agent の鍵での認証で受け入れる公開鍵（--ssh-authorized-keys、なければ設定ファイルの値）
+    let ssh_keys = match args
+        .ssh_authorized_keys
+        .as_ref()
+        .or(config.ssh_authorized_keys.as_ref())
+    {
+        Some(path) => {
+            let keys = AuthorizedSshKeys::load(path)?;
+            info!("SSH の鍵での認証: {} 個の鍵を受け入れます", keys.count());
+            Some(Arc::new(keys))
+        }
+        None => None,
+    };
+
+    let mut context = ReceiveContext {
+        save_template: Arc::new(save_template),
+        partial_files: Arc::new(Mutex::new(HashMap::new())),
+        part_prompts: Arc::new(Mutex::new(HashMap::new())),
+        batches: Arc::new(Mutex::new(HashMap::new())),
+        dedup: if args.dedup_link {
+            DedupMode::Link
+        } else if args.dedup {
+            DedupMode::Skip
+        } else {
+            DedupMode::Off
+        },
+        checksum: Arc::new(args.checksum.clone()),
+        clipboard: args.clipboard,
+        on_receive: if args.on_receive_open {
+            Some(OnReceive::Open)
+        } else if args.on_receive_reveal {
+            Some(OnReceive::Reveal)
+        } else {
+            None
+        },
+        index: !args.no_index,
+        enforce_type: args.enforce_type || config.enforce_type,
+        quarantine: args.quarantine || config.quarantine,
+        dedup_lock: Arc::new(Mutex::new(())),
+        require_token: args.require_token || config.require_token,
+        token_lock: Arc::new(Mutex::new(())),
+        ssh_keys,
+        acl: Arc::new(Acl::new(config.acl.clone())),
+        trusted: Arc::new(TrustedNetworks::new(&config.trusted_networks)?),
+        limiter: Limiter::new(config.client_limits.clone()),
+        pending_headers: Arc::new(Semaphore::new(MAX_PENDING_HEADERS)),
+        scheduler: args
+            .max_concurrent_transfers
+            .map(|max| Scheduler::new(max as usize)),
+        audit,
+        prompt,
+        transfers,
+        layers: Layers::default(),
+        events,
+        webhooks: Webhooks::new(&config.webhooks)?,
+        cancel,
+    };
+    // 速度の上限（設定ファイルの [[bandwidth]] の時間帯以外は --limit-rate）
+    let schedule = Schedule::new(
+        &config.bandwidth,
+        args.limit_rate.map(|rate| rate.saturating_mul(1024)),
+    )?;
+    if let Some(rate) = args.limit_rate {
+        info!("受信速度の上限: {} KB/s", rate);
+    }
+    if !config.bandwidth.is_empty() {
+        info!("時間帯ごとの速度の上限: {} 件", config.bandwidth.len());
+    }
+    if !schedule.is_unlimited() {
+        context.layers.push(RateLimit::new(schedule));
+    }
+    if context.clipboard.is_some() && !picker::has_display() {
+        warn!("画面がないため、受信したファイルをクリップボードにコピーしません");
+        context.clipboard = None;
+    }
+    if context.on_receive.is_some() && !picker::has_display() {
+        warn!("画面がないため、受信したファイルを開きません");
+        context.on_receive = None;
+    }
+    if context.require_token {
+        info!("トークン認証: 有効");
+    }
+    if !context.acl.is_empty() {
+        info!("アクセス制御ルール: {} 件", config.acl.len());
+    }
+    if context.enforce_type {
+        info!("内容の種類が拡張子と合わないファイルは拒否します");
+    }
+    if context.quarantine {
+        info!("認証していない送信元からのファイルは隔離フォルダに受信します");
+    }
+
+    Ok(context)
+}
+
+// 受け入れるかの確認を待っている受信
+//
+// accept・accept_into・reject のいずれかを呼ぶまで送信側は待つ。
+// 呼ばずに破棄した場合は拒否として扱う
+pub struct IncomingTransfer {
+    pub peer: String,
+    pub filename: String,
+    pub size: u64,
+    // 送信側が転送に添えたメモ
+    pub note: Option<String>,
+    // 送信側が転送に添えたメタデータ
+    pub metadata: BTreeMap<String, String>,
+    // 送信側と受信側で共通の転送ID
+    pub transfer_id: Uuid,
+    reply: oneshot::Sender<Decision>,
+}
+
+#[derive(Clone)]
+enum Decision {
+    // 保存先フォルダ（None なら既定の保存先）
+    Accept(Option<PathBuf>),
+    Reject(String),
+}
+
+impl IncomingTransfer {
+    // 既定の保存先に受信する
+    pub fn accept(self) {
+        let _ = self.reply.send(Decision::Accept(None));
+    }
+
+    // save_dir に受信する（保存パスのテンプレートは save_dir からの相対パスとして適用する）
+    pub fn accept_into(self, save_dir: PathBuf) {
+        let _ = self.reply.send(Decision::Accept(Some(save_dir)));
+    }
+
+    // 拒否する（reason は送信側への応答と監査ログに含める）
+    pub fn reject(self, reason: &str) {
+        let _ = self.reply.send(Decision::Reject(reason.to_string()));
+    }
+}
+
+// 分割転送中のファイル（転送IDごと）
+struct PartialFile {
+    partial_path: PathBuf,
+    // 保存先（先頭のストリームで受け入れた保存先が優先される）
+    save_dir: PathBuf,
+    final_path: PathBuf,
+    part_count: u32,
+    // 受信済みのストリームの終端のオフセット
+    // （途中から再送されたストリームも同じ終端になるため、重ねて数えない）
+    received: HashSet<u64>,
+    // 受け入れの確認を経たストリームが届いた（受け入れていない分割転送は保存しない）
+    accepted: bool,
+    // 受信中のストリーム数
+    active: u32,
+    // 受信の開始・終了のたびに増やす（再送を待つ間に動きがあったかの確認用）
+    activity: u64,
+}
+
+type PartialFiles = Arc<Mutex<HashMap<Uuid, PartialFile>>>;
+
+// 分割転送の受け入れの確認の結果（送信元と転送IDごと。同時に届いたストリームでも確認は1度だけにする）
+type PartPrompts = Arc<Mutex<HashMap<(String, Uuid), (Instant, Arc<OnceCell<Option<Decision>>>)>>>;
+
+// マニフェストで受け入れたバッチ（バッチIDごと）
+struct Batch {
+    // 受け入れたときの保存先
+    save_dir: PathBuf,
+    // 受け入れたときに提示されたトークンのID（同じトークンの接続にだけ使う）
+    token_id: Option<String>,
+    // まだ受信していない、確認なしに受け取るファイル（受信を終えたものは取り除く）
+    pending: HashMap<String, Expected>,
+    last_used: Instant,
+}
+
+// トークンで受信するファイル数として先に数えた分（commit せずに破棄すると戻す）
+struct TokenUse<'a> {
+    context: &'a ReceiveContext,
+    id: Option<String>,
+    files: u32,
+}
+
+impl TokenUse<'_> {
+    // 受信に成功したため、数えたままにする
+    fn commit(mut self) {
+        self.id = None;
+    }
+}
+
+impl Drop for TokenUse<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = &self.id {
+            let _guard = self.context.token_lock.lock().unwrap();
+            if let Err(e) = token::release_use(id, self.files) {
+                error!("トークンの使用数の記録に失敗: {:#}", e);
+            }
+        }
+    }
+}
+
+// マニフェストで申告された、バッチの1ファイルの大きさとハッシュ
+#[derive(Clone)]
+struct Expected {
+    size: u64,
+    sha256: Option<String>,
+}
+
+type Batches = Arc<Mutex<HashMap<Uuid, Batch>>>;
+
+// 受信処理で共有する状態
+#[derive(Clone)]
+struct ReceiveContext {
+    save_template: Arc<String>,
+    partial_files: PartialFiles,
+    part_prompts: PartPrompts,
+    batches: Batches,
+    dedup: DedupMode,
+    // 保存したファイルのハッシュの書き出し先
+    checksum: Arc<Vec<ChecksumOutput>>,
+    // 保存したファイルをクリップボードにコピーする形式
+    clipboard: Option<ClipboardCopy>,
+    // 保存したファイルを開くか、ファイルマネージャーで表示するか
+    on_receive: Option<OnReceive>,
+    // 受信したファイルを索引に記録するか（--no-index でなければ記録する）
+    index: bool,
+    // 内容の種類が拡張子・申告と合わないファイルを拒否するか（合わなければ常に警告する）
+    enforce_type: bool,
+    // 認証していない送信元からのファイルを隔離フォルダに受信するか
+    quarantine: bool,
+    // ハッシュ一覧の確認と追記を接続間で直列化する
+    dedup_lock: Arc<Mutex<()>>,
+    require_token: bool,
+    // トークン一覧の使用数の更新を接続間で直列化する
+    token_lock: Arc<Mutex<()>>,
+    // ssh-agent の鍵での認証で受け入れる公開鍵
+    ssh_keys: Option<Arc<AuthorizedSshKeys>>,
+    acl: Arc<Acl>,
+    // 確認せずに受け入れる、信頼するネットワークの認証済みの送信元
+    trusted: Arc<TrustedNetworks>,
+    // 接続元ごとの同時接続数・転送数・受信量の上限
+    limiter: Arc<Limiter>,
+    // ヘッダーを読み終えていない接続の数の上限
+    pending_headers: Arc<Semaphore>,
+    // 同時に受信する転送の数の上限（--max-concurrent-transfers）
+    scheduler: Option<Arc<Scheduler>>,
+    audit: Option<Arc<AuditLog>>,
+    // GUIモードで受信のたびに確認を求める先
+    prompt: Option<mpsc::Sender<IncomingTransfer>>,
+    // 受信中の転送の進捗を記録する一覧（TUIモード）
+    transfers: Option<Transfers>,
+    // 受け付けた接続に適用するレイヤー（速度制限など）
+    layers: Layers,
+    // 受信の経過の通知先（GUIモード）
+    events: Option<Arc<dyn TransferEvents>>,
+    // 受信の結果を知らせる Webhook（設定ファイルの [[webhooks]]）
+    webhooks: Option<Arc<Webhooks>>,
+    // 取り消すと待ち受けと受信中のすべての転送を中断する
+    cancel: CancellationToken,
+}
+
+impl ReceiveContext {
+    // 分割転送の確認の結果を待つ場所（RESUME_WINDOW より前に確認したものは忘れる）
+    fn part_prompt(&self, peer: &str, transfer_id: Uuid) -> Arc<OnceCell<Option<Decision>>> {
+        let mut prompts = self.part_prompts.lock().unwrap();
+        prompts.retain(|_, (asked_at, _)| asked_at.elapsed() < RESUME_WINDOW);
+        prompts
+            .entry((peer.to_string(), transfer_id))
+            .or_insert_with(|| (Instant::now(), Arc::new(OnceCell::new())))
+            .1
+            .clone()
+    }
+
+    // 保存するファイルのハッシュが必要か（重複排除・監査ログ・チェックサムの書き出し・索引に使う）
+    fn needs_hash(&self) -> bool {
+        self.dedup != DedupMode::Off
+            || self.audit.is_some()
+            || !self.checksum.is_empty()
+            || self.index
+    }
+
+    // 受け入れ済みのバッチのまだ受信していないファイルで、マニフェストと大きさが同じなら、
+    // バッチを受け入れたときの保存先を返す
+    fn batch_save_dir(
+        &self,
+        batch_id: Uuid,
+        token_id: Option<&str>,
+        filename: &str,
+        size: u64,
+    ) -> Option<PathBuf> {
+        let mut batches = self.batches.lock().unwrap();
+        batches.retain(|_, batch| batch.last_used.elapsed() < BATCH_WINDOW);
+        let batch = batches.get_mut(&batch_id)?;
+        if batch.token_id.as_deref() != token_id
+            || !matches!(batch.pending.get(filename), Some(expected) if expected.size == size)
+        {
+            return None;
+        }
+        batch.last_used = Instant::now();
+        Some(batch.save_dir.clone())
+    }
+
+    // バッチのまだ受信していないファイルの、マニフェストで申告された大きさとハッシュ
+    fn batch_entry(&self, batch_id: Uuid, filename: &str) -> Option<Expected> {
+        let batches = self.batches.lock().unwrap();
+        batches.get(&batch_id)?.pending.get(filename).cloned()
+    }
+
+    // 受信を終えたファイルをバッチから取り除く（同じ名前では確認なしに受け取らない）
+    fn batch_received(&self, batch_id: Uuid, filenames: &[String]) {
+        let mut batches = self.batches.lock().unwrap();
+        if let Some(batch) = batches.get_mut(&batch_id) {
+            for filename in filenames {
+                batch.pending.remove(filename);
+            }
+        }
+    }
+
+    // トークンで受信するファイル数を先に数える（SSH の鍵で認証した接続は数えない）
+    fn reserve_token_use(&self, token_id: Option<&str>, files: u32) -> Result<TokenUse<'_>> {
+        let id = token_id
+            .filter(|id| files > 0 && !id.starts_with(ssh_agent::IDENTITY_PREFIX))
+            .map(str::to_string);
+        if let Some(id) = &id {
+            let _guard = self.token_lock.lock().unwrap();
+            token::reserve_use(id, files)?;
+        }
+        Ok(TokenUse {
+            context: self,
+            id,
+            files,
+        })
+    }
+
+    // 監査ログに記録する（監査ログを使わない場合は何もしない）
+    fn audit(&self, event: &str, peer: &str, detail: &str) {
+        if let Some(audit) = &self.audit {
+            if let Err(e) = audit.record(event, peer, detail) {
+                error!("監査ログへの記録に失敗: {:#}", e);
+            }
+        }
+    }
+
+    // 受信の経過を通知する（通知先がない場合は何もしない）
+    fn notify(&self, id: u64, event: TransferEvent) {
+        if let Some(events) = &self.events {
+            events.on_event(id, event);
+        }
+    }
+
+    // 受信の結果を Webhook で知らせる（設定がない場合は何もしない）
+    fn hook(&self, payload: Payload) {
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.fire(payload);
+        }
+    }
+}
+
+// マニフェストの扱い（試し送信・検証の指定がなければバッチとして受け入れる）
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ManifestMode {
+    Accept,
+    DryRun,
+    Verify,
+}
+
+// 接続元（テンプレートの {sender} に使う表示名と、適用するアクセス制御ルール）
+#[derive(Clone)]
+struct Sender {
+    name: String,
+    grant: Grant,
+    // 受信済みバイト数の通知を求めているか
+    acks: bool,
+    // 送信側が転送に添えたメモ
+    note: Option<String>,
+    // 送信側が転送に添えたメタデータ
+    metadata: BTreeMap<String, String>,
+    // 隔離フォルダに受信しているか（受信したファイルを開かず、索引には解放したときに記録する）
+    quarantined: bool,
+    // 受け入れ済みのバッチのファイルとして確認なしに受け取っている場合は、そのバッチID
+    batch: Option<Uuid>,
+}
+
+impl Sender {
+    // 監査ログの詳細にメモを添える
+    fn with_note(&self, detail: String) -> String {
+        match &self.note {
+            Some(note) => format!("{}（メモ: {}）", detail, note),
+            None => detail,
+        }
+    }
+}
+
+// 1接続分の受信結果
+enum Received {
+    // ファイルを保存した（保存名は保存先フォルダからの相対パス。ハッシュは計算した場合のみ）
+    Saved {
+        filename: String,
+        sha256: Option<String>,
+    },
+    // 分割転送の途中のストリームを書き込んだ
+    Part,
+    // 同じ内容のファイルが既にあったため保存を省略した（保存名は既にあるファイル）
+    Duplicate {
+        filename: String,
+        sha256: String,
+    },
+    // まとめて送られたファイルを1つずつ保存した
+    Bundle(Vec<Received>),
+}
+
+impl Received {
+    // 成功の応答
+    fn into_response(self) -> Response {
+        match self {
+            Received::Saved { filename, sha256 } => Response {
+                filename: Some(filename),
+                sha256,
+                ..Response::new(Reason::Saved)
+            },
+            Received::Part => Response::new(Reason::Part),
+            Received::Duplicate { filename, sha256 } => Response {
+                filename: Some(filename),
+                sha256: Some(sha256),
+                ..Response::new(Reason::Duplicate)
+            },
+            Received::Bundle(_) => Response::new(Reason::Saved),
+        }
+    }
+
+    // 受信を終えたファイルの数（分割転送の途中のストリームは数えない）
+    fn file_count(&self) -> u32 {
+        match self {
+            Received::Saved { .. } | Received::Duplicate { .. } => 1,
+            Received::Part => 0,
+            Received::Bundle(files) => files.iter().map(Received::file_count).sum(),
+        }
+    }
+}
+
+// 保存先フォルダからの相対パス（"/" 区切り）
+fn relative_name(save_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(save_dir)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// 1接続分の受信処理（ログの各行に転送IDを付けられるよう log::with_transfer_id の中で動かす）
+async fn handle_connection(accepted: Accepted, save_dir: Option<PathBuf>, context: ReceiveContext) {
+    let Accepted {
+        connection: socket,
+        peer,
+    } = accepted;
+    // 接続ごとに中断できるようにする（全体の取り消しでも中断される）
+    let cancel = context.cancel.child_token();
+    let mut socket = Cancel::new(cancel.clone()).wrap(context.layers.wrap(socket));
+    let id = events::new_id();
+    // 送信側が転送IDを送ってくれば、ヘッダーを読んだところでそれに置き換える
+    let mut transfer_id = Uuid::new_v4();
+    log::set_transfer_id(transfer_id);
+
+    // 接続元ごとの同時接続数に数える（接続を閉じるまで）
+    let _permit = match context.limiter.connect(&peer) {
+        Ok(permit) => permit,
+        // 遮断中の接続元からの接続は、ログや通知があふれないよう、遮断を始めたときだけ記録して黙って断る
+        Err(e @ LimitExceeded::Banned(_)) => {
+            debug!("遮断中の接続元からの接続を断りました: {} ({})", peer, e);
+            let response = Response::new(e.reason()).with_message(e.to_string());
+            reject(&mut socket, &response, transfer_id, false).await;
+            return;
+        }
+        Err(e) => {
+            error!("接続を拒否しました: {} ({})", peer, e);
+            context.audit("reject", &peer, &e.to_string());
+            context.notify(id, TransferEvent::Rejected(e.to_string()));
+            context.hook(
+                Payload::new(WebhookEvent::Rejected, transfer_id, &peer)
+                    .with_message(e.to_string()),
+            );
+
+            let response = Response::new(e.reason()).with_message(e.to_string());
+            reject(&mut socket, &response, transfer_id, false).await;
+            return;
+        }
+    };
+
+    let Some(save_dir) = save_dir else {
+        error!("保存先が選択されていません");
+        context.audit("reject", &peer, "保存先が選択されていません");
+        context.notify(
+            id,
+            TransferEvent::Rejected("保存先が選択されていません".to_string()),
+        );
+        context.hook(
+            Payload::new(WebhookEvent::Rejected, transfer_id, &peer)
+                .with_message("保存先が選択されていません"),
+        );
+
+        reject(
+            &mut socket,
+            &Response::new(Reason::NoSaveDirectory),
+            transfer_id,
+            false,
+        )
+        .await;
+        return;
+    };
+
+    // 接続しただけでヘッダーを送らない相手に受信側を占有されないよう、
+    // ヘッダーを読み終えていない接続の数を抑え、期限までに届かなければ切断する
+    let Ok(pending) = context.pending_headers.clone().try_acquire_owned() else {
+        error!(
+            "ヘッダーを待っている接続が多すぎるため切断しました: {}",
+            peer
+        );
+        return;
+    };
+    let deadline = tokio::time::Instant::now() + HEADER_TIMEOUT;
+
+    // 認証の後にアクセス制御ルールを評価する
+    let authorized = within(deadline, async {
+        let (header, token_id) = read_authorized_header(&mut socket, &peer, &context).await?;
+        let grant = context.acl.check(&peer, token_id.as_deref())?;
+        Ok((header, token_id, grant))
+    })
+    .await;
+    let (header, token_id, grant) = match authorized {
+        Ok(authorized) => authorized,
+        Err(e) => {
+            error!("接続を拒否しました: {} ({:#})", peer, e);
+            context.audit("reject", &peer, &format!("{:#}", e));
+            context.notify(id, TransferEvent::Rejected(format!("{:#}", e)));
+            context.hook(
+                Payload::new(WebhookEvent::Rejected, transfer_id, &peer)
+                    .with_message(format!("{:#}", e)),
+            );
+
+            let response = Response::new(Reason::Unauthorized).with_message(format!("{:#}", e));
+            reject(&mut socket, &response, transfer_id, false).await;
+            return;
+        }
+    };
+
+    // 通常のヘッダーの前に置かれる、接続の維持・応答の形式・通知・バッチ・マニフェストの扱いの指定とメモ・メタデータ・転送IDを読む
+    let mut header = header;
+    let mut note = None;
+    let mut metadata = BTreeMap::new();
+    let mut keepalive = None;
+    let mut structured = false;
+    let mut acks = false;
+    let mut queue_status = false;
+    let mut batch = None;
+    let mut mode = ManifestMode::Accept;
+    loop {
+        match header {
+            Header::KeepAlive(requested) => keepalive = Some(requested.interval_secs),
+            Header::Response => structured = true,
+            Header::Ack => acks = true,
+            Header::Queue => queue_status = true,
+            Header::Batch(batch_id) => batch = Some(batch_id),
+            Header::DryRun => mode = ManifestMode::DryRun,
+            Header::Verify => mode = ManifestMode::Verify,
+            Header::Note(text) => note = Some(text),
+            Header::Metadata(fields) => metadata = fields,
+            Header::TransferId(requested) => {
+                transfer_id = requested;
+                log::set_transfer_id(requested);
+            }
+            _ => break,
+        }
+        header = match within(deadline, protocol::read_header(&mut socket)).await {
+            Ok(header) => header,
+            Err(e) => {
+                error!("ヘッダーの読み取りに失敗: {} ({:#})", peer, e);
+                return;
+            }
+        };
+    }
+    drop(pending);
+
+    // 接続の維持を求められたら、応答までの間 PING を送り、読み書きが途切れたら失敗させる
+    // （データは続くヘッダーの直後から途切れずに届くため、そのヘッダーを読んでから包む）
+    if let Some(interval_secs) = keepalive {
+        let interval = Duration::from_secs(interval_secs.clamp(1, 60) as u64);
+        socket = KeepAlive::new(interval).wrap(socket);
+        socket = IdleTimeout::new(IDLE_TIMEOUT).wrap(socket);
+    }
+
+    // バージョン情報の問い合わせにはこちらのバージョン情報を返す（データは続かない）
+    if let Header::Hello(hello) = &header {
+        answer_hello(&mut socket, &peer, hello, context.require_token).await;
+        return;
+    }
+
+    // 試し送信・検証で確認できるのはマニフェストだけ（データを受け取る転送は受け付けない）
+    if mode != ManifestMode::Accept && !matches!(header, Header::Manifest(_)) {
+        error!("試し送信・検証にマニフェスト以外が送られました: {}", peer);
+        let response = Response::new(Reason::Declined)
+            .with_message("試し送信・検証で確認できるのはマニフェストだけです");
+        reject(&mut socket, &response, transfer_id, structured).await;
+        return;
+    }
+
+    // 試し送信・検証は保存先のファイルの有無・内容・更新日時を明かすため、認証した送信元か、
+    // 確認で受け入れた場合だけ応じる（確認の先がない場合と隔離する設定では、認証していない送信元には断る）
+    let unauthenticated = token_id.is_none() && (context.prompt.is_none() || context.quarantine);
+    if mode != ManifestMode::Accept && unauthenticated {
+        info!(
+            "認証していない送信元からの試し送信・検証を断りました: {}",
+            peer
+        );
+        context.audit("reject", &peer, "認証していない送信元からの試し送信・検証");
+        let response = Response::new(Reason::Declined);
+        reject(&mut socket, &response, transfer_id, structured).await;
+        return;
+    }
+
+    // 一時停止中は新しい転送を断る（受け入れ済みのバッチのファイルと分割転送の続きは受け取る）
+    let starts_transfer = batch.is_none()
+        && mode == ManifestMode::Accept
+        && !matches!(&header, Header::Part(header) if header.offset != 0);
+    if starts_transfer && is_paused() {
+        info!("受信を一時停止しているため断りました: {}", peer);
+        context.audit("reject", &peer, "受信を一時停止しています");
+        context.notify(
+            id,
+            TransferEvent::Rejected("受信を一時停止しています".to_string()),
+        );
+        context.hook(
+            Payload::new(WebhookEvent::Rejected, transfer_id, &peer)
+                .with_file(header_label(&header), header_size(&header))
+                .with_message("受信を一時停止しています"),
+        );
+
+        let response = Response::new(Reason::Paused).with_message("受信を一時停止しています");
+        reject(&mut socket, &response, transfer_id, structured).await;
+        return;
+    }
+
+    // 容量制限と接続元ごとの上限に計上する（転送数は分割転送の先頭のストリームでのみ数える）
+    let bytes = payload_len(&header);
+    let new_transfer = match &header {
+        Header::Part(header) => header.offset == 0,
+        Header::Manifest(_) => false,
+        _ => true,
+    };
+    let client = match &token_id {
+        Some(id) => format!("token:{}", id),
+        None => peer.clone(),
+    };
+    let reserved = context
+        .acl
+        .reserve(grant, bytes)
+        .map_err(|e| Response::new(Reason::QuotaExceeded).with_message(format!("{:#}", e)))
+        .and_then(|charge| {
+            context
+                .limiter
+                .reserve(&client, bytes, new_transfer)
+                .map(|()| charge)
+                .map_err(|e| Response::new(e.reason()).with_message(e.to_string()))
+        })
+        .and_then(|charge| {
+            context
+                .reserve_token_use(token_id.as_deref(), token_files(&header, new_transfer))
+                .map(|token_use| (charge, token_use))
+                .map_err(|e| Response::new(Reason::Unauthorized).with_message(format!("{:#}", e)))
+        });
+    // 容量制限とトークンのファイル数への計上は、受信に成功するまでは拒否・失敗・中断で戻す
+    let (charge, token_use) = match reserved {
+        Ok(reserved) => reserved,
+        Err(response) => {
+            let message = response.message.clone().unwrap_or_default();
+            error!("接続を拒否しました: {} ({})", peer, message);
+            context.audit("reject", &peer, &message);
+            context.hook(
+                Payload::new(WebhookEvent::Rejected, transfer_id, &peer)
+                    .with_file(header_label(&header), header_size(&header))
+                    .with_message(message.clone()),
+            );
+            context.notify(id, TransferEvent::Rejected(message));
+
+            reject(&mut socket, &response, transfer_id, structured).await;
+            return;
+        }
+    };
+
+    // 受け入れ済みのバッチのファイルは確認せず、バッチを受け入れたときの保存先に保存する
+    // （まとめて送られたファイルは、すべてがバッチに含まれる場合のみ）
+    let batch_dir = match batch {
+        Some(batch_id) => header_files(&header)
+            .into_iter()
+            .map(|(filename, size)| {
+                context.batch_save_dir(batch_id, token_id.as_deref(), filename, size)
+            })
+            .collect::<Option<Vec<_>>>()
+            .and_then(|dirs| dirs.into_iter().next()),
+        None => None,
+    };
+
+    // 受け入れるかの確認（分割転送はどのストリームからでもファイルごとに1度だけ確認する。
+    // 試し送信は受け入れる場合と同じく確認し、検証は認証していない送信元の場合だけ確認する）
+    let needs_prompt = batch_dir.is_none()
+        && (mode != ManifestMode::Verify || token_id.is_none())
+        && match &header {
+            Header::Part(header) => !context
+                .partial_files
+                .lock()
+                .unwrap()
+                .get(&header.transfer_id)
+                .is_some_and(|partial| partial.accepted),
+            Header::Auth(_) | Header::SshAuth(_) => false,
+            _ => true,
+        };
+    let batch_dir_used = batch_dir.is_some();
+    let mut save_dir = batch_dir.unwrap_or(save_dir);
+    if let (Some(note), true) = (&note, needs_prompt) {
+        info!("{} からのメモ: {}", peer, note);
+    }
+    // 信頼するネットワーク（設定ファイルの [[trusted_networks]]）から認証して接続してきた送信元は確認しない
+    let trusted = context.prompt.is_some()
+        && needs_prompt
+        && context.trusted.trusts(&peer, token_id.as_deref());
+    if trusted {
+        info!(
+            "信頼するネットワークの認証済みの送信元のため、確認せずに受け入れます: {}",
+            peer
+        );
+    }
+    if let (Some(prompt), true) = (&context.prompt, needs_prompt && !trusted) {
+        let ask = async {
+            let (reply, decision) = oneshot::channel();
+            let request = IncomingTransfer {
+                peer: peer.clone(),
+                filename: header_label(&header),
+                size: header_size(&header),
+                note: note.clone(),
+                metadata: metadata.clone(),
+                transfer_id,
+                reply,
+            };
+            match prompt.send(request).await {
+                Ok(()) => decision.await.ok(),
+                Err(_) => None,
+            }
+        };
+        let decision = match &header {
+            // 後から届いたストリームは、最初のストリームで確認した結果に従う
+            Header::Part(part) => context
+                .part_prompt(&peer, part.transfer_id)
+                .get_or_init(|| ask)
+                .await
+                .clone(),
+            _ => ask.await,
+        };
+        let decision = match decision {
+            Some(Decision::Accept(Some(dir))) => match fs::create_dir_all(&dir) {
+                Ok(()) => {
+                    save_dir = dir;
+                    None
+                }
+                Err(e) => Some(format!("保存先フォルダの作成に失敗: {:?}: {}", dir, e)),
+            },
+            Some(Decision::Accept(None)) => None,
+            Some(Decision::Reject(reason)) => Some(reason),
+            None => Some(String::new()),
+        };
+        if let Some(reason) = decision {
+            let detail = if reason.is_empty() {
+                "受信が拒否されました".to_string()
+            } else {
+                format!("受信が拒否されました: {}", reason)
+            };
+            info!("受信を拒否しました: {}", peer);
+            context.audit("reject", &peer, &detail);
+            context.hook(
+                Payload::new(WebhookEvent::Rejected, transfer_id, &peer)
+                    .with_file(header_label(&header), header_size(&header))
+                    .with_message(detail.clone()),
+            );
+            context.notify(id, TransferEvent::Rejected(detail));
+            if let Header::Part(header) = &header {
+                if let Some(partial) = context
+                    .partial_files
+                    .lock()
+                    .unwrap()
+                    .remove(&header.transfer_id)
+                {
+                    let _ = fs::remove_file(partial.partial_path);
+                }
+            }
+
+            // 理由が入力されなければ、受信側で拒否されたことだけを伝える
+            let message = if reason.is_empty() {
+                "受信側で拒否されました".to_string()
+            } else {
+                reason
+            };
+            let response = Response::new(Reason::Declined).with_message(message);
+            reject(&mut socket, &response, transfer_id, structured).await;
+            return;
+        }
+    }
+
+    // 認証していない送信元からのファイルは、隔離フォルダに受信する
+    // （受け入れたときの保存先は、解放したときに移す先として記録する）
+    let quarantined = context.quarantine
+        && token_id.is_none()
+        && mode == ManifestMode::Accept
+        && !matches!(header, Header::Auth(_) | Header::SshAuth(_));
+    if quarantined && !quarantine::contains(&save_dir) {
+        let admitted = quarantine::admit(
+            transfer_id,
+            &save_dir,
+            &peer,
+            note.as_deref(),
+            &metadata,
+            context.index,
+        );
+        match admitted {
+            Ok((quarantine_id, dir)) => {
+                info!(
+                    "認証していない送信元のため隔離フォルダに受信します（`file-transfer release {}` で保存先に移します）: {}",
+                    quarantine_id, peer
+                );
+                context.audit("quarantine", &peer, &format!("{:?}", dir));
+                save_dir = dir;
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                let response = Response::new(Reason::Failed)
+                    .with_message("受信側で隔離フォルダを作成できません");
+                reject(&mut socket, &response, transfer_id, structured).await;
+                return;
+            }
+        }
+    }
+
+    let sender = Sender {
+        name: peer.clone(),
+        grant,
+        acks,
+        note: note.clone(),
+        metadata: metadata.clone(),
+        quarantined: quarantine::contains(&save_dir),
+        batch: batch.filter(|_| batch_dir_used),
+    };
+
+    // マニフェストはバッチを登録（または確認・検証）して応答するだけで、データは続かない
+    // （既にあるファイルのハッシュの計算で他の接続の受信を止めないよう、別のスレッドで確認する）
+    if let Header::Manifest(manifest) = header {
+        let checked = {
+            let context = context.clone();
+            let save_dir = save_dir.clone();
+            let sender = sender.clone();
+            tokio::task::spawn_blocking(move || match mode {
+                ManifestMode::Verify => verify_manifest(&context, &save_dir, &sender, &manifest),
+                mode => accept_manifest(
+                    &context,
+                    &save_dir,
+                    &sender,
+                    token_id,
+                    &manifest,
+                    mode == ManifestMode::DryRun,
+                ),
+            })
+            .await
+        };
+        let response = checked.unwrap_or_else(|e| {
+            error!("マニフェストの確認に失敗: {} ({})", peer, e);
+            Response::new(Reason::Failed).with_message("受信側でマニフェストを確認できません")
+        });
+        respond(&mut socket, &response, transfer_id, structured).await;
+        return;
+    }
+
+    // 同時に受信する転送の数の上限に達していれば、枠が空くまで待たせる
+    let _slot = match &context.scheduler {
+        Some(scheduler) => {
+            match wait_for_slot(&mut socket, scheduler, &peer, queue_status, &cancel).await {
+                Ok(slot) => Some(slot),
+                Err(e) => {
+                    error!("受信の順番を待つ間に接続が切れました: {} ({:#})", peer, e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    if let Some(filename) = header_filename(&header) {
+        context.audit(
+            "accept",
+            &peer,
+            &sender.with_note(format!("{} ({} バイト)", filename, payload_len(&header))),
+        );
+    }
+
+    // 経過の通知のため、接続を包む
+    if let Some(events) = &context.events {
+        let total = payload_len(&header);
+        events.on_event(
+            id,
+            TransferEvent::Started {
+                peer: peer.clone(),
+                filename: header_label(&header),
+                total,
+                note,
+                transfer_id,
+            },
+        );
+        socket = Observe::new(events.clone(), id, total).wrap(socket);
+    }
+
+    // 進捗の記録と中断のため、接続を包む
+    let mut tracked = None;
+    if let Some(transfers) = &context.transfers {
+        let (tracked_id, connection) = progress::track(
+            transfers,
+            socket,
+            &peer,
+            &header_label(&header),
+            payload_len(&header),
+            cancel.clone(),
+        );
+        socket = connection;
+        tracked = Some(tracked_id);
+    }
+
+    // 受信の結果を Webhook で知らせるため、ヘッダーを渡す前に名前と大きさを控えておく
+    let (label, size) = (header_label(&header), header_size(&header));
+    let filenames: Vec<String> = header_files(&header)
+        .into_iter()
+        .map(|(filename, _)| filename.to_string())
+        .collect();
+    let result = match header {
+        Header::File(header) => {
+            receive_file(&mut socket, &save_dir, &sender, header, &context).await
+        }
+        Header::Part(header) => {
+            receive_part(&mut socket, &save_dir, &sender, header, &context, &cancel).await
+        }
+        Header::Symlink(header) => receive_symlink(&save_dir, &sender, header, &context),
+        Header::Sparse(header) => {
+            receive_sparse(&mut socket, &save_dir, &sender, header, &context).await
+        }
+        Header::Bundle(header) => {
+            receive_bundle(&mut socket, &save_dir, &sender, header, &context).await
+        }
+        Header::Auth(_) | Header::SshAuth(_) => {
+            Err(anyhow::anyhow!("認証ヘッダーが重複しています"))
+        }
+        Header::Hello(_)
+        | Header::KeepAlive(_)
+        | Header::Response
+        | Header::Ack
+        | Header::Batch(_)
+        | Header::DryRun
+        | Header::Verify
+        | Header::Note(_)
+        | Header::TransferId(_)
+        | Header::Queue
+        | Header::Manifest(_) => Err(anyhow::anyhow!("ヘッダーの位置が不正です")),
+    };
+
+    if let (Some(transfers), Some(tracked_id)) = (&context.transfers, tracked) {
+        let result = result.as_ref().map(|_| ()).map_err(|e| format!("{:#}", e));
+        progress::finish(transfers, tracked_id, result);
+    }
+    context.notify(
+        id,
+        match &result {
+            Ok(_) => TransferEvent::Completed,
+            Err(_) if cancel.is_cancelled() => TransferEvent::Cancelled,
+            Err(e) => TransferEvent::Failed(format!("{:#}", e)),
+        },
+    );
+
+    match result {
+        Ok(received) => {
+            charge.commit();
+            token_use.commit();
+
+            let files = received.file_count();
+            if let (Some(batch_id), true) = (sender.batch, files > 0) {
+                context.batch_received(batch_id, &filenames);
+            }
+            // 分割転送の途中のストリームやマニフェストの受け入れでは知らせない
+            if files > 0 {
+                context.hook(
+                    Payload::new(WebhookEvent::Completed, transfer_id, &sender.name)
+                        .with_file(label, size)
+                        .with_metadata(&sender.metadata),
+                );
+            }
+
+            // 成功応答の送信
+            respond(
+                &mut socket,
+                &received.into_response(),
+                transfer_id,
+                structured,
+            )
+            .await;
+        }
+        Err(_) if cancel.is_cancelled() => {
+            info!("受信を中断しました: {}", sender.name);
+            context.audit("cancelled", &sender.name, "受信が中断されました");
+        }
+        Err(e) => {
+            error!("ファイルの受信に失敗: {:#}", e);
+            context.audit("failed", &sender.name, &format!("{:#}", e));
+            context.hook(
+                Payload::new(WebhookEvent::Failed, transfer_id, &sender.name)
+                    .with_file(label, size)
+                    .with_metadata(&sender.metadata)
+                    .with_message(format!("{:#}", e)),
+            );
+
+            // 接続が切れていなければ、失敗の理由を送信側に伝える
+            let response = Response::new(Reason::Failed).with_message(format!("{:#}", e));
+            reject(&mut socket, &response, transfer_id, structured).await;
+        }
+    }
+}
+
+// マニフェストのバッチを受け入れる
+//
+// 同じ内容のファイルを既に持っていれば送らなくてよいものとして応答で返し、
+// 残りのファイルが保存先に収まるかを確認してからバッチを登録する。
+// 試し送信では、上書きされるファイルも応答で返し、バッチは登録しない
+fn accept_manifest(
+    context: &ReceiveContext,
+    save_dir: &Path,
+    sender: &Sender,
+    token_id: Option<String>,
+    manifest: &ManifestHeader,
+    dry_run: bool,
+) -> Response {
+    let mut skip = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut needed = 0;
+    for entry in &manifest.entries {
+        match existing_file(context, save_dir, sender, entry, true) {
+            Ok(Existing::Same) => skip.push(entry.filename.clone()),
+            Ok(Existing::Different) => {
+                conflicts.push(entry.filename.clone());
+                needed += entry.size;
+            }
+            Ok(Existing::Missing) => needed += entry.size,
+            Err(e) => {
+                error!("バッチを拒否しました: {} ({:#})", sender.name, e);
+                context.audit("reject", &sender.name, &format!("{:#}", e));
+                return Response::new(Reason::Declined).with_message(format!("{:#}", e));
+            }
+        }
+    }
+
+    match fs2::available_space(save_dir) {
+        Ok(available) if needed > available => {
+            let message = format!(
+                "{} バイト必要ですが、空きは {} バイトです",
+                needed, available
+            );
+            error!("バッチを拒否しました: {} ({})", sender.name, message);
+            context.audit("reject", &sender.name, &message);
+            return Response::new(Reason::InsufficientStorage).with_message(message);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("保存先の空き容量を確認できません: {:?}: {}", save_dir, e),
+    }
+
+    if dry_run {
+        info!(
+            "試し送信のマニフェストを確認しました: {} ({} 個のファイル、受信済み {} 個、上書き {} 個)",
+            manifest.name,
+            manifest.entries.len(),
+            skip.len(),
+            conflicts.len()
+        );
+        return Response {
+            skip,
+            conflicts,
+            ..Response::new(Reason::BatchAccepted)
+        };
+    }
+
+    context.batches.lock().unwrap().insert(
+        manifest.batch_id,
+        Batch {
+            save_dir: save_dir.to_path_buf(),
+            token_id,
+            pending: manifest
+                .entries
+                .iter()
+                .map(|entry| {
+                    let expected = Expected {
+                        size: entry.size,
+                        sha256: entry.sha256.clone(),
+                    };
+                    (entry.filename.clone(), expected)
+                })
+                .collect(),
+            last_used: Instant::now(),
+        },
+    );
+    let detail = format!(
+        "{} ({} 個のファイル、{} バイト。受信済みの {} 個は省略)",
+        manifest.name,
+        manifest.entries.len(),
+        manifest.total_size(),
+        skip.len()
+    );
+    info!("バッチを受け入れました: {}", detail);
+    context.audit("accept", &sender.name, &sender.with_note(detail));
+
+    Response {
+        skip,
+        ..Response::new(Reason::BatchAccepted)
+    }
+}
+
+// 送信済みのファイルが、保存先にあるものと同じ内容かをマニフェストのハッシュで確かめる
+//
+// 重複排除で別の名前のファイルを使い回した場合も含め、保存先のその名前のファイルだけと比べる
+fn verify_manifest(
+    context: &ReceiveContext,
+    save_dir: &Path,
+    sender: &Sender,
+    manifest: &ManifestHeader,
+) -> Response {
+    let mut conflicts = Vec::new();
+    let mut missing = Vec::new();
+    for entry in &manifest.entries {
+        match existing_file(context, save_dir, sender, entry, false) {
+            Ok(Existing::Same) => {}
+            Ok(Existing::Different) => conflicts.push(entry.filename.clone()),
+            Ok(Existing::Missing) => missing.push(entry.filename.clone()),
+            Err(e) => {
+                error!("検証を拒否しました: {} ({:#})", sender.name, e);
+                context.audit("reject", &sender.name, &format!("{:#}", e));
+                return Response::new(Reason::Declined).with_message(format!("{:#}", e));
+            }
+        }
+    }
+
+    let detail = format!(
+        "{} ({} 個のファイル、不一致 {} 個、なし {} 個)",
+        manifest.name,
+        manifest.entries.len(),
+        conflicts.len(),
+        missing.len()
+    );
+    info!("検証しました: {}", detail);
+    context.audit("verify", &sender.name, &detail);
+
+    Response {
+        conflicts,
+        missing,
+        ..Response::new(Reason::Verified)
+    }
+}
+
+// マニフェストのファイルの保存先に既にあるもの
+enum Existing {
+    Missing,
+    // 同じ内容のファイルがある（重複排除で省略する場合は、保存先フォルダ内のどこかにある）
+    Same,
+    // 同じ名前で内容の異なるものがある（受信すると上書きされる）
+    Different,
+}
+
+// 保存先に既にあるものを調べる（フォルダは作成しない）
+//
+// anywhere が true なら、重複排除で省略する設定のときは保存先フォルダ内のどこかにあれば同じとみなす
+fn existing_file(
+    context: &ReceiveContext,
+    save_dir: &Path,
+    sender: &Sender,
+    entry: &ManifestEntry,
+    anywhere: bool,
+) -> Result<Existing> {
+    // 保存先が許可されていなければ、バッチ全体を受け入れない
+    let relative = template::expand(&context.save_template, &sender.name, &entry.filename)?;
+    context
+        .acl
+        .check_destination(sender.grant, save_dir, &relative)?;
+    let save_path = template::resolve(save_dir, &relative);
+    if let Some(hash) = &entry.sha256 {
+        if anywhere && context.dedup == DedupMode::Skip && dedup::find(save_dir, hash)?.is_some() {
+            return Ok(Existing::Same);
+        }
+    }
+    let Ok(metadata) = fs::metadata(&save_path) else {
+        return Ok(Existing::Missing);
+    };
+    let same = match &entry.sha256 {
+        Some(hash) => {
+            metadata.is_file()
+                && metadata.len() == entry.size
+                && dedup::sha256_file(&save_path)? == *hash
+        }
+        None => false,
+    };
+    Ok(if same {
+        Existing::Same
+    } else {
+        Existing::Different
+    })
+}
+
+// 期限までにヘッダーを読み終えなければエラーにする
+async fn within<T>(
+    deadline: tokio::time::Instant,
+    read: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::time::timeout_at(deadline, read)
+        .await
+        .unwrap_or_else(|_| {
+            Err(anyhow::anyhow!(
+                "ヘッダーが {} 秒以内に届きませんでした",
+                HEADER_TIMEOUT.as_secs()
+            ))
+        })
+}
+
+// 同時に受信する転送の数の枠が空くのを待つ（求められていれば、順番が変わるたびに送信側に知らせる）
+async fn wait_for_slot(
+    socket: &mut impl Connection,
+    scheduler: &Arc<Scheduler>,
+    peer: &str,
+    notify: bool,
+    cancel: &CancellationToken,
+) -> Result<Slot> {
+    let mut waiting = scheduler.enqueue(peer);
+    loop {
+        let turn = tokio::select! {
+            turn = waiting.next() => turn,
+            _ = cancel.cancelled() => anyhow::bail!("受信が中断されました"),
+        };
+        match turn {
+            Turn::Ready(slot) => return Ok(slot),
+            Turn::Queued(position) => {
+                info!(
+                    "同時に受信する転送の数の上限に達しているため待たせています: {}（{} 番目）",
+                    peer, position
+                );
+                if notify {
+                    protocol::write_queued(socket, position as u32).await?;
+                }
+            }
+        }
+    }
+}
+
+// 応答の送信（structured なら構造化した形式、それ以外は旧形式）
+async fn respond(
+    socket: &mut impl Connection,
+    response: &Response,
+    transfer_id: Uuid,
+    structured: bool,
+) {
+    // 旧形式の応答には転送IDを載せられない
+    let text = if structured {
+        Response {
+            transfer_id: Some(transfer_id),
+            ..response.clone()
+        }
+        .to_line()
+    } else {
+        response.to_legacy()
+    };
+    if let Err(e) = socket.write_all(text.as_bytes()).await {
+        error!("応答の送信に失敗: {}", e);
+    }
+}
+
+// 拒否・失敗の応答を返して接続を閉じる
+//
+// 送信側はヘッダーに続けてデータを送っている場合があり、読み残したまま閉じると接続がリセットされて
+// 送信側が応答を読めないことがあるため、送信側が閉じるまで（長くても REJECT_DRAIN_TIMEOUT まで）読み捨てる
+async fn reject(
+    socket: &mut impl Connection,
+    response: &Response,
+    transfer_id: Uuid,
+    structured: bool,
+) {
+    respond(socket, response, transfer_id, structured).await;
+    let _ = socket.shutdown().await;
+    let drain = async {
+        let mut buf = [0u8; 8192];
+        while matches!(socket.read(&mut buf).await, Ok(n) if n > 0) {}
+    };
+    let _ = tokio::time::timeout(REJECT_DRAIN_TIMEOUT, drain).await;
+}
+
+// クライアントのバージョンを記録し、こちらのバージョン情報を返す（認証が必要なら AUTH_REQUIRED を添える）
+async fn answer_hello(
+    socket: &mut impl Connection,
+    peer: &str,
+    hello: &Hello,
+    require_token: bool,
+) {
+    let mut current = Hello::current();
+    if require_token {
+        current.features.push(AUTH_REQUIRED.to_string());
+    }
+    if hello.version != current.version {
+        warn!(
+            "クライアント {} のバージョン {} はこのサーバー（{}）と異なります",
+            peer, hello.version, current.version
+        );
+    }
+    if let Err(e) = socket.write_all(current.to_response().as_bytes()).await {
+        error!("バージョン情報の送信に失敗: {}", e);
+    }
+}
+
+// 接続の先頭のヘッダーを読み取る
+// （認証トークンのヘッダーが付いていれば検証し、続くヘッダーとトークンのIDを返す。
+// ssh-agent の鍵での認証の場合は、トークンのIDの代わりに "ssh:SHA256:..." を返す）
+async fn read_authorized_header(
+    socket: &mut impl Connection,
+    peer: &str,
+    context: &ReceiveContext,
+) -> Result<(Header, Option<String>)> {
+    let header = protocol::read_header(socket).await?;
+    if let Header::SshAuth(auth) = &header {
+        let Some(keys) = &context.ssh_keys else {
+            context.audit(
+                "auth-failed",
+                peer,
+                "SSH の鍵での認証は有効になっていません",
+            );
+            anyhow::bail!("SSH の鍵での認証は有効になっていません");
+        };
+        let verified = keys.verify(socket, auth).await;
+        match &verified {
+            Ok(id) => context.audit("auth", peer, &format!("SSH の鍵 {}", id)),
+            Err(e) => context.audit("auth-failed", peer, &format!("{:#}", e)),
+        }
+        return Ok((protocol::read_header(socket).await?, Some(verified?)));
+    }
+    let Header::Auth(auth) = header else {
+        // バージョン情報の問い合わせは、認証が必要なことを送信前に知らせるため認証なしでも答える
+        if context.require_token && !matches!(header, Header::Hello(_)) {
+            context.audit("auth-failed", peer, "トークンが提示されていません");
+            anyhow::bail!("トークンが提示されていません");
+        }
+        return Ok((header, None));
+    };
+
+    // トークン認証が無効な場合、提示されたトークンは無視する
+    let token_id = if context.require_token {
+        let authorized = {
+            let _guard = context.token_lock.lock().unwrap();
+            token::authorize(&auth.token)
+        };
+        match &authorized {
+            Ok(id) => context.audit("auth", peer, &format!("トークン {}", id)),
+            Err(e) => context.audit("auth-failed", peer, &format!("{:#}", e)),
+        }
+        Some(authorized?)
+    } else {
+        None
+    };
+
+    Ok((protocol::read_header(socket).await?, token_id))
+}
+
+// トークンの使用数として数えるファイル数（分割転送は先頭のストリームで1つ）
+fn token_files(header: &Header, new_transfer: bool) -> u32 {
+    match header {
+        Header::Part(_) => u32::from(new_transfer),
+        Header::Bundle(bundle) => bundle.entries.len() as u32,
+        header => u32::from(header_filename(header).is_some()),
+    }
+}
+
+// ヘッダーに続いて受信するデータのバイト数
+fn payload_len(header: &Header) -> u64 {
+    match header {
+        Header::File(header) => header.filedata_len as u64,
+        Header::Part(header) => header.length,
+        Header::Sparse(header) => header.extents.iter().map(|(_, length)| length).sum(),
+        Header::Bundle(header) => header.total_size(),
+        Header::Symlink(_)
+        | Header::Auth(_)
+        | Header::SshAuth(_)
+        | Header::Hello(_)
+        | Header::KeepAlive(_)
+        | Header::Response
+        | Header::Ack
+        | Header::Batch(_)
+        | Header::DryRun
+        | Header::Verify
+        | Header::Note(_)
+        | Header::TransferId(_)
+        | Header::Queue
+        | Header::Manifest(_) => 0,
+    }
+}
+
+// ヘッダーで送られてきたファイル名
+fn header_filename(header: &Header) -> Option<&str> {
+    match header {
+        Header::File(header) => Some(&header.filename),
+        Header::Part(header) => Some(&header.filename),
+        Header::Symlink(header) => Some(&header.filename),
+        Header::Sparse(header) => Some(&header.filename),
+        Header::Auth(_)
+        | Header::SshAuth(_)
+        | Header::Hello(_)
+        | Header::KeepAlive(_)
+        | Header::Response
+        | Header::Ack
+        | Header::Batch(_)
+        | Header::DryRun
+        | Header::Verify
+        | Header::Note(_)
+        | Header::TransferId(_)
+        | Header::Queue
+        | Header::Manifest(_)
+        | Header::Bundle(_) => None,
+    }
+}
+
+// ヘッダーで送られてきたファイル名の一覧（まとめて送られた場合はすべて）
+fn header_files(header: &Header) -> Vec<(&str, u64)> {
+    match header {
+        Header::Bundle(bundle) => bundle
+            .entries
+            .iter()
+            .map(|entry| (entry.filename.as_str(), entry.size as u64))
+            .collect(),
+        header => header_filename(header)
+            .map(|filename| (filename, header_size(header)))
+            .into_iter()
+            .collect(),
+    }
+}
+
+// 確認・Webhook で知らせる大きさ（分割転送・スパースファイルはファイル全体、マニフェストは合計）
+fn header_size(header: &Header) -> u64 {
+    match header {
+        Header::Part(header) => header.file_size,
+        Header::Sparse(header) => header.file_size,
+        Header::Manifest(manifest) => manifest.total_size(),
+        header => payload_len(header),
+    }
+}
+
+// 確認・経過の表示に使う名前
+fn header_label(header: &Header) -> String {
+    match header {
+        Header::Manifest(manifest) => format!(
+            "{}（{} 個のファイル）",
+            manifest.name,
+            manifest.entries.len()
+        ),
+        Header::Bundle(bundle) => format!(
+            "{} ほか（{} 個のファイル）",
+            bundle.entries[0].filename,
+            bundle.entries.len()
+        ),
+        header => header_filename(header).unwrap_or_default().to_string(),
+    }
+}
+
+// 保存先のパスを求める（アクセス制御ルールで許可されていない保存先はエラー）
+fn save_path_of(
+    context: &ReceiveContext,
+    save_dir: &Path,
+    sender: &Sender,
+    filename: &str,
+) -> Result<PathBuf> {
+    let relative = template::expand(&context.save_template, &sender.name, filename)?;
+    context
+        .acl
+        .check_destination(sender.grant, save_dir, &relative)?;
+    refuse_links(save_dir, &relative)?;
+    template::join(save_dir, &relative)
+}
+
+// 保存先フォルダからの途中のフォルダと受信中の一時ファイルにシンボリックリンクがあれば拒否する
+//
+// 受信したリンクを重ねて（"a/d -> .." の中に "a/d/e -> .."）保存先フォルダの外に書き込まれないよう、
+// リンクの中には書き込まない
+pub fn refuse_links(save_dir: &Path, relative: &Path) -> Result<()> {
+    let is_link = |path: &Path| {
+        path.symlink_metadata()
+            .is_ok_and(|metadata| metadata.file_type().is_symlink())
+    };
+    for ancestor in relative.ancestors().skip(1) {
+        let path = save_dir.join(ancestor);
+        if !ancestor.as_os_str().is_empty() && is_link(&path) {
+            anyhow::bail!(
+                "保存先のパスにシンボリックリンクが含まれるため書き込めません: {:?}",
+                path
+            );
+        }
+    }
+    let partial_path = partial_path_of(&template::resolve(save_dir, relative));
+    if is_link(&partial_path) {
+        anyhow::bail!(
+            "一時ファイルがシンボリックリンクのため書き込めません: {:?}",
+            partial_path
+        );
+    }
+    Ok(())
+}
+
+// 単一ストリームで送られたファイルの受信
+async fn receive_file(
+    socket: &mut impl Connection,
+    save_dir: &Path,
+    sender: &Sender,
+    header: FileHeader,
+    context: &ReceiveContext,
+) -> Result<Received> {
+    // 一時ファイルに受信してから保存先に確定する
+    let save_path = save_path_of(context, save_dir, sender, &header.filename)?;
+    let partial_path = partial_path_of(&save_path);
+    let length = header.filedata_len as u64;
+
+    let result = async {
+        let file = tokio::fs::File::create(&partial_path)
+            .await
+            .context("一時ファイルの作成に失敗")?;
+        // データは先頭から順に届くため、ハッシュは受信しながら計算する
+        let mut pipeline = Pipeline::new(sender.acks, context.needs_hash());
+        let (mut file, written) = pipeline.copy(socket, file, length).await?;
+        if written != length {
+            anyhow::bail!(
+                "ファイルデータが途中で途切れました ({} / {} バイト)",
+                written,
+                length
+            );
+        }
+        pipeline.finish(socket, &mut file).await?;
+        Ok(pipeline.sha256())
+    }
+    .await;
+
+    let hash = match result {
+        Ok(hash) => hash,
+        Err(e) => {
+            let _ = fs::remove_file(&partial_path);
+            return Err(e);
+        }
+    };
+
+    finish_file(
+        context,
+        save_dir,
+        sender,
+        &header.filename,
+        &save_path,
+        &partial_path,
+        hash,
+    )
+    .await
+}
+
+// まとめて送られた小さなファイルを受信し、1つずつ保存先に確定する
+//
+// データは一覧の順に続けて届くため、ファイルごとにメモリに読み切ってから一時ファイルに書き込む
+async fn receive_bundle(
+    socket: &mut impl Connection,
+    save_dir: &Path,
+    sender: &Sender,
+    header: BundleHeader,
+    context: &ReceiveContext,
+) -> Result<Received> {
+    info!(
+        "{} 個のファイルをまとめて受信します ({} バイト)",
+        header.entries.len(),
+        header.total_size()
+    );
+
+    let mut received = Vec::with_capacity(header.entries.len());
+    let mut written = 0;
+    for entry in header.entries {
+        let save_path = save_path_of(context, save_dir, sender, &entry.filename)?;
+        let partial_path = partial_path_of(&save_path);
+
+        let mut data = vec![0; entry.size as usize];
+        socket
+            .read_exact(&mut data)
+            .await
+            .with_context(|| format!("ファイルデータの読み取りに失敗: {}", entry.filename))?;
+        let hash = context.needs_hash().then(|| dedup::sha256_hex(&data));
+
+        let result = async {
+            let mut file = tokio::fs::File::create(&partial_path)
+                .await
+                .context("一時ファイルの作成に失敗")?;
+            file.write_all(&data)
+                .await
+                .context("ファイルの書き込みに失敗")?;
+            // 書き込み済みを通知する場合は、ディスクへの書き込みを確定させる
+            if sender.acks {
+                file.sync_data().await.context("ファイルの書き込みに失敗")?;
+            }
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        if let Err(e) = result {
+            let _ = fs::remove_file(&partial_path);
+            return Err(e);
+        }
+
+        received.push(
+            finish_file(
+                context,
+                save_dir,
+                sender,
+                &entry.filename,
+                &save_path,
+                &partial_path,
+                hash,
+            )
+            .await?,
+        );
+        written += entry.size as u64;
+    }
+
+    if sender.acks {
+        protocol::write_ack(socket, written).await?;
+    }
+    Ok(Received::Bundle(received))
+}
+
+// 分割転送の1ストリーム分を受信し、オフセット位置に書き込む
+async fn receive_part(
+    socket: &mut impl Connection,
+    save_dir: &Path,
+    sender: &Sender,
+    header: PartHeader,
+    context: &ReceiveContext,
+    cancel: &CancellationToken,
+) -> Result<Received> {
+    let partial_files = &context.partial_files;
+    let partial_path = {
+        let mut partial_files = partial_files.lock().unwrap();
+        let partial = match partial_files.entry(header.transfer_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let final_path = save_path_of(context, save_dir, sender, &header.filename)?;
+                let partial_path = partial_path_of(&final_path);
+                let received = match PartialState::load(&partial_path, &header) {
+                    // 再起動する前に受信していた分割転送は、一時ファイルの続きに書き込む
+                    Some(state) => {
+                        info!("中断していた分割転送を再開します: {}", header.filename);
+                        state.received.into_iter().collect()
+                    }
+                    None => {
+                        // 途中から再送されたストリームは、届いていた分が残っていないため受け付けない
+                        if !is_whole_part(&header) {
+                            anyhow::bail!(
+                                "再開する分割転送のデータが残っていません: {}",
+                                header.filename
+                            );
+                        }
+                        // 最初に届いたストリームで書き込み先を確保する
+                        let file =
+                            fs::File::create(&partial_path).context("一時ファイルの作成に失敗")?;
+                        file.set_len(header.file_size)
+                            .context("一時ファイルの領域確保に失敗")?;
+                        let received = HashSet::new();
+                        if let Err(e) = PartialState::new(&header, &received).save(&partial_path) {
+                            warn!("{:#}", e);
+                        }
+                        received
+                    }
+                };
+                entry.insert(PartialFile {
+                    partial_path,
+                    save_dir: save_dir.to_path_buf(),
+                    final_path,
+                    part_count: header.part_count,
+                    received,
+                    accepted: false,
+                    active: 0,
+                    activity: 0,
+                })
+            }
+        };
+        // ここに届くストリームは受け入れの確認を経ている（確認が不要だった場合を含む）。
+        // 受け入れた保存先が一時ファイルを作ったときと異なれば付け替える
+        if !partial.accepted {
+            if partial.save_dir != save_dir {
+                partial.final_path = save_path_of(context, save_dir, sender, &header.filename)?;
+                partial.save_dir = save_dir.to_path_buf();
+            }
+            partial.accepted = true;
+        }
+        partial.active += 1;
+        partial.activity += 1;
+        partial.partial_path.clone()
+    };
+
+    info!(
+        "分割データを受信: {} (オフセット {}, {} バイト)",
+        header.filename, header.offset, header.length
+    );
+
+    let written = write_part(
+        socket,
+        &partial_path,
+        header.offset,
+        header.length,
+        sender.acks,
+    )
+    .await;
+
+    let mut partial_files_guard = partial_files.lock().unwrap();
+    let partial = partial_files_guard
+        .get_mut(&header.transfer_id)
+        .context("分割転送が中断されています")?;
+    partial.active -= 1;
+    partial.activity += 1;
+    if let Err(e) = written {
+        if cancel.is_cancelled() {
+            // 中断した場合は転送全体を破棄する
+            partial_files_guard.remove(&header.transfer_id);
+            let _ = fs::remove_file(&partial_path);
+            PartialState::remove(&partial_path);
+        } else if partial.active == 0 {
+            // 接続が切れた場合は届いた分を残し、再送を待つ
+            info!("分割転送の再送を待ちます: {}", header.filename);
+            expire_later(partial_files.clone(), header.transfer_id, partial.activity);
+        }
+        return Err(e);
+    }
+
+    partial.received.insert(header.offset + header.length);
+    if partial.received.len() < partial.part_count as usize {
+        if let Err(e) = PartialState::new(&header, &partial.received).save(&partial_path) {
+            warn!("{:#}", e);
+        }
+        if partial.active == 0 {
+            expire_later(partial_files.clone(), header.transfer_id, partial.activity);
+        }
+        return Ok(Received::Part);
+    }
+    let partial = partial_files_guard
+        .remove(&header.transfer_id)
+        .context("分割転送が中断されています")?;
+    drop(partial_files_guard);
+    PartialState::remove(&partial.partial_path);
+    if !partial.accepted {
+        let _ = fs::remove_file(&partial.partial_path);
+        anyhow::bail!(
+            "受け入れていない分割転送は保存しません: {}",
+            header.filename
+        );
+    }
+
+    finish_file(
+        context,
+        &partial.save_dir,
+        sender,
+        &header.filename,
+        &partial.final_path,
+        &partial.partial_path,
+        None,
+    )
+    .await
+}
+
+// 残りのストリームが RESUME_WINDOW の間届かなければ、分割転送を破棄する
+fn expire_later(partial_files: PartialFiles, transfer_id: Uuid, activity: u64) {
+    tokio::spawn(async move {
+        tokio::time::sleep(RESUME_WINDOW).await;
+        let mut partial_files = partial_files.lock().unwrap();
+        let idle = partial_files
+            .get(&transfer_id)
+            .is_some_and(|partial| partial.active == 0 && partial.activity == activity);
+        if !idle {
+            return;
+        }
+        if let Some(partial) = partial_files.remove(&transfer_id) {
+            warn!(
+                "再送されなかった分割転送を破棄しました: {:?}",
+                partial.final_path
+            );
+            let _ = fs::remove_file(&partial.partial_path);
+            PartialState::remove(&partial.partial_path);
+        }
+    });
+}
+
+// 新しい分割転送のストリームか（送信側が分割したとおりの範囲が先頭から届いた）
+fn is_whole_part(header: &PartHeader) -> bool {
+    let part_size = header
+        .file_size
+        .div_ceil(header.part_count.max(1) as u64)
+        .max(1);
+    header.offset % part_size == 0
+        && header.length == part_size.min(header.file_size.saturating_sub(header.offset))
+}
+
+// 受信中のデータを書き込む一時ファイルのパス
+pub fn partial_path_of(final_path: &Path) -> PathBuf {
+    let mut partial_path = final_path.as_os_str().to_owned();
+    partial_path.push(".part");
+    PathBuf::from(partial_path)
+}
+
+// スパースファイルの受信（データ領域だけを書き込み、残りは穴のままにする）
+async fn receive_sparse(
+    socket: &mut impl Connection,
+    save_dir: &Path,
+    sender: &Sender,
+    header: SparseHeader,
+    context: &ReceiveContext,
+) -> Result<Received> {
+    let final_path = save_path_of(context, save_dir, sender, &header.filename)?;
+    let partial_path = partial_path_of(&final_path);
+
+    let data_size: u64 = header.extents.iter().map(|(_, length)| length).sum();
+    info!(
+        "スパースファイルを受信: {} ({} バイト中 {} バイトがデータ)",
+        header.filename, header.file_size, data_size
+    );
+
+    let result = async {
+        let mut file = tokio::fs::File::create(&partial_path)
+            .await
+            .context("一時ファイルの作成に失敗")?;
+        file.set_len(header.file_size)
+            .await
+            .context("一時ファイルの領域確保に失敗")?;
+
+        // 穴の部分は流れてこないため、ハッシュは保存前にファイルから計算する
+        let mut pipeline = Pipeline::new(sender.acks, false);
+        for &(offset, length) in &header.extents {
+            file.seek(SeekFrom::Start(offset)).await?;
+            let written;
+            (file, written) = pipeline.copy(socket, file, length).await?;
+            if written != length {
+                anyhow::bail!(
+                    "データが途中で途切れました ({} / {} バイト)",
+                    written,
+                    length
+                );
+            }
+        }
+        pipeline.finish(socket, &mut file).await?;
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial_path);
+        return Err(e);
+    }
+
+    finish_file(
+        context,
+        save_dir,
+        sender,
+        &header.filename,
+        &final_path,
+        &partial_path,
+        None,
+    )
+    .await
+}
+
+// シンボリックリンクの受信（保存先フォルダの外を指すリンクは作成しない）
+fn receive_symlink(
+    save_dir: &Path,
+    sender: &Sender,
+    header: SymlinkHeader,
+    context: &ReceiveContext,
+) -> Result<Received> {
+    let link_path = save_path_of(context, save_dir, sender, &header.filename)?;
+
+    let is_absolute = header.target.starts_with('/')
+        || header.target.starts_with('\\')
+        || header.target.contains(':');
+    let target: PathBuf = filename::sanitize_received(&header.target)
+        .split('/')
+        .collect();
+    let parent = link_path.parent().unwrap_or(save_dir);
+    if is_absolute || !resolves_within(save_dir, &parent.join(&target))? {
+        anyhow::bail!(
+            "保存先フォルダの外を指すリンクは作成できません: {}",
+            header.target
+        );
+    }
+
+    // 既存のファイルは通常の受信と同様に置き換える
+    if link_path.symlink_metadata().is_ok() {
+        fs::remove_file(&link_path).context("既存ファイルの削除に失敗")?;
+    }
+    create_symlink(&target, &link_path).context("シンボリックリンクの作成に失敗")?;
+    success!(
+        "シンボリックリンクを作成しました: {:?} -> {}",
+        link_path,
+        header.target
+    );
+    context.audit(
+        "symlink",
+        &sender.name,
+        &format!("{:?} -> {}", link_path, header.target),
+    );
+
+    Ok(Received::Saved {
+        filename: relative_name(save_dir, &link_path),
+        sha256: None,
+    })
+}
+
+// path が実際に root 以下を指しているか
+//
+// 既にあるフォルダやリンクはたどった先で判定し、まだない部分は字句的に判定する
+fn resolves_within(root: &Path, path: &Path) -> Result<bool> {
+    let root = fs::canonicalize(root)
+        .with_context(|| format!("保存先フォルダの確認に失敗: {:?}", root))?;
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return Ok(false);
+                }
+            }
+            Component::CurDir => {}
+            component => {
+                resolved.push(component);
+                if let Ok(real) = fs::canonicalize(&resolved) {
+                    resolved = real;
+                }
+            }
+        }
+    }
+    Ok(resolved.starts_with(root))
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    // Windowsではリンク先がフォルダかどうかで作成方法が異なる
+    let is_dir = link
+        .parent()
+        .is_some_and(|parent| parent.join(target).is_dir());
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+// 一時ファイルに受信したデータを保存先に確定する（重複排除が有効なら既存ファイルと照合する）
+//
+// 大きなファイルのハッシュの計算で他の接続の受信を止めないよう、別のスレッドで行う
+async fn finish_file(
+    context: &ReceiveContext,
+    save_dir: &Path,
+    sender: &Sender,
+    filename: &str,
+    save_path: &Path,
+    partial_path: &Path,
+    hash: Option<String>,
+) -> Result<Received> {
+    let context = context.clone();
+    let save_dir = save_dir.to_path_buf();
+    let sender = sender.clone();
+    let filename = filename.to_string();
+    let save_path = save_path.to_path_buf();
+    let partial_path = partial_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        commit_partial(
+            &context,
+            &save_dir,
+            &sender,
+            &filename,
+            &save_path,
+            &partial_path,
+            hash,
+        )
+    })
+    .await?
+}
+
+fn commit_partial(
+    context: &ReceiveContext,
+    save_dir: &Path,
+    sender: &Sender,
+    // ヘッダーで送られた名前（バッチのファイルをマニフェストと照らし合わせるのに使う）
+    filename: &str,
+    save_path: &Path,
+    partial_path: &Path,
+    // 受信しながら計算済みのハッシュ（なければ必要な場合に一時ファイルから計算する）
+    hash: Option<String>,
+) -> Result<Received> {
+    // 内容の種類がファイル名・送信側の申告と合っているかを確かめる
+    let name = save_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let declared = sender.metadata.get(META_MIME).map(String::as_str);
+    if let Some(reason) = mime::check(partial_path, &name, declared)? {
+        context.audit(
+            "type-mismatch",
+            &sender.name,
+            &format!("{:?}: {}", save_path, reason),
+        );
+        if context.enforce_type {
+            let _ = fs::remove_file(partial_path);
+            anyhow::bail!(
+                "内容の種類が合わないため保存しません: {} ({})",
+                name,
+                reason
+            );
+        }
+        warn!("警告: {:?}: {}", save_path, reason);
+    }
+
+    // 確認なしに受け取ったバッチのファイルは、マニフェストで申告された大きさ・内容と同じものだけを保存する
+    let expected = sender
+        .batch
+        .and_then(|batch_id| context.batch_entry(batch_id, filename));
+    let needs_hash = context.needs_hash()
+        || expected
+            .as_ref()
+            .is_some_and(|expected| expected.sha256.is_some());
+    let hash = match hash {
+        Some(hash) => Some(hash),
+        None if needs_hash => Some(dedup::sha256_file(partial_path)?),
+        None => None,
+    };
+    if let Some(expected) = expected {
+        let size = fs::metadata(partial_path)?.len();
+        if size != expected.size || expected.sha256.is_some_and(|sha256| Some(sha256) != hash) {
+            let _ = fs::remove_file(partial_path);
+            anyhow::bail!("マニフェストと内容が異なるため保存しません: {}", filename);
+        }
+    }
+
+    let _guard = context.dedup_lock.lock().unwrap();
+
+    let dedup_hash = hash.as_ref().filter(|_| context.dedup != DedupMode::Off);
+    if let Some(hash) = dedup_hash {
+        if let Some(existing) = dedup::reuse_existing(context.dedup, save_dir, hash, save_path)? {
+            let _ = fs::remove_file(partial_path);
+            info!(
+                "同じ内容のファイルが既にあるため保存を省略しました: {:?}",
+                existing
+            );
+            context.audit(
+                "duplicate",
+                &sender.name,
+                &format!("{:?} sha256={}", existing, hash),
+            );
+            // ハードリンクを作った場合は、保存先にもファイルがある
+            if context.dedup == DedupMode::Link {
+                write_checksum(context, save_dir, save_path, hash);
+            }
+            index_file(context, save_dir, sender, &existing, Some(hash.as_str()));
+            return Ok(Received::Duplicate {
+                filename: relative_name(save_dir, &existing),
+                sha256: hash.clone(),
+            });
+        }
+    }
+
+    // 保存先が別のドライブの場合は名前の変更ができないため複製する
+    fs::rename(partial_path, save_path)
+        .or_else(|_| {
+            fs::copy(partial_path, save_path)?;
+            fs::remove_file(partial_path)
+        })
+        .context("ファイルの保存に失敗")?;
+    success!("ファイルを保存しました: {:?}", save_path);
+
+    if let Some(hash) = &hash {
+        context.audit(
+            "saved",
+            &sender.name,
+            &format!("{:?} sha256={}", save_path, hash),
+        );
+    }
+    if let Some(hash) = dedup_hash {
+        dedup::record(save_dir, hash, save_path)?;
+    }
+    if let Some(hash) = &hash {
+        write_checksum(context, save_dir, save_path, hash);
+    }
+    index_file(context, save_dir, sender, save_path, hash.as_deref());
+    // 隔離したファイルは、解放するまでクリップボードにコピーせず開かない
+    if let (Some(mode), false) = (context.clipboard, sender.quarantined) {
+        if let Err(e) = clipboard::copy(mode, save_path) {
+            warn!("{:#}", e);
+        }
+    }
+    if let (Some(action), false) = (context.on_receive, sender.quarantined) {
+        desktop::run(action, save_path);
+    }
+
+    Ok(Received::Saved {
+        filename: relative_name(save_dir, save_path),
+        sha256: hash,
+    })
+}
+
+// --checksum の指定どおりにハッシュを書き出す（ファイルは保存済みのため、失敗してもログに残すだけにする）
+fn write_checksum(context: &ReceiveContext, save_dir: &Path, save_path: &Path, hash: &str) {
+    if let Err(e) = checksum::write(&context.checksum, save_dir, save_path, hash) {
+        error!("{:#}", e);
+    }
+}
+
+// 受信したファイルの索引に記録する（ファイルは保存済みのため、失敗してもログに残すだけにする）
+fn index_file(
+    context: &ReceiveContext,
+    save_dir: &Path,
+    sender: &Sender,
+    path: &Path,
+    hash: Option<&str>,
+) {
+    if !context.index || sender.quarantined {
+        return;
+    }
+    let result = received::record(
+        save_dir,
+        path,
+        hash,
+        &sender.name,
+        sender.note.as_deref(),
+        &sender.metadata,
+    );
+    if let Err(e) = result {
+        error!("{:#}", e);
+    }
+}
+
+async fn write_part(
+    socket: &mut impl Connection,
+    partial_path: &Path,
+    offset: u64,
+    length: u64,
+    acks: bool,
+) -> Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(partial_path)
+        .await
+        .context("一時ファイルのオープンに失敗")?;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let mut pipeline = Pipeline::new(acks, false);
+    let (mut file, written) = pipeline.copy(socket, file, length).await?;
+    if written != length {
+        anyhow::bail!(
+            "分割データが途中で途切れました ({} / {} バイト)",
+            written,
+            length
+        );
+    }
+    pipeline.finish(socket, &mut file).await?;
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use anyhow::Result;
+use std::fs::{File, Metadata};
+
+// 見かけのサイズより実際に割り当てられた領域が小さい（穴がある）ファイルか
+#[cfg(unix)]
+pub fn looks_sparse(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512 < metadata.len()
+}
+
+#[cfg(not(unix))]
+pub fn looks_sparse(_metadata: &Metadata) -> bool {
+    false
+}
+
+// ファイル内のデータ領域（オフセット, 長さ）の一覧を返す
+//
+// SEEK_DATA/SEEK_HOLE に対応していない環境ではファイル全体を1つの領域として扱う
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+pub fn data_extents(file: &File, len: u64) -> Result<Vec<(u64, u64)>> {
+    use std::{io, os::unix::io::AsRawFd};
+
+    let fd = file.as_raw_fd();
+    let mut extents = Vec::new();
+    let mut pos: libc::off_t = 0;
+
+    while (pos as u64) < len {
+        // SAFETY: fd は file が所有する有効なファイル記述子
+        let data = unsafe { libc::lseek(fd, pos, libc::SEEK_DATA) };
+        if data < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                // 以降にデータがない（末尾が穴）
+                Some(libc::ENXIO) => break,
+                // ファイルシステムが対応していない
+                Some(libc::EINVAL) if extents.is_empty() => return Ok(vec![(0, len)]),
+                _ => return Err(err.into()),
+            }
+        }
+
+        // SAFETY: 同上
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let end = (hole as u64).min(len);
+        if end > data as u64 {
+            extents.push((data as u64, end - data as u64));
+        }
+        pos = hole;
+    }
+
+    Ok(extents)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+)))]
+pub fn data_extents(_file: &File, len: u64) -> Result<Vec<(u64, u64)>> {
+    Ok(vec![(0, len)])
+}
@@ -0,0 +1,241 @@
+use crate::protocol::{self, SshAuthHeader, SSH_CHALLENGE_LEN};
+use anyhow::{Context, Result};
+use ssh_key::{AuthorizedKeys, HashAlg, PublicKey, Signature, SshSig};
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+// チャレンジの署名に使う名前空間（ほかの用途の署名を流用されないようにする）
+const NAMESPACE: &str = "file-transfer";
+
+// 認証した鍵をアクセス制御ルールの token で指定するときの接頭辞（"ssh:SHA256:..."）
+pub const IDENTITY_PREFIX: &str = "ssh:";
+
+// ssh-agent のメッセージの種類
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+
+// RSA の鍵で SHA-512 の署名を求めるフラグ
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+// ssh-agent の応答の最大長
+const MAX_AGENT_MESSAGE_LEN: u32 = 256 * 1024;
+
+// ssh-agent に登録されている鍵
+#[derive(Clone)]
+pub struct AgentKey {
+    pub public_key: PublicKey,
+    pub comment: String,
+}
+
+impl AgentKey {
+    // 鍵の SHA-256 フィンガープリント（"SHA256:..."）
+    pub fn fingerprint(&self) -> String {
+        self.public_key.fingerprint(HashAlg::Sha256).to_string()
+    }
+}
+
+// ssh-agent から鍵を選ぶ（name はフィンガープリントかコメント。省略すると最初の鍵）
+pub async fn select_key(name: Option<&str>) -> Result<AgentKey> {
+    let keys = list_keys().await?;
+    let key = match name {
+        Some(name) => keys
+            .into_iter()
+            .find(|key| key.fingerprint() == name || key.comment == name)
+            .with_context(|| format!("ssh-agent に鍵 {} が登録されていません", name))?,
+        None => keys
+            .into_iter()
+            .next()
+            .context("ssh-agent に鍵が登録されていません")?,
+    };
+    Ok(key)
+}
+
+// ssh-agent に登録されている鍵の一覧
+async fn list_keys() -> Result<Vec<AgentKey>> {
+    let mut agent = connect().await?;
+    let reply = request(&mut agent, SSH_AGENTC_REQUEST_IDENTITIES, &[]).await?;
+    let mut reply = reply.as_slice();
+    if take_u8(&mut reply)? != SSH_AGENT_IDENTITIES_ANSWER {
+        anyhow::bail!("ssh-agent が鍵の一覧を返しませんでした");
+    }
+    let count = take_u32(&mut reply)?;
+    let mut keys = Vec::new();
+    for _ in 0..count {
+        let blob = take_string(&mut reply)?;
+        let comment = String::from_utf8_lossy(take_string(&mut reply)?).into_owned();
+        // このプログラムが扱えない種類の鍵は飛ばす
+        if let Ok(public_key) = PublicKey::from_bytes(blob) {
+            keys.push(AgentKey {
+                public_key,
+                comment,
+            });
+        }
+    }
+    Ok(keys)
+}
+
+// ssh-agent に data への署名を求め、SSH の形式の署名を返す
+async fn sign(key: &PublicKey, data: &[u8]) -> Result<Vec<u8>> {
+    let blob = key.to_bytes()?;
+    let flags = if key.algorithm().is_rsa() {
+        SSH_AGENT_RSA_SHA2_512
+    } else {
+        0
+    };
+    let mut payload = Vec::new();
+    put_string(&mut payload, &blob);
+    put_string(&mut payload, data);
+    payload.extend_from_slice(&flags.to_be_bytes());
+
+    let mut agent = connect().await?;
+    let reply = request(&mut agent, SSH_AGENTC_SIGN_REQUEST, &payload).await?;
+    let mut reply = reply.as_slice();
+    match take_u8(&mut reply)? {
+        SSH_AGENT_SIGN_RESPONSE => Ok(take_string(&mut reply)?.to_vec()),
+        SSH_AGENT_FAILURE => anyhow::bail!("ssh-agent が署名を拒否しました"),
+        kind => anyhow::bail!("ssh-agent の応答が不正です: {}", kind),
+    }
+}
+
+// クライアント側の認証（接続の先頭で公開鍵を送り、サーバーのチャレンジに署名して返す）
+pub async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
+    key: &AgentKey,
+) -> Result<()> {
+    let header = SshAuthHeader {
+        public_key: key.public_key.to_bytes()?,
+    };
+    protocol::write_ssh_auth_header(socket, &header).await?;
+    let mut challenge = [0u8; SSH_CHALLENGE_LEN];
+    socket
+        .read_exact(&mut challenge)
+        .await
+        .context("チャレンジの読み取りに失敗")?;
+    let data = SshSig::signed_data(NAMESPACE, HashAlg::Sha512, &challenge)?;
+    let signature = sign(&key.public_key, &data).await?;
+    protocol::write_ssh_signature(socket, &signature).await
+}
+
+// サーバー側で受け入れる公開鍵（authorized_keys の形式のファイル）
+pub struct AuthorizedSshKeys {
+    keys: Vec<PublicKey>,
+}
+
+impl AuthorizedSshKeys {
+    pub fn load(path: &Path) -> Result<Self> {
+        let keys = AuthorizedKeys::read_file(path)
+            .with_context(|| format!("SSH の公開鍵の一覧の読み込みに失敗: {:?}", path))?
+            .into_iter()
+            .map(|entry| entry.public_key().clone())
+            .collect();
+        Ok(Self { keys })
+    }
+
+    pub fn count(&self) -> usize {
+        self.keys.len()
+    }
+
+    // サーバー側の認証（チャレンジを送って署名を検証し、鍵の識別子 "ssh:SHA256:..." を返す）
+    //
+    // 鍵が一覧にあるかは署名を受け取ってから確かめる（一覧にない鍵でもチャレンジは送る）
+    pub async fn verify<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        socket: &mut S,
+        header: &SshAuthHeader,
+    ) -> Result<String> {
+        let mut challenge = [0u8; SSH_CHALLENGE_LEN];
+        challenge[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+        challenge[16..].copy_from_slice(Uuid::new_v4().as_bytes());
+        socket.write_all(&challenge).await?;
+        let signature = protocol::read_ssh_signature(socket).await?;
+
+        let public_key = PublicKey::from_bytes(&header.public_key).context("公開鍵が不正です")?;
+        let fingerprint = public_key.fingerprint(HashAlg::Sha256);
+        if !self
+            .keys
+            .iter()
+            .any(|key| key.key_data() == public_key.key_data())
+        {
+            anyhow::bail!("SSH の鍵 {} は登録されていません", fingerprint);
+        }
+        let signature = Signature::try_from(signature.as_slice()).context("署名が不正です")?;
+        let signature = SshSig::new(
+            public_key.key_data().clone(),
+            NAMESPACE,
+            HashAlg::Sha512,
+            signature,
+        )?;
+        public_key
+            .verify(NAMESPACE, &challenge, &signature)
+            .with_context(|| format!("SSH の鍵 {} の署名を検証できません", fingerprint))?;
+        Ok(format!("{}{}", IDENTITY_PREFIX, fingerprint))
+    }
+}
+
+// ssh-agent に接続する（SSH_AUTH_SOCK、Windows では OpenSSH の名前付きパイプ）
+#[cfg(unix)]
+async fn connect() -> Result<tokio::net::UnixStream> {
+    let path = std::env::var_os("SSH_AUTH_SOCK").context("SSH_AUTH_SOCK が設定されていません")?;
+    tokio::net::UnixStream::connect(&path)
+        .await
+        .with_context(|| format!("ssh-agent に接続できません: {:?}", path))
+}
+
+#[cfg(windows)]
+async fn connect() -> Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    let name = std::env::var("SSH_AUTH_SOCK")
+        .unwrap_or_else(|_| r"\\.\pipe\openssh-ssh-agent".to_string());
+    tokio::net::windows::named_pipe::ClientOptions::new()
+        .open(&name)
+        .with_context(|| format!("ssh-agent に接続できません: {}", name))
+}
+
+// ssh-agent に要求を送り、応答の本体（種類の1バイトを含む）を返す
+async fn request<S: AsyncRead + AsyncWrite + Unpin>(
+    agent: &mut S,
+    kind: u8,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    agent.write_u32(payload.len() as u32 + 1).await?;
+    agent.write_u8(kind).await?;
+    agent.write_all(payload).await?;
+    let len = agent.read_u32().await?;
+    if len == 0 || len > MAX_AGENT_MESSAGE_LEN {
+        anyhow::bail!("ssh-agent の応答の長さが不正です: {}", len);
+    }
+    let mut reply = vec![0u8; len as usize];
+    agent.read_exact(&mut reply).await?;
+    Ok(reply)
+}
+
+fn put_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        anyhow::bail!("ssh-agent の応答が途中で終わっています");
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+fn take_u8(buf: &mut &[u8]) -> Result<u8> {
+    Ok(take(buf, 1)?[0])
+}
+
+fn take_u32(buf: &mut &[u8]) -> Result<u32> {
+    let bytes = take(buf, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn take_string<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = take_u32(buf)? as usize;
+    take(buf, len)
+}
@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+
+// ~/.ssh/config の Host ブロック（パターンと、その中の HostName）
+struct HostBlock {
+    patterns: Vec<String>,
+    host_name: Option<String>,
+}
+
+// ~/.ssh/config のパス
+fn path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh").join("config"))
+}
+
+// ~/.ssh/config の Host ブロックを読む（ファイルがなければ空。Include・Match は読まない）
+fn load() -> Vec<HostBlock> {
+    let Some(text) = path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+        return Vec::new();
+    };
+    parse(&text)
+}
+
+fn parse(text: &str) -> Vec<HostBlock> {
+    let mut blocks = Vec::new();
+    // 最初の Host より前の設定は全てのホストに当てはまる
+    let mut current = HostBlock {
+        patterns: vec!["*".to_string()],
+        host_name: None,
+    };
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // "Keyword value" と "Keyword=value" のどちらも書ける
+        let (keyword, value) = match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+            Some((keyword, value)) => (keyword, value.trim_start_matches([' ', '\t', '=']).trim()),
+            None => (line, ""),
+        };
+        match keyword.to_ascii_lowercase().as_str() {
+            "host" => {
+                blocks.push(current);
+                current = HostBlock {
+                    patterns: value.split_whitespace().map(str::to_string).collect(),
+                    host_name: None,
+                };
+            }
+            // Match ブロックの条件は評価しないため、次の Host までは読み飛ばす
+            "match" => {
+                blocks.push(current);
+                current = HostBlock {
+                    patterns: Vec::new(),
+                    host_name: None,
+                };
+            }
+            "hostname" if current.host_name.is_none() => {
+                current.host_name = Some(value.trim_matches('"').to_string());
+            }
+            _ => {}
+        }
+    }
+    blocks.push(current);
+    blocks
+}
+
+// Host のパターンの一覧が alias に一致するか（"!" で始まるパターンに一致すれば一致しない）
+fn matches(patterns: &[String], alias: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) if glob_match(negated, alias) => return false,
+            Some(_) => {}
+            None => matched |= glob_match(pattern, alias),
+        }
+    }
+    matched
+}
+
+// "*" と "?" だけのワイルドカード（ssh と同じく大文字小文字を区別しない）
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // 直前の "*" の位置と、そこから試している text の位置
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// ~/.ssh/config の Host の別名を HostName に解決する（ssh と同じく最初に一致した HostName を使う）
+//
+// HostName の "%h" は別名に置き換える。HostName がなければ None
+pub fn resolve(alias: &str) -> Option<String> {
+    load()
+        .into_iter()
+        .filter(|block| matches(&block.patterns, alias))
+        .find_map(|block| block.host_name)
+        .map(|host_name| host_name.replace("%h", alias).replace("%%", "%"))
+}
+
+// ~/.ssh/config でワイルドカードを使わずに書かれた Host の別名（補完の候補にする）
+pub fn aliases() -> Vec<String> {
+    load()
+        .into_iter()
+        .filter(|block| block.host_name.is_some())
+        .flat_map(|block| block.patterns)
+        .filter(|pattern| !pattern.contains(['*', '?', '!']))
+        .collect()
+}
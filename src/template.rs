@@ -0,0 +1,137 @@
+use crate::filename;
+use anyhow::Result;
+use std::path::{Component, Path, PathBuf};
+
+// 既定の保存テンプレート（保存先フォルダの直下にそのまま保存）
+pub const DEFAULT_SAVE_TEMPLATE: &str = "{filename}";
+
+// テンプレートに {filename} が含まれているか確認する
+pub fn validate(template: &str) -> Result<()> {
+    if !template.contains("{filename}") {
+        anyhow::bail!(
+            "保存テンプレートには {{filename}} を含めてください: {}",
+            template
+        );
+    }
+    Ok(())
+}
+
+// 保存テンプレートを展開し、保存先フォルダからの相対パスを返す
+//
+// 使用できる置換子: {date}（YYYY-MM-DD）, {time}（HHMMSS）, {sender}, {filename}
+pub fn expand(template: &str, sender: &str, filename: &str) -> Result<PathBuf> {
+    let now = chrono::Local::now();
+    let expanded = template
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{time}", &now.format("%H%M%S").to_string())
+        .replace(
+            "{sender}",
+            &filename::sanitize_component(&sender.replace('/', "_")),
+        )
+        .replace("{filename}", &filename::sanitize_received(filename));
+
+    // 保存先フォルダの外に出るパスは受け付けない
+    let path = PathBuf::from(expanded);
+    if path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        anyhow::bail!("保存先フォルダの外を指すパスです: {:?}", path);
+    }
+
+    Ok(path)
+}
+
+// expand で求めた相対パスから保存先のパスを求める（フォルダは作成しない）
+pub fn resolve(save_dir: &Path, relative: &Path) -> PathBuf {
+    filename::extend_long_path(save_dir.join(relative))
+}
+
+// expand で求めた相対パスから保存先のパスを求め、途中のフォルダを作成する
+pub fn join(save_dir: &Path, relative: &Path) -> Result<PathBuf> {
+    let path = resolve(save_dir, relative);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 展開した相対パスが保存先フォルダの中を指すか
+    fn stays_inside(relative: &Path) -> bool {
+        relative.is_relative()
+            && relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    }
+
+    #[test]
+    fn parent_components_are_refused() {
+        for filename in ["..", "../x", "a/../../x", "a/.."] {
+            assert!(
+                expand("{filename}", "sender", filename).is_err(),
+                "{}",
+                filename
+            );
+        }
+        assert!(expand("../{filename}", "sender", "x").is_err());
+        assert!(expand("{sender}/{filename}", "..", "x").is_err());
+    }
+
+    #[test]
+    fn absolute_paths_are_refused() {
+        for filename in ["/etc/passwd", "//server/share/x"] {
+            assert!(
+                expand("{filename}", "sender", filename).is_err(),
+                "{}",
+                filename
+            );
+        }
+        assert!(expand("/tmp/{filename}", "sender", "x").is_err());
+    }
+
+    #[test]
+    fn drive_prefixes_stay_inside_the_save_folder() {
+        for filename in [
+            "C:/Windows/x",
+            r"C:\Windows\x",
+            "C:x",
+            r"\\?\C:\x",
+            r"\\server\share\x",
+        ] {
+            let relative = expand("{filename}", "sender", filename).unwrap();
+            assert!(stays_inside(&relative), "{} -> {:?}", filename, relative);
+        }
+    }
+
+    #[test]
+    fn senders_cannot_add_folders() {
+        let relative = expand("{sender}/{filename}", "a/b", "x").unwrap();
+        assert_eq!(relative, Path::new("a_b/x"));
+        let relative = expand("{sender}/{filename}", r"a\b", "x").unwrap();
+        assert_eq!(relative, Path::new("a_b/x"));
+    }
+
+    #[test]
+    fn windows_reserved_names_are_renamed_on_windows() {
+        for filename in ["CON", "nul.txt", "CONIN$", "CONOUT$"] {
+            let relative = expand("{filename}", "sender", &format!("dir/{}", filename)).unwrap();
+            let expected = if cfg!(windows) {
+                format!("dir/_{}", filename)
+            } else {
+                format!("dir/{}", filename)
+            };
+            assert_eq!(relative, Path::new(&expected));
+        }
+    }
+
+    #[test]
+    fn templates_must_contain_the_filename() {
+        assert!(validate("{filename}").is_ok());
+        assert!(validate("{date}/{sender}/{filename}").is_ok());
+        assert!(validate("{date}/{sender}").is_err());
+    }
+}
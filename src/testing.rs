@@ -0,0 +1,112 @@
+use crate::{
+    client::{self, ClientArgs},
+    config::Config,
+    server::{self, ServerArgs},
+    transport::{Accepted, BoxFuture, BoxedConnection, Transport, LOCAL_PEER},
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tokio::{io::DuplexStream, sync::mpsc};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+// 同じプロセスの中の待ち受けを指定するアドレスの接頭辞（"loopback:name"）
+pub const LOOPBACK_PREFIX: &str = "loopback:";
+
+// ループバック接続ごとのメモリ上のバッファ（書き込む側は読み取られるまでこれ以上は溜めずに待つ）
+const DUPLEX_BUFFER_SIZE: usize = 256 * 1024;
+
+// 待ち受け中のアドレスと、受け付けた接続を流すチャネル
+static LISTENERS: Mutex<Vec<(String, mpsc::Sender<Accepted>)>> = Mutex::new(Vec::new());
+
+// 同じプロセスの中でメモリ上の接続を受け渡すトランスポート（addr は "loopback:name"）
+//
+// ソケットを使わないため、ポートの空きや他のプロセスに左右されずに送受信を動かせる。
+// transport::for_addr はこの接頭辞のアドレスにこのトランスポートを返すため、
+// 送信側は --server loopback:name を指定するだけで使える
+pub struct Loopback;
+
+impl Transport for Loopback {
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<BoxedConnection>> {
+        Box::pin(async move {
+            let tx = LISTENERS
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(name, tx)| name == addr && !tx.is_closed())
+                .map(|(_, tx)| tx.clone())
+                .with_context(|| format!("{} で待ち受けていません", addr))?;
+            let (client, server) = pair();
+            let accepted = Accepted {
+                connection: Box::new(server),
+                peer: LOCAL_PEER.to_string(),
+            };
+            tx.send(accepted)
+                .await
+                .map_err(|_| anyhow::anyhow!("{} の待ち受けは終了しています", addr))?;
+            Ok(Box::new(client) as BoxedConnection)
+        })
+    }
+
+    fn listen<'a>(
+        &'a self,
+        addr: &'a str,
+        tx: mpsc::Sender<Accepted>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut listeners = LISTENERS.lock().unwrap();
+            // 終了した待ち受けは取り除く
+            listeners.retain(|(_, tx)| !tx.is_closed());
+            if listeners.iter().any(|(name, _)| name == addr) {
+                anyhow::bail!("{} はすでに待ち受けています", addr);
+            }
+            listeners.push((addr.to_string(), tx));
+            Ok(())
+        })
+    }
+}
+
+// 互いにつながったメモリ上の接続の組（プロトコルの読み書きを直接試す場合やファジングに使う）
+pub fn pair() -> (DuplexStream, DuplexStream) {
+    tokio::io::duplex(DUPLEX_BUFFER_SIZE)
+}
+
+// コマンドラインと同じ形式のオプションから受信側の引数を作る（例: &["--dedup"]）
+pub fn server_args(options: &[&str]) -> Result<ServerArgs> {
+    let args = ["file-transfer"].iter().chain(options);
+    ServerArgs::try_parse_from(args).context("受信側の引数が不正です")
+}
+
+// addr に送信する送信側の引数を作る（options はコマンドラインと同じ形式）
+pub fn client_args(addr: &str, options: &[&str]) -> Result<ClientArgs> {
+    let args = ["file-transfer", "--server", addr].iter().chain(options);
+    ClientArgs::try_parse_from(args).context("送信側の引数が不正です")
+}
+
+// 新しいループバックのアドレスで受信側を起動し、そのアドレスを返す
+//
+// 受信したファイルは save_dir に保存する。cancel を取り消すと受信側を終了する
+pub async fn start_receiver(
+    args: &ServerArgs,
+    config: &Config,
+    save_dir: PathBuf,
+    cancel: CancellationToken,
+) -> Result<String> {
+    let addr = format!("{}{}", LOOPBACK_PREFIX, Uuid::new_v4());
+    server::serve_on(args, config, save_dir, &Loopback, &addr, cancel).await?;
+    Ok(addr)
+}
+
+// 既定の設定の受信側を起動して path を送信し、受信側を終了する
+pub async fn transfer(path: &Path, save_dir: PathBuf) -> Result<()> {
+    let config = Config::default();
+    let cancel = CancellationToken::new();
+    let addr = start_receiver(&server_args(&[])?, &config, save_dir, cancel.clone()).await?;
+    let result = client::send_path(&client_args(&addr, &[])?, &config, path, None, &cancel).await;
+    cancel.cancel();
+    result
+}
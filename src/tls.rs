@@ -0,0 +1,333 @@
+use crate::{
+    dedup,
+    limits::Limiter,
+    log::error,
+    transport::{Accepted, BoxFuture, BoxedConnection, Transport},
+};
+use anyhow::{Context, Result};
+use std::{fs, io::BufReader, path::Path, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{self, CryptoProvider},
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+        server::WebPkiClientVerifier,
+        ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+// PEMファイルから証明書チェーンを読み込む
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file = fs::File::open(path).with_context(|| format!("証明書を開けません: {:?}", path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("証明書の読み込みに失敗: {:?}", path))?;
+    if certs.is_empty() {
+        anyhow::bail!("証明書が含まれていません: {:?}", path);
+    }
+    Ok(certs)
+}
+
+// PEMファイルから秘密鍵を読み込む
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let file = fs::File::open(path).with_context(|| format!("秘密鍵を開けません: {:?}", path))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("秘密鍵の読み込みに失敗: {:?}", path))?
+        .with_context(|| format!("秘密鍵が含まれていません: {:?}", path))
+}
+
+// 証明書のSHA-256フィンガープリント（クライアントでのピン留めに使う）
+pub fn fingerprint(cert: &CertificateDer) -> String {
+    dedup::sha256_hex(cert)
+}
+
+// "AB:CD:..." 形式も受け付けるよう、フィンガープリントを小文字の16進数にそろえる
+fn normalize_fingerprint(fingerprint: &str) -> Result<String> {
+    let normalized: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    if normalized.len() != 64 || !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("SHA-256フィンガープリントの形式が不正です: {}", fingerprint);
+    }
+    Ok(normalized)
+}
+
+// サーバー側のTLS設定を作り、サーバー証明書のフィンガープリントとともに返す
+// （client_ca を指定すると、そのCAが発行したクライアント証明書を必須にする）
+pub fn acceptor(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<(TlsAcceptor, String)> {
+    let certs = load_certs(cert)?;
+    let key = load_key(key)?;
+    let server_fingerprint = fingerprint(&certs[0]);
+
+    let builder = ServerConfig::builder();
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca)? {
+                roots.add(cert).context("CA証明書の登録に失敗")?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .context("クライアント証明書の検証設定に失敗")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(certs, key)
+        .context("サーバー証明書の設定に失敗")?;
+
+    Ok((TlsAcceptor::from(Arc::new(config)), server_fingerprint))
+}
+
+// クライアント側のTLS設定を作る
+// （サーバー証明書はフィンガープリントで照合し、client_cert があれば提示する）
+pub fn connector(pinned: &str, client_cert: Option<(&Path, &Path)>) -> Result<TlsConnector> {
+    let verifier = PinnedServerCert {
+        fingerprint: normalize_fingerprint(pinned)?,
+        provider: Arc::new(crypto::ring::default_provider()),
+    };
+    let builder = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+    let config = match client_cert {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .context("クライアント証明書の設定に失敗")?,
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+// 接続済みのソケット上でTLSのハンドシェイクを行う（server_addr は "host:port"）
+pub async fn connect(
+    connector: &TlsConnector,
+    server_addr: &str,
+    socket: BoxedConnection,
+) -> Result<BoxedConnection> {
+    let host = server_addr
+        .rsplit_once(':')
+        .map_or(server_addr, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let server_name = ServerName::try_from(host.to_string())
+        .with_context(|| format!("サーバー名が不正です: {}", host))?;
+    let stream = connector
+        .connect(server_name, socket)
+        .await
+        .context("TLSのハンドシェイクに失敗")?;
+    Ok(Box::new(stream))
+}
+
+// 待ち受ける側が TLS のハンドシェイクを待つ時間
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 別のトランスポートで確立した接続をTLSで包むトランスポート
+pub struct Tls {
+    inner: Arc<dyn Transport>,
+    connector: Option<TlsConnector>,
+    acceptor: Option<TlsAcceptor>,
+    // 待ち受ける側で、ハンドシェイクの前に遮断中の接続元を断るのに使う
+    limiter: Option<Arc<Limiter>>,
+    // 待ち受ける側で、ハンドシェイク中の接続を数える（ヘッダーを待つ接続と同じ上限）
+    pending: Option<Arc<Semaphore>>,
+}
+
+impl Tls {
+    // 接続する側
+    pub fn client(inner: Arc<dyn Transport>, connector: TlsConnector) -> Self {
+        Self {
+            inner,
+            connector: Some(connector),
+            acceptor: None,
+            limiter: None,
+            pending: None,
+        }
+    }
+
+    // 待ち受ける側
+    //
+    // ハンドシェイクは重いため、遮断中の接続元は始める前に断り、同時にハンドシェイクする数と時間を抑える
+    pub fn server(
+        inner: Arc<dyn Transport>,
+        acceptor: TlsAcceptor,
+        limiter: Arc<Limiter>,
+        pending: Arc<Semaphore>,
+    ) -> Self {
+        Self {
+            inner,
+            connector: None,
+            acceptor: Some(acceptor),
+            limiter: Some(limiter),
+            pending: Some(pending),
+        }
+    }
+}
+
+impl Transport for Tls {
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<BoxedConnection>> {
+        Box::pin(async move {
+            let connector = self
+                .connector
+                .as_ref()
+                .context("TLSのクライアント設定がありません")?;
+            let socket = self.inner.connect(addr).await?;
+            connect(connector, addr, socket).await
+        })
+    }
+
+    fn listen<'a>(
+        &'a self,
+        addr: &'a str,
+        tx: mpsc::Sender<Accepted>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let acceptor = self
+                .acceptor
+                .clone()
+                .context("TLSのサーバー設定がありません")?;
+            let (limiter, pending) = (self.limiter.clone(), self.pending.clone());
+            let (inner_tx, mut inner_rx) = mpsc::channel::<Accepted>(10);
+            self.inner.listen(addr, inner_tx).await?;
+
+            tokio::spawn(async move {
+                loop {
+                    let accepted = tokio::select! {
+                        accepted = inner_rx.recv() => accepted,
+                        // 受け取る側がいなくなったら内側の待ち受けも終了させる
+                        _ = tx.closed() => None,
+                    };
+                    let Some(accepted) = accepted else {
+                        break;
+                    };
+                    if limiter
+                        .as_ref()
+                        .is_some_and(|limiter| limiter.is_banned(&accepted.peer))
+                    {
+                        error!("遮断中の接続元のため切断しました: {}", accepted.peer);
+                        continue;
+                    }
+                    let permit = match &pending {
+                        Some(pending) => match pending.clone().try_acquire_owned() {
+                            Ok(permit) => Some(permit),
+                            Err(_) => {
+                                error!(
+                                    "ハンドシェイク中の接続が多すぎるため切断しました: {}",
+                                    accepted.peer
+                                );
+                                continue;
+                            }
+                        },
+                        None => None,
+                    };
+                    let tx = tx.clone();
+                    let acceptor = acceptor.clone();
+
+                    // ハンドシェイクで受付ループを止めないよう接続ごとにタスクを起動
+                    tokio::spawn(async move {
+                        let handshake = tokio::time::timeout(
+                            HANDSHAKE_TIMEOUT,
+                            acceptor.accept(accepted.connection),
+                        )
+                        .await;
+                        // ヘッダーを待つ間は受信処理が改めて数える
+                        drop(permit);
+                        let connection = match handshake {
+                            Ok(Ok(stream)) => Box::new(stream),
+                            Ok(Err(e)) => {
+                                error!("TLSのハンドシェイクに失敗: {}: {}", accepted.peer, e);
+                                return;
+                            }
+                            Err(_) => {
+                                error!(
+                                    "TLSのハンドシェイクが {} 秒以内に終わらないため切断しました: {}",
+                                    HANDSHAKE_TIMEOUT.as_secs(),
+                                    accepted.peer
+                                );
+                                return;
+                            }
+                        };
+                        let accepted = Accepted {
+                            connection,
+                            peer: accepted.peer,
+                        };
+                        if let Err(e) = tx.send(accepted).await {
+                            error!("ソケットの送信に失敗: {}", e);
+                        }
+                    });
+                }
+            });
+            Ok(())
+        })
+    }
+}
+
+// 事前に共有したフィンガープリントと一致するサーバー証明書だけを受け入れる
+#[derive(Debug)]
+struct PinnedServerCert {
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedServerCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(end_entity) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "サーバー証明書のフィンガープリントが一致しません".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
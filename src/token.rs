@@ -0,0 +1,369 @@
+use crate::{config::Config, dedup, pairing::Link};
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use uuid::Uuid;
+
+// クライアントがトークンを保存しておくキーチェーンの項目名
+pub const TOKEN_SECRET_NAME: &str = "token";
+
+// token サブコマンドの操作
+#[derive(Subcommand)]
+pub enum TokenCommand {
+    /// 新しいトークンを発行する
+    Create {
+        /// 有効期限（例: "30m", "24h", "7d"、省略すると無期限）
+        #[arg(long, value_parser = parse_duration)]
+        expires: Option<Duration>,
+
+        /// このトークンで受信できるファイル数の上限
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_files: Option<u32>,
+
+        /// 送信側に共有するペアリング用のリンク（filetransfer://）も表示する
+        #[arg(long)]
+        link: bool,
+    },
+    /// 発行済みのトークンを一覧表示する
+    List,
+    /// トークンを失効させる
+    Revoke {
+        /// トークンのID
+        id: String,
+    },
+    /// トークンの値を再発行する（期限と上限は引き継ぎ、古い値は使えなくなる）
+    Rotate {
+        /// トークンのID
+        id: String,
+    },
+}
+
+// 発行済みのトークン（値そのものは保存せずハッシュだけを持つ）
+#[derive(Serialize, Deserialize)]
+struct TokenEntry {
+    id: String,
+    secret_hash: String,
+    created_at: i64,
+    expires_at: Option<i64>,
+    max_files: Option<u32>,
+    #[serde(default)]
+    used_files: u32,
+    #[serde(default)]
+    revoked: bool,
+}
+
+// トークン一覧ファイル（tokens.toml）の内容
+#[derive(Default, Serialize, Deserialize)]
+struct TokenStore {
+    #[serde(default)]
+    tokens: Vec<TokenEntry>,
+}
+
+impl TokenStore {
+    // トークン一覧ファイルのパス
+    fn path() -> Result<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join("file-transfer").join("tokens.toml"))
+            .context("設定フォルダが見つかりません")
+    }
+
+    fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("トークン一覧の読み込みに失敗: {:?}", path))?;
+        toml::from_str(&text).with_context(|| format!("トークン一覧の解析に失敗: {:?}", path))
+    }
+
+    // トークンが他のユーザーから読めず、途中で失敗しても壊れた一覧が残らないよう、
+    // 所有者だけが読み書きできる一時ファイルに書いてから置き換える
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self)?;
+        let temp = path.with_file_name(format!("tokens.toml.{}.tmp", Uuid::new_v4().simple()));
+        let written = create_private(&temp)
+            .and_then(|mut file| {
+                file.write_all(text.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        written.with_context(|| format!("トークン一覧の保存に失敗: {:?}", path))
+    }
+
+    fn find_mut(&mut self, id: &str) -> Result<&mut TokenEntry> {
+        self.tokens
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .with_context(|| format!("トークン {} は存在しません", id))
+    }
+}
+
+// 所有者だけが読み書きできる新しいファイルを作る
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+// Windows では設定フォルダがユーザーごとにあり、アクセス許可はフォルダから継承する
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+// token サブコマンドの実装
+pub fn run(command: &TokenCommand, config: &Config) -> Result<()> {
+    let mut store = TokenStore::load()?;
+    let now = chrono::Utc::now().timestamp();
+
+    match command {
+        TokenCommand::Create {
+            expires,
+            max_files,
+            link,
+        } => {
+            let (id, token) = create(&mut store, *expires, *max_files)?;
+            println!("トークン {} を発行しました（値は再表示できません）", id);
+            println!("{}", token);
+            if *link {
+                let ip =
+                    local_ip_address::local_ip().context("この端末のIPアドレスを取得できません")?;
+                let link = Link::Send {
+                    server: ip.to_string(),
+                    port: config.port,
+                    token: Some(token),
+                };
+                println!("送信側に共有するリンク: {}", link.to_url());
+            }
+        }
+        TokenCommand::List => {
+            if store.tokens.is_empty() {
+                println!("発行済みのトークンはありません");
+            }
+            for entry in &store.tokens {
+                let expires = match entry.expires_at {
+                    Some(expires_at) => format_time(expires_at),
+                    None => "無期限".to_string(),
+                };
+                let files = match entry.max_files {
+                    Some(max_files) => format!("{} / {}", entry.used_files, max_files),
+                    None => entry.used_files.to_string(),
+                };
+                let state = if entry.revoked {
+                    "失効"
+                } else if entry.expires_at.is_some_and(|expires_at| expires_at <= now) {
+                    "期限切れ"
+                } else {
+                    "有効"
+                };
+                println!(
+                    "{}  {}  期限: {}  ファイル数: {}",
+                    entry.id, state, expires, files
+                );
+            }
+        }
+        TokenCommand::Revoke { id } => {
+            store.find_mut(id)?.revoked = true;
+            store.save()?;
+            println!("トークン {} を失効させました", id);
+        }
+        TokenCommand::Rotate { id } => {
+            let entry = store.find_mut(id)?;
+            if entry.revoked {
+                anyhow::bail!("トークン {} は失効しています", id);
+            }
+            let secret = new_secret();
+            entry.secret_hash = dedup::sha256_hex(secret.as_bytes());
+            store.save()?;
+            println!("トークン {} を再発行しました（値は再表示できません）", id);
+            println!("{}.{}", id, secret);
+        }
+    }
+
+    Ok(())
+}
+
+// 新しいトークンを発行し、IDと送信側に渡す値（"ID.シークレット"）を返す（セットアップウィザードで使用）
+pub fn issue(expires: Option<Duration>, max_files: Option<u32>) -> Result<(String, String)> {
+    create(&mut TokenStore::load()?, expires, max_files)
+}
+
+fn create(
+    store: &mut TokenStore,
+    expires: Option<Duration>,
+    max_files: Option<u32>,
+) -> Result<(String, String)> {
+    let now = chrono::Utc::now().timestamp();
+    let id = Uuid::new_v4().simple().to_string()[..8].to_string();
+    let secret = new_secret();
+    store.tokens.push(TokenEntry {
+        id: id.clone(),
+        secret_hash: dedup::sha256_hex(secret.as_bytes()),
+        created_at: now,
+        expires_at: expires.map(|expires| now + expires.as_secs() as i64),
+        max_files,
+        used_files: 0,
+        revoked: false,
+    });
+    store.save()?;
+    let token = format!("{}.{}", id, secret);
+    Ok((id, token))
+}
+
+// 受信時にトークンを検証し、ファイル数の上限に達していなければIDを返す
+pub fn authorize(token: &str) -> Result<String> {
+    let (id, secret) = token.split_once('.').context("トークンの形式が不正です")?;
+    let store = TokenStore::load()?;
+    let entry = store
+        .tokens
+        .iter()
+        .find(|entry| entry.id == id && entry.secret_hash == dedup::sha256_hex(secret.as_bytes()))
+        .context("トークンが無効です")?;
+
+    if entry.revoked {
+        anyhow::bail!("トークン {} は失効しています", id);
+    }
+    if entry
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= chrono::Utc::now().timestamp())
+    {
+        anyhow::bail!("トークン {} は期限切れです", id);
+    }
+    if entry
+        .max_files
+        .is_some_and(|max_files| entry.used_files >= max_files)
+    {
+        anyhow::bail!("トークン {} はファイル数の上限に達しています", id);
+    }
+
+    Ok(entry.id.clone())
+}
+
+// トークンで受信するファイル数を、受信を始める前に files 増やす（上限を超える場合は増やさずエラー）
+//
+// 同じトークンで同時に送られても上限を超えないよう、確認と記録を1度に行う。受信に失敗したら release_use で戻す
+pub fn reserve_use(id: &str, files: u32) -> Result<()> {
+    let mut store = TokenStore::load()?;
+    let entry = store.find_mut(id)?;
+    if entry
+        .max_files
+        .is_some_and(|max_files| entry.used_files.saturating_add(files) > max_files)
+    {
+        anyhow::bail!("トークン {} はファイル数の上限に達しています", id);
+    }
+    entry.used_files += files;
+    store.save()
+}
+
+// reserve_use で増やしたファイル数を戻す
+pub fn release_use(id: &str, files: u32) -> Result<()> {
+    let mut store = TokenStore::load()?;
+    let entry = store.find_mut(id)?;
+    entry.used_files = entry.used_files.saturating_sub(files);
+    store.save()
+}
+
+// 推測されにくいトークンの値（UUIDv4 2つ分の乱数）
+fn new_secret() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+fn format_time(timestamp: i64) -> String {
+    chrono::DateTime::<chrono::Utc>::from_timestamp(timestamp, 0)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+// "30s", "30m", "24h", "7d" 形式の期間を解析する
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let Some(unit) = text.chars().last() else {
+        return Err("期間を指定してください".to_string());
+    };
+    let number: u64 = text[..text.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| format!("期間の形式が不正です: {}", text))?;
+    let unit_seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 60 * 60 * 24,
+        _ => return Err(format!("期間の単位は s, m, h, d のいずれかです: {}", text)),
+    };
+    match number.checked_mul(unit_seconds) {
+        Some(0) => Err("期間には1以上を指定してください".to_string()),
+        Some(seconds) if seconds <= i32::MAX as u64 => Ok(Duration::from_secs(seconds)),
+        _ => Err(format!("期間が長すぎます: {}", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_unit() {
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("24h"), Ok(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(
+            parse_duration("7d"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn zero_is_refused() {
+        for text in ["0s", "0m", "0h", "0d"] {
+            assert!(parse_duration(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn overflow_is_refused() {
+        // i32 の秒数に収まる最大の日数までは受け付ける
+        assert_eq!(
+            parse_duration("24855d"),
+            Ok(Duration::from_secs(24855 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("24856d").is_err());
+        // 掛け算で u64 を超える
+        assert!(parse_duration("18446744073709551615d").is_err());
+        // 数値自体が u64 を超える
+        assert!(parse_duration("99999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn garbage_is_refused() {
+        for text in [
+            "", "d", "10", "10x", "-5m", "1.5h", " 5m", "5 m", "5M", "五m", "5分",
+        ] {
+            assert!(parse_duration(text).is_err(), "{:?}", text);
+        }
+    }
+}
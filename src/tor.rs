@@ -0,0 +1,189 @@
+use crate::{
+    dedup,
+    log::{error, info},
+    secrets,
+};
+use anyhow::{Context, Result};
+use std::{fs, net::SocketAddr};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+use tokio_util::sync::CancellationToken;
+
+// Tor のコントロールポートの既定のアドレス
+pub const DEFAULT_CONTROL_ADDR: &str = "127.0.0.1:9051";
+
+// Tor の SOCKS ポート（クライアントはここを経由して .onion に接続する）
+pub const SOCKS_PROXY: &str = "socks5h://127.0.0.1:9050";
+
+// 再起動しても同じ .onion アドレスになるよう、秘密鍵を保存するキーチェーンの名前
+const ONION_KEY_SECRET_NAME: &str = "onion-key";
+
+// HASHEDPASSWORD で認証する場合のパスワードのキーチェーンの名前
+// （`file-transfer secret set tor-control-password` で保存する）
+const CONTROL_PASSWORD_SECRET_NAME: &str = "tor-control-password";
+
+// .onion のアドレスか
+pub fn is_onion(server: &str) -> bool {
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    host.ends_with(".onion")
+}
+
+// コントロールポートとの接続
+struct Controller {
+    stream: BufStream<TcpStream>,
+}
+
+impl Controller {
+    async fn connect(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await.with_context(|| {
+            format!(
+                "Tor のコントロールポート {} に接続できません（Tor の ControlPort を有効にしてください）",
+                addr
+            )
+        })?;
+        Ok(Self {
+            stream: BufStream::new(stream),
+        })
+    }
+
+    // コマンドを送り、"250" の応答の各行（"250-" などを除いたもの）を返す
+    async fn command(&mut self, command: &str) -> Result<Vec<String>> {
+        self.stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+        self.stream.flush().await?;
+
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                anyhow::bail!("Tor のコントロールポートが接続を閉じました");
+            }
+            let line = line.trim_end();
+            let status = line.get(..4).unwrap_or(line);
+            let rest = line.get(4..).unwrap_or_default();
+            if !status.starts_with("250") {
+                anyhow::bail!("Tor がコマンドを受け付けませんでした: {}", line);
+            }
+            lines.push(rest.to_string());
+            // "250 " が最後の行（"250-" は続きがある）
+            if status.ends_with(' ') {
+                return Ok(lines);
+            }
+        }
+    }
+
+    // PROTOCOLINFO で使える認証方式を調べて認証する
+    async fn authenticate(&mut self) -> Result<()> {
+        let info = self.command("PROTOCOLINFO 1").await?;
+        let auth = info
+            .iter()
+            .find_map(|line| line.strip_prefix("AUTH "))
+            .context("Tor の認証方式を取得できません")?;
+        let methods: Vec<&str> = auth
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("METHODS="))
+            .map(|methods| methods.split(',').collect())
+            .unwrap_or_default();
+        let cookie_file = auth
+            .split_once("COOKIEFILE=")
+            .map(|(_, path)| unquote(path));
+
+        let command = if methods.contains(&"NULL") {
+            "AUTHENTICATE".to_string()
+        } else if let (true, Some(path)) = (methods.contains(&"COOKIE"), cookie_file) {
+            let cookie = fs::read(&path)
+                .with_context(|| format!("Tor の認証クッキーを読めません: {}", path))?;
+            format!("AUTHENTICATE {}", dedup::to_hex(&cookie))
+        } else if methods.contains(&"HASHEDPASSWORD") {
+            let password = secrets::get(CONTROL_PASSWORD_SECRET_NAME)?.with_context(|| {
+                format!(
+                    "Tor のコントロールポートのパスワードがありません（`file-transfer secret set {}` で保存してください）",
+                    CONTROL_PASSWORD_SECRET_NAME
+                )
+            })?;
+            format!("AUTHENTICATE {}", quote(&password))
+        } else {
+            anyhow::bail!(
+                "対応している Tor の認証方式がありません: {}",
+                methods.join(",")
+            );
+        };
+        self.command(&command)
+            .await
+            .context("Tor のコントロールポートの認証に失敗")?;
+        Ok(())
+    }
+}
+
+// "..." の形式の値を取り出す（\" と \\ を戻す）
+fn unquote(value: &str) -> String {
+    let mut chars = value.trim_start_matches('"').chars();
+    let mut unquoted = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// target で待ち受けているサーバーを、port で接続できるオニオンサービスとして公開し、.onion のアドレスを返す
+//
+// 公開はコントロールポートとの接続の間だけ続くため、cancel まで接続を保つ。
+// 秘密鍵はキーチェーンに保存し、次回も同じアドレスで公開する
+pub async fn publish(
+    control_addr: &str,
+    port: u16,
+    target: SocketAddr,
+    cancel: CancellationToken,
+) -> Result<String> {
+    let mut controller = Controller::connect(control_addr).await?;
+    controller.authenticate().await?;
+
+    let saved_key = secrets::get(ONION_KEY_SECRET_NAME).unwrap_or_else(|e| {
+        error!(
+            "キーチェーンからオニオンサービスの鍵を読み出せません: {:#}",
+            e
+        );
+        None
+    });
+    let key = saved_key.as_deref().unwrap_or("NEW:ED25519-V3");
+    let reply = controller
+        .command(&format!("ADD_ONION {} Port={},{}", key, port, target))
+        .await
+        .context("オニオンサービスの公開に失敗")?;
+
+    let service_id = reply
+        .iter()
+        .find_map(|line| line.strip_prefix("ServiceID="))
+        .context("Tor の応答にオニオンサービスのアドレスがありません")?;
+    if let Some(new_key) = reply
+        .iter()
+        .find_map(|line| line.strip_prefix("PrivateKey="))
+    {
+        if let Err(e) = secrets::set(ONION_KEY_SECRET_NAME, new_key) {
+            error!(
+                "オニオンサービスの鍵を保存できません（次回は別のアドレスになります）: {:#}",
+                e
+            );
+        }
+    }
+    let address = format!("{}.onion", service_id);
+
+    tokio::spawn(async move {
+        cancel.cancelled().await;
+        // 接続を閉じると Tor がオニオンサービスを取り下げる
+        drop(controller);
+        info!("オニオンサービスを取り下げました");
+    });
+    Ok(address)
+}
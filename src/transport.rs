@@ -0,0 +1,427 @@
+use crate::log::{error, info};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::{future::Future, net::SocketAddr, path::Path, pin::Pin, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{lookup_host, TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinSet,
+};
+
+// Unixドメインソケットを指定するアドレスの接頭辞
+pub const UNIX_PREFIX: &str = "unix:";
+
+// Windowsの名前付きパイプを指定するアドレスの接頭辞
+pub const PIPE_PREFIX: &str = r"\\.\pipe\";
+
+// TCP・Unixドメインソケット・名前付きパイプの接続を同じように扱うためのトレイト
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+pub type BoxedConnection = Box<dyn Connection>;
+
+// 受け付けた接続と、その接続元の表示名
+pub struct Accepted {
+    pub connection: BoxedConnection,
+    pub peer: String,
+}
+
+// ローカル接続（Unixドメインソケット・名前付きパイプ）の接続元の表示名
+pub const LOCAL_PEER: &str = "local";
+
+// --transport で選ぶ、ネットワーク越しの接続の方式
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransportKind {
+    /// TCP
+    #[default]
+    Tcp,
+    /// 再送と輻輳制御を載せた UDP（遅延の大きい回線向け）
+    Udp,
+}
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// 接続の確立と待ち受けを抽象化したトレイト
+//
+// 送受信の処理は BoxedConnection だけを扱うため、このトレイトを実装すれば
+// TCP・TLS・Unixドメインソケット・名前付きパイプ（今後はQUICなど）を差し替えられる
+pub trait Transport: Send + Sync {
+    // addr に接続する
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<BoxedConnection>>;
+
+    // addr で待ち受けを開始し、受け付けた接続を tx に流す
+    fn listen<'a>(&'a self, addr: &'a str, tx: mpsc::Sender<Accepted>)
+        -> BoxFuture<'a, Result<()>>;
+}
+
+// ローカル接続（Unixドメインソケット・名前付きパイプ）のアドレスか
+pub fn is_local(server_addr: &str) -> bool {
+    #[cfg(feature = "testing")]
+    if server_addr.starts_with(crate::testing::LOOPBACK_PREFIX) {
+        return true;
+    }
+    server_addr.starts_with(UNIX_PREFIX) || server_addr.starts_with(PIPE_PREFIX)
+}
+
+// アドレスの形式に合ったトランスポート
+// （"unix:/path" の場合はUnixドメインソケット、"\\.\pipe\name" の場合は名前付きパイプ、それ以外はTCP）
+pub fn for_addr(addr: &str) -> Arc<dyn Transport> {
+    // 同じプロセスの中の待ち受け（testing フィーチャーのみ）
+    #[cfg(feature = "testing")]
+    if addr.starts_with(crate::testing::LOOPBACK_PREFIX) {
+        return Arc::new(crate::testing::Loopback);
+    }
+    if addr.starts_with(UNIX_PREFIX) {
+        Arc::new(Unix)
+    } else if addr.starts_with(PIPE_PREFIX) {
+        Arc::new(Pipe)
+    } else {
+        Arc::new(Tcp)
+    }
+}
+
+// サーバーアドレスに接続する
+pub async fn connect(server_addr: &str) -> Result<BoxedConnection> {
+    for_addr(server_addr).connect(server_addr).await
+}
+
+// 受け付けた接続をチャネルに流す
+async fn forward(tx: &mpsc::Sender<Accepted>, connection: BoxedConnection, peer: String) {
+    let accepted = Accepted { connection, peer };
+    if let Err(e) = tx.send(accepted).await {
+        error!("ソケットの送信に失敗: {}", e);
+    }
+}
+
+// TCP（addr は "host:port"）
+pub struct Tcp;
+
+// 次のアドレスへの接続を始めるまでの待ち時間（RFC 8305 の Connection Attempt Delay）
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+// addr の名前解決で得たアドレスに順に接続を試し、最初に成功した接続を使う（Happy Eyeballs）
+//
+// IPv6 と IPv4 を交互に並べ、前の試行が終わらなくても待ち時間が過ぎたら次を始める。
+// 片方の経路が応答しなくても、接続のタイムアウトまで待たずに済む
+async fn connect_tcp(addr: &str) -> Result<TcpStream> {
+    let addrs = lookup_host(addr)
+        .await
+        .with_context(|| format!("{} の名前解決に失敗", addr))?;
+    let mut pending = interleave(addrs.collect()).into_iter();
+
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    loop {
+        if let Some(next) = pending.next() {
+            attempts.spawn(async move {
+                TcpStream::connect(next)
+                    .await
+                    .with_context(|| format!("{} への接続に失敗", next))
+            });
+        }
+        if attempts.is_empty() {
+            break;
+        }
+
+        // 試行が失敗するか待ち時間が過ぎたら、残っているアドレスへの接続を始める
+        let has_next = !pending.as_slice().is_empty();
+        let result = tokio::select! {
+            Some(result) = attempts.join_next() => result,
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if has_next => continue,
+        };
+        match result {
+            // 残りの試行は attempts の破棄で中止される
+            Ok(Ok(socket)) => return Ok(socket),
+            Ok(Err(e)) => last_error = Some(e),
+            Err(e) => last_error = Some(e.into()),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("{} のアドレスが見つかりません", addr)))
+}
+
+// 名前解決の順（最初のアドレスの種類が優先）を保ったまま、IPv6 と IPv4 を交互に並べる
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_is_ipv6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+    preferred.reverse();
+    other.reverse();
+
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    while let Some(addr) = preferred.pop() {
+        ordered.push(addr);
+        ordered.extend(other.pop());
+    }
+    ordered.extend(other.into_iter().rev());
+    ordered
+}
+
+impl Transport for Tcp {
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<BoxedConnection>> {
+        Box::pin(async move {
+            let socket = connect_tcp(addr).await?;
+            Ok(Box::new(socket) as BoxedConnection)
+        })
+    }
+
+    fn listen<'a>(
+        &'a self,
+        addr: &'a str,
+        tx: mpsc::Sender<Accepted>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let addr: SocketAddr = addr
+                .parse()
+                .with_context(|| format!("待ち受けるアドレスが不正です: {}", addr))?;
+            let listener = TcpListener::bind(addr).await?;
+            info!("{} でリッスン中", addr);
+
+            tokio::spawn(async move {
+                loop {
+                    let accepted = tokio::select! {
+                        accepted = listener.accept() => accepted,
+                        // 受け取る側がいなくなったら待ち受けを終了する
+                        _ = tx.closed() => break,
+                    };
+                    match accepted {
+                        Ok((socket, addr)) => {
+                            info!("新しい接続: {}", addr);
+                            forward(&tx, Box::new(socket), addr.ip().to_string()).await;
+                        }
+                        Err(e) => {
+                            error!("接続の受付に失敗: {}", e);
+                        }
+                    }
+                }
+            });
+            Ok(())
+        })
+    }
+}
+
+// Unixドメインソケット（addr は "unix:/path"）
+pub struct Unix;
+
+impl Transport for Unix {
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<BoxedConnection>> {
+        Box::pin(connect_unix(unix_path(addr)))
+    }
+
+    fn listen<'a>(
+        &'a self,
+        addr: &'a str,
+        tx: mpsc::Sender<Accepted>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { listen_unix(unix_path(addr), tx) })
+    }
+}
+
+fn unix_path(addr: &str) -> &Path {
+    Path::new(addr.strip_prefix(UNIX_PREFIX).unwrap_or(addr))
+}
+
+// 名前付きパイプ（addr は "\\.\pipe\name"）
+pub struct Pipe;
+
+impl Transport for Pipe {
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<BoxedConnection>> {
+        Box::pin(connect_pipe(addr))
+    }
+
+    fn listen<'a>(
+        &'a self,
+        addr: &'a str,
+        tx: mpsc::Sender<Accepted>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { listen_pipe(addr, tx) })
+    }
+}
+
+#[cfg(unix)]
+async fn connect_unix(path: &Path) -> Result<BoxedConnection> {
+    let socket = tokio::net::UnixStream::connect(path).await?;
+    Ok(Box::new(socket))
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(_path: &Path) -> Result<BoxedConnection> {
+    anyhow::bail!("このプラットフォームではUnixドメインソケットを使用できません")
+}
+
+// Unixドメインソケットで待ち受け、受け付けた接続をチャネルに流す
+#[cfg(unix)]
+fn listen_unix(path: &Path, tx: mpsc::Sender<Accepted>) -> Result<()> {
+    use std::os::unix::{fs::FileTypeExt, net::UnixStream};
+
+    // 前回の起動で残ったソケットファイルを取り除く（ソケット以外のファイルや、待ち受け中のソケットは消さない）
+    if let Ok(metadata) = path.symlink_metadata() {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("ソケットではないファイルがあります: {:?}", path);
+        }
+        if UnixStream::connect(path).is_ok() {
+            anyhow::bail!("他のプロセスが待ち受けています: {:?}", path);
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    info!("Unixドメインソケット {:?} でリッスン中", path);
+
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                // 受け取る側がいなくなったら待ち受けを終了する
+                _ = tx.closed() => break,
+            };
+            match accepted {
+                Ok((socket, _)) => {
+                    info!("新しい接続: Unixドメインソケット");
+                    forward(&tx, Box::new(socket), LOCAL_PEER.to_string()).await;
+                }
+                Err(e) => {
+                    error!("接続の受付に失敗: {}", e);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_path: &Path, _tx: mpsc::Sender<Accepted>) -> Result<()> {
+    anyhow::bail!("このプラットフォームではUnixドメインソケットを使用できません")
+}
+
+#[cfg(windows)]
+async fn connect_pipe(name: &str) -> Result<BoxedConnection> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+
+    const ERROR_PIPE_BUSY: i32 = 231;
+    // 全インスタンスが使用中のまま、これ以上待たない時間
+    const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+    let deadline = tokio::time::Instant::now() + BUSY_TIMEOUT;
+    loop {
+        match ClientOptions::new().open(name) {
+            Ok(client) => return Ok(Box::new(client)),
+            // 全インスタンスが使用中の場合はサーバーが次のインスタンスを作るまで待つ
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                if tokio::time::Instant::now() >= deadline {
+                    return Err(e).with_context(|| {
+                        format!(
+                            "名前付きパイプ {} が {} 秒間使用中のままです",
+                            name,
+                            BUSY_TIMEOUT.as_secs()
+                        )
+                    });
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(not(windows))]
+async fn connect_pipe(_name: &str) -> Result<BoxedConnection> {
+    anyhow::bail!("名前付きパイプはWindowsでのみ使用できます")
+}
+
+// 名前付きパイプで待ち受け、受け付けた接続をチャネルに流す
+#[cfg(windows)]
+fn listen_pipe(name: &str, tx: mpsc::Sender<Accepted>) -> Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    // 接続の受付に失敗したとき、次のインスタンスで待ち受けるまでの間隔
+    const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+    let name = name.to_string();
+    let mut server = create_pipe(ServerOptions::new().first_pipe_instance(true), &name)?;
+    info!("名前付きパイプ {} でリッスン中", name);
+
+    tokio::spawn(async move {
+        loop {
+            let connected = tokio::select! {
+                connected = server.connect() => connected,
+                // 受け取る側がいなくなったら待ち受けを終了する
+                _ = tx.closed() => break,
+            };
+
+            // 次のクライアント用のインスタンスを先に作ってから接続を引き渡す
+            let next = match create_pipe(&ServerOptions::new(), &name) {
+                Ok(next) => next,
+                Err(e) => {
+                    error!("名前付きパイプの作成に失敗: {}", e);
+                    return;
+                }
+            };
+            let pipe = std::mem::replace(&mut server, next);
+            if let Err(e) = connected {
+                // 失敗したインスタンスは作り直したものに置き換え、同じエラーを繰り返さないよう少し待つ
+                error!("接続の受付に失敗: {}", e);
+                drop(pipe);
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+
+            info!("新しい接続: 名前付きパイプ");
+            forward(&tx, Box::new(pipe), LOCAL_PEER.to_string()).await;
+        }
+    });
+
+    Ok(())
+}
+
+// 他のユーザーから接続されないよう、作ったユーザーだけが使える名前付きパイプを作る
+#[cfg(windows)]
+fn create_pipe(
+    options: &tokio::net::windows::named_pipe::ServerOptions,
+    name: &str,
+) -> std::io::Result<tokio::net::windows::named_pipe::NamedPipeServer> {
+    use windows_sys::Win32::{
+        Foundation::LocalFree,
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+            },
+            PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+        },
+    };
+
+    // 所有者だけにすべての操作を許す（継承したアクセス許可は使わない）
+    let sddl: Vec<u16> = "D:P(A;;GA;;;OW)".encode_utf16().chain(Some(0)).collect();
+    let mut descriptor: PSECURITY_DESCRIPTOR = std::ptr::null_mut();
+    let converted = unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl.as_ptr(),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            std::ptr::null_mut(),
+        )
+    };
+    if converted == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: descriptor,
+        bInheritHandle: 0,
+    };
+    let server = unsafe {
+        options.create_with_security_attributes_raw(name, &mut attributes as *mut _ as *mut _)
+    };
+    unsafe { LocalFree(descriptor) };
+    server
+}
+
+#[cfg(not(windows))]
+fn listen_pipe(_name: &str, _tx: mpsc::Sender<Accepted>) -> Result<()> {
+    anyhow::bail!("名前付きパイプはWindowsでのみ使用できます")
+}
@@ -0,0 +1,103 @@
+use crate::{log::info, transport::LOCAL_PEER};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+// 設定ファイルの [[trusted_networks]] に書く、確認せずに受け入れる接続元
+//
+// トークンか SSH の鍵で認証し、subnet の範囲から接続してきた転送だけを確認せずに受け入れる。
+// 認証していない接続元は、どのネットワークからでも常に確認する
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrustedNetwork {
+    // 接続元のアドレスの範囲（例: "192.168.1.0/24"、"fd00::/8"。"local" でローカル接続）
+    pub subnet: String,
+
+    // 認証に使ったトークンのID（SSH の鍵は "ssh:SHA256:..."。省略すると認証した接続元すべて）
+    pub token: Option<String>,
+}
+
+// 接続元のアドレスの範囲
+enum Subnet {
+    Local,
+    Ip { network: IpAddr, prefix: u8 },
+}
+
+impl Subnet {
+    fn parse(subnet: &str) -> Result<Self> {
+        if subnet == LOCAL_PEER {
+            return Ok(Subnet::Local);
+        }
+        let (addr, prefix) = subnet.split_once('/').unwrap_or((subnet, ""));
+        let network: IpAddr = addr
+            .parse()
+            .with_context(|| format!("アドレスの範囲が正しくありません: {}", subnet))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => max,
+            prefix => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .with_context(|| format!("プレフィックス長が正しくありません: {}", subnet))?,
+        };
+        Ok(Subnet::Ip { network, prefix })
+    }
+
+    fn contains(&self, peer: &str) -> bool {
+        match self {
+            Subnet::Local => peer == LOCAL_PEER,
+            Subnet::Ip { network, prefix } => {
+                let Ok(ip) = peer.parse::<IpAddr>() else {
+                    return false;
+                };
+                // IPv4 射影アドレスで届いた IPv4 の接続元も IPv4 の範囲と比べる
+                let ip = match ip {
+                    IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+                    ip => ip,
+                };
+                match (network, ip) {
+                    (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                        let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                        u32::from(*network) & mask == u32::from(ip) & mask
+                    }
+                    (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                        let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                        u128::from(*network) & mask == u128::from(ip) & mask
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+// 確認せずに受け入れる接続元の一覧
+pub struct TrustedNetworks {
+    rules: Vec<(Subnet, Option<String>)>,
+}
+
+impl TrustedNetworks {
+    pub fn new(networks: &[TrustedNetwork]) -> Result<Self> {
+        let rules = networks
+            .iter()
+            .map(|network| Ok((Subnet::parse(&network.subnet)?, network.token.clone())))
+            .collect::<Result<Vec<_>>>()?;
+        if !rules.is_empty() {
+            info!(
+                "確認せずに受け入れる信頼するネットワーク: {} 件",
+                rules.len()
+            );
+        }
+        Ok(Self { rules })
+    }
+
+    // peer から token_id で認証した接続を、確認せずに受け入れてよいか
+    pub fn trusts(&self, peer: &str, token_id: Option<&str>) -> bool {
+        let Some(token_id) = token_id else {
+            return false;
+        };
+        self.rules.iter().any(|(subnet, token)| {
+            subnet.contains(peer) && (token.is_none() || token.as_deref() == Some(token_id))
+        })
+    }
+}
@@ -0,0 +1,361 @@
+use crate::{
+    config::Config,
+    log,
+    progress::{self, TransferState, Transfers, MAX_SAMPLES},
+    server::{self, IncomingTransfer, ServerArgs},
+};
+use anyhow::{Context, Result};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph, Row, Sparkline, Table, TableState},
+    Terminal,
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Stdout},
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+// ログ欄に残す行数
+const MAX_LOG_LINES: usize = 500;
+
+// 進捗バーの幅（文字数）
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+// 受信速度のグラフを更新する間隔
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+type DashboardTerminal = Terminal<CrosstermBackend<Stdout>>;
+
+// TUIモード（サーバーモードの --tui）の実装
+pub async fn run(args: &ServerArgs, config: &Config) -> Result<()> {
+    let save_dir = args
+        .save_dir
+        .clone()
+        .or_else(|| config.save_dir.clone())
+        .context("--tui では --save-dir か設定ファイルの save_dir で保存先を指定してください")?;
+
+    // 画面を崩さないよう、以降の出力はログ欄に流す
+    let (log_tx, log_rx) = std::sync::mpsc::channel();
+    log::redirect(log_tx);
+
+    let (prompt_tx, prompt_rx) = mpsc::channel(16);
+    let transfers = Transfers::default();
+    let cancel = CancellationToken::new();
+    let server = {
+        let args = args.clone();
+        let config = config.clone();
+        let transfers = transfers.clone();
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            server::run_prompted(
+                &args,
+                &config,
+                save_dir,
+                prompt_tx,
+                Some(transfers),
+                None,
+                cancel,
+            )
+            .await
+        })
+    };
+
+    // 画面の描画とキー入力の待ち受けはブロックするため専用のスレッドで行う
+    let dashboard = Dashboard {
+        prompts: prompt_rx,
+        logs: log_rx,
+        transfers,
+        pending: Vec::new(),
+        lines: VecDeque::new(),
+        table: TableState::default(),
+        throughput: VecDeque::new(),
+        sampled_at: Instant::now(),
+    };
+    let result = tokio::task::spawn_blocking(move || dashboard.run(server)).await?;
+    log::restore();
+
+    // 終了時は待ち受けを止め、受信中の転送も中断する
+    let server = result?;
+    cancel.cancel();
+    // 待ち受けの開始に失敗していればそのエラーを返す
+    server.await??;
+    Ok(())
+}
+
+fn setup_terminal() -> Result<DashboardTerminal> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    Ok(Terminal::new(CrosstermBackend::new(stdout))?)
+}
+
+fn restore_terminal(terminal: &mut DashboardTerminal) -> Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+// "[#####...............]  25%" 形式の進捗バー
+fn progress_bar(received: u64, total: u64) -> String {
+    let ratio = if total == 0 {
+        1.0
+    } else {
+        (received as f64 / total as f64).min(1.0)
+    };
+    let filled = (ratio * PROGRESS_BAR_WIDTH as f64).round() as usize;
+    format!(
+        "[{}{}] {:>3}%",
+        "#".repeat(filled),
+        ".".repeat(PROGRESS_BAR_WIDTH - filled),
+        (ratio * 100.0).round() as u32
+    )
+}
+
+struct Dashboard {
+    prompts: mpsc::Receiver<IncomingTransfer>,
+    logs: Receiver<String>,
+    transfers: Transfers,
+
+    // 受け入れの確認待ち（一覧の先頭に表示する）
+    pending: Vec<IncomingTransfer>,
+    lines: VecDeque<String>,
+    table: TableState,
+    // 1秒ごとのすべての転送の受信バイト数（古い順）
+    throughput: VecDeque<u64>,
+    sampled_at: Instant,
+}
+
+type ServerHandle = JoinHandle<Result<()>>;
+
+impl Dashboard {
+    fn run(mut self, server: ServerHandle) -> Result<ServerHandle> {
+        let mut terminal = setup_terminal()?;
+        let result = self.event_loop(&mut terminal, &server);
+        restore_terminal(&mut terminal)?;
+        result.map(|()| server)
+    }
+
+    fn event_loop(
+        &mut self,
+        terminal: &mut DashboardTerminal,
+        server: &ServerHandle,
+    ) -> Result<()> {
+        // 待ち受けが終了した（開始に失敗した）場合も画面を閉じる
+        while !server.is_finished() {
+            while let Ok(prompt) = self.prompts.try_recv() {
+                self.pending.push(prompt);
+            }
+            while let Ok(line) = self.logs.try_recv() {
+                if self.lines.len() == MAX_LOG_LINES {
+                    self.lines.pop_front();
+                }
+                self.lines.push_back(line);
+            }
+
+            if self.sampled_at.elapsed() >= SAMPLE_INTERVAL {
+                self.sampled_at = Instant::now();
+                if self.throughput.len() == MAX_SAMPLES {
+                    self.throughput.pop_front();
+                }
+                self.throughput.push_back(progress::sample(&self.transfers));
+            }
+
+            let row_count = self.pending.len() + self.transfers.lock().unwrap().len();
+            match self.table.selected() {
+                None if row_count > 0 => self.table.select(Some(0)),
+                Some(selected) if selected >= row_count => {
+                    self.table.select(row_count.checked_sub(1))
+                }
+                _ => {}
+            }
+
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let selected = self.table.selected();
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.table
+                        .select(Some(selected.unwrap_or(0).saturating_sub(1)));
+                }
+                KeyCode::Down | KeyCode::Char('j') if row_count > 0 => {
+                    self.table
+                        .select(Some(selected.map_or(0, |s| s + 1).min(row_count - 1)));
+                }
+                KeyCode::Char('a') => self.answer(selected, true),
+                KeyCode::Char('r') => self.answer(selected, false),
+                KeyCode::Char('c') => self.cancel(selected),
+                KeyCode::Char('p') => {
+                    server::toggle_paused();
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // 選択中の確認待ちに答える
+    fn answer(&mut self, selected: Option<usize>, accept: bool) {
+        let Some(index) = selected.filter(|index| *index < self.pending.len()) else {
+            return;
+        };
+        let prompt = self.pending.remove(index);
+        if accept {
+            prompt.accept();
+        } else {
+            prompt.reject("");
+        }
+    }
+
+    // 選択中の受信を中断する
+    fn cancel(&mut self, selected: Option<usize>) {
+        let Some(index) = selected.and_then(|index| index.checked_sub(self.pending.len())) else {
+            return;
+        };
+        let transfers = self.transfers.lock().unwrap();
+        if let Some(transfer) = transfers.get(index) {
+            if matches!(transfer.state, TransferState::Receiving) {
+                transfer.cancel();
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut ratatui::Frame) {
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(6),
+                Constraint::Length(5),
+                Constraint::Length(10),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+
+        // 転送の一覧
+        let mut rows: Vec<Row> = self
+            .pending
+            .iter()
+            .map(|prompt| {
+                Row::new(vec![
+                    "確認待ち".to_string(),
+                    prompt.peer.clone(),
+                    match &prompt.note {
+                        Some(note) => format!("{}（メモ: {}）", prompt.filename, note),
+                        None => prompt.filename.clone(),
+                    },
+                    format!("{} バイト", prompt.size),
+                ])
+            })
+            .collect();
+        for transfer in self.transfers.lock().unwrap().iter() {
+            let state = match &transfer.state {
+                TransferState::Receiving => "受信中".to_string(),
+                TransferState::Done => "完了".to_string(),
+                TransferState::Cancelled => "中断".to_string(),
+                TransferState::Failed(e) => format!("失敗: {}", e),
+            };
+            rows.push(Row::new(vec![
+                state,
+                transfer.peer.clone(),
+                transfer.filename.clone(),
+                progress_bar(transfer.received(), transfer.total),
+            ]));
+        }
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(12),
+                Constraint::Length(16),
+                Constraint::Min(20),
+                Constraint::Length(PROGRESS_BAR_WIDTH as u16 + 7),
+            ],
+        )
+        .header(
+            Row::new(vec!["状態", "送信元", "ファイル", "進捗"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::default().borders(Borders::ALL).title("転送"))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, areas[0], &mut self.table);
+
+        // 受信速度のグラフ（受信の一覧で転送を選んでいればその転送、それ以外はすべての転送の合計）
+        let transfers = self.transfers.lock().unwrap();
+        let selected = self
+            .table
+            .selected()
+            .and_then(|index| index.checked_sub(self.pending.len()))
+            .and_then(|index| transfers.get(index));
+        let (title, samples) = match selected {
+            Some(transfer) => (
+                format!("受信速度: {}", transfer.filename),
+                &transfer.samples,
+            ),
+            None => ("受信速度: 合計".to_string(), &self.throughput),
+        };
+        // 幅に収まる直近の分だけ表示する
+        let width = areas[1].width.saturating_sub(2) as usize;
+        let data: Vec<u64> = samples
+            .iter()
+            .skip(samples.len().saturating_sub(width))
+            .copied()
+            .collect();
+        let current = samples.back().copied().unwrap_or(0);
+        let max = data.iter().copied().max().unwrap_or(0);
+        let graph = Sparkline::default()
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "{}（現在 {} KB/s、最大 {} KB/s）",
+                title,
+                current / 1024,
+                max / 1024
+            )))
+            .data(&data);
+        frame.render_widget(graph, areas[1]);
+        drop(transfers);
+
+        // ログ欄（新しい行が下に来るよう末尾を表示する）
+        let visible = areas[2].height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = self
+            .lines
+            .iter()
+            .skip(self.lines.len().saturating_sub(visible))
+            .map(|line| Line::from(line.as_str()))
+            .collect();
+        let logs =
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("ログ"));
+        frame.render_widget(logs, areas[2]);
+
+        // 一時停止中は再開の操作を案内する
+        let pause = if server::is_paused() {
+            "p: 再開（一時停止中）"
+        } else {
+            "p: 一時停止"
+        };
+        let help = Paragraph::new(format!(
+            "↑↓: 選択  a: 受け入れ  r: 拒否  c: 中断  {}  q: 終了",
+            pause
+        ));
+        frame.render_widget(help, areas[3]);
+    }
+}
@@ -0,0 +1,827 @@
+use crate::{
+    fec::{Decoder, Encoder, Fec},
+    log::{error, info, warn},
+    transport::{Accepted, BoxFuture, BoxedConnection, Transport},
+};
+use anyhow::{Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    net::{lookup_host, UdpSocket},
+    sync::mpsc,
+};
+use uuid::Uuid;
+
+// パケットの種類
+const SYN: u8 = 1;
+const SYN_ACK: u8 = 2;
+const DATA: u8 = 3;
+const ACK: u8 = 4;
+const FIN: u8 = 5;
+const RESET: u8 = 6;
+const PARITY: u8 = 7;
+const COOKIE: u8 = 8;
+
+// パケットの先頭（接続ID 4バイトと種類 1バイト）
+const HEADER_LEN: usize = 5;
+
+// 1パケットで送るデータの上限（VPN のトンネルの中でも分割されない大きさ）
+const MAX_PAYLOAD: usize = 1200;
+
+// 受信したパケットを読み込むバッファーの大きさ
+const MAX_PACKET: usize = 2048;
+
+// 1つの ACK に載せる、順番を飛ばして届いたパケットの番号の数の上限
+const MAX_ACK_SEQS: usize = (MAX_PAYLOAD - 12) / 8;
+
+// 受信側が溜めておけるパケット数（送信側にウィンドウとして通知する）
+const RECV_WINDOW: usize = 1024;
+
+// 輻輳ウィンドウ（応答を待たずに送れるパケット数）の初期値と下限
+const INITIAL_CWND: f64 = 16.0;
+const MIN_CWND: f64 = 2.0;
+
+// 再送タイマーの初期値と範囲
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+const MIN_RTO: Duration = Duration::from_millis(30);
+const MAX_RTO: Duration = Duration::from_secs(5);
+
+// 再送のたびに再送タイマーを延ばす倍率（TCP の 2 倍より緩やかにし、損失の多い回線でも止まりにくくする）
+const RTO_BACKOFF: f64 = 1.5;
+
+// 後のパケットに追い越されたら、再送タイマーを待たずに送り直す回数（高速再送）
+const FAST_RESEND: u32 = 3;
+
+// 1つのパケットを再送する回数の上限（超えたら接続が切れたとみなす）
+const MAX_RETRANSMITS: u32 = 20;
+
+// 再送や ACK の確認をする間隔
+const TICK: Duration = Duration::from_millis(10);
+
+// 送るものがなくても接続を保つために ACK を送る間隔と、相手から何も届かなければ切れたとみなす時間
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+const DEAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 双方が閉じた後も、相手の再送に ACK を返し続ける時間
+const LINGER: Duration = Duration::from_secs(2);
+
+// 接続要求を送り直す間隔と回数
+const SYN_INTERVAL: Duration = Duration::from_millis(500);
+const SYN_RETRIES: u32 = 10;
+
+// 接続要求に返すクッキーの長さと、発行してから使える時間の単位（前の単位に発行したものまで受け付ける）
+const COOKIE_LEN: usize = 8;
+const COOKIE_PERIOD: Duration = Duration::from_secs(30);
+
+// 同時に受け付ける接続の上限
+const MAX_CONNECTIONS: usize = 256;
+
+// 送受信の処理とアプリの間のバッファーの大きさ
+const DUPLEX_BUFFER_SIZE: usize = 256 * 1024;
+
+// UDP の上に再送と輻輳制御を載せたトランスポート（addr は "host:port"。--transport udp で選ぶ）
+//
+// 遅延の大きい回線（VPN や大陸間の接続）で TCP の速度が出ない場合に使う。
+// パケットごとに ACK を返し（KCP と同じく、順番を飛ばして届いた番号も知らせる）、
+// 追い越されたパケットはすぐに送り直す。輻輳ウィンドウは損失を検出するたびに半分にし、
+// ACK が届くたびに広げる
+//
+// 接続要求で誤り訂正を求められ、受け付けた側が応じた場合は、データパケットに
+// Reed-Solomon のパリティパケットを添えて送る
+pub struct Udp {
+    // 接続するときに求める誤り訂正（待ち受ける側は求められた設定に応じる）
+    fec: Option<Fec>,
+}
+
+impl Udp {
+    pub fn new(fec: Option<Fec>) -> Self {
+        Self { fec }
+    }
+}
+
+impl Transport for Udp {
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<BoxedConnection>> {
+        Box::pin(connect_udp(addr, self.fec))
+    }
+
+    fn listen<'a>(
+        &'a self,
+        addr: &'a str,
+        tx: mpsc::Sender<Accepted>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(listen_udp(addr, tx))
+    }
+}
+
+// 接続要求を送り、応答が届いたら送受信の処理を始める
+//
+// 最初の接続要求にはクッキーの代わりに 0 を載せ、受け付ける側から届いたクッキーを載せて送り直す
+async fn connect_udp(addr: &str, fec: Option<Fec>) -> Result<BoxedConnection> {
+    let peer = lookup_host(addr)
+        .await
+        .with_context(|| format!("{} の名前解決に失敗", addr))?
+        .next()
+        .with_context(|| format!("{} のアドレスが見つかりません", addr))?;
+    let local: SocketAddr = if peer.is_ipv6() {
+        "[::]:0".parse()?
+    } else {
+        "0.0.0.0:0".parse()?
+    };
+    let socket = Arc::new(UdpSocket::bind(local).await?);
+    let conn_id = Uuid::new_v4().as_u128() as u32;
+
+    let mut buf = [0u8; MAX_PACKET];
+    let mut accepted = None;
+    let mut cookie = [0u8; COOKIE_LEN];
+    'handshake: for _ in 0..SYN_RETRIES {
+        socket
+            .send_to(&syn_packet(conn_id, &cookie, fec), peer)
+            .await?;
+        let deadline = tokio::time::Instant::now() + SYN_INTERVAL;
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await
+        {
+            let (n, from) = received?;
+            if from != peer {
+                continue;
+            }
+            match parse_header(&buf[..n]) {
+                Some((id, SYN_ACK)) if id == conn_id => {
+                    accepted = Some(parse_fec(&buf[HEADER_LEN..n]));
+                    break 'handshake;
+                }
+                Some((id, COOKIE)) if id == conn_id && n == HEADER_LEN + COOKIE_LEN => {
+                    cookie.copy_from_slice(&buf[HEADER_LEN..n]);
+                    continue 'handshake;
+                }
+                Some((id, RESET)) if id == conn_id => {
+                    return Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+                        .with_context(|| format!("{} に接続を拒否されました", peer));
+                }
+                _ => {}
+            }
+        }
+    }
+    let Some(negotiated) = accepted else {
+        return Err(io::Error::from(io::ErrorKind::TimedOut))
+            .with_context(|| format!("{} が UDP で応答しません", peer));
+    };
+    match (fec, negotiated) {
+        (Some(_), Some(fec)) => info!(
+            "誤り訂正: データパケット {} 個ごとにパリティパケット {} 個",
+            fec.data, fec.parity
+        ),
+        (Some(_), None) => warn!("受信側が誤り訂正に対応していないため、再送だけで送ります"),
+        _ => {}
+    }
+
+    // 相手からのパケットを送受信の処理に渡す（処理が終われば止める）
+    let (packet_tx, packet_rx) = mpsc::channel(RECV_WINDOW);
+    let receiving = socket.clone();
+    tokio::spawn(async move {
+        let mut buf = [0u8; MAX_PACKET];
+        loop {
+            let received = tokio::select! {
+                received = receiving.recv_from(&mut buf) => received,
+                _ = packet_tx.closed() => break,
+            };
+            let Ok((n, from)) = received else {
+                break;
+            };
+            if from != peer {
+                continue;
+            }
+            if packet_tx
+                .send(Bytes::copy_from_slice(&buf[..n]))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let (connection, app) = tokio::io::duplex(DUPLEX_BUFFER_SIZE);
+    let engine = Engine::new(socket, peer, conn_id, false, negotiated);
+    tokio::spawn(engine.run(packet_rx, app));
+    Ok(Box::new(connection))
+}
+
+// UDP で待ち受け、接続要求ごとに送受信の処理を始めて、接続をチャネルに流す
+//
+// 送信元を偽った接続要求で状態を溜め込まないよう、最初の接続要求にはクッキーだけを返し、
+// そのクッキーを載せた接続要求が届いてから接続を作る
+async fn listen_udp(addr: &str, tx: mpsc::Sender<Accepted>) -> Result<()> {
+    let addr: SocketAddr = addr
+        .parse()
+        .with_context(|| format!("待ち受けるアドレスが不正です: {}", addr))?;
+    let socket = Arc::new(UdpSocket::bind(addr).await?);
+    info!("{} でリッスン中（UDP）", addr);
+
+    tokio::spawn(async move {
+        // 接続元のアドレスと接続IDごとの、送受信の処理へのパケットの渡し先
+        let mut connections: HashMap<(SocketAddr, u32), mpsc::Sender<Bytes>> = HashMap::new();
+        let cookies = Cookies::new();
+        let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+        let mut buf = [0u8; MAX_PACKET];
+        loop {
+            let received = tokio::select! {
+                received = socket.recv_from(&mut buf) => received,
+                Some(key) = closed_rx.recv() => {
+                    connections.remove(&key);
+                    continue;
+                }
+                // 受け取る側がいなくなったら待ち受けを終了する
+                _ = tx.closed() => break,
+            };
+            let (n, from) = match received {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("UDP の受信に失敗: {}", e);
+                    continue;
+                }
+            };
+            let Some((conn_id, kind)) = parse_header(&buf[..n]) else {
+                continue;
+            };
+            let key = (from, conn_id);
+            if let Some(packet_tx) = connections.get(&key) {
+                // 処理が追いつかなければ、回線で失われた場合と同じく捨てて再送を待つ
+                let _ = packet_tx.try_send(Bytes::copy_from_slice(&buf[..n]));
+                continue;
+            }
+            match kind {
+                SYN if n >= HEADER_LEN + COOKIE_LEN => {
+                    let (echoed, request) = buf[HEADER_LEN..n].split_at(COOKIE_LEN);
+                    if !cookies.verify(from, conn_id, request, echoed) {
+                        let mut reply = packet(conn_id, COOKIE);
+                        reply.put_slice(&cookies.issue(from, conn_id, request, 0));
+                        let _ = socket.send_to(&reply, from).await;
+                        continue;
+                    }
+                    if connections.len() >= MAX_CONNECTIONS {
+                        warn!("接続が多すぎるため {} からの接続を断りました（UDP）", from);
+                        let _ = socket.send_to(&packet(conn_id, RESET), from).await;
+                        continue;
+                    }
+                    info!("新しい接続: {}（UDP）", from);
+                    let (packet_tx, packet_rx) = mpsc::channel(RECV_WINDOW);
+                    connections.insert(key, packet_tx);
+                    let (connection, app) = tokio::io::duplex(DUPLEX_BUFFER_SIZE);
+                    // 求められた誤り訂正の設定に応じる（扱えない設定なら使わない）
+                    let fec = parse_fec(request);
+                    let engine = Engine::new(socket.clone(), from, conn_id, true, fec);
+                    let closed_tx = closed_tx.clone();
+                    tokio::spawn(async move {
+                        engine.run(packet_rx, app).await;
+                        let _ = closed_tx.send(key);
+                    });
+                    let accepted = Accepted {
+                        connection: Box::new(connection),
+                        peer: from.ip().to_string(),
+                    };
+                    if let Err(e) = tx.send(accepted).await {
+                        error!("ソケットの送信に失敗: {}", e);
+                    }
+                }
+                // 知らない接続のパケットには、接続がないことを知らせる
+                SYN | RESET => {}
+                _ => {
+                    let _ = socket.send_to(&packet(conn_id, RESET), from).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+// 種類だけのパケット（データを載せるものは後ろに書き足す）
+fn packet(conn_id: u32, kind: u8) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(MAX_PACKET);
+    buf.put_u32(conn_id);
+    buf.put_u8(kind);
+    buf
+}
+
+// 接続要求への応答（誤り訂正を使う場合は、データパケット数とパリティパケット数を載せる）
+fn handshake_packet(conn_id: u32, kind: u8, fec: Option<Fec>) -> BytesMut {
+    let mut buf = packet(conn_id, kind);
+    if let Some(fec) = fec {
+        buf.put_u8(fec.data);
+        buf.put_u8(fec.parity);
+    }
+    buf
+}
+
+// クッキーを載せた接続要求（誤り訂正の設定はクッキーの後ろに載せる）
+fn syn_packet(conn_id: u32, cookie: &[u8; COOKIE_LEN], fec: Option<Fec>) -> BytesMut {
+    let mut buf = packet(conn_id, SYN);
+    buf.put_slice(cookie);
+    if let Some(fec) = fec {
+        buf.put_u8(fec.data);
+        buf.put_u8(fec.parity);
+    }
+    buf
+}
+
+// 接続要求に返すクッキー（待ち受けごとの鍵・送信元・接続ID・接続要求の内容・発行した時間から作る）
+struct Cookies {
+    key: [u8; 32],
+    started: Instant,
+}
+
+impl Cookies {
+    fn new() -> Self {
+        Self {
+            key: rand::random(),
+            started: Instant::now(),
+        }
+    }
+
+    // 今の時間の単位から age 単位前に発行したクッキー
+    fn issue(&self, from: SocketAddr, conn_id: u32, request: &[u8], age: u64) -> [u8; COOKIE_LEN] {
+        let period = self.started.elapsed().as_secs() / COOKIE_PERIOD.as_secs();
+        let mut hasher = Sha256::new();
+        hasher.update(self.key);
+        hasher.update(period.saturating_sub(age).to_be_bytes());
+        hasher.update(from.to_string().as_bytes());
+        hasher.update(conn_id.to_be_bytes());
+        hasher.update(request);
+        let mut cookie = [0u8; COOKIE_LEN];
+        cookie.copy_from_slice(&hasher.finalize()[..COOKIE_LEN]);
+        cookie
+    }
+
+    fn verify(&self, from: SocketAddr, conn_id: u32, request: &[u8], echoed: &[u8]) -> bool {
+        (0..2).any(|age| self.issue(from, conn_id, request, age) == echoed)
+    }
+}
+
+fn parse_fec(body: &[u8]) -> Option<Fec> {
+    let fec = Fec {
+        data: *body.first()?,
+        parity: *body.get(1)?,
+    };
+    fec.is_valid().then_some(fec)
+}
+
+fn parse_header(packet: &[u8]) -> Option<(u32, u8)> {
+    if packet.len() < HEADER_LEN {
+        return None;
+    }
+    let conn_id = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]);
+    Some((conn_id, packet[4]))
+}
+
+// 送ったが ACK が届いていないパケット
+struct Segment {
+    data: Bytes,
+    fin: bool,
+    sent_at: Instant,
+    retransmits: u32,
+    // 後のパケットの ACK が先に届いた回数
+    skipped: u32,
+}
+
+// 1つの接続の送受信の処理（アプリとは DuplexStream でつながる）
+struct Engine {
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    conn_id: u32,
+    // 受け付けた側か（接続要求が再送されたら応答し直す）
+    server: bool,
+    // 誤り訂正（使わない場合は None）
+    fec: Option<Fec>,
+    encoder: Option<Encoder>,
+    decoder: Option<Decoder>,
+    // 追い越されたら再送する回数（誤り訂正を使う場合は、ブロックの復元を待つ分だけ増やす）
+    fast_resend: u32,
+
+    // 送信側
+    next_seq: u64,
+    unacked: BTreeMap<u64, Segment>,
+    cwnd: f64,
+    ssthresh: f64,
+    // この番号より前のパケットの損失では輻輳ウィンドウを縮めない（1往復に1回だけ縮める）
+    recovery: u64,
+    peer_window: usize,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+    fin_sent: bool,
+
+    // 受信側
+    rcv_next: u64,
+    // 順番を飛ばして届いたパケット（データと、FIN か）
+    out_of_order: BTreeMap<u64, (Bytes, bool)>,
+    // 順番がそろい、アプリに渡すのを待つデータ
+    deliver: VecDeque<Bytes>,
+    peer_fin: bool,
+    // 次の ACK で知らせる、順番を飛ばして届いたパケットの番号
+    ack_seqs: Vec<u64>,
+    need_ack: bool,
+
+    last_received: Instant,
+    last_sent: Instant,
+}
+
+impl Engine {
+    fn new(
+        socket: Arc<UdpSocket>,
+        peer: SocketAddr,
+        conn_id: u32,
+        server: bool,
+        fec: Option<Fec>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            socket,
+            peer,
+            conn_id,
+            server,
+            fec,
+            encoder: fec.and_then(Encoder::new),
+            decoder: fec.and_then(|fec| Decoder::new(fec, RECV_WINDOW as u64)),
+            fast_resend: FAST_RESEND + fec.map_or(0, |fec| fec.data as u32),
+            next_seq: 0,
+            unacked: BTreeMap::new(),
+            cwnd: INITIAL_CWND,
+            ssthresh: f64::MAX,
+            recovery: 0,
+            peer_window: RECV_WINDOW,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+            fin_sent: false,
+            rcv_next: 0,
+            out_of_order: BTreeMap::new(),
+            deliver: VecDeque::new(),
+            peer_fin: false,
+            ack_seqs: Vec::new(),
+            need_ack: false,
+            last_received: now,
+            last_sent: now,
+        }
+    }
+
+    // 接続が閉じるか切れるまで、パケットの送受信とアプリとの受け渡しを続ける
+    async fn run(mut self, mut incoming: mpsc::Receiver<Bytes>, app: DuplexStream) {
+        if self.server {
+            self.send(handshake_packet(self.conn_id, SYN_ACK, self.fec))
+                .await;
+        }
+        let (mut app_reader, mut app_writer) = tokio::io::split(app);
+        // アプリが書き込みを終えた（FIN を送る）か、アプリが読まなくなったか
+        let mut app_eof = false;
+        let mut app_closed = false;
+        let mut writer_shutdown = false;
+        let mut closed_at = None;
+        let mut read_buf = [0u8; MAX_PAYLOAD];
+        let mut tick = tokio::time::interval(TICK);
+
+        loop {
+            let can_read = !app_eof && self.can_send();
+            let front = self.deliver.front().cloned().unwrap_or_default();
+            tokio::select! {
+                received = incoming.recv() => {
+                    let Some(received) = received else {
+                        break;
+                    };
+                    if !self.handle(received).await {
+                        return;
+                    }
+                }
+                read = app_reader.read(&mut read_buf), if can_read => match read {
+                    Ok(n) if n > 0 => {
+                        self.send_segment(Bytes::copy_from_slice(&read_buf[..n]), false).await;
+                    }
+                    _ => {
+                        app_eof = true;
+                        self.send_segment(Bytes::new(), true).await;
+                    }
+                },
+                written = app_writer.write(&front), if !front.is_empty() => match written {
+                    Ok(n) => {
+                        if let Some(front) = self.deliver.front_mut() {
+                            front.advance(n);
+                            if front.is_empty() {
+                                self.deliver.pop_front();
+                            }
+                        }
+                    }
+                    // アプリが読まなくなったら、届いたデータは捨てる
+                    Err(_) => {
+                        app_closed = true;
+                        self.deliver.clear();
+                    }
+                },
+                _ = tick.tick() => {
+                    let now = Instant::now();
+                    if now - self.last_received >= DEAD_TIMEOUT || !self.retransmit_expired().await {
+                        self.send(packet(self.conn_id, RESET)).await;
+                        break;
+                    }
+                    if now - self.last_sent >= KEEPALIVE_INTERVAL {
+                        self.need_ack = true;
+                    }
+                    // 双方が閉じたら、しばらく相手の再送に応えてから終える
+                    if self.fin_sent && self.unacked.is_empty() && self.peer_fin && self.deliver.is_empty() {
+                        let closed_at = *closed_at.get_or_insert(now);
+                        if now - closed_at >= LINGER {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if self.need_ack {
+                self.send_ack().await;
+            }
+            // 相手の FIN までのデータを渡し終えたら、アプリに終わりを知らせる
+            if self.peer_fin && self.deliver.is_empty() && !writer_shutdown && !app_closed {
+                let _ = app_writer.shutdown().await;
+                writer_shutdown = true;
+            }
+        }
+    }
+
+    fn can_send(&self) -> bool {
+        let window = (self.cwnd as usize).min(self.peer_window).max(1);
+        !self.fin_sent && self.unacked.len() < window
+    }
+
+    async fn send(&mut self, packet: BytesMut) {
+        // 送れなかったパケットは回線で失われた場合と同じく再送で補う
+        let _ = self.socket.send_to(&packet, self.peer).await;
+        self.last_sent = Instant::now();
+    }
+
+    // データ（fin なら FIN）に次の番号を付けて送る
+    async fn send_segment(&mut self, data: Bytes, fin: bool) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.fin_sent |= fin;
+        let parity = self
+            .encoder
+            .as_mut()
+            .and_then(|encoder| encoder.push(seq, &data, fin));
+        self.unacked.insert(
+            seq,
+            Segment {
+                data,
+                fin,
+                sent_at: Instant::now(),
+                retransmits: 0,
+                skipped: 0,
+            },
+        );
+        self.transmit(seq).await;
+
+        // ブロックのデータパケットを送り終えたら、パリティパケットを送る
+        if let Some((block, shards)) = parity {
+            for (index, shard) in shards.into_iter().enumerate() {
+                let mut buf = packet(self.conn_id, PARITY);
+                buf.put_u64(block);
+                buf.put_u8(index as u8);
+                buf.put_slice(&shard);
+                self.send(buf).await;
+            }
+        }
+    }
+
+    async fn transmit(&mut self, seq: u64) {
+        let Some(segment) = self.unacked.get(&seq) else {
+            return;
+        };
+        let mut buf = packet(self.conn_id, if segment.fin { FIN } else { DATA });
+        buf.put_u64(seq);
+        buf.put_slice(&segment.data);
+        self.send(buf).await;
+    }
+
+    // 受け取ったパケットを処理する（相手が接続を切った場合は false）
+    async fn handle(&mut self, received: Bytes) -> bool {
+        let Some((_, kind)) = parse_header(&received) else {
+            return true;
+        };
+        self.last_received = Instant::now();
+        let mut body = received.slice(HEADER_LEN..);
+        match kind {
+            SYN if self.server => {
+                self.send(handshake_packet(self.conn_id, SYN_ACK, self.fec))
+                    .await
+            }
+            DATA | FIN if body.len() >= 8 => {
+                let seq = body.get_u64();
+                self.receive(seq, body, kind == FIN);
+            }
+            ACK if body.len() >= 12 => {
+                let una = body.get_u64();
+                let window = body.get_u16() as usize;
+                let count = body.get_u16() as usize;
+                let mut seqs = Vec::with_capacity(count);
+                while seqs.len() < count && body.len() >= 8 {
+                    seqs.push(body.get_u64());
+                }
+                self.acknowledged(una, window, &seqs).await;
+            }
+            PARITY if body.len() >= 9 => {
+                let block = body.get_u64();
+                let index = body.get_u8();
+                let recovered = match &mut self.decoder {
+                    Some(decoder) => decoder.parity(block, index, body.to_vec()),
+                    None => Vec::new(),
+                };
+                for (seq, data, fin) in recovered {
+                    self.need_ack = true;
+                    self.accept(seq, data, fin);
+                }
+            }
+            RESET => return false,
+            _ => {}
+        }
+        true
+    }
+
+    // データのパケットを受け取る（誤り訂正を使う場合は、ブロックの欠けたパケットも復元する）
+    fn receive(&mut self, seq: u64, data: Bytes, fin: bool) {
+        self.need_ack = true;
+        if !self.accept(seq, data.clone(), fin) {
+            return;
+        }
+        let recovered = match &mut self.decoder {
+            Some(decoder) => decoder.data(seq, &data, fin),
+            None => Vec::new(),
+        };
+        for (seq, data, fin) in recovered {
+            self.accept(seq, data, fin);
+        }
+    }
+
+    // データを溜め、順番がそろった分をアプリに渡す待ちに移す（新しく溜めた場合は true）
+    fn accept(&mut self, seq: u64, data: Bytes, fin: bool) -> bool {
+        let buffered = self.out_of_order.len() + self.deliver.len();
+        // 受け取り済みか、溜めておけない先のパケットは捨てる（受け取り済みなら ACK だけ返す）
+        if seq < self.rcv_next
+            || seq - self.rcv_next >= RECV_WINDOW as u64
+            || buffered >= RECV_WINDOW
+            || self.out_of_order.contains_key(&seq)
+        {
+            return false;
+        }
+        self.out_of_order.insert(seq, (data, fin));
+        self.ack_seqs.push(seq);
+        while let Some((data, fin)) = self.out_of_order.remove(&self.rcv_next) {
+            self.rcv_next += 1;
+            if fin {
+                self.peer_fin = true;
+            } else if !data.is_empty() {
+                self.deliver.push_back(data);
+            }
+        }
+        if let Some(decoder) = &mut self.decoder {
+            decoder.forget_before(self.rcv_next);
+        }
+        true
+    }
+
+    async fn send_ack(&mut self) {
+        self.need_ack = false;
+        let window = RECV_WINDOW.saturating_sub(self.out_of_order.len() + self.deliver.len());
+        let rcv_next = self.rcv_next;
+        // 順番がそろった分は una で知らせる
+        let mut seqs: Vec<u64> = self
+            .ack_seqs
+            .drain(..)
+            .filter(|&seq| seq >= rcv_next)
+            .collect();
+        loop {
+            let rest = seqs.split_off(seqs.len().min(MAX_ACK_SEQS));
+            let mut buf = packet(self.conn_id, ACK);
+            buf.put_u64(rcv_next);
+            buf.put_u16(window as u16);
+            buf.put_u16(seqs.len() as u16);
+            for &seq in &seqs {
+                buf.put_u64(seq);
+            }
+            self.send(buf).await;
+            if rest.is_empty() {
+                break;
+            }
+            seqs = rest;
+        }
+    }
+
+    // ACK を受け取り、届いたパケットを送信待ちから除いて輻輳ウィンドウを調整する
+    async fn acknowledged(&mut self, una: u64, window: usize, seqs: &[u64]) {
+        self.peer_window = window;
+        let now = Instant::now();
+        let mut acked: Vec<Segment> = Vec::new();
+        while let Some(entry) = self.unacked.first_entry() {
+            if *entry.key() >= una {
+                break;
+            }
+            acked.push(entry.remove());
+        }
+        acked.extend(seqs.iter().filter_map(|seq| self.unacked.remove(seq)));
+        for segment in &acked {
+            // 再送したパケットの往復時間は、どちらへの ACK か分からないため測らない
+            if segment.retransmits == 0 {
+                self.update_rtt(now - segment.sent_at);
+            }
+            if self.cwnd < self.ssthresh {
+                self.cwnd += 1.0;
+            } else {
+                self.cwnd += 1.0 / self.cwnd;
+            }
+        }
+
+        // ACK が届いた最大の番号より前で届いていないパケットは、追い越された回数を数える
+        let Some(&newest) = seqs.iter().max() else {
+            return;
+        };
+        let mut resend = Vec::new();
+        for (&seq, segment) in self.unacked.range_mut(..newest) {
+            segment.skipped += 1;
+            if segment.skipped >= self.fast_resend {
+                segment.skipped = 0;
+                resend.push(seq);
+            }
+        }
+        for seq in resend {
+            self.lost(seq, false);
+            self.retransmit(seq).await;
+        }
+    }
+
+    // 往復時間から再送タイマーを決める（RFC 6298）
+    fn update_rtt(&mut self, sample: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+            Some(srtt) => {
+                let diff = if srtt > sample {
+                    srtt - sample
+                } else {
+                    sample - srtt
+                };
+                self.rttvar = self.rttvar * 3 / 4 + diff / 4;
+                self.srtt = Some(srtt * 7 / 8 + sample / 8);
+            }
+        }
+        let srtt = self.srtt.unwrap_or(sample);
+        self.rto = (srtt + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    // パケットの損失を輻輳ウィンドウに反映する（timeout なら再送タイマーが切れた）
+    fn lost(&mut self, seq: u64, timeout: bool) {
+        if seq < self.recovery {
+            return;
+        }
+        self.ssthresh = (self.cwnd / 2.0).max(MIN_CWND);
+        self.cwnd = if timeout { MIN_CWND } else { self.ssthresh };
+        self.recovery = self.next_seq;
+    }
+
+    async fn retransmit(&mut self, seq: u64) {
+        if let Some(segment) = self.unacked.get_mut(&seq) {
+            segment.retransmits += 1;
+            segment.sent_at = Instant::now();
+        }
+        self.transmit(seq).await;
+    }
+
+    // 再送タイマーが切れたパケットを送り直す（再送の回数が上限を超えたら false）
+    async fn retransmit_expired(&mut self) -> bool {
+        let now = Instant::now();
+        let expired: Vec<(u64, u32)> = self
+            .unacked
+            .iter()
+            .filter(|(_, segment)| {
+                let rto = self
+                    .rto
+                    .mul_f64(RTO_BACKOFF.powi(segment.retransmits as i32))
+                    .min(MAX_RTO);
+                now - segment.sent_at >= rto
+            })
+            .map(|(&seq, segment)| (seq, segment.retransmits))
+            .collect();
+        for (seq, retransmits) in expired {
+            if retransmits >= MAX_RETRANSMITS {
+                return false;
+            }
+            self.lost(seq, true);
+            self.retransmit(seq).await;
+        }
+        true
+    }
+}
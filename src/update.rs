@@ -0,0 +1,262 @@
+use crate::{
+    config::Config,
+    log::{info, success},
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{env, fs, path::Path};
+
+// 最新のリリースを問い合わせる GitHub の API
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/tanase-j-ww/file-transfer/releases/latest";
+
+// リリースの署名を検証する minisign の公開鍵（リリース用のビルドで埋め込む）
+const BUILTIN_PUBLIC_KEY: Option<&str> = option_env!("FILE_TRANSFER_UPDATE_KEY");
+
+// このバイナリのターゲット（build.rs で設定する。リリースの添付ファイル名に使う）
+const TARGET: &str = env!("FILE_TRANSFER_TARGET");
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Result<&Asset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .with_context(|| format!("リリース {} に {} がありません", self.tag_name, name))
+    }
+}
+
+// self-update サブコマンドの実装
+//
+// 最新のリリースからこのターゲット用のバイナリと署名（.minisig）を取得し、
+// 署名を検証してから実行中のバイナリを置き換える
+pub async fn run(config: &Config, check: bool) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .user_agent(concat!("file-transfer/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+
+    let release: Release = client
+        .get(LATEST_RELEASE_URL)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context("最新のリリースの取得に失敗")?
+        .json()
+        .await
+        .context("リリース情報の解析に失敗")?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    let latest = release.tag_name.trim_start_matches('v');
+    if parse_version(latest)? <= parse_version(current)? {
+        info!("最新のバージョンです: {}", current);
+        return Ok(());
+    }
+    info!("新しいバージョンがあります: {} → {}", current, latest);
+    if check {
+        return Ok(());
+    }
+
+    let public_key = config
+        .update_public_key
+        .as_deref()
+        .or(BUILTIN_PUBLIC_KEY)
+        .context("署名を検証する公開鍵がありません（設定ファイルの update_public_key で指定）")?;
+    let public_key = minisign_verify::PublicKey::from_base64(public_key)
+        .map_err(|e| anyhow::anyhow!("公開鍵の解析に失敗: {}", e))?;
+
+    let name = format!("file-transfer-{}{}", TARGET, env::consts::EXE_SUFFIX);
+    let binary = download(&client, release.asset(&name)?).await?;
+    let signature = download(&client, release.asset(&format!("{}.minisig", name))?).await?;
+
+    let signature = minisign_verify::Signature::decode(&String::from_utf8_lossy(&signature))
+        .map_err(|e| anyhow::anyhow!("署名の解析に失敗: {}", e))?;
+    public_key
+        .verify(&binary, &signature, false)
+        .map_err(|e| anyhow::anyhow!("署名の検証に失敗しました。更新を中止します: {}", e))?;
+    check_signed_version(&signature, latest)?;
+
+    replace_current_exe(&binary)?;
+    success!("バージョン {} に更新しました", latest);
+    Ok(())
+}
+
+async fn download(client: &reqwest::Client, asset: &Asset) -> Result<Vec<u8>> {
+    info!("ダウンロード中: {}", asset.name);
+    let bytes = client
+        .get(&asset.browser_download_url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("{} のダウンロードに失敗", asset.name))?
+        .bytes()
+        .await
+        .with_context(|| format!("{} のダウンロードに失敗", asset.name))?;
+    Ok(bytes.to_vec())
+}
+
+// 署名された信頼できるコメントのバージョンが、リリースのバージョンと同じかを確かめる
+//
+// 古いリリースのバイナリと署名を新しいリリースに添付して戻されないよう、リリースでは
+// `minisign -S -t "file:<添付ファイル名> version:<バージョン>"` のようにバージョンを含めて署名する
+fn check_signed_version(signature: &minisign_verify::Signature, latest: &str) -> Result<()> {
+    let comment = signature.trusted_comment();
+    let signed = comment
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("version:"))
+        .with_context(|| {
+            format!(
+                "署名にバージョンが含まれていません。更新を中止します: {}",
+                comment
+            )
+        })?;
+    if signed.trim_start_matches('v') != latest {
+        anyhow::bail!(
+            "署名されたバージョン {} がリリースのバージョン {} と異なります。更新を中止します",
+            signed,
+            latest
+        );
+    }
+    Ok(())
+}
+
+// 比較できる形にしたバージョン（semver の順序。"+" に続くビルド情報は比較に使わない）
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Version {
+    core: [u64; 3],
+    // プレリリースでない（同じ番号ならプレリリースより新しい。1.2.0-rc1 < 1.2.0）
+    release: bool,
+    pre: Vec<PreRelease>,
+}
+
+// プレリリースの "." で区切られた各部分（数字だけの部分は数値で比べ、文字を含む部分より前にする）
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum PreRelease {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+// "1.2.3"、"1.2.0-rc1"、"1.2.3+build.5" などを比較できる形にする
+fn parse_version(version: &str) -> Result<Version> {
+    let invalid = || anyhow::anyhow!("バージョンの解析に失敗: {}", version);
+    let without_build = version.split_once('+').map_or(version, |(rest, _)| rest);
+    let (core, pre) = match without_build.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (without_build, None),
+    };
+
+    let core: Vec<u64> = core
+        .split('.')
+        .map(|part| part.parse().map_err(|_| invalid()))
+        .collect::<Result<_>>()?;
+    let [major, minor, patch] = core[..] else {
+        return Err(invalid());
+    };
+    let pre = match pre {
+        Some(pre) => pre
+            .split('.')
+            .map(|part| match part.parse() {
+                Ok(number) => Ok(PreRelease::Numeric(number)),
+                Err(_) if !part.is_empty() => Ok(PreRelease::Alphanumeric(part.to_string())),
+                Err(_) => Err(invalid()),
+            })
+            .collect::<Result<_>>()?,
+        None => Vec::new(),
+    };
+
+    Ok(Version {
+        core: [major, minor, patch],
+        release: pre.is_empty(),
+        pre,
+    })
+}
+
+// 実行中のバイナリを置き換える（同じフォルダに書き出してから入れ替える）
+fn replace_current_exe(binary: &[u8]) -> Result<()> {
+    let current = env::current_exe().context("実行中のバイナリのパスを取得できません")?;
+    let dir = current
+        .parent()
+        .context("実行中のバイナリのフォルダを取得できません")?;
+    let new_path = dir.join(".file-transfer.update");
+    fs::write(&new_path, binary)
+        .with_context(|| format!("新しいバイナリの書き込みに失敗: {:?}", new_path))?;
+    make_executable(&new_path)?;
+
+    let result = self_replace::self_replace(&new_path)
+        .with_context(|| format!("バイナリの置き換えに失敗: {:?}", current));
+    let _ = fs::remove_file(&new_path);
+    result
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("実行権限の設定に失敗: {:?}", path))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> Version {
+        parse_version(text).unwrap()
+    }
+
+    #[test]
+    fn compares_release_numbers() {
+        assert!(version("1.2.3") < version("1.2.4"));
+        assert!(version("1.2.10") > version("1.2.9"));
+        assert!(version("2.0.0") > version("1.99.99"));
+        assert_eq!(version("1.2.3"), version("1.2.3"));
+    }
+
+    #[test]
+    fn a_pre_release_is_older_than_its_release() {
+        assert!(version("1.2.0-rc1") < version("1.2.0"));
+        assert!(version("1.2.0-rc1") > version("1.1.9"));
+        assert!(version("1.2.0-rc1") < version("1.2.0-rc2"));
+        assert!(version("1.2.0-alpha") < version("1.2.0-beta"));
+        // 数字だけの部分は数値で比べ、文字を含む部分より前にする
+        assert!(version("1.2.0-rc.2") < version("1.2.0-rc.10"));
+        assert!(version("1.2.0-1") < version("1.2.0-alpha"));
+    }
+
+    #[test]
+    fn build_metadata_is_ignored() {
+        assert_eq!(version("1.2.3+build.5"), version("1.2.3"));
+        assert_eq!(version("1.2.3-rc1+abc"), version("1.2.3-rc1"));
+    }
+
+    #[test]
+    fn malformed_versions_are_refused() {
+        for text in [
+            "",
+            "1",
+            "1.2",
+            "1.2.3.4",
+            "1.2.x",
+            "1.2.3-",
+            "1.2.3-rc..1",
+            "v1.2.3",
+        ] {
+            assert!(parse_version(text).is_err(), "{:?}", text);
+        }
+    }
+}
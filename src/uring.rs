@@ -0,0 +1,70 @@
+use crate::{buffer, pipeline::ACK_INTERVAL};
+use anyhow::{Context, Result};
+use bytes::BytesMut;
+use std::{io::SeekFrom, time::Instant};
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWriteExt},
+    sync::{mpsc, watch},
+};
+
+// パイプラインの書き込みの段を io_uring で行う（Linux で io-uring フィーチャーを有効にした場合のみ）
+//
+// tokio-uring は専用のランタイムで動かす必要があるため、段ごとにスレッドを立てて
+// パイプラインのチャンネルからチャンクを受け取る。書き込みは今の位置からの位置指定で行う
+pub async fn write_stage(
+    mut file: File,
+    rx: mpsc::Receiver<BytesMut>,
+    base: u64,
+    acked: Option<watch::Sender<u64>>,
+) -> Result<(File, u64)> {
+    file.flush().await?;
+    let position = file.stream_position().await?;
+    let file = file.into_std().await;
+    let uring_file = file
+        .try_clone()
+        .context("io_uring 用のファイルの複製に失敗")?;
+
+    let written = tokio::task::spawn_blocking(move || {
+        tokio_uring::start(write_all(uring_file, rx, position, base, acked))
+    })
+    .await
+    .context("io_uring の書き込みが異常終了しました")??;
+
+    // 続けて書き込めるよう、書き込んだ分だけ位置を進めて返す
+    let mut file = File::from_std(file);
+    file.seek(SeekFrom::Start(position + written)).await?;
+    Ok((file, written))
+}
+
+async fn write_all(
+    file: std::fs::File,
+    mut rx: mpsc::Receiver<BytesMut>,
+    position: u64,
+    base: u64,
+    acked: Option<watch::Sender<u64>>,
+) -> Result<u64> {
+    let file = tokio_uring::fs::File::from_std(file);
+    let result = async {
+        let mut written = 0;
+        let mut last = Instant::now();
+        while let Some(chunk) = rx.recv().await {
+            let len = chunk.len() as u64;
+            let (result, chunk) = file.write_all_at(chunk, position + written).await;
+            buffer::release(chunk);
+            result.context("ファイルの書き込みに失敗")?;
+            written += len;
+            if let (Some(acked), true) = (&acked, last.elapsed() >= ACK_INTERVAL) {
+                file.sync_data()
+                    .await
+                    .context("ファイルの書き込みの確定に失敗")?;
+                let _ = acked.send(base + written);
+                last = Instant::now();
+            }
+        }
+        Ok(written)
+    }
+    .await;
+    file.close().await?;
+    result
+}
@@ -0,0 +1,125 @@
+use crate::log::warn;
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use std::path::{Path, PathBuf};
+
+// 送信しないファイルを書いておくファイル（.gitignore と同じ書き方。サブフォルダにも置ける）
+pub const IGNORE_FILE: &str = ".ftignore";
+
+// フォルダ送信時の対象ファイルの絞り込み
+#[derive(Args, Clone, Default)]
+pub struct WalkOptions {
+    /// フォルダ送信時に含めるファイルのglob（複数指定可、指定しない場合はすべて）
+    #[arg(long = "include", value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// フォルダ送信時に除外するファイル・フォルダのglob（例: "node_modules", "*.tmp"）
+    #[arg(long = "exclude", value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// フォルダ送信時に .gitignore の内容に従って除外する
+    #[arg(long)]
+    pub gitignore: bool,
+
+    /// フォルダ内の .ftignore（.gitignore と同じ書き方）に従わない
+    #[arg(long)]
+    pub no_ftignore: bool,
+
+    /// フォルダ内のシンボリックリンクの扱い
+    #[arg(long, value_enum, default_value_t = LinkPolicy::Skip)]
+    pub links: LinkPolicy,
+}
+
+// シンボリックリンクの扱い
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkPolicy {
+    /// リンク先の実体を送る（循環するリンクは検出してスキップ）
+    Follow,
+    /// リンクを送らない
+    #[default]
+    Skip,
+    /// 受信側にリンクとして再作成する
+    Preserve,
+}
+
+// 送信対象のファイル
+pub struct WalkEntry {
+    pub path: PathBuf,
+    // フォルダからの相対パス（区切り文字は '/'）
+    pub relative: String,
+    // リンクとして送る場合のリンク先（LinkPolicy::Preserve のときのみ）
+    pub link_target: Option<PathBuf>,
+}
+
+// フォルダ内の送信対象ファイルを列挙する
+pub fn collect_files(root: &Path, options: &WalkOptions) -> Result<Vec<WalkEntry>> {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in &options.include {
+        overrides
+            .add(glob)
+            .with_context(|| format!("不正なglob: {}", glob))?;
+    }
+    for glob in &options.exclude {
+        overrides
+            .add(&format!("!{}", glob))
+            .with_context(|| format!("不正なglob: {}", glob))?;
+    }
+
+    let mut builder = WalkBuilder::new(root);
+    if !options.no_ftignore {
+        builder.add_custom_ignore_filename(IGNORE_FILE);
+    }
+    let walker = builder
+        .hidden(false)
+        .ignore(false)
+        .git_global(false)
+        .git_ignore(options.gitignore)
+        .git_exclude(options.gitignore)
+        .parents(options.gitignore)
+        .require_git(false)
+        .follow_links(options.links == LinkPolicy::Follow)
+        .overrides(overrides.build()?)
+        .build();
+
+    let mut entries = Vec::new();
+    for entry in walker {
+        // 循環するリンクや読み取れない項目は警告してスキップする
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("スキップしました: {}", e);
+                continue;
+            }
+        };
+        let Some(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        let link_target = if file_type.is_symlink() {
+            if options.links != LinkPolicy::Preserve {
+                continue;
+            }
+            Some(std::fs::read_link(entry.path())?)
+        } else if file_type.is_file() {
+            None
+        } else {
+            continue;
+        };
+
+        let relative = entry
+            .path()
+            .strip_prefix(root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        entries.push(WalkEntry {
+            path: entry.path().to_path_buf(),
+            relative,
+            link_target,
+        });
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,200 @@
+use crate::log::{info, warn};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use uuid::Uuid;
+
+// 1件の Webhook の送信を待つ時間の上限
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 設定ファイルの [[webhooks]] に書く、転送の結果を知らせる先
+//
+// 結果ごとに url へ POST する。本文は template を展開したもの（省略すると Payload の JSON）
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    // 送り先の URL
+    pub url: String,
+
+    // 知らせる結果（省略するとすべて）
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+
+    // 本文のテンプレート（例: '{"text": "{peer} から {filename} を受信しました"}'）
+    //
+    // 使用できる置換子: {event}, {transfer_id}, {peer}, {filename}, {size}, {message}, {time}、
+    // 送信側が添えたメタデータの {meta.キー}（なければ空）。値は JSON の文字列の中に置けるようエスケープする
+    pub template: Option<String>,
+}
+
+// Webhook で知らせる転送の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Completed,
+    Failed,
+    Rejected,
+}
+
+impl WebhookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            WebhookEvent::Completed => "completed",
+            WebhookEvent::Failed => "failed",
+            WebhookEvent::Rejected => "rejected",
+        }
+    }
+}
+
+// 既定の本文（JSON）と、テンプレートに埋め込む値
+#[derive(Serialize)]
+pub struct Payload {
+    pub event: WebhookEvent,
+    pub transfer_id: Uuid,
+    pub peer: String,
+    // ヘッダーを読む前に拒否した場合は None
+    pub filename: Option<String>,
+    pub size: Option<u64>,
+    // 失敗・拒否の理由
+    pub message: Option<String>,
+    // 送信側が転送に添えたメタデータ
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    // 結果が出た時刻（RFC 3339）
+    pub time: String,
+}
+
+impl Payload {
+    pub fn new(event: WebhookEvent, transfer_id: Uuid, peer: &str) -> Self {
+        Self {
+            event,
+            transfer_id,
+            peer: peer.to_string(),
+            filename: None,
+            size: None,
+            message: None,
+            metadata: BTreeMap::new(),
+            time: chrono::Local::now().to_rfc3339(),
+        }
+    }
+
+    pub fn with_file(mut self, filename: String, size: u64) -> Self {
+        self.filename = Some(filename);
+        self.size = Some(size);
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: &BTreeMap<String, String>) -> Self {
+        self.metadata = metadata.clone();
+        self
+    }
+
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    // テンプレートの置換子の値（知らない置換子は None）
+    fn field(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "event" => self.event.name().to_string(),
+            "transfer_id" => self.transfer_id.to_string(),
+            "peer" => self.peer.clone(),
+            "filename" => self.filename.clone().unwrap_or_default(),
+            "size" => self.size.map(|size| size.to_string()).unwrap_or_default(),
+            "message" => self.message.clone().unwrap_or_default(),
+            "time" => self.time.clone(),
+            _ => match name.strip_prefix("meta.") {
+                Some(key) => self.metadata.get(key).cloned().unwrap_or_default(),
+                None => return None,
+            },
+        })
+    }
+}
+
+// 設定された Webhook の一覧
+pub struct Webhooks {
+    hooks: Vec<WebhookConfig>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    // 設定ファイルの [[webhooks]] から作る（1件もなければ None）
+    pub fn new(hooks: &[WebhookConfig]) -> Result<Option<Arc<Self>>> {
+        if hooks.is_empty() {
+            return Ok(None);
+        }
+        for hook in hooks {
+            reqwest::Url::parse(&hook.url)
+                .with_context(|| format!("Webhook の URL が不正です: {}", hook.url))?;
+        }
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("file-transfer/", env!("CARGO_PKG_VERSION")))
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()?;
+        info!("Webhook: {} 件", hooks.len());
+        Ok(Some(Arc::new(Self {
+            hooks: hooks.to_vec(),
+            client,
+        })))
+    }
+
+    // payload の結果を知らせる設定の Webhook に送る（送信は裏で行い、失敗は警告するだけ）
+    pub fn fire(&self, payload: Payload) {
+        for hook in &self.hooks {
+            if !hook.events.is_empty() && !hook.events.contains(&payload.event) {
+                continue;
+            }
+            let body = match &hook.template {
+                Some(template) => render(template, &payload),
+                None => serde_json::to_string(&payload).unwrap_or_default(),
+            };
+            let request = self
+                .client
+                .post(&hook.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body);
+            let (url, transfer_id) = (hook.url.clone(), payload.transfer_id);
+            tokio::spawn(async move {
+                let result = request
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    warn!("[{}] Webhook の送信に失敗: {} ({})", transfer_id, url, e);
+                }
+            });
+        }
+    }
+}
+
+// テンプレートの置換子を値に置き換える（置き換えた値の中の置換子はそのまま残す）
+fn render(template: &str, payload: &Payload) -> String {
+    let mut body = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        body.push_str(&rest[..start]);
+        let after = &rest[start..];
+        let replaced = after
+            .find('}')
+            .and_then(|end| payload.field(&after[1..end]).map(|value| (end, value)));
+        match replaced {
+            Some((end, value)) => {
+                body.push_str(&escape(&value));
+                rest = &after[end + 1..];
+            }
+            // JSON の '{' など、置換子でないものはそのまま残す
+            None => {
+                body.push('{');
+                rest = &after[1..];
+            }
+        }
+    }
+    body.push_str(rest);
+    body
+}
+
+// JSON の文字列の中に置けるようにエスケープする（前後の '"' は付けない）
+fn escape(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
@@ -0,0 +1,219 @@
+use crate::{
+    client::{run_client, ClientArgs},
+    config::Config,
+    hotkey::{self, Action},
+    picker, secrets,
+    server::{run_server, ServerArgs},
+    token,
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use dialoguer::{Confirm, Input, Password, Select};
+use std::{net::TcpListener, path::PathBuf};
+
+// 選んだ役割
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Server,
+    Client,
+}
+
+impl Role {
+    // 尋ねるホットキーの操作
+    fn hotkey_action(self) -> Action {
+        match self {
+            Role::Server => Action::ChangeSaveDir,
+            Role::Client => Action::SendFile,
+        }
+    }
+}
+
+// 引数なしで起動した場合のセットアップウィザード
+//
+// 役割・ポート・端末名・トークン・ホットキーを順に尋ねて設定ファイルに保存し、
+// 保存した設定でそのままサーバーモードかクライアントモードを開始する
+pub async fn run(config: &Config) -> Result<()> {
+    println!("ファイル転送プログラム セットアップ");
+    println!("==================================");
+    if let Some(path) = Config::path() {
+        println!("設定は {:?} に保存します（既定値は今の設定です）", path);
+    }
+    println!();
+
+    let role = match Select::new()
+        .with_prompt("この端末で行うこと")
+        .items(&[
+            "ファイルを受信する（サーバー）",
+            "ファイルを送信する（クライアント）",
+        ])
+        .default(0)
+        .interact()?
+    {
+        0 => Role::Server,
+        _ => Role::Client,
+    };
+
+    let mut values = Vec::new();
+    let port = ask_port(config, role)?;
+    values.push(("port", toml::Value::Integer(port.into())));
+
+    let device_name: String = Input::new()
+        .with_prompt("この端末の名前（空欄なら設定しない）")
+        .with_initial_text(
+            config
+                .device_name
+                .clone()
+                .unwrap_or_else(default_device_name),
+        )
+        .allow_empty(true)
+        .interact_text()?;
+    let device_name = device_name.trim();
+    if !device_name.is_empty() {
+        values.push(("device_name", toml::Value::String(device_name.to_string())));
+    }
+
+    match role {
+        Role::Server => {
+            let save_dir = ask_save_dir(config)?;
+            values.push((
+                "save_dir",
+                toml::Value::String(save_dir.to_string_lossy().into_owned()),
+            ));
+            let require_token = Confirm::new()
+                .with_prompt("送信側にトークンの提示を求めますか")
+                .default(config.require_token)
+                .interact()?;
+            if require_token {
+                let (id, token) = token::issue(None, None)?;
+                println!("トークン {} を発行しました（値は再表示できません）", id);
+                println!("送信側のセットアップでこの値を入力してください: {}", token);
+            }
+            values.push(("require_token", toml::Value::Boolean(require_token)));
+        }
+        Role::Client => {
+            let server_addr: String = Input::new()
+                .with_prompt("送信先（受信側のIPアドレス）")
+                .with_initial_text(config.peers.first().cloned().unwrap_or_default())
+                .interact_text()?;
+            let server_addr = server_addr.trim().to_string();
+            let mut peers = config.peers.clone();
+            if !peers.contains(&server_addr) {
+                peers.insert(0, server_addr);
+            }
+            values.push((
+                "peers",
+                toml::Value::Array(peers.into_iter().map(toml::Value::String).collect()),
+            ));
+
+            if Confirm::new()
+                .with_prompt("受信側で発行したトークンを保存しますか")
+                .default(false)
+                .interact()?
+            {
+                let value = Password::new().with_prompt("トークン").interact()?;
+                secrets::set(token::TOKEN_SECRET_NAME, &value)?;
+                println!("トークンをキーチェーンに保存しました");
+            }
+        }
+    }
+
+    let hotkey = ask_hotkey(config, role)?;
+
+    Config::set_values(values)?;
+    if let Some(hotkey) = &hotkey {
+        Config::set_hotkey(role.hotkey_action(), hotkey)?;
+    }
+    println!("設定を保存しました");
+    println!();
+
+    // 保存した設定で開始する
+    let config = Config::load()?;
+    match role {
+        Role::Server => {
+            let mut args = ServerArgs::parse_from(["server"]);
+            args.no_hotkey = hotkey.is_none();
+            run_server(&args, &config).await
+        }
+        Role::Client => {
+            let mut args = ClientArgs::parse_from(["client"]);
+            args.server = config.peers.first().cloned();
+            args.no_hotkey = hotkey.is_none();
+            run_client(&args, &config).await
+        }
+    }
+}
+
+// 待ち受けと接続に使うポート（受信側の場合は待ち受けできるまで尋ね直す）
+fn ask_port(config: &Config, role: Role) -> Result<u16> {
+    loop {
+        let port: u16 = Input::new()
+            .with_prompt("ポート")
+            .default(config.port.unwrap_or(crate::FILE_TRANSFER_PORT))
+            .interact_text()?;
+        if role == Role::Client {
+            return Ok(port);
+        }
+        match TcpListener::bind(("0.0.0.0", port)) {
+            Ok(_) => return Ok(port),
+            Err(e) => println!(
+                "ポート {} で待ち受けできません（{}）。別のポートを入力してください",
+                port, e
+            ),
+        }
+    }
+}
+
+// 受信したファイルの保存先（なければ作成する）
+fn ask_save_dir(config: &Config) -> Result<PathBuf> {
+    let default = config
+        .save_dir
+        .clone()
+        .or_else(dirs::download_dir)
+        .unwrap_or_default();
+    let save_dir: String = Input::new()
+        .with_prompt("保存先フォルダ")
+        .with_initial_text(default.to_string_lossy())
+        .interact_text()?;
+    let save_dir = PathBuf::from(save_dir.trim());
+    std::fs::create_dir_all(&save_dir)
+        .with_context(|| format!("保存先フォルダの作成に失敗: {:?}", save_dir))?;
+    Ok(save_dir)
+}
+
+// ホットキー（使わない場合は None。登録できるまで尋ね直す）
+fn ask_hotkey(config: &Config, role: Role) -> Result<Option<String>> {
+    if !picker::has_display() {
+        println!("画面がないため、ホットキーは使用しません");
+        return Ok(None);
+    }
+    let prompt = match role {
+        Role::Server => "ホットキーで保存先を変更できるようにしますか",
+        Role::Client => "ホットキーで送信するファイルを選べるようにしますか",
+    };
+    if !Confirm::new()
+        .with_prompt(prompt)
+        .default(true)
+        .interact()?
+    {
+        return Ok(None);
+    }
+
+    let default = role.hotkey_action().configured(config).unwrap_or_default();
+    loop {
+        let name: String = Input::new()
+            .with_prompt("ホットキー（例: ctrl+shift+s）")
+            .default(default.clone())
+            .interact_text()?;
+        match hotkey::check(&name) {
+            Ok(()) => return Ok(Some(name)),
+            Err(e) => println!("{:#}。別の組み合わせを入力してください", e),
+        }
+    }
+}
+
+// 端末名の初期値（OSのコンピューター名）
+fn default_device_name() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_default()
+}
@@ -0,0 +1,35 @@
+use anyhow::{Context, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+
+// マジックパケットを送るポート（discard）
+const WOL_PORT: u16 = 9;
+
+// "aa:bb:cc:dd:ee:ff"（"-" 区切りも可）の MAC アドレスを読む
+pub fn parse_mac(s: &str) -> Result<[u8; 6]> {
+    let parts: Vec<&str> = s.split([':', '-']).collect();
+    if parts.len() != 6 {
+        anyhow::bail!("MAC アドレスの形式が正しくありません: {}", s);
+    }
+    let mut mac = [0u8; 6];
+    for (byte, part) in mac.iter_mut().zip(parts) {
+        *byte = u8::from_str_radix(part, 16)
+            .with_context(|| format!("MAC アドレスの形式が正しくありません: {}", s))?;
+    }
+    Ok(mac)
+}
+
+// LAN 全体にマジックパケット（0xFF を6バイト、続けて MAC アドレスを16回）をブロードキャストする
+pub async fn send_magic_packet(mac: [u8; 6]) -> Result<()> {
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac);
+    }
+    let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?;
+    socket.set_broadcast(true)?;
+    socket
+        .send_to(&packet, SocketAddr::from((Ipv4Addr::BROADCAST, WOL_PORT)))
+        .await
+        .context("マジックパケットの送信に失敗")?;
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use file_transfer::{encrypt, walk::WalkOptions};
+use std::{fs, path::PathBuf};
+use uuid::Uuid;
+
+fn scratch_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("file-transfer-test-{}", Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn decrypting_a_folder_does_not_overwrite_existing_files() {
+    let root = scratch_dir();
+    let folder = root.join("photos");
+    fs::create_dir_all(&folder).unwrap();
+    fs::write(folder.join("a.txt"), "sent").unwrap();
+    let encrypted = root.join("encrypted");
+    fs::create_dir_all(&encrypted).unwrap();
+    let archive =
+        encrypt::encrypt(&folder, &WalkOptions::default(), "password", &encrypted).unwrap();
+
+    // 展開先には同じ名前のファイルが既にある
+    let output = root.join("output");
+    fs::create_dir_all(output.join("photos")).unwrap();
+    fs::write(output.join("photos").join("a.txt"), "existing").unwrap();
+
+    assert!(encrypt::decrypt(&archive, Some(&output), "password").is_err());
+    assert_eq!(
+        fs::read_to_string(output.join("photos").join("a.txt")).unwrap(),
+        "existing"
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
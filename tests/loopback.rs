@@ -0,0 +1,58 @@
+#![cfg(all(feature = "testing", unix))]
+
+use file_transfer::{client, config::Config, testing};
+use std::{fs, path::PathBuf};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+fn scratch_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("file-transfer-test-{}", Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn sends_a_file_and_a_folder_over_loopback() {
+    let root = scratch_dir();
+
+    let file = root.join("report.txt");
+    fs::write(&file, "quarterly numbers").unwrap();
+    let folder = root.join("photos");
+    fs::create_dir_all(folder.join("2024")).unwrap();
+    fs::write(folder.join("a.jpg"), vec![1u8; 4096]).unwrap();
+    fs::write(folder.join("2024").join("b.jpg"), vec![2u8; 100]).unwrap();
+
+    // 確認の先がない受信側は、届いた転送をすべて受け入れる
+    let save_dir = root.join("save");
+    let config = Config::default();
+    let cancel = CancellationToken::new();
+    let args = testing::server_args(&["--no-index"]).unwrap();
+    let addr = testing::start_receiver(&args, &config, save_dir.clone(), cancel.clone())
+        .await
+        .unwrap();
+    // 送信履歴をユーザーの設定フォルダに書かないよう、一時フォルダに保存する
+    let config_dir = root.join("config");
+    let client_args =
+        testing::client_args(&addr, &["--config-dir", config_dir.to_str().unwrap()]).unwrap();
+    client::send_path(&client_args, &config, &file, None, &cancel)
+        .await
+        .unwrap();
+    client::send_path(&client_args, &config, &folder, None, &cancel)
+        .await
+        .unwrap();
+    cancel.cancel();
+
+    assert_eq!(
+        fs::read_to_string(save_dir.join("report.txt")).unwrap(),
+        "quarterly numbers"
+    );
+    assert_eq!(
+        fs::read(save_dir.join("photos").join("a.jpg")).unwrap(),
+        vec![1u8; 4096]
+    );
+    assert_eq!(
+        fs::read(save_dir.join("photos").join("2024").join("b.jpg")).unwrap(),
+        vec![2u8; 100]
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,73 @@
+#![cfg(all(feature = "testing", unix))]
+
+use file_transfer::{
+    config::Config,
+    protocol::{self, FileHeader, SymlinkHeader},
+    testing::{self, Loopback},
+    transport::Transport,
+};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+fn scratch_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("file-transfer-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// 1接続で header を送り、受信側が応答して接続を閉じるまで待つ
+async fn send_symlink(addr: &str, filename: &str, target: &str) {
+    let mut socket = Loopback.connect(addr).await.unwrap();
+    let header = SymlinkHeader {
+        filename: filename.to_string(),
+        target: target.to_string(),
+    };
+    protocol::write_symlink_header(&mut socket, &header)
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    let _ = socket.read_to_end(&mut response).await;
+}
+
+async fn send_file(addr: &str, filename: &str, data: &[u8]) {
+    let mut socket = Loopback.connect(addr).await.unwrap();
+    let header = FileHeader {
+        filename: filename.to_string(),
+        filedata_len: data.len() as u32,
+    };
+    protocol::write_file_header(&mut socket, &header)
+        .await
+        .unwrap();
+    socket.write_all(data).await.unwrap();
+    let mut response = Vec::new();
+    let _ = socket.read_to_end(&mut response).await;
+}
+
+fn is_link(path: &Path) -> bool {
+    path.symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+#[tokio::test]
+async fn chained_symlinks_cannot_escape_save_dir() {
+    let root = scratch_dir();
+    let save_dir = root.join("save");
+    let cancel = CancellationToken::new();
+    let args = testing::server_args(&["--no-index"]).unwrap();
+    let addr = testing::start_receiver(&args, &Config::default(), save_dir.clone(), cancel.clone())
+        .await
+        .unwrap();
+
+    // "a/d" は保存先フォルダ自身を指すため作成してよい
+    send_symlink(&addr, "a/d", "..").await;
+    // "a/d" の中に作ると保存先フォルダの外（root）を指すリンクになる
+    send_symlink(&addr, "a/d/e", "..").await;
+    send_file(&addr, "a/d/e/x", b"escaped").await;
+    cancel.cancel();
+
+    assert!(!root.join("x").exists());
+    assert!(!is_link(&save_dir.join("e")));
+    std::fs::remove_dir_all(&root).unwrap();
+}